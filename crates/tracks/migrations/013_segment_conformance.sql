@@ -0,0 +1,35 @@
+-- Migration: 013_segment_conformance
+-- Per-activity-type path tolerance for segment matching, and a record of
+-- endpoint matches that were rejected because the track did not follow the segment
+
+-- Maximum discrete Fréchet distance (meters) between the matched part of a
+-- track and the segment line for the effort to count
+ALTER TABLE activity_types
+    ADD COLUMN match_tolerance_meters DOUBLE PRECISION NOT NULL DEFAULT 30;
+
+-- Foot activities are slow enough for tight GPS traces; tree cover on
+-- mountain bike trails makes traces noisier
+UPDATE activity_types SET match_tolerance_meters = 25
+    WHERE id IN ('00000000-0000-0000-0000-000000000001',
+                 '00000000-0000-0000-0000-000000000002',
+                 '00000000-0000-0000-0000-000000000003');
+UPDATE activity_types SET match_tolerance_meters = 40
+    WHERE id IN ('00000000-0000-0000-0000-000000000005',
+                 '00000000-0000-0000-0000-000000000006');
+
+COMMENT ON COLUMN activity_types.match_tolerance_meters IS 'Max Fréchet distance (m) between track and segment for a segment effort to count';
+
+-- Rejected segment matches, visible to the segment creator
+CREATE TABLE segment_match_rejections (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    segment_id UUID NOT NULL REFERENCES segments(id) ON DELETE CASCADE,
+    activity_id UUID NOT NULL REFERENCES activities(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    reason TEXT NOT NULL,
+    frechet_distance_meters DOUBLE PRECISION,
+    tolerance_meters DOUBLE PRECISION NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE(segment_id, activity_id)
+);
+
+CREATE INDEX idx_segment_match_rejections_segment ON segment_match_rejections(segment_id, created_at DESC);
@@ -0,0 +1,12 @@
+-- Migration: 014_segment_effort_laps
+-- An activity can now traverse a segment several times (laps), so match
+-- rejections are recorded per traversal rather than per activity
+
+ALTER TABLE segment_match_rejections
+    DROP CONSTRAINT segment_match_rejections_segment_id_activity_id_key;
+
+-- Position of the rejected traversal on the activity track (fractional 0-1)
+ALTER TABLE segment_match_rejections ADD COLUMN start_fraction DOUBLE PRECISION;
+ALTER TABLE segment_match_rejections ADD COLUMN end_fraction DOUBLE PRECISION;
+
+CREATE INDEX idx_segment_match_rejections_activity ON segment_match_rejections(segment_id, activity_id);
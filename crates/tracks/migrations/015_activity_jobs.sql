@@ -0,0 +1,53 @@
+-- Migration: 015_activity_jobs
+-- Durable activity processing queue, replacing the in-memory queue and the
+-- "no track geometry" orphan heuristic
+
+CREATE TYPE job_status AS ENUM (
+    'queued',
+    'processing',
+    'succeeded',
+    'failed'
+);
+
+CREATE TABLE activity_jobs (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    activity_id UUID NOT NULL REFERENCES activities(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    status job_status NOT NULL DEFAULT 'queued',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    max_attempts INTEGER NOT NULL DEFAULT 5,
+    last_error TEXT,
+    -- Earliest time a queued job may be picked up (used for retry backoff)
+    run_after TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    -- When a worker claimed the job; stale claims are requeued
+    locked_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    finished_at TIMESTAMPTZ
+);
+
+-- At most one live job per activity
+CREATE UNIQUE INDEX idx_activity_jobs_active
+    ON activity_jobs(activity_id)
+    WHERE status IN ('queued', 'processing');
+
+CREATE INDEX idx_activity_jobs_ready ON activity_jobs(run_after) WHERE status = 'queued';
+CREATE INDEX idx_activity_jobs_user ON activity_jobs(user_id, created_at DESC);
+
+-- Pipeline writes must be idempotent so a retried job doesn't duplicate rows.
+-- Keep the newest score per activity, then enforce one score row per activity.
+DELETE FROM scores a
+    USING scores b
+    WHERE a.activity_id = b.activity_id
+      AND (a.created_at, a.id) < (b.created_at, b.id);
+
+DROP INDEX IF EXISTS idx_scores_activity_id;
+CREATE UNIQUE INDEX idx_scores_activity_id ON scores(activity_id);
+
+-- Queue any activity that never finished processing under the old queue
+INSERT INTO activity_jobs (activity_id, user_id)
+SELECT a.id, a.user_id
+FROM activities a
+LEFT JOIN tracks t ON t.activity_id = a.id
+WHERE t.id IS NULL
+  AND a.deleted_at IS NULL;
@@ -0,0 +1,33 @@
+-- Migration: 016_activity_processing_stages
+-- Track which pipeline stage an activity job is in, and keep the errors each
+-- stage hit so clients can show processing progress and failures
+
+CREATE TYPE processing_stage AS ENUM (
+    'queued',
+    'parsing',
+    'storing_geometry',
+    'matching_segments',
+    'achievements',
+    'done'
+);
+
+ALTER TABLE activity_jobs
+    ADD COLUMN stage processing_stage NOT NULL DEFAULT 'queued';
+
+UPDATE activity_jobs SET stage = 'done' WHERE status = 'succeeded';
+
+-- Errors captured while processing. Fatal errors ended the attempt; the others
+-- (a segment that failed to save, a crown update that failed) only skipped part
+-- of a stage.
+CREATE TABLE activity_processing_errors (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    job_id UUID NOT NULL REFERENCES activity_jobs(id) ON DELETE CASCADE,
+    activity_id UUID NOT NULL REFERENCES activities(id) ON DELETE CASCADE,
+    stage processing_stage NOT NULL,
+    attempt INTEGER NOT NULL,
+    fatal BOOLEAN NOT NULL,
+    message TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_activity_processing_errors_job ON activity_processing_errors(job_id, created_at DESC);
@@ -0,0 +1,30 @@
+-- Migration: 017_activity_processing_error_kinds
+-- Classify processing errors so clients can explain a failed upload, and keep
+-- the technical detail separate from the message shown to the user
+
+CREATE TYPE processing_error_kind AS ENUM (
+    'file_unavailable',
+    'parse_failed',
+    'database',
+    'interrupted',
+    'internal'
+);
+
+ALTER TABLE activity_processing_errors
+    ADD COLUMN kind processing_error_kind NOT NULL DEFAULT 'internal',
+    ADD COLUMN detail TEXT;
+
+UPDATE activity_processing_errors SET kind = 'interrupted'
+    WHERE message = 'Worker stopped while processing';
+
+ALTER TABLE activity_jobs
+    ADD COLUMN last_error_kind processing_error_kind;
+
+UPDATE activity_jobs j SET last_error_kind = e.kind
+    FROM (
+        SELECT DISTINCT ON (job_id) job_id, kind
+        FROM activity_processing_errors
+        WHERE fatal
+        ORDER BY job_id, created_at DESC
+    ) e
+    WHERE e.job_id = j.id AND j.last_error IS NOT NULL;
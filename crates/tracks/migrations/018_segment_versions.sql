@@ -0,0 +1,33 @@
+-- Migration: 018_segment_versions
+-- Segment geometry can be edited (trimmed or replaced). Each edit creates a
+-- new version; efforts, PRs and crowns are rebuilt against the latest one.
+
+ALTER TABLE segments
+    ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+
+CREATE TABLE segment_versions (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    segment_id UUID NOT NULL REFERENCES segments(id) ON DELETE CASCADE,
+    version INTEGER NOT NULL,
+    geo GEOGRAPHY(LineStringZ, 4326) NOT NULL,
+    distance_meters FLOAT NOT NULL,
+    elevation_gain_meters FLOAT,
+    elevation_loss_meters FLOAT,
+    average_grade FLOAT,
+    max_grade FLOAT,
+    climb_category INTEGER,
+    created_by UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE(segment_id, version)
+);
+
+-- Existing segments start at version 1
+INSERT INTO segment_versions (
+    segment_id, version, geo, distance_meters, elevation_gain_meters, elevation_loss_meters,
+    average_grade, max_grade, climb_category, created_by, created_at
+)
+SELECT id, 1, geo, distance_meters, elevation_gain_meters, elevation_loss_meters,
+       average_grade, max_grade, climb_category, creator_id, created_at
+FROM segments;
+
+COMMENT ON TABLE segment_versions IS 'Geometry history of segments; the highest version is the one in segments';
@@ -0,0 +1,11 @@
+-- Migration: 019_local_legend
+-- Bring back Local Legend as a consistency award: the athlete with the most
+-- efforts on a segment over a rolling window. The count is kept in
+-- achievements.effort_count; the award has no single effort.
+
+ALTER TYPE achievement_type ADD VALUE IF NOT EXISTS 'local_legend';
+
+-- Counting recent efforts per segment
+CREATE INDEX idx_segment_efforts_recent ON segment_efforts(segment_id, started_at);
+
+COMMENT ON COLUMN achievements.achievement_type IS 'Type of crown: kom, qom, course_record, local_legend';
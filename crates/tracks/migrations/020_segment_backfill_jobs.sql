@@ -0,0 +1,34 @@
+-- Migration: 020_segment_backfill_jobs
+-- Matching a new or reprocessed segment against existing activities runs as a
+-- background job instead of inside the HTTP request, with its progress stored
+-- so clients can poll it
+
+CREATE TABLE segment_backfill_jobs (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    segment_id UUID NOT NULL REFERENCES segments(id) ON DELETE CASCADE,
+    requested_by UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    status job_status NOT NULL DEFAULT 'queued',
+    -- Candidate activities found near the segment; NULL until the job starts
+    activities_total INTEGER,
+    activities_checked INTEGER NOT NULL DEFAULT 0,
+    efforts_created INTEGER NOT NULL DEFAULT 0,
+    -- Activities that could not be checked (missing track, database error)
+    failures INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    -- When a worker claimed the job; stale claims are requeued
+    locked_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    finished_at TIMESTAMPTZ
+);
+
+-- At most one live backfill per segment
+CREATE UNIQUE INDEX idx_segment_backfill_jobs_active
+    ON segment_backfill_jobs(segment_id)
+    WHERE status IN ('queued', 'processing');
+
+CREATE INDEX idx_segment_backfill_jobs_ready ON segment_backfill_jobs(created_at) WHERE status = 'queued';
+CREATE INDEX idx_segment_backfill_jobs_segment ON segment_backfill_jobs(segment_id, created_at DESC);
+
+-- Site admins may manage any segment
+ALTER TABLE users ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT FALSE;
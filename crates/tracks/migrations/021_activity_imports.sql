@@ -0,0 +1,59 @@
+-- Migration: 021_activity_imports
+-- Bulk import of activity export archives (Strava-style activities.csv plus
+-- activity files). Each import keeps a per-file report of what happened.
+
+CREATE TABLE activity_imports (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- Name of the uploaded archive
+    source_name TEXT NOT NULL,
+    status job_status NOT NULL DEFAULT 'queued',
+    -- Activity files found in the archive; NULL until the archive is read
+    files_total INTEGER,
+    imported INTEGER NOT NULL DEFAULT 0,
+    duplicates INTEGER NOT NULL DEFAULT 0,
+    failed INTEGER NOT NULL DEFAULT 0,
+    -- Why the whole import stopped (unreadable archive, interrupted)
+    last_error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    finished_at TIMESTAMPTZ
+);
+
+CREATE INDEX idx_activity_imports_user ON activity_imports(user_id, created_at DESC);
+
+CREATE TYPE import_item_status AS ENUM (
+    'imported',
+    'duplicate',
+    'failed'
+);
+
+CREATE TABLE activity_import_items (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    import_id UUID NOT NULL REFERENCES activity_imports(id) ON DELETE CASCADE,
+    -- Path of the file inside the archive
+    file_name TEXT NOT NULL,
+    -- Activity ID in the exporting service, from activities.csv
+    external_id TEXT,
+    activity_name TEXT,
+    status import_item_status NOT NULL,
+    -- The created activity, or the existing one a duplicate matched
+    activity_id UUID REFERENCES activities(id) ON DELETE SET NULL,
+    message TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_activity_import_items_import ON activity_import_items(import_id, created_at);
+
+-- Activity type names used by Strava and Garmin exports
+INSERT INTO activity_aliases (alias, activity_type_id) VALUES
+    ('ride', '00000000-0000-0000-0000-000000000004'),
+    ('road_biking', '00000000-0000-0000-0000-000000000004'),
+    ('mountain_bike_ride', '00000000-0000-0000-0000-000000000005'),
+    ('gravel_ride', '00000000-0000-0000-0000-000000000007'),
+    ('gravel_cycling', '00000000-0000-0000-0000-000000000007'),
+    ('e-mountain_bike_ride', '00000000-0000-0000-0000-000000000006'),
+    ('e-bike_ride', '00000000-0000-0000-0000-000000000006'),
+    ('trail_run', '00000000-0000-0000-0000-000000000002'),
+    ('trail_running', '00000000-0000-0000-0000-000000000002')
+ON CONFLICT DO NOTHING;
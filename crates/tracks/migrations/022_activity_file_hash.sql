@@ -0,0 +1,9 @@
+-- Migration: 022_activity_file_hash
+-- Remember a hash of each uploaded activity file so the same file uploaded
+-- twice is recognised as a duplicate. Compressed uploads are hashed after
+-- decompression. NULL for activities uploaded before this migration.
+
+ALTER TABLE activities ADD COLUMN file_hash TEXT;
+
+CREATE INDEX idx_activities_user_file_hash ON activities(user_id, file_hash)
+    WHERE file_hash IS NOT NULL AND deleted_at IS NULL;
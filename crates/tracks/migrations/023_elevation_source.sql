@@ -0,0 +1,11 @@
+-- Migration: 023_elevation_source
+-- Record whether an activity's elevation is as the device recorded it or was
+-- replaced with terrain elevation from DEM tiles.
+
+CREATE TYPE elevation_source AS ENUM (
+    'device',
+    'corrected'
+);
+
+ALTER TABLE activities
+    ADD COLUMN elevation_source elevation_source NOT NULL DEFAULT 'device';
@@ -0,0 +1,18 @@
+-- Migration: 024_activity_stats
+-- Moving time, speed, elevation and sensor statistics per activity. Nullable
+-- because activities processed before this migration don't have them, and
+-- sensor statistics only exist when the file recorded that sensor.
+
+ALTER TABLE scores
+    ADD COLUMN moving_time FLOAT,
+    ADD COLUMN max_speed FLOAT,
+    ADD COLUMN avg_speed FLOAT,
+    ADD COLUMN elevation_loss FLOAT,
+    ADD COLUMN max_elevation FLOAT,
+    ADD COLUMN min_elevation FLOAT,
+    ADD COLUMN avg_heart_rate FLOAT,
+    ADD COLUMN max_heart_rate INTEGER,
+    ADD COLUMN avg_cadence FLOAT,
+    ADD COLUMN max_cadence INTEGER,
+    ADD COLUMN avg_power FLOAT,
+    ADD COLUMN max_power INTEGER;
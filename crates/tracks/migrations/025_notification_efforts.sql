@@ -0,0 +1,7 @@
+-- Migration: 025_notification_efforts
+-- Crown and PR notifications point at the segment effort that caused them.
+-- Efforts are rebuilt when a segment's geometry changes, so the link is
+-- cleared rather than the notification deleted.
+
+ALTER TABLE notifications
+    ADD COLUMN effort_id UUID REFERENCES segment_efforts(id) ON DELETE SET NULL;
@@ -0,0 +1,23 @@
+-- Migration: 026_notification_events
+-- Announce new and read notifications on the `notifications` channel so every
+-- API instance can push them to its connected clients. The payload carries
+-- the recipient and, for a new notification, its ID. Marking many
+-- notifications read in one statement sends identical payloads, which
+-- Postgres delivers once.
+
+CREATE FUNCTION notify_notification_change() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify(
+        'notifications',
+        json_build_object(
+            'user_id', NEW.user_id,
+            'notification_id', CASE WHEN TG_OP = 'INSERT' THEN NEW.id END
+        )::text
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER notifications_notify
+    AFTER INSERT OR UPDATE OF read_at ON notifications
+    FOR EACH ROW EXECUTE FUNCTION notify_notification_change();
@@ -0,0 +1,35 @@
+-- Migration: 027_segment_leaderboard_events
+-- Announce new segment efforts and KOM/QOM/course record changes on the
+-- `segment_leaderboards` channel so every API instance can push live
+-- leaderboard updates. Efforts are announced once their transaction commits,
+-- so listeners can read them straight away.
+
+CREATE FUNCTION notify_segment_effort() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify(
+        'segment_leaderboards',
+        json_build_object('segment_id', NEW.segment_id, 'effort_id', NEW.id)::text
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER segment_efforts_notify
+    AFTER INSERT ON segment_efforts
+    FOR EACH ROW EXECUTE FUNCTION notify_segment_effort();
+
+CREATE FUNCTION notify_segment_crown() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify(
+        'segment_leaderboards',
+        json_build_object('segment_id', NEW.segment_id, 'achievement_id', NEW.id)::text
+    );
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER achievements_notify_crown
+    AFTER INSERT ON achievements
+    FOR EACH ROW
+    WHEN (NEW.achievement_type IN ('kom', 'qom', 'course_record'))
+    EXECUTE FUNCTION notify_segment_crown();
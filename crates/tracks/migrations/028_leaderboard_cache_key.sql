@@ -0,0 +1,15 @@
+-- Migration: 028_leaderboard_cache_key
+-- Make leaderboard_cache rows unique per segment and filter bucket. The
+-- cache key's NULLs mean "all genders" and "all ages", but a plain UNIQUE
+-- constraint treats them as distinct, so the all-riders leaderboards could be
+-- stored any number of times and upserts never conflicted. Time scopes are
+-- rolling windows, so scope_value is left NULL.
+
+DELETE FROM leaderboard_cache;
+
+ALTER TABLE leaderboard_cache DROP CONSTRAINT leaderboard_cache_key;
+ALTER TABLE leaderboard_cache ADD CONSTRAINT leaderboard_cache_key
+    UNIQUE NULLS NOT DISTINCT (segment_id, scope, scope_value, gender, age_group);
+
+COMMENT ON COLUMN leaderboard_cache.scope_value IS 'Unused: time scopes are rolling windows ending now';
+COMMENT ON COLUMN leaderboard_cache.expires_at IS 'When the entries may be stale without any effort changing, e.g. the oldest effort leaving a rolling scope';
@@ -0,0 +1,5 @@
+-- Migration: 029_followers_visibility
+-- Activities can be shared with the owner's followers only. Visibility stays
+-- free text, so existing activities keep theirs.
+
+COMMENT ON COLUMN activities.visibility IS 'Visibility: public, followers, private, or teams_only';
@@ -0,0 +1,13 @@
+-- Migration: 030_activity_rematch
+-- Editing an activity's type or multi-sport parts re-matches it. The match
+-- generation is bumped by every such edit, so a job that started before the
+-- edit stops recording efforts for the old version; the job then runs again
+-- instead of a second job being queued beside it.
+
+ALTER TABLE activities ADD COLUMN match_generation INTEGER NOT NULL DEFAULT 0;
+
+COMMENT ON COLUMN activities.match_generation IS 'Bumped whenever an edit invalidates the activity''s segment efforts';
+
+ALTER TABLE activity_jobs ADD COLUMN rerun BOOLEAN NOT NULL DEFAULT FALSE;
+
+COMMENT ON COLUMN activity_jobs.rerun IS 'Activity was edited while the job ran; queue it again when it finishes';
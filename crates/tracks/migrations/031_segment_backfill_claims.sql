@@ -0,0 +1,6 @@
+-- Migration: 031_segment_backfill_claims
+-- Each claim of a backfill gets a token. A worker whose job was requeued as
+-- stale and claimed by another worker can no longer record progress or finish
+-- the job, since its token no longer matches.
+
+ALTER TABLE segment_backfill_jobs ADD COLUMN claim_token UUID;
@@ -0,0 +1,13 @@
+-- Migration: 032_activity_import_heartbeat
+-- A running import refreshes locked_at as it records each file. Only imports
+-- that stopped refreshing it are failed as interrupted, so restarting one
+-- server no longer fails imports another server or the import_archive command
+-- is still running.
+
+ALTER TABLE activity_imports ADD COLUMN locked_at TIMESTAMPTZ;
+
+UPDATE activity_imports
+SET locked_at = updated_at
+WHERE status IN ('queued', 'processing');
+
+COMMENT ON COLUMN activity_imports.locked_at IS 'Last sign of life from the process running the import';
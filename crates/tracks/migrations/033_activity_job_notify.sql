@@ -0,0 +1,7 @@
+-- Migration: 033_activity_job_notify
+-- Imported activities are history: PRs they set are recorded but not
+-- announced, so importing years of rides doesn't flood the notifications.
+
+ALTER TABLE activity_jobs ADD COLUMN notify BOOLEAN NOT NULL DEFAULT TRUE;
+
+COMMENT ON COLUMN activity_jobs.notify IS 'Whether the rider is notified of PRs the job finds; off for imported activities';
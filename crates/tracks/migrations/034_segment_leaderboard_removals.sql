@@ -0,0 +1,46 @@
+-- Migration: 034_segment_leaderboard_removals
+-- Announce efforts leaving a segment's leaderboards as well as joining them:
+-- deleting or re-matching an activity and rebuilding a segment delete
+-- efforts. The triggers now fire once per statement and list the efforts it
+-- changed on each segment. A statement changing more than 50 efforts on a
+-- segment announces a reset instead, which keeps payloads well under the
+-- NOTIFY size limit.
+
+DROP TRIGGER segment_efforts_notify ON segment_efforts;
+DROP FUNCTION notify_segment_effort();
+
+CREATE FUNCTION notify_segment_efforts() RETURNS trigger AS $$
+DECLARE
+    changed RECORD;
+    ids_key TEXT := CASE TG_OP WHEN 'INSERT' THEN 'effort_ids' ELSE 'removed_effort_ids' END;
+BEGIN
+    FOR changed IN
+        SELECT segment_id, array_agg(id) AS effort_ids
+        FROM changed_efforts
+        GROUP BY segment_id
+    LOOP
+        IF cardinality(changed.effort_ids) > 50 THEN
+            PERFORM pg_notify(
+                'segment_leaderboards',
+                json_build_object('segment_id', changed.segment_id, 'reset', TRUE)::text
+            );
+        ELSE
+            PERFORM pg_notify(
+                'segment_leaderboards',
+                json_build_object('segment_id', changed.segment_id, ids_key, changed.effort_ids)::text
+            );
+        END IF;
+    END LOOP;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER segment_efforts_notify_insert
+    AFTER INSERT ON segment_efforts
+    REFERENCING NEW TABLE AS changed_efforts
+    FOR EACH STATEMENT EXECUTE FUNCTION notify_segment_efforts();
+
+CREATE TRIGGER segment_efforts_notify_delete
+    AFTER DELETE ON segment_efforts
+    REFERENCING OLD TABLE AS changed_efforts
+    FOR EACH STATEMENT EXECUTE FUNCTION notify_segment_efforts();
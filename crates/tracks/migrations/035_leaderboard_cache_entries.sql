@@ -0,0 +1,57 @@
+-- Migration: 035_leaderboard_cache_entries
+-- Store cached leaderboards as one row per effort instead of a JSONB array on
+-- the leaderboard_cache row. Adding or removing an effort rewrote the whole
+-- array of every cached leaderboard on its segment, and reading a page
+-- unpacked the whole array. Entries are now kept in leaderboard order by an
+-- index, so adding or removing an effort touches one row per leaderboard and
+-- a page is an index range scan. Rider names and PR flags are read from their
+-- tables, so they no longer go stale.
+
+DELETE FROM leaderboard_cache;
+ALTER TABLE leaderboard_cache DROP COLUMN entries;
+
+CREATE TABLE leaderboard_cache_entries (
+    cache_id UUID NOT NULL REFERENCES leaderboard_cache(id) ON DELETE CASCADE,
+    effort_id UUID NOT NULL REFERENCES segment_efforts(id) ON DELETE CASCADE,
+    -- Copied from the effort, which never changes them, to order by
+    elapsed_time_seconds FLOAT8 NOT NULL,
+    started_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    PRIMARY KEY (cache_id, effort_id)
+);
+
+-- Leaderboard order: fastest first, ties going to the earlier effort
+CREATE INDEX idx_leaderboard_cache_entries_rank
+    ON leaderboard_cache_entries(cache_id, elapsed_time_seconds, started_at, effort_id);
+CREATE INDEX idx_leaderboard_cache_entries_effort ON leaderboard_cache_entries(effort_id);
+
+-- Keep entry_count in step however entries come and go, including efforts
+-- deleted along with their rider
+CREATE FUNCTION count_leaderboard_cache_entries() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        UPDATE leaderboard_cache c
+        SET entry_count = c.entry_count + changed.n
+        FROM (SELECT cache_id, COUNT(*) AS n FROM new_entries GROUP BY cache_id) changed
+        WHERE c.id = changed.cache_id;
+    ELSE
+        UPDATE leaderboard_cache c
+        SET entry_count = c.entry_count - changed.n
+        FROM (SELECT cache_id, COUNT(*) AS n FROM old_entries GROUP BY cache_id) changed
+        WHERE c.id = changed.cache_id;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER leaderboard_cache_entries_count_insert
+    AFTER INSERT ON leaderboard_cache_entries
+    REFERENCING NEW TABLE AS new_entries
+    FOR EACH STATEMENT EXECUTE FUNCTION count_leaderboard_cache_entries();
+
+CREATE TRIGGER leaderboard_cache_entries_count_delete
+    AFTER DELETE ON leaderboard_cache_entries
+    REFERENCING OLD TABLE AS old_entries
+    FOR EACH STATEMENT EXECUTE FUNCTION count_leaderboard_cache_entries();
+
+COMMENT ON TABLE leaderboard_cache_entries IS 'Efforts on a cached leaderboard; an entry''s rank is its position in idx_leaderboard_cache_entries_rank order';
+COMMENT ON COLUMN leaderboard_cache.entry_count IS 'Number of leaderboard_cache_entries rows, kept up to date by trigger';
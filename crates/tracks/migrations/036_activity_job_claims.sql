@@ -0,0 +1,6 @@
+-- Migration: 036_activity_job_claims
+-- Each claim of an activity job gets a token, as backfills do (031). A worker
+-- whose job was requeued as stale and claimed by another worker can no longer
+-- refresh, complete or fail the job, since its token no longer matches.
+
+ALTER TABLE activity_jobs ADD COLUMN claim_token UUID;
@@ -0,0 +1,21 @@
+-- Migration: 037_activity_file_hash_unique
+-- Make the file hash index unique, so two copies of the same file uploaded at
+-- the same moment can't both pass the duplicate check and be saved. Copies
+-- already saved that way keep their activities, but only the first one
+-- submitted keeps its hash.
+
+UPDATE activities a
+SET file_hash = NULL
+WHERE a.file_hash IS NOT NULL
+  AND a.deleted_at IS NULL
+  AND EXISTS (
+      SELECT 1 FROM activities b
+      WHERE b.user_id = a.user_id
+        AND b.file_hash = a.file_hash
+        AND b.deleted_at IS NULL
+        AND (b.submitted_at, b.id) < (a.submitted_at, a.id)
+  );
+
+DROP INDEX idx_activities_user_file_hash;
+CREATE UNIQUE INDEX idx_activities_user_file_hash ON activities(user_id, file_hash)
+    WHERE file_hash IS NOT NULL AND deleted_at IS NULL;
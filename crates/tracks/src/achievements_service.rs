@@ -3,6 +3,10 @@
 //! This service handles checking and awarding achievements when segment efforts are created.
 //! - KOM (King of the Mountain): Fastest male time on a segment
 //! - QOM (Queen of the Mountain): Fastest female time on a segment
+//! - Course Record: Fastest time on a segment by anyone
+//! - Local Legend: Most efforts on a segment over the last 90 days
+
+use std::time::Duration as StdDuration;
 
 use tracing::{info, warn};
 use uuid::Uuid;
@@ -10,9 +14,15 @@ use uuid::Uuid;
 use crate::{
     database::Database,
     errors::AppError,
-    models::{AchievementType, Gender},
+    models::{AchievementType, CrownChange, Gender, NotificationType},
 };
 
+/// Efforts started within this window count towards Local Legend.
+pub const LOCAL_LEGEND_WINDOW: time::Duration = time::Duration::days(90);
+
+/// How often the sweep recomputes Local Legends as old efforts age out of the window.
+const LOCAL_LEGEND_SWEEP_INTERVAL: StdDuration = StdDuration::from_secs(6 * 60 * 60);
+
 /// Check and award KOM/QOM achievement if this effort is the fastest for its gender category.
 ///
 /// This function:
@@ -25,13 +35,13 @@ pub async fn check_and_award_kom_qom(
     user_id: Uuid,
     effort_id: Uuid,
     elapsed_time_seconds: f64,
-) -> Result<(), AppError> {
+) -> Result<Option<CrownChange>, AppError> {
     // Get user's gender to determine achievement type
     let user = match db.get_user_with_demographics(user_id).await? {
         Some(u) => u,
         None => {
             warn!("User {user_id} not found when checking achievements");
-            return Ok(());
+            return Ok(None);
         }
     };
 
@@ -41,78 +51,266 @@ pub async fn check_and_award_kom_qom(
         // Users without gender or with "other"/"prefer not to say" are not eligible for KOM/QOM
         _ => {
             info!("User {user_id} has no gender set or is not male/female, skipping KOM/QOM check");
-            return Ok(());
+            return Ok(None);
         }
     };
 
-    // Get current achievement holder for this segment and type
-    let current_holder = db
-        .get_current_achievement_holder(segment_id, achievement_type)
+    award_if_fastest(
+        db,
+        segment_id,
+        user_id,
+        effort_id,
+        elapsed_time_seconds,
+        achievement_type,
+    )
+    .await
+}
+
+/// Check and award the course record if this effort is the fastest on the
+/// segment. Every athlete is eligible, whatever their gender.
+pub async fn check_and_award_course_record(
+    db: &Database,
+    segment_id: Uuid,
+    user_id: Uuid,
+    effort_id: Uuid,
+    elapsed_time_seconds: f64,
+) -> Result<Option<CrownChange>, AppError> {
+    award_if_fastest(
+        db,
+        segment_id,
+        user_id,
+        effort_id,
+        elapsed_time_seconds,
+        AchievementType::CourseRecord,
+    )
+    .await
+}
+
+/// Recompute the Local Legend of a segment.
+pub async fn update_local_legend(
+    db: &Database,
+    segment_id: Uuid,
+) -> Result<Option<CrownChange>, AppError> {
+    let change = db
+        .refresh_local_legend(segment_id, LOCAL_LEGEND_WINDOW)
         .await?;
+    if let Some(change) = &change {
+        info!(
+            "Local Legend on segment {segment_id} moved from {:?} to {:?}",
+            change.previous_holder_id, change.new_holder_id
+        );
+    }
+    Ok(change)
+}
 
-    // Check if we should award the achievement
-    let should_award = match &current_holder {
-        None => {
-            // No current holder, this is the first eligible effort
-            info!(
-                "No current {achievement_type} holder for segment {segment_id}, awarding to user {user_id}"
-            );
-            true
-        }
-        Some(holder) => {
-            // Check if this effort is faster
-            if let Some(holder_time) = holder.elapsed_time_seconds {
-                if elapsed_time_seconds < holder_time {
-                    info!(
-                        "User {user_id} beat {achievement_type} on segment {segment_id}: {elapsed_time_seconds:.1}s < {holder_time:.1}s"
-                    );
-                    true
-                } else {
-                    false
+/// Recompute Local Legends on every segment with recent efforts or a current
+/// holder, notifying riders who gained or lost the title.
+pub async fn sweep_local_legends(db: &Database) -> Result<usize, AppError> {
+    let segment_ids = db.get_local_legend_segments(LOCAL_LEGEND_WINDOW).await?;
+    let mut changed = 0;
+
+    for segment_id in segment_ids {
+        match update_local_legend(db, segment_id).await {
+            Ok(Some(change)) => {
+                changed += 1;
+                match db.get_segment(segment_id).await {
+                    Ok(Some(segment)) => {
+                        notify_crown_change(db, segment_id, &segment.name, &change).await;
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        warn!("Failed to notify Local Legend change on segment {segment_id}: {e}")
+                    }
                 }
-            } else {
-                // Current holder has no time recorded (shouldn't happen), award to this user
-                warn!(
-                    "Current {achievement_type} holder has no elapsed time, awarding to user {user_id}"
-                );
-                true
             }
+            Ok(None) => {}
+            Err(e) => warn!("Failed to update Local Legend for segment {segment_id}: {e}"),
         }
-    };
+    }
+
+    Ok(changed)
+}
 
-    if should_award {
-        // Dethrone current holder if exists
-        if current_holder.is_some() {
-            db.dethrone_achievement(segment_id, achievement_type)
-                .await?;
+/// Spawn the periodic Local Legend sweep on the current runtime.
+pub fn start_local_legend_sweep(db: Database) {
+    tokio::spawn(async move {
+        loop {
+            match sweep_local_legends(&db).await {
+                Ok(0) => {}
+                Ok(count) => info!(count, "Local Legend sweep changed holders"),
+                Err(e) => tracing::error!("Local Legend sweep failed: {e}"),
+            }
+            tokio::time::sleep(LOCAL_LEGEND_SWEEP_INTERVAL).await;
         }
+    });
+}
+
+/// Award `achievement_type` to this effort if it beats the current holder's
+/// time, dethroning them. Returns the change of holder, if any.
+async fn award_if_fastest(
+    db: &Database,
+    segment_id: Uuid,
+    user_id: Uuid,
+    effort_id: Uuid,
+    elapsed_time_seconds: f64,
+    achievement_type: AchievementType,
+) -> Result<Option<CrownChange>, AppError> {
+    let change = db
+        .award_fastest_effort(
+            segment_id,
+            user_id,
+            effort_id,
+            elapsed_time_seconds,
+            achievement_type,
+        )
+        .await?;
 
-        // Award achievement to this user
-        db.create_achievement(user_id, segment_id, Some(effort_id), achievement_type, None)
-            .await?;
+    if let Some(change) = &change {
+        info!(
+            "Awarded {achievement_type} to user {user_id} on segment {segment_id} ({elapsed_time_seconds:.1}s), previously held by {:?}",
+            change.previous_holder_id
+        );
+    }
+    Ok(change)
+}
 
-        info!("Awarded {achievement_type} to user {user_id} on segment {segment_id}");
+/// Notify the riders involved in a crown changing hands. A holder who improved
+/// on their own time is not notified.
+pub async fn notify_crown_change(
+    db: &Database,
+    segment_id: Uuid,
+    segment_name: &str,
+    change: &CrownChange,
+) {
+    if change.previous_holder_id == change.new_holder_id {
+        return;
     }
+    let message = format!("{} on {segment_name}", change.achievement_type);
 
+    if let Some(previous) = change.previous_holder_id
+        && let Err(e) = db
+            .create_effort_notification(
+                previous,
+                NotificationType::CrownLost.as_str(),
+                change.new_holder_id,
+                segment_id,
+                change.effort_id,
+                &message,
+            )
+            .await
+    {
+        warn!("Failed to notify {previous} of lost crown: {e}");
+    }
+
+    if let Some(new_holder) = change.new_holder_id
+        && let Err(e) = db
+            .create_effort_notification(
+                new_holder,
+                NotificationType::CrownAchieved.as_str(),
+                None,
+                segment_id,
+                change.effort_id,
+                &message,
+            )
+            .await
+    {
+        warn!("Failed to notify {new_holder} of new crown: {e}");
+    }
+}
+
+/// Notify a rider that an effort beat their previous best on the segment. A
+/// first effort on a segment is trivially the fastest and is not announced,
+/// nor is an effort that didn't improve on the previous best.
+pub async fn notify_personal_record(
+    db: &Database,
+    segment_id: Uuid,
+    user_id: Uuid,
+    effort_id: Uuid,
+    elapsed_time_seconds: f64,
+) -> Result<(), AppError> {
+    let Some(previous_best) = db
+        .get_best_other_effort_time(segment_id, user_id, effort_id)
+        .await?
+    else {
+        return Ok(());
+    };
+    if elapsed_time_seconds >= previous_best {
+        return Ok(());
+    }
+    let Some(segment) = db.get_segment(segment_id).await? else {
+        return Ok(());
+    };
+
+    let message = format!(
+        "PR on {}, {} faster",
+        segment.name,
+        format_time_saved(previous_best - elapsed_time_seconds)
+    );
+    db.create_effort_notification(
+        user_id,
+        NotificationType::PersonalRecord.as_str(),
+        None,
+        segment_id,
+        Some(effort_id),
+        &message,
+    )
+    .await?;
     Ok(())
 }
 
+/// How much faster a PR was, with tenths of a second for close margins.
+fn format_time_saved(seconds: f64) -> String {
+    if seconds < 10.0 {
+        format!("{seconds:.1}s")
+    } else {
+        format!("{seconds:.0}s")
+    }
+}
+
 /// Process achievements after a segment effort is created.
 ///
 /// This is the main entry point called from activity_queue after creating a segment effort.
-/// It checks KOM/QOM achievements.
+/// It checks KOM/QOM, course record and Local Legend achievements and, with
+/// `notify`, notifies riders whose crowns changed hands. Imported activities
+/// change crowns silently.
 pub async fn process_achievements(
     db: &Database,
     segment_id: Uuid,
     user_id: Uuid,
     effort_id: Uuid,
     elapsed_time_seconds: f64,
+    notify: bool,
 ) -> Result<(), AppError> {
+    let mut changes = Vec::new();
+
     // Check KOM/QOM
-    if let Err(e) =
-        check_and_award_kom_qom(db, segment_id, user_id, effort_id, elapsed_time_seconds).await
+    match check_and_award_kom_qom(db, segment_id, user_id, effort_id, elapsed_time_seconds).await {
+        Ok(change) => changes.extend(change),
+        Err(e) => warn!("Failed to check KOM/QOM for segment {segment_id}: {e}"),
+    }
+
+    // Check course record
+    match check_and_award_course_record(db, segment_id, user_id, effort_id, elapsed_time_seconds)
+        .await
     {
-        warn!("Failed to check KOM/QOM for segment {segment_id}: {e}");
+        Ok(change) => changes.extend(change),
+        Err(e) => warn!("Failed to check course record for segment {segment_id}: {e}"),
+    }
+
+    // The new effort counts towards Local Legend
+    match update_local_legend(db, segment_id).await {
+        Ok(change) => changes.extend(change),
+        Err(e) => warn!("Failed to update Local Legend for segment {segment_id}: {e}"),
+    }
+
+    if !notify || changes.is_empty() {
+        return Ok(());
+    }
+    let Some(segment) = db.get_segment(segment_id).await? else {
+        return Ok(());
+    };
+    for change in &changes {
+        notify_crown_change(db, segment_id, &segment.name, change).await;
     }
 
     Ok(())
@@ -0,0 +1,835 @@
+//! Activity export to GPX, TCX and FIT.
+//!
+//! Files are rebuilt from the stored track and sensor arrays rather than the
+//! original upload, so every activity can be downloaded in every format. A
+//! multi-sport activity keeps its parts: one `<trk>` per part in GPX, one
+//! `Activity` per part in TCX and one session per part in FIT.
+
+use std::{fmt::Write, ops::Range};
+
+use quick_xml::escape::escape;
+use serde::Deserialize;
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    file_parsers::SensorData,
+    models::{Activity, TrackPointData, builtin_types},
+    segment_matching::haversine_distance,
+};
+
+/// File format of an activity export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Gpx,
+    Tcx,
+    Fit,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Gpx => "application/gpx+xml",
+            ExportFormat::Tcx => "application/vnd.garmin.tcx+xml",
+            ExportFormat::Fit => "application/vnd.ant.fit",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Gpx => "gpx",
+            ExportFormat::Tcx => "tcx",
+            ExportFormat::Fit => "fit",
+        }
+    }
+}
+
+/// A stretch of the track recorded as a single activity type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportPart {
+    pub activity_type_id: Uuid,
+    /// Indices into the track points
+    pub points: Range<usize>,
+}
+
+/// How each format names an activity type.
+#[derive(Debug, Clone, Copy)]
+struct Sport {
+    gpx: &'static str,
+    tcx: &'static str,
+    fit_sport: u8,
+    fit_sub_sport: u8,
+}
+
+impl Sport {
+    fn for_activity_type(activity_type_id: Uuid) -> Self {
+        let (gpx, tcx, fit_sport, fit_sub_sport) = match activity_type_id {
+            builtin_types::RUN => ("running", "Running", 1, 0),
+            builtin_types::WALK => ("walking", "Other", 11, 0),
+            builtin_types::HIKE => ("hiking", "Other", 17, 0),
+            builtin_types::ROAD => ("cycling", "Biking", 2, 7),
+            builtin_types::MTB => ("mountain_biking", "Biking", 2, 8),
+            builtin_types::GRAVEL => ("gravel_cycling", "Biking", 2, 46),
+            builtin_types::EMTB => ("e_biking", "Biking", 21, 47),
+            // Custom types have no equivalent in the file formats
+            _ => ("other", "Other", 0, 0),
+        };
+        Self {
+            gpx,
+            tcx,
+            fit_sport,
+            fit_sub_sport,
+        }
+    }
+}
+
+/// Everything needed to write an activity file.
+pub struct ActivityExport<'a> {
+    pub name: &'a str,
+    pub started_at: OffsetDateTime,
+    pub track_points: &'a [TrackPointData],
+    pub sensor_data: &'a SensorData,
+    pub parts: Vec<ExportPart>,
+    /// Cumulative distance in meters at each track point
+    distances: Vec<f64>,
+}
+
+impl<'a> ActivityExport<'a> {
+    pub fn new(
+        activity: &'a Activity,
+        track_points: &'a [TrackPointData],
+        sensor_data: &'a SensorData,
+    ) -> Self {
+        let parts = split_parts(activity, track_points);
+
+        let mut distances = Vec::with_capacity(track_points.len());
+        let mut total = 0.0;
+        for (i, p) in track_points.iter().enumerate() {
+            if i > 0 {
+                let prev = &track_points[i - 1];
+                total += haversine_distance(prev.lat, prev.lon, p.lat, p.lon);
+            }
+            distances.push(total);
+        }
+
+        Self {
+            name: &activity.name,
+            started_at: activity.started_at,
+            track_points,
+            sensor_data,
+            parts,
+            distances,
+        }
+    }
+
+    pub fn render(&self, format: ExportFormat) -> Vec<u8> {
+        match format {
+            ExportFormat::Gpx => self.to_gpx().into_bytes(),
+            ExportFormat::Tcx => self.to_tcx().into_bytes(),
+            ExportFormat::Fit => self.to_fit(),
+        }
+    }
+
+    fn heart_rate(&self, i: usize) -> Option<i32> {
+        self.sensor_data.heart_rates.get(i).copied().flatten()
+    }
+
+    fn cadence(&self, i: usize) -> Option<i32> {
+        self.sensor_data.cadences.get(i).copied().flatten()
+    }
+
+    fn power(&self, i: usize) -> Option<i32> {
+        self.sensor_data.powers.get(i).copied().flatten()
+    }
+
+    fn temperature(&self, i: usize) -> Option<f64> {
+        self.sensor_data.temperatures.get(i).copied().flatten()
+    }
+
+    /// Timestamp of the first point of a part, falling back to the activity start.
+    fn part_start(&self, part: &ExportPart) -> OffsetDateTime {
+        self.track_points[part.points.clone()]
+            .iter()
+            .find_map(|p| p.timestamp)
+            .unwrap_or(self.started_at)
+    }
+
+    /// Elapsed seconds between the first and last timestamped points of a part.
+    fn part_elapsed_seconds(&self, part: &ExportPart) -> f64 {
+        let points = &self.track_points[part.points.clone()];
+        match (
+            points.iter().find_map(|p| p.timestamp),
+            points.iter().rev().find_map(|p| p.timestamp),
+        ) {
+            (Some(first), Some(last)) => (last - first).as_seconds_f64(),
+            _ => 0.0,
+        }
+    }
+
+    fn part_distance(&self, part: &ExportPart) -> f64 {
+        if part.points.is_empty() {
+            return 0.0;
+        }
+        self.distances[part.points.end - 1] - self.distances[part.points.start]
+    }
+
+    fn to_gpx(&self) -> String {
+        let name = escape(self.name);
+        let mut gpx = String::new();
+        gpx.push_str(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+        gpx.push_str(
+            r#"
+<gpx version="1.1" creator="Track Leader" xmlns="http://www.topografix.com/GPX/1/1" xmlns:gpxtpx="http://www.garmin.com/xmlschemas/TrackPointExtension/v1" xmlns:gpxpx="http://www.garmin.com/xmlschemas/PowerExtension/v1">"#,
+        );
+        let _ = write!(
+            gpx,
+            "\n  <metadata><name>{name}</name><time>{}</time></metadata>",
+            rfc3339(self.started_at)
+        );
+
+        for part in &self.parts {
+            let sport = Sport::for_activity_type(part.activity_type_id);
+            let _ = write!(
+                gpx,
+                "\n  <trk>\n    <name>{name}</name>\n    <type>{}</type>\n    <trkseg>",
+                sport.gpx
+            );
+            for i in part.points.clone() {
+                let p = &self.track_points[i];
+                let _ = write!(
+                    gpx,
+                    "\n      <trkpt lat=\"{:.7}\" lon=\"{:.7}\">",
+                    p.lat, p.lon
+                );
+                if let Some(ele) = p.elevation {
+                    let _ = write!(gpx, "<ele>{ele:.1}</ele>");
+                }
+                if let Some(ts) = p.timestamp {
+                    let _ = write!(gpx, "<time>{}</time>", rfc3339(ts));
+                }
+                self.write_gpx_extensions(&mut gpx, i);
+                gpx.push_str("</trkpt>");
+            }
+            gpx.push_str("\n    </trkseg>\n  </trk>");
+        }
+
+        gpx.push_str("\n</gpx>\n");
+        gpx
+    }
+
+    fn write_gpx_extensions(&self, gpx: &mut String, i: usize) {
+        let (hr, cad, power, temp) = (
+            self.heart_rate(i),
+            self.cadence(i),
+            self.power(i),
+            self.temperature(i),
+        );
+        if hr.is_none() && cad.is_none() && power.is_none() && temp.is_none() {
+            return;
+        }
+
+        gpx.push_str("<extensions>");
+        if let Some(power) = power {
+            let _ = write!(
+                gpx,
+                "<gpxpx:PowerExtension><gpxpx:PowerInWatts>{power}</gpxpx:PowerInWatts></gpxpx:PowerExtension>"
+            );
+        }
+        if hr.is_some() || cad.is_some() || temp.is_some() {
+            gpx.push_str("<gpxtpx:TrackPointExtension>");
+            if let Some(temp) = temp {
+                let _ = write!(gpx, "<gpxtpx:atemp>{temp:.1}</gpxtpx:atemp>");
+            }
+            if let Some(hr) = hr {
+                let _ = write!(gpx, "<gpxtpx:hr>{hr}</gpxtpx:hr>");
+            }
+            if let Some(cad) = cad {
+                let _ = write!(gpx, "<gpxtpx:cad>{cad}</gpxtpx:cad>");
+            }
+            gpx.push_str("</gpxtpx:TrackPointExtension>");
+        }
+        gpx.push_str("</extensions>");
+    }
+
+    /// TCX requires a time on every trackpoint, so points without one are left out.
+    fn to_tcx(&self) -> String {
+        let mut tcx = String::new();
+        tcx.push_str(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+        tcx.push_str(
+            r#"
+<TrainingCenterDatabase xmlns="http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2" xmlns:ns3="http://www.garmin.com/xmlschemas/ActivityExtension/v2">
+  <Activities>"#,
+        );
+
+        for part in &self.parts {
+            let sport = Sport::for_activity_type(part.activity_type_id);
+            let start = rfc3339(self.part_start(part));
+            let _ = write!(
+                tcx,
+                "\n    <Activity Sport=\"{}\">\n      <Id>{start}</Id>\n      <Lap StartTime=\"{start}\">",
+                sport.tcx
+            );
+            let _ = write!(
+                tcx,
+                "\n        <TotalTimeSeconds>{:.1}</TotalTimeSeconds>\n        <DistanceMeters>{:.1}</DistanceMeters>",
+                self.part_elapsed_seconds(part),
+                self.part_distance(part)
+            );
+            tcx.push_str(
+                "\n        <Calories>0</Calories>\n        <Intensity>Active</Intensity>\n        <TriggerMethod>Manual</TriggerMethod>\n        <Track>",
+            );
+
+            for i in part.points.clone() {
+                let p = &self.track_points[i];
+                let Some(ts) = p.timestamp else {
+                    continue;
+                };
+                let _ = write!(
+                    tcx,
+                    "\n          <Trackpoint><Time>{}</Time><Position><LatitudeDegrees>{:.7}</LatitudeDegrees><LongitudeDegrees>{:.7}</LongitudeDegrees></Position>",
+                    rfc3339(ts),
+                    p.lat,
+                    p.lon
+                );
+                if let Some(ele) = p.elevation {
+                    let _ = write!(tcx, "<AltitudeMeters>{ele:.1}</AltitudeMeters>");
+                }
+                let _ = write!(
+                    tcx,
+                    "<DistanceMeters>{:.1}</DistanceMeters>",
+                    self.distances[i]
+                );
+                if let Some(hr) = self.heart_rate(i) {
+                    let _ = write!(
+                        tcx,
+                        "<HeartRateBpm><Value>{}</Value></HeartRateBpm>",
+                        hr.clamp(0, 255)
+                    );
+                }
+                if let Some(cad) = self.cadence(i) {
+                    let _ = write!(tcx, "<Cadence>{}</Cadence>", cad.clamp(0, 254));
+                }
+                if let Some(power) = self.power(i) {
+                    let _ = write!(
+                        tcx,
+                        "<Extensions><ns3:TPX><ns3:Watts>{power}</ns3:Watts></ns3:TPX></Extensions>"
+                    );
+                }
+                tcx.push_str("</Trackpoint>");
+            }
+
+            let _ = write!(
+                tcx,
+                "\n        </Track>\n      </Lap>\n      <Notes>{}</Notes>\n    </Activity>",
+                escape(self.name)
+            );
+        }
+
+        tcx.push_str("\n  </Activities>\n</TrainingCenterDatabase>\n");
+        tcx
+    }
+
+    /// FIT activity file: a record per track point, then a lap and a session
+    /// per part and a closing activity message.
+    fn to_fit(&self) -> Vec<u8> {
+        let mut fit = FitWriter::default();
+        let end_time = self
+            .track_points
+            .iter()
+            .rev()
+            .find_map(|p| p.timestamp)
+            .unwrap_or(self.started_at);
+
+        fit.begin(&FIT_FILE_ID);
+        fit.enum_value(4); // activity file
+        fit.u16(Some(255)); // development manufacturer
+        fit.u16(Some(0));
+        fit.u32(Some(fit_timestamp(self.started_at)));
+
+        for (i, p) in self.track_points.iter().enumerate() {
+            fit.begin(&FIT_RECORD);
+            fit.u32(p.timestamp.map(fit_timestamp));
+            fit.i32(Some(degrees_to_semicircles(p.lat)));
+            fit.i32(Some(degrees_to_semicircles(p.lon)));
+            // Altitude is stored as (meters + 500) * 5
+            fit.u16(
+                p.elevation
+                    .map(|ele| ((ele + 500.0) * 5.0).round().clamp(0.0, 65534.0) as u16),
+            );
+            fit.u32(Some((self.distances[i] * 100.0).round() as u32));
+            fit.u8(self.heart_rate(i).map(|v| v.clamp(0, 254) as u8));
+            fit.u8(self.cadence(i).map(|v| v.clamp(0, 254) as u8));
+            fit.u16(self.power(i).map(|v| v.clamp(0, 65534) as u16));
+            fit.i8(self
+                .temperature(i)
+                .map(|v| v.round().clamp(-127.0, 126.0) as i8));
+        }
+
+        let mut total_elapsed = 0.0;
+        for part in &self.parts {
+            let start = fit_timestamp(self.part_start(part));
+            let elapsed = self.part_elapsed_seconds(part);
+            let elapsed_ms = (elapsed * 1000.0).round() as u32;
+            let distance_cm = (self.part_distance(part) * 100.0).round() as u32;
+            total_elapsed += elapsed;
+
+            fit.begin(&FIT_LAP);
+            fit.u32(Some(start + elapsed.round() as u32));
+            fit.enum_value(9); // lap event
+            fit.enum_value(1); // stop
+            fit.u32(Some(start));
+            fit.u32(Some(elapsed_ms));
+            fit.u32(Some(elapsed_ms));
+            fit.u32(Some(distance_cm));
+        }
+
+        for (index, part) in self.parts.iter().enumerate() {
+            let sport = Sport::for_activity_type(part.activity_type_id);
+            let start = fit_timestamp(self.part_start(part));
+            let elapsed = self.part_elapsed_seconds(part);
+            let elapsed_ms = (elapsed * 1000.0).round() as u32;
+
+            fit.begin(&FIT_SESSION);
+            fit.u32(Some(start + elapsed.round() as u32));
+            fit.enum_value(8); // session event
+            fit.enum_value(1); // stop
+            fit.u32(Some(start));
+            fit.enum_value(sport.fit_sport);
+            fit.enum_value(sport.fit_sub_sport);
+            fit.u32(Some(elapsed_ms));
+            fit.u32(Some(elapsed_ms));
+            fit.u32(Some((self.part_distance(part) * 100.0).round() as u32));
+            fit.u16(Some(index as u16)); // first lap index
+            fit.u16(Some(1)); // laps
+        }
+
+        fit.begin(&FIT_ACTIVITY);
+        fit.u32(Some(fit_timestamp(end_time)));
+        fit.u32(Some((total_elapsed * 1000.0).round() as u32));
+        fit.u16(Some(self.parts.len() as u16));
+        fit.enum_value(0); // manual
+        fit.enum_value(26); // activity event
+        fit.enum_value(1); // stop
+
+        fit.finish()
+    }
+}
+
+/// Split the track into the activity's single-sport parts. Points are assigned
+/// to a part by timestamp, the same way multi-sport segment matching does;
+/// parts without any points are dropped.
+fn split_parts(activity: &Activity, track_points: &[TrackPointData]) -> Vec<ExportPart> {
+    let single = || {
+        vec![ExportPart {
+            activity_type_id: activity.activity_type_id,
+            points: 0..track_points.len(),
+        }]
+    };
+    let (Some(boundaries), Some(types)) = (&activity.type_boundaries, &activity.segment_types)
+    else {
+        return single();
+    };
+    if types.is_empty() || boundaries.len() != types.len() + 1 {
+        return single();
+    }
+
+    // Boundaries between parts, without the activity's start and end
+    let inner = &boundaries[1..boundaries.len() - 1];
+    let mut parts = Vec::new();
+    let mut current = 0;
+    let mut start = 0;
+    for (i, point) in track_points.iter().enumerate() {
+        let Some(ts) = point.timestamp else {
+            continue;
+        };
+        let part = inner.iter().filter(|b| **b <= ts).count();
+        if part != current {
+            if i > start {
+                parts.push(ExportPart {
+                    activity_type_id: types[current],
+                    points: start..i,
+                });
+            }
+            current = part;
+            start = i;
+        }
+    }
+    if track_points.len() > start {
+        parts.push(ExportPart {
+            activity_type_id: types[current],
+            points: start..track_points.len(),
+        });
+    }
+    parts
+}
+
+fn rfc3339(ts: OffsetDateTime) -> String {
+    ts.format(&Rfc3339).unwrap_or_default()
+}
+
+// ============================================================================
+// FIT encoding
+// ============================================================================
+
+/// Seconds between the Unix epoch and the FIT epoch (1989-12-31T00:00:00Z).
+const FIT_EPOCH_OFFSET: i64 = 631_065_600;
+
+const FIT_ENUM: u8 = 0x00;
+const FIT_SINT8: u8 = 0x01;
+const FIT_UINT8: u8 = 0x02;
+const FIT_UINT16: u8 = 0x84;
+const FIT_SINT32: u8 = 0x85;
+const FIT_UINT32: u8 = 0x86;
+
+/// A FIT message layout: local message type, global message number and
+/// (field number, size, base type) for each field, in the order written.
+struct FitMessage {
+    local: u8,
+    global: u16,
+    fields: &'static [(u8, u8, u8)],
+}
+
+const FIT_FILE_ID: FitMessage = FitMessage {
+    local: 0,
+    global: 0,
+    fields: &[
+        (0, 1, FIT_ENUM),   // type
+        (1, 2, FIT_UINT16), // manufacturer
+        (2, 2, FIT_UINT16), // product
+        (4, 4, FIT_UINT32), // time_created
+    ],
+};
+
+const FIT_RECORD: FitMessage = FitMessage {
+    local: 1,
+    global: 20,
+    fields: &[
+        (253, 4, FIT_UINT32), // timestamp
+        (0, 4, FIT_SINT32),   // position_lat
+        (1, 4, FIT_SINT32),   // position_long
+        (2, 2, FIT_UINT16),   // altitude
+        (5, 4, FIT_UINT32),   // distance
+        (3, 1, FIT_UINT8),    // heart_rate
+        (4, 1, FIT_UINT8),    // cadence
+        (7, 2, FIT_UINT16),   // power
+        (13, 1, FIT_SINT8),   // temperature
+    ],
+};
+
+const FIT_LAP: FitMessage = FitMessage {
+    local: 2,
+    global: 19,
+    fields: &[
+        (253, 4, FIT_UINT32), // timestamp
+        (0, 1, FIT_ENUM),     // event
+        (1, 1, FIT_ENUM),     // event_type
+        (2, 4, FIT_UINT32),   // start_time
+        (7, 4, FIT_UINT32),   // total_elapsed_time
+        (8, 4, FIT_UINT32),   // total_timer_time
+        (9, 4, FIT_UINT32),   // total_distance
+    ],
+};
+
+const FIT_SESSION: FitMessage = FitMessage {
+    local: 3,
+    global: 18,
+    fields: &[
+        (253, 4, FIT_UINT32), // timestamp
+        (0, 1, FIT_ENUM),     // event
+        (1, 1, FIT_ENUM),     // event_type
+        (2, 4, FIT_UINT32),   // start_time
+        (5, 1, FIT_ENUM),     // sport
+        (6, 1, FIT_ENUM),     // sub_sport
+        (7, 4, FIT_UINT32),   // total_elapsed_time
+        (8, 4, FIT_UINT32),   // total_timer_time
+        (9, 4, FIT_UINT32),   // total_distance
+        (25, 2, FIT_UINT16),  // first_lap_index
+        (26, 2, FIT_UINT16),  // num_laps
+    ],
+};
+
+const FIT_ACTIVITY: FitMessage = FitMessage {
+    local: 4,
+    global: 34,
+    fields: &[
+        (253, 4, FIT_UINT32), // timestamp
+        (0, 4, FIT_UINT32),   // total_timer_time
+        (1, 2, FIT_UINT16),   // num_sessions
+        (2, 1, FIT_ENUM),     // type
+        (3, 1, FIT_ENUM),     // event
+        (4, 1, FIT_ENUM),     // event_type
+    ],
+};
+
+/// Writes the data records of a FIT file. Each message's definition is written
+/// before its first data message; `None` values are written as the base type's
+/// invalid value.
+#[derive(Default)]
+struct FitWriter {
+    data: Vec<u8>,
+    defined: [bool; 16],
+}
+
+impl FitWriter {
+    fn begin(&mut self, message: &FitMessage) {
+        let local = message.local as usize;
+        if !self.defined[local] {
+            self.data.push(0x40 | message.local);
+            self.data.push(0); // reserved
+            self.data.push(0); // little-endian
+            self.data.extend_from_slice(&message.global.to_le_bytes());
+            self.data.push(message.fields.len() as u8);
+            for &(number, size, base_type) in message.fields {
+                self.data.extend_from_slice(&[number, size, base_type]);
+            }
+            self.defined[local] = true;
+        }
+        self.data.push(message.local);
+    }
+
+    fn enum_value(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    fn u8(&mut self, value: Option<u8>) {
+        self.data.push(value.unwrap_or(u8::MAX));
+    }
+
+    fn i8(&mut self, value: Option<i8>) {
+        self.data
+            .extend_from_slice(&value.unwrap_or(i8::MAX).to_le_bytes());
+    }
+
+    fn u16(&mut self, value: Option<u16>) {
+        self.data
+            .extend_from_slice(&value.unwrap_or(u16::MAX).to_le_bytes());
+    }
+
+    fn u32(&mut self, value: Option<u32>) {
+        self.data
+            .extend_from_slice(&value.unwrap_or(u32::MAX).to_le_bytes());
+    }
+
+    fn i32(&mut self, value: Option<i32>) {
+        self.data
+            .extend_from_slice(&value.unwrap_or(i32::MAX).to_le_bytes());
+    }
+
+    /// Prepend the 14-byte file header and append the file CRC.
+    fn finish(self) -> Vec<u8> {
+        let mut file = Vec::with_capacity(self.data.len() + 16);
+        file.push(14); // header size
+        file.push(0x20); // protocol 2.0
+        file.extend_from_slice(&2132u16.to_le_bytes()); // profile 21.32
+        file.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        file.extend_from_slice(b".FIT");
+        let header_crc = fit_crc(&file);
+        file.extend_from_slice(&header_crc.to_le_bytes());
+
+        file.extend_from_slice(&self.data);
+        let crc = fit_crc(&file);
+        file.extend_from_slice(&crc.to_le_bytes());
+        file
+    }
+}
+
+fn fit_timestamp(ts: OffsetDateTime) -> u32 {
+    (ts.unix_timestamp() - FIT_EPOCH_OFFSET).max(0) as u32
+}
+
+/// Convert degrees to FIT semicircles, where 2^31 semicircles = 180 degrees.
+fn degrees_to_semicircles(degrees: f64) -> i32 {
+    (degrees * (2_147_483_648.0 / 180.0)).round() as i32
+}
+
+/// CRC-16 as specified by the FIT protocol.
+fn fit_crc(bytes: &[u8]) -> u16 {
+    const TABLE: [u16; 16] = [
+        0x0000, 0xCC01, 0xD801, 0x1400, 0xF001, 0x3C00, 0x2800, 0xE401, 0xA001, 0x6C00, 0x7800,
+        0xB401, 0x5000, 0x9C01, 0x8801, 0x4400,
+    ];
+
+    bytes.iter().fold(0u16, |mut crc, &byte| {
+        let tmp = TABLE[(crc & 0xF) as usize];
+        crc = (crc >> 4) & 0x0FFF;
+        crc = crc ^ tmp ^ TABLE[(byte & 0xF) as usize];
+        let tmp = TABLE[(crc & 0xF) as usize];
+        crc = (crc >> 4) & 0x0FFF;
+        crc ^ tmp ^ TABLE[((byte >> 4) & 0xF) as usize]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_parsers::{parse_fit, parse_gpx, parse_tcx};
+    use bytes::Bytes;
+    use time::Duration;
+
+    fn track(points: usize) -> Vec<TrackPointData> {
+        let start = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        (0..points)
+            .map(|i| TrackPointData {
+                lat: 45.0 + i as f64 * 0.001,
+                lon: 7.0,
+                elevation: Some(100.0 + i as f64),
+                timestamp: Some(start + Duration::seconds(i as i64 * 10)),
+            })
+            .collect()
+    }
+
+    fn sensors(points: usize) -> SensorData {
+        SensorData {
+            heart_rates: (0..points).map(|i| Some(120 + i as i32)).collect(),
+            cadences: (0..points)
+                .map(|i| (i % 2 == 0).then_some(80 + i as i32))
+                .collect(),
+            powers: (0..points).map(|i| Some(200 + i as i32)).collect(),
+            temperatures: vec![None; points],
+        }
+    }
+
+    fn activity(points: &[TrackPointData]) -> Activity {
+        let started_at = points[0].timestamp.unwrap();
+        Activity {
+            id: Uuid::new_v4(),
+            user_id: Uuid::new_v4(),
+            activity_type_id: builtin_types::ROAD,
+            name: "Morning <ride> & more".to_string(),
+            object_store_path: String::new(),
+            started_at,
+            submitted_at: started_at,
+            visibility: "public".to_string(),
+            type_boundaries: None,
+            segment_types: None,
+        }
+    }
+
+    /// A run followed by a ride, switching at the fourth point.
+    fn brick(points: &[TrackPointData]) -> Activity {
+        let mut activity = activity(points);
+        activity.type_boundaries = Some(vec![
+            points[0].timestamp.unwrap(),
+            points[3].timestamp.unwrap(),
+            points.last().unwrap().timestamp.unwrap(),
+        ]);
+        activity.segment_types = Some(vec![builtin_types::RUN, builtin_types::MTB]);
+        activity
+    }
+
+    #[test]
+    fn test_split_parts_single_sport() {
+        let points = track(5);
+        let activity = activity(&points);
+        assert_eq!(
+            split_parts(&activity, &points),
+            vec![ExportPart {
+                activity_type_id: builtin_types::ROAD,
+                points: 0..5,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_split_parts_multi_sport() {
+        let points = track(8);
+        let activity = brick(&points);
+        assert_eq!(
+            split_parts(&activity, &points),
+            vec![
+                ExportPart {
+                    activity_type_id: builtin_types::RUN,
+                    points: 0..3,
+                },
+                ExportPart {
+                    activity_type_id: builtin_types::MTB,
+                    points: 3..8,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_gpx_export_round_trips() {
+        let points = track(6);
+        let sensor_data = sensors(6);
+        let activity = brick(&points);
+        let export = ActivityExport::new(&activity, &points, &sensor_data);
+
+        let bytes = export.render(ExportFormat::Gpx);
+        let gpx = String::from_utf8(bytes.clone()).unwrap();
+        assert!(gpx.contains("Morning &lt;ride&gt; &amp; more"));
+        assert_eq!(gpx.matches("<trk>").count(), 2);
+
+        let parsed = parse_gpx(Bytes::from(bytes)).unwrap();
+        assert_eq!(parsed.track_points.len(), 6);
+        assert_eq!(parsed.track_points[5].timestamp, points[5].timestamp);
+        assert_eq!(parsed.track_points[5].elevation, Some(105.0));
+        assert_eq!(parsed.sensor_data.heart_rates, sensor_data.heart_rates);
+        assert_eq!(parsed.sensor_data.cadences, sensor_data.cadences);
+        assert_eq!(parsed.sensor_data.powers, sensor_data.powers);
+    }
+
+    #[test]
+    fn test_tcx_export_round_trips() {
+        let points = track(6);
+        let sensor_data = sensors(6);
+        let activity = brick(&points);
+        let export = ActivityExport::new(&activity, &points, &sensor_data);
+
+        let bytes = export.render(ExportFormat::Tcx);
+        let tcx = String::from_utf8(bytes.clone()).unwrap();
+        assert!(tcx.contains(r#"<Activity Sport="Running">"#));
+        assert!(tcx.contains(r#"<Activity Sport="Biking">"#));
+
+        let parsed = parse_tcx(Bytes::from(bytes)).unwrap();
+        assert_eq!(parsed.track_points.len(), 6);
+        assert!((parsed.track_points[2].lat - points[2].lat).abs() < 1e-6);
+        assert_eq!(parsed.track_points[2].timestamp, points[2].timestamp);
+        assert_eq!(parsed.sensor_data.heart_rates, sensor_data.heart_rates);
+        assert_eq!(parsed.sensor_data.cadences, sensor_data.cadences);
+
+        // TCX only knows "Biking", so the MTB part comes back as a road ride
+        let sports: Vec<Uuid> = parsed
+            .sport_segments
+            .iter()
+            .map(|s| s.activity_type_id)
+            .collect();
+        assert_eq!(sports, vec![builtin_types::RUN, builtin_types::ROAD]);
+    }
+
+    #[test]
+    fn test_fit_export_round_trips() {
+        let points = track(6);
+        let mut sensor_data = sensors(6);
+        sensor_data.temperatures[1] = Some(21.0);
+        let activity = brick(&points);
+        let export = ActivityExport::new(&activity, &points, &sensor_data);
+
+        let parsed = parse_fit(Bytes::from(export.render(ExportFormat::Fit))).unwrap();
+        assert_eq!(parsed.track_points.len(), 6);
+        for (parsed, original) in parsed.track_points.iter().zip(&points) {
+            assert!((parsed.lat - original.lat).abs() < 1e-6);
+            assert!((parsed.lon - original.lon).abs() < 1e-6);
+            assert!((parsed.elevation.unwrap() - original.elevation.unwrap()).abs() < 0.2);
+            assert_eq!(parsed.timestamp, original.timestamp);
+        }
+        assert_eq!(parsed.sensor_data.heart_rates, sensor_data.heart_rates);
+        assert_eq!(parsed.sensor_data.cadences, sensor_data.cadences);
+        assert_eq!(parsed.sensor_data.powers, sensor_data.powers);
+        assert_eq!(
+            parsed.sensor_data.temperatures,
+            vec![None, Some(21.0), None, None, None, None]
+        );
+
+        let sports: Vec<Uuid> = parsed
+            .sport_segments
+            .iter()
+            .map(|s| s.activity_type_id)
+            .collect();
+        assert_eq!(sports, vec![builtin_types::RUN, builtin_types::MTB]);
+        assert_eq!(parsed.sport_segments[1].start_time, points[3].timestamp);
+    }
+}
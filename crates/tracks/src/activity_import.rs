@@ -0,0 +1,618 @@
+//! Bulk import of activity export archives.
+//!
+//! An archive is a zip laid out like a Strava bulk export: `activities.csv`
+//! plus the files it references under `activities/` (`.fit.gz`, `.gpx`,
+//! `.tcx`, ...). The CSV supplies each activity's name, type, date and
+//! visibility; without one, every activity file in the archive is imported
+//! and named after its file. Each file goes through the same path as a single
+//! upload (object store, `activities` row, `ActivityQueue`), and its outcome is
+//! recorded in `activity_import_items`. Unzipping and parsing run on the
+//! blocking thread pool.
+//!
+//! The archive only lives in the importing process's memory. Recording each
+//! file refreshes the import's heartbeat; imports whose heartbeat stops are
+//! failed as interrupted by [`start_stale_import_reaper`].
+
+use std::{
+    io::{Read, Seek},
+    sync::{Arc, Mutex},
+    time::Duration as StdDuration,
+};
+
+use bytes::Bytes;
+use time::{OffsetDateTime, PrimitiveDateTime, format_description::well_known::Rfc3339};
+use uuid::Uuid;
+use zip::ZipArchive;
+
+use crate::{
+    activity_queue::ActivityQueue,
+    compression::{self, MAX_DECOMPRESSED_BYTES, is_activity_file},
+    database::Database,
+    duplicate_detection,
+    errors::AppError,
+    file_parsers::{ParsedActivity, activity_type_ids, parse_activity_file},
+    models::{Activity, ActivityImport, ImportItemStatus, ResolvedActivityType},
+    object_store_service::{FileType, ObjectStoreService},
+};
+
+/// Largest archive accepted over HTTP; bigger exports go through the
+/// `import_archive` command.
+pub const MAX_ARCHIVE_BYTES: usize = 256 * 1024 * 1024;
+
+/// How often the reaper looks for imports whose process stopped.
+const REAPER_INTERVAL: StdDuration = StdDuration::from_secs(60);
+
+/// An import refreshes its heartbeat with every file it records; one that
+/// hasn't for this long is assumed interrupted.
+const STALE_IMPORT_TIMEOUT: time::Duration = time::Duration::minutes(10);
+
+type SharedArchive<R> = Arc<Mutex<ZipArchive<R>>>;
+
+/// An activity file to import, with what the archive's CSV says about it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ArchiveActivity {
+    /// Path of the file inside the archive
+    pub file_name: String,
+    pub external_id: Option<String>,
+    pub name: Option<String>,
+    pub sport: Option<String>,
+    pub started_at: Option<OffsetDateTime>,
+    pub visibility: Option<&'static str>,
+}
+
+/// What happened to one file of an archive.
+#[derive(Debug)]
+enum EntryOutcome {
+    Imported(Uuid),
+    Duplicate(Uuid),
+    Failed(String),
+}
+
+/// An activity file read from the archive and parsed.
+struct PreparedEntry {
+    /// The file as stored in the archive, still gzipped if it was
+    bytes: Bytes,
+    file_type: FileType,
+    file_hash: String,
+    parsed: ParsedActivity,
+}
+
+/// Spawn the reaper failing imports whose process stopped on the current runtime.
+pub fn start_stale_import_reaper(db: Database) {
+    tokio::spawn(async move {
+        loop {
+            match db.fail_stale_activity_imports(STALE_IMPORT_TIMEOUT).await {
+                Ok(0) => {}
+                Ok(count) => tracing::warn!(count, "Marked interrupted activity imports as failed"),
+                Err(e) => tracing::error!("Failed to clean up interrupted activity imports: {e}"),
+            }
+            tokio::time::sleep(REAPER_INTERVAL).await;
+        }
+    });
+}
+
+#[derive(Clone)]
+pub struct ActivityImporter {
+    db: Database,
+    store: ObjectStoreService,
+    aq: ActivityQueue,
+}
+
+impl ActivityImporter {
+    pub fn new(db: Database, store: ObjectStoreService, aq: ActivityQueue) -> Self {
+        Self { db, store, aq }
+    }
+
+    /// Import every activity in an archive for the import's user, then mark the
+    /// import finished. `visibility` applies to activities the CSV doesn't
+    /// give one for.
+    pub async fn run<R: Read + Seek + Send + 'static>(
+        &self,
+        import: &ActivityImport,
+        archive: R,
+        visibility: &str,
+    ) {
+        let error = match self.import_archive(import, archive, visibility).await {
+            Ok(()) => None,
+            Err(e) => {
+                tracing::error!(import_id = %import.id, "Activity import failed: {e}");
+                Some(e.to_string())
+            }
+        };
+        if let Err(e) = self
+            .db
+            .finish_activity_import(import.id, error.as_deref())
+            .await
+        {
+            tracing::error!(import_id = %import.id, "Failed to record import result: {e}");
+        }
+    }
+
+    async fn import_archive<R: Read + Seek + Send + 'static>(
+        &self,
+        import: &ActivityImport,
+        archive: R,
+        visibility: &str,
+    ) -> Result<(), AppError> {
+        let (archive, entries) = tokio::task::spawn_blocking(move || {
+            let mut archive = ZipArchive::new(archive)
+                .map_err(|e| AppError::InvalidInput(format!("Not a readable zip archive: {e}")))?;
+            let entries = archive_activities(&mut archive)?;
+            Ok::<_, AppError>((archive, entries))
+        })
+        .await
+        .map_err(|_| AppError::Internal)??;
+        let archive = Arc::new(Mutex::new(archive));
+        self.db
+            .start_activity_import(import.id, entries.len() as i32)
+            .await?;
+
+        tracing::info!(
+            import_id = %import.id,
+            files = entries.len(),
+            "Importing activity archive"
+        );
+
+        for entry in &entries {
+            let outcome = match prepare_entry(&archive, &entry.file_name).await {
+                Ok(prepared) => {
+                    self.import_entry(import.user_id, entry, prepared, visibility)
+                        .await
+                }
+                Err(message) => EntryOutcome::Failed(message),
+            };
+
+            let (status, activity_id, message) = match outcome {
+                EntryOutcome::Imported(id) => (ImportItemStatus::Imported, Some(id), None),
+                EntryOutcome::Duplicate(id) => (
+                    ImportItemStatus::Duplicate,
+                    Some(id),
+                    Some("Activity already exists".to_string()),
+                ),
+                EntryOutcome::Failed(message) => (ImportItemStatus::Failed, None, Some(message)),
+            };
+            self.db
+                .record_activity_import_item(
+                    import.id,
+                    &entry.file_name,
+                    entry.external_id.as_deref(),
+                    entry.name.as_deref(),
+                    status,
+                    activity_id,
+                    message.as_deref(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn import_entry(
+        &self,
+        user_id: Uuid,
+        entry: &ArchiveActivity,
+        prepared: PreparedEntry,
+        visibility: &str,
+    ) -> EntryOutcome {
+        let PreparedEntry {
+            bytes,
+            file_type,
+            file_hash,
+            parsed,
+        } = prepared;
+        let Some(started_at) = parsed.started_at().or(entry.started_at) else {
+            return EntryOutcome::Failed("Activity has no start time".to_string());
+        };
+
+        match self
+            .save_entry(
+                user_id, entry, &parsed, started_at, file_type, bytes, &file_hash, visibility,
+            )
+            .await
+        {
+            Ok(outcome) => outcome,
+            Err(e) => EntryOutcome::Failed(e.to_string()),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn save_entry(
+        &self,
+        user_id: Uuid,
+        entry: &ArchiveActivity,
+        parsed: &ParsedActivity,
+        started_at: OffsetDateTime,
+        file_type: FileType,
+        bytes: Bytes,
+        file_hash: &str,
+        visibility: &str,
+    ) -> Result<EntryOutcome, AppError> {
+        if let Some(existing) =
+            duplicate_detection::find_duplicate_activity(&self.db, user_id, file_hash, Some(parsed))
+                .await?
+        {
+            return Ok(EntryOutcome::Duplicate(existing));
+        }
+
+        let activity_type_id = self.resolve_type(entry.sport.as_deref(), parsed).await?;
+        let activity_id = Uuid::new_v4();
+        let object_store_path = self
+            .store
+            .store_file(user_id, activity_id, file_type, bytes)
+            .await?;
+
+        let activity = Activity {
+            id: activity_id,
+            user_id,
+            activity_type_id,
+            name: entry
+                .name
+                .clone()
+                .unwrap_or_else(|| default_name(&entry.file_name)),
+            object_store_path,
+            started_at,
+            submitted_at: OffsetDateTime::now_utc(),
+            visibility: entry.visibility.unwrap_or(visibility).to_string(),
+            type_boundaries: None,
+            segment_types: None,
+        };
+        match self.db.save_activity(&activity, Some(file_hash)).await {
+            Ok(()) => {}
+            // The same file was uploaded while this entry was being imported
+            Err(AppError::DuplicateActivity(existing)) => {
+                if let Err(e) = self.store.delete_file(&activity.object_store_path).await {
+                    tracing::warn!("Failed to delete duplicate import {}: {e}", activity.id);
+                }
+                return Ok(EntryOutcome::Duplicate(existing));
+            }
+            Err(e) => return Err(e),
+        }
+        self.aq.submit_import(user_id, activity.id).await?;
+
+        Ok(EntryOutcome::Imported(activity.id))
+    }
+
+    /// Map the export's activity type through the type names and aliases. When
+    /// the name is ambiguous or unknown, the sport recorded in the file decides.
+    async fn resolve_type(
+        &self,
+        sport: Option<&str>,
+        parsed: &ParsedActivity,
+    ) -> Result<Uuid, AppError> {
+        let detected = parsed.sport_segments.first().map(|s| s.activity_type_id);
+
+        let resolved = match sport {
+            Some(sport) => {
+                self.db
+                    .resolve_activity_type(&normalize_sport(sport))
+                    .await?
+            }
+            None => ResolvedActivityType::NotFound,
+        };
+
+        Ok(match resolved {
+            ResolvedActivityType::Exact(id) => id,
+            ResolvedActivityType::Ambiguous(ids) => {
+                detected.filter(|id| ids.contains(id)).unwrap_or(ids[0])
+            }
+            ResolvedActivityType::NotFound => detected.unwrap_or(activity_type_ids::UNKNOWN),
+        })
+    }
+}
+
+/// List the activity files to import, from `activities.csv` when the archive
+/// has one.
+fn archive_activities<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+) -> Result<Vec<ArchiveActivity>, AppError> {
+    let csv_name = archive
+        .file_names()
+        .filter(|name| name.rsplit('/').next() == Some("activities.csv"))
+        .min_by_key(|name| name.len())
+        .map(str::to_string);
+
+    let Some(csv_name) = csv_name else {
+        let mut files: Vec<ArchiveActivity> = archive
+            .file_names()
+            .filter(|name| is_activity_file(name))
+            .map(|name| ArchiveActivity {
+                file_name: name.to_string(),
+                ..Default::default()
+            })
+            .collect();
+        files.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+        return Ok(files);
+    };
+
+    let csv = read_entry(archive, &csv_name).map_err(AppError::InvalidInput)?;
+    let csv = String::from_utf8_lossy(&csv);
+    // Paths in the CSV are relative to the directory holding it
+    let base = csv_name
+        .rsplit_once('/')
+        .map(|(dir, _)| format!("{dir}/"))
+        .unwrap_or_default();
+
+    Ok(parse_activities_csv(&csv)
+        .into_iter()
+        .map(|mut activity| {
+            activity.file_name = format!("{base}{}", activity.file_name);
+            activity
+        })
+        .collect())
+}
+
+/// Read one file from the archive and parse it, on the blocking thread pool.
+async fn prepare_entry<R: Read + Seek + Send + 'static>(
+    archive: &SharedArchive<R>,
+    name: &str,
+) -> Result<PreparedEntry, String> {
+    let archive = archive.clone();
+    let name = name.to_string();
+    tokio::task::spawn_blocking(move || {
+        let bytes = {
+            let mut archive = archive.lock().unwrap_or_else(|e| e.into_inner());
+            read_entry(&mut archive, &name)?
+        };
+        parse_entry(Bytes::from(bytes))
+    })
+    .await
+    .unwrap_or_else(|_| Err("The file could not be parsed".to_string()))
+}
+
+/// Decompress and parse an activity file. Gzipped files are stored as they
+/// are in the archive.
+fn parse_entry(bytes: Bytes) -> Result<PreparedEntry, String> {
+    let contents = compression::decompress(bytes.clone())
+        .map_err(|e| format!("Failed to decompress file: {e}"))?;
+    let file_type = FileType::detect_from_bytes(&contents);
+    if !file_type.is_supported_activity_format() {
+        return Err("Not a GPX, TCX or FIT file".to_string());
+    }
+
+    let file_hash = duplicate_detection::file_hash(&contents);
+    let parsed = parse_activity_file(file_type, contents).map_err(|e| e.to_string())?;
+    Ok(PreparedEntry {
+        bytes,
+        file_type,
+        file_hash,
+        parsed,
+    })
+}
+
+/// Read one file from the archive as stored, still gzipped if it was. Files
+/// larger than `MAX_DECOMPRESSED_BYTES` are refused.
+fn read_entry<R: Read + Seek>(archive: &mut ZipArchive<R>, name: &str) -> Result<Vec<u8>, String> {
+    let file = archive
+        .by_name(name)
+        .map_err(|_| "File not found in archive".to_string())?;
+    let mut bytes = Vec::new();
+    file.take(MAX_DECOMPRESSED_BYTES + 1)
+        .read_to_end(&mut bytes)
+        .map_err(|e| format!("Failed to read file: {e}"))?;
+    if bytes.len() as u64 > MAX_DECOMPRESSED_BYTES {
+        return Err(format!(
+            "File is larger than {} MB",
+            MAX_DECOMPRESSED_BYTES / 1024 / 1024
+        ));
+    }
+    Ok(bytes)
+}
+
+/// Parse a Strava `activities.csv`. Rows without a file (manual entries) are
+/// skipped. Columns are found by header name; where a header repeats, as
+/// several do in Strava exports, the first one wins.
+pub fn parse_activities_csv(csv: &str) -> Vec<ArchiveActivity> {
+    let mut rows = parse_csv(csv).into_iter();
+    let Some(header) = rows.next() else {
+        return Vec::new();
+    };
+    let column = |names: &[&str]| {
+        header
+            .iter()
+            .position(|h| names.iter().any(|n| h.trim().eq_ignore_ascii_case(n)))
+    };
+    let file_col = column(&["Filename"]);
+    let id_col = column(&["Activity ID"]);
+    let name_col = column(&["Activity Name"]);
+    let type_col = column(&["Activity Type"]);
+    let date_col = column(&["Activity Date"]);
+    let visibility_col = column(&["Activity Visibility", "Visibility"]);
+
+    let field = |row: &[String], col: Option<usize>| {
+        col.and_then(|c| row.get(c))
+            .map(|v| v.trim())
+            .filter(|v| !v.is_empty())
+            .map(str::to_string)
+    };
+
+    rows.filter_map(|row| {
+        let file_name = field(&row, file_col)?;
+        Some(ArchiveActivity {
+            file_name,
+            external_id: field(&row, id_col),
+            name: field(&row, name_col),
+            sport: field(&row, type_col),
+            started_at: field(&row, date_col).and_then(|d| parse_export_date(&d)),
+            visibility: field(&row, visibility_col).and_then(|v| map_visibility(&v)),
+        })
+    })
+    .collect()
+}
+
+/// Split CSV text into rows of fields, handling quoted fields with embedded
+/// commas, newlines and doubled quotes.
+fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut chars = text.trim_start_matches('\u{feff}').chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, in_quotes) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            ('"', true) => in_quotes = false,
+            ('"', false) if field.is_empty() => in_quotes = true,
+            (',', false) => row.push(std::mem::take(&mut field)),
+            ('\r', false) => {}
+            ('\n', false) => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            (c, _) => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows
+}
+
+/// Parse an export date: RFC 3339, or Strava's "Jan 17, 2024, 6:48:12 AM" in UTC.
+fn parse_export_date(value: &str) -> Option<OffsetDateTime> {
+    if let Ok(date) = OffsetDateTime::parse(value, &Rfc3339) {
+        return Some(date);
+    }
+    let format = time::format_description::parse(
+        "[month repr:short] [day padding:none], [year], \
+         [hour repr:12 padding:none]:[minute]:[second] [period]",
+    )
+    .ok()?;
+    PrimitiveDateTime::parse(value, &format)
+        .ok()
+        .map(PrimitiveDateTime::assume_utc)
+}
+
+/// Map an export's visibility to ours.
+fn map_visibility(value: &str) -> Option<&'static str> {
+    match normalize_sport(value).as_str() {
+        "everyone" | "public" => Some("public"),
+        "followers_only" | "followers" => Some("followers"),
+        "only_me" | "private" => Some("private"),
+        _ => None,
+    }
+}
+
+/// Lowercase an export's type name and join its words with underscores, the
+/// form activity type names and aliases use ("Mountain Bike Ride" ->
+/// "mountain_bike_ride").
+fn normalize_sport(value: &str) -> String {
+    value
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("_")
+        .to_lowercase()
+}
+
+/// Name an activity after its file: "activities/1234.fit.gz" -> "1234".
+fn default_name(file_name: &str) -> String {
+    let base = file_name.rsplit('/').next().unwrap_or(file_name);
+    base.split('.').next().unwrap_or(base).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::test_fixtures::{gzip, zip};
+    use std::io::Cursor;
+
+    const STRAVA_CSV: &str = "\u{feff}Activity ID,Activity Date,Activity Name,Activity Type,Activity Description,Elapsed Time,Distance,Filename,Distance\r\n\
+        123,\"Jan 17, 2024, 6:48:12 AM\",\"Hills, \"\"again\"\"\",Mountain Bike Ride,\"Two\nlines\",3600,20.5,activities/123.fit.gz,20500\r\n\
+        124,\"Jan 18, 2024, 7:00:00 PM\",Yoga,Yoga,,1800,0,,0\r\n\
+        125,\"Jan 19, 2024, 12:05:09 PM\",Commute,Ride,,900,5.1,activities/125.gpx,5100\r\n";
+
+    #[test]
+    fn test_parse_activities_csv() {
+        let activities = parse_activities_csv(STRAVA_CSV);
+
+        // The yoga session has no file
+        assert_eq!(activities.len(), 2);
+        assert_eq!(
+            activities[0],
+            ArchiveActivity {
+                file_name: "activities/123.fit.gz".to_string(),
+                external_id: Some("123".to_string()),
+                name: Some("Hills, \"again\"".to_string()),
+                sport: Some("Mountain Bike Ride".to_string()),
+                started_at: parse_export_date("2024-01-17T06:48:12Z"),
+                visibility: None,
+            }
+        );
+        assert_eq!(
+            activities[1].started_at,
+            parse_export_date("2024-01-19T12:05:09Z")
+        );
+    }
+
+    #[test]
+    fn test_normalize_sport_matches_alias_form() {
+        assert_eq!(normalize_sport("Mountain Bike Ride"), "mountain_bike_ride");
+        assert_eq!(normalize_sport(" E-Bike  Ride "), "e-bike_ride");
+        assert_eq!(normalize_sport("Run"), "run");
+    }
+
+    #[test]
+    fn test_map_visibility() {
+        assert_eq!(map_visibility("Everyone"), Some("public"));
+        assert_eq!(map_visibility("only_me"), Some("private"));
+        assert_eq!(map_visibility("Followers Only"), Some("followers"));
+        assert_eq!(map_visibility("something else"), None);
+    }
+
+    fn zip_archive(files: &[(&str, &[u8])]) -> ZipArchive<Cursor<Vec<u8>>> {
+        ZipArchive::new(Cursor::new(zip(files))).unwrap()
+    }
+
+    #[test]
+    fn test_archive_activities_from_csv() {
+        let mut archive = zip_archive(&[
+            ("export/activities.csv", STRAVA_CSV.as_bytes()),
+            ("export/activities/123.fit.gz", b""),
+            ("export/activities/125.gpx", b""),
+            ("export/activities/999.gpx", b""),
+        ]);
+
+        let files: Vec<String> = archive_activities(&mut archive)
+            .unwrap()
+            .into_iter()
+            .map(|a| a.file_name)
+            .collect();
+        assert_eq!(
+            files,
+            vec!["export/activities/123.fit.gz", "export/activities/125.gpx"]
+        );
+    }
+
+    #[test]
+    fn test_archive_activities_without_csv() {
+        let mut archive = zip_archive(&[
+            ("b.tcx", b""),
+            ("a.fit.gz", b""),
+            ("notes.txt", b""),
+            ("c.GPX", b""),
+        ]);
+
+        let files: Vec<String> = archive_activities(&mut archive)
+            .unwrap()
+            .into_iter()
+            .map(|a| a.file_name)
+            .collect();
+        assert_eq!(files, vec!["a.fit.gz", "b.tcx", "c.GPX"]);
+        assert_eq!(default_name("activities/1234.fit.gz"), "1234");
+    }
+
+    #[test]
+    fn test_read_entry_keeps_compression() {
+        let gpx = b"<?xml version=\"1.0\"?><gpx version=\"1.1\"></gpx>";
+        let gz = gzip(gpx);
+        let mut archive = zip_archive(&[("ride.gpx.gz", &gz), ("ride.gpx", gpx)]);
+
+        assert_eq!(read_entry(&mut archive, "ride.gpx.gz").unwrap(), gz);
+        assert_eq!(read_entry(&mut archive, "ride.gpx").unwrap(), gpx);
+        assert!(read_entry(&mut archive, "missing.gpx").is_err());
+    }
+}
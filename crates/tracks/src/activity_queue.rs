@@ -1,233 +1,783 @@
+//! Durable activity processing queue.
+//!
+//! Jobs live in the `activity_jobs` table so uploads survive restarts. Workers
+//! claim jobs with `FOR UPDATE SKIP LOCKED`, run the processing pipeline, and
+//! either complete the job or put it back on the queue with exponential backoff.
+//! Every pipeline write is idempotent, so a job that dies part-way through is
+//! simply run again from the start. Each claim carries a token, so a worker
+//! that lost its job to the reaper stops instead of finishing a job another
+//! worker has picked up.
+
 use std::{
-    collections::HashSet,
+    panic::AssertUnwindSafe,
     sync::{Arc, Mutex},
+    time::{Duration as StdDuration, Instant},
 };
 
-use bytes::{Buf as _, Bytes};
-use tokio::runtime::Handle;
+use bytes::Bytes;
+use futures_util::FutureExt;
+use tokio::sync::{Notify, oneshot};
 use uuid::Uuid;
 
 use crate::{
     achievements_service,
-    database::Database,
-    file_parsers::{self, ParsedActivity},
-    models::TrackPointData,
-    object_store_service::FileType,
+    database::{ClaimedActivityJob, Database, ProcessingErrorRecord, UpdatedActivity},
+    elevation::ElevationService,
+    errors::AppError,
+    file_parsers::{self, ParseError, ParsedActivity},
+    models::{
+        Activity, ActivityJob, ElevationSource, ProcessingErrorKind, ProcessingEvent,
+        ProcessingStage, Scores, SegmentEffort, TrackPointData,
+    },
+    object_store_service::{FileType, ObjectStoreService},
     scoring,
-    segment_matching::{self, SegmentMatch},
+    segment_matching::{self, STOPPED_SPEED_THRESHOLD_MPS, SegmentMatch},
+    track_cleaning::TrackCleaning,
 };
 use time::OffsetDateTime;
 
-/// Submission data for processing an activity
-pub struct ActivitySubmission {
-    pub user_id: Uuid,
-    pub activity_id: Uuid,
-    pub file_type: FileType,
-    pub bytes: Bytes,
-    pub activity_type_id: Uuid,
-    pub type_boundaries: Option<Vec<OffsetDateTime>>,
-    pub segment_types: Option<Vec<Uuid>>,
+/// Workers started when `ACTIVITY_QUEUE_WORKERS` is not set.
+pub const DEFAULT_WORKERS: usize = 2;
+
+/// How often idle workers check for jobs whose backoff has elapsed.
+const POLL_INTERVAL: StdDuration = StdDuration::from_secs(5);
+
+/// How often the reaper looks for jobs abandoned by a dead worker.
+const REAPER_INTERVAL: StdDuration = StdDuration::from_secs(60);
+
+/// A job whose claim hasn't been refreshed for this long is assumed abandoned.
+const STALE_JOB_TIMEOUT: time::Duration = time::Duration::minutes(15);
+
+/// How often a running job refreshes its claim, well inside [`STALE_JOB_TIMEOUT`].
+const HEARTBEAT_INTERVAL: StdDuration = StdDuration::from_secs(60);
+
+/// Retry backoff: 30s, 1m, 2m, ... capped at one hour.
+const BACKOFF_BASE_SECONDS: i64 = 30;
+const BACKOFF_MAX_SECONDS: i64 = 3600;
+
+/// Why an activity processing attempt, or part of one, failed.
+///
+/// The `Display` text is written for the uploader and stored as the job's error
+/// message; the underlying cause is kept separately as technical detail.
+#[derive(Debug, thiserror::Error)]
+pub enum ProcessingError {
+    #[error("Your file could not be loaded from storage")]
+    FileUnavailable(#[source] AppError),
+    #[error("Your file could not be parsed: {0}")]
+    Parse(ParseError),
+    #[error("Your file could not be parsed: the parser crashed on it")]
+    ParserPanicked,
+    #[error("Could not {action}")]
+    Database {
+        action: &'static str,
+        #[source]
+        source: AppError,
+    },
+    #[error("Could not record your effort on segment {segment_id}")]
+    SegmentEffort {
+        segment_id: Uuid,
+        #[source]
+        source: AppError,
+    },
+    #[error("Could not update records on segment {segment_id}")]
+    Achievements {
+        segment_id: Uuid,
+        #[source]
+        source: AppError,
+    },
+    #[error("Processing stopped unexpectedly")]
+    Panicked,
+    /// The job's claim expired and another worker took it over
+    #[error("Processing was interrupted")]
+    Reclaimed,
+}
+
+impl ProcessingError {
+    pub fn kind(&self) -> ProcessingErrorKind {
+        match self {
+            Self::FileUnavailable(_) => ProcessingErrorKind::FileUnavailable,
+            Self::Parse(_) | Self::ParserPanicked => ProcessingErrorKind::ParseFailed,
+            Self::Database { .. } | Self::SegmentEffort { .. } | Self::Achievements { .. } => {
+                ProcessingErrorKind::Database
+            }
+            Self::Panicked => ProcessingErrorKind::Internal,
+            Self::Reclaimed => ProcessingErrorKind::Interrupted,
+        }
+    }
+
+    /// A file that can't be parsed won't parse on the next attempt either.
+    pub fn is_permanent(&self) -> bool {
+        matches!(self, Self::Parse(_) | Self::ParserPanicked)
+    }
+
+    /// The chain of underlying causes, for logs and the processing API.
+    pub fn detail(&self) -> Option<String> {
+        let mut source = std::error::Error::source(self)?;
+        let mut detail = source.to_string();
+        while let Some(next) = source.source() {
+            detail.push_str(": ");
+            detail.push_str(&next.to_string());
+            source = next;
+        }
+        Some(detail)
+    }
+
+    /// Wrap a database error from one of the pipeline steps.
+    fn database(action: &'static str) -> impl FnOnce(AppError) -> Self {
+        move |source| Self::Database { action, source }
+    }
+
+    /// The error behind a failed pipeline step, for callers outside the
+    /// pipeline.
+    fn into_source(self) -> AppError {
+        match self {
+            Self::FileUnavailable(source)
+            | Self::Database { source, .. }
+            | Self::SegmentEffort { source, .. }
+            | Self::Achievements { source, .. } => source,
+            Self::Parse(_) | Self::ParserPanicked | Self::Panicked | Self::Reclaimed => {
+                AppError::Internal
+            }
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct ActivityQueue {
     db: Database,
-    handle: Handle,
+    store: ObjectStoreService,
     pool: Arc<rayon::ThreadPool>,
-    activities: Arc<Mutex<HashSet<Uuid>>>,
-    done_tx: std::sync::mpsc::Sender<Uuid>,
+    wake: Arc<Notify>,
+    cleaning: TrackCleaning,
+    elevation: ElevationService,
 }
 
 impl ActivityQueue {
-    pub fn new(db: Database) -> Self {
-        let rpool = rayon::ThreadPoolBuilder::new().build().unwrap();
-        let handle = Handle::current();
-        let activities = Arc::new(Mutex::new(HashSet::new()));
-
-        let (tx, rx) = std::sync::mpsc::channel::<Uuid>();
-        let worker_activities = activities.clone();
-        rpool.spawn(move || {
-            while let Ok(id) = rx.recv() {
-                let mut activities = worker_activities.lock().unwrap();
-                activities.remove(&id);
-            }
-        });
-        Self {
+    /// Create a queue; track cleaning is configured from the environment,
+    /// see [`TrackCleaning::from_env`].
+    pub fn new(
+        db: Database,
+        store: ObjectStoreService,
+    ) -> Result<Self, rayon::ThreadPoolBuildError> {
+        let rpool = rayon::ThreadPoolBuilder::new().build()?;
+        Ok(Self {
             db,
+            store,
             pool: Arc::new(rpool),
-            handle,
-            activities,
-            done_tx: tx,
+            wake: Arc::new(Notify::new()),
+            cleaning: TrackCleaning::from_env(),
+            elevation: ElevationService::disabled(),
+        })
+    }
+
+    /// Replace track elevations with terrain elevation from `elevation`.
+    pub fn with_elevation(mut self, elevation: ElevationService) -> Self {
+        self.elevation = elevation;
+        self
+    }
+
+    /// Spawn `workers` job workers plus the stale-job reaper on the current runtime.
+    pub fn start(&self, workers: usize) {
+        for worker in 0..workers.max(1) {
+            let queue = self.clone();
+            tokio::spawn(async move { queue.run_worker(worker).await });
         }
+
+        let db = self.db.clone();
+        let wake = self.wake.clone();
+        tokio::spawn(async move {
+            loop {
+                match db.requeue_stale_activity_jobs(STALE_JOB_TIMEOUT).await {
+                    Ok(0) => {}
+                    Ok(count) => {
+                        tracing::warn!(count, "Requeued stale activity jobs");
+                        wake.notify_waiters();
+                    }
+                    Err(e) => tracing::error!("Failed to requeue stale activity jobs: {e}"),
+                }
+                tokio::time::sleep(REAPER_INTERVAL).await;
+            }
+        });
     }
 
-    /// Reprocess an orphaned activity by loading its file from the object store.
-    pub async fn reprocess_orphaned(
+    /// Queue an activity for processing. The activity row and its file in the
+    /// object store must already exist.
+    pub async fn submit(&self, user_id: Uuid, activity_id: Uuid) -> Result<ActivityJob, AppError> {
+        self.enqueue(user_id, activity_id, true).await
+    }
+
+    /// Queue an imported activity for processing. It is processed like any
+    /// other, but the PRs it sets are old news and aren't announced.
+    pub async fn submit_import(
+        &self,
+        user_id: Uuid,
+        activity_id: Uuid,
+    ) -> Result<ActivityJob, AppError> {
+        self.enqueue(user_id, activity_id, false).await
+    }
+
+    async fn enqueue(
         &self,
-        activity: crate::models::OrphanedActivity,
-        store: &crate::object_store_service::ObjectStoreService,
-    ) -> anyhow::Result<()> {
-        let bytes = store.get_file(&activity.object_store_path).await?;
-        let file_type = crate::object_store_service::FileType::detect_from_bytes(&bytes);
+        user_id: Uuid,
+        activity_id: Uuid,
+        notify: bool,
+    ) -> Result<ActivityJob, AppError> {
+        let job = self
+            .db
+            .enqueue_activity_job(activity_id, user_id, notify)
+            .await?;
+        self.wake.notify_one();
+        Ok(job)
+    }
+
+    /// Match an activity against segments again after an edit removed its
+    /// efforts (see `Database::update_activity`). A job that is already running
+    /// for the activity stops recording efforts and runs again once finished.
+    pub async fn resubmit(&self, updated: &UpdatedActivity) -> Result<ActivityJob, AppError> {
+        // Segments the activity no longer matches won't be visited by the new
+        // job, so their Local Legend has to be recomputed here
+        for &segment_id in &updated.invalidated_segments {
+            if let Err(e) = achievements_service::update_local_legend(&self.db, segment_id).await {
+                tracing::warn!("Failed to update Local Legend for segment {segment_id}: {e}");
+            }
+        }
 
         tracing::info!(
-            activity_id = %activity.id,
-            "Reprocessing orphaned activity"
+            activity_id = %updated.activity.id,
+            invalidated = updated.invalidated_segments.len(),
+            "Re-matching edited activity"
         );
-
-        self.submit(ActivitySubmission {
-            user_id: activity.user_id,
-            activity_id: activity.id,
-            file_type,
-            bytes,
-            activity_type_id: activity.activity_type_id,
-            type_boundaries: activity.type_boundaries,
-            segment_types: activity.segment_types,
-        })
+        let job = self
+            .db
+            .enqueue_activity_rematch(updated.activity.id, updated.activity.user_id)
+            .await?;
+        self.wake.notify_one();
+        Ok(job)
     }
 
-    pub fn submit(&self, submission: ActivitySubmission) -> anyhow::Result<()> {
-        let ActivitySubmission {
-            user_id: uid,
-            activity_id: id,
-            file_type,
-            bytes,
-            activity_type_id,
-            type_boundaries,
-            segment_types,
-        } = submission;
+    /// Put a user's failed job back on the queue.
+    pub async fn retry(
+        &self,
+        job_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<ActivityJob>, AppError> {
+        let job = self.db.retry_activity_job(job_id, user_id).await?;
+        if job.is_some() {
+            self.wake.notify_one();
+        }
+        Ok(job)
+    }
 
-        self.activities.lock().unwrap().insert(id);
-        let tx = self.done_tx.clone();
-        let db = self.db.clone();
-        let handle = self.handle.clone();
-        self.pool.spawn(move || {
-            // Parse the activity file using the unified parser
-            let parsed = match file_parsers::parse_activity_file(file_type, bytes.clone()) {
-                Ok(p) => p,
+    async fn run_worker(&self, worker: usize) {
+        loop {
+            let claim = match self.db.claim_activity_job().await {
+                Ok(Some(claim)) => claim,
+                Ok(None) => {
+                    // Idle: wait for a submission or poll for backed-off jobs
+                    let _ = tokio::time::timeout(POLL_INTERVAL, self.wake.notified()).await;
+                    continue;
+                }
                 Err(e) => {
-                    tracing::error!("Failed to parse activity file: {e}");
-                    tx.send(id).unwrap();
-                    return;
+                    tracing::error!(worker, "Failed to claim activity job: {e}");
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                    continue;
                 }
             };
 
-            let ParsedActivity {
-                track_points,
-                sensor_data,
-                sport_segments: _,
-            } = parsed;
-
-            // For GPX files, also parse the raw GPX for segment timing extraction
-            // (segment matching currently requires the gpx::Gpx structure)
-            let gpx_data = if file_type == FileType::Gpx || file_type == FileType::Other {
-                // Re-parse as GPX if it's a GPX file (or might be detected as GPX)
-                if FileType::detect_from_bytes(&bytes) == FileType::Gpx {
-                    gpx::read(bytes.reader()).ok()
-                } else {
-                    None
-                }
-            } else {
-                None
-            };
+            let job = &claim.job;
+            tracing::info!(
+                worker,
+                job_id = %job.id,
+                activity_id = %job.activity_id,
+                attempt = job.attempts,
+                "Processing activity job"
+            );
 
-            // Calculate scores from track points
-            let scores = scoring::score_track_points(&track_points);
-
-            handle.block_on(async move {
-                // Save scores
-                db.save_scores(uid, id, scores).await.unwrap();
-
-                // Save track geometry with elevation and timestamps
-                let track_saved = if !track_points.is_empty() {
-                    match db
-                        .save_track_geometry_with_data(uid, id, &track_points)
-                        .await
-                    {
-                        Ok(()) => true,
-                        Err(e) => {
-                            tracing::error!("Failed to save track geometry: {e}");
-                            false
-                        }
-                    }
-                } else {
-                    false
-                };
+            let progress = JobProgress::new(&self.db, &claim);
+            // A panic fails this attempt instead of taking the worker down with it
+            let result = AssertUnwindSafe(self.run_job(&progress))
+                .catch_unwind()
+                .await
+                .unwrap_or(Err(ProcessingError::Panicked));
+            if let Err(e) = self.finish_job(&claim, result).await {
+                tracing::error!(job_id = %job.id, "Failed to record job result: {e}");
+            }
+        }
+    }
 
-                // Save sensor data if present
-                if sensor_data.has_any_data()
-                    && let Err(e) = db.save_sensor_data(id, &sensor_data).await
+    async fn finish_job(
+        &self,
+        claim: &ClaimedActivityJob,
+        result: Result<(), ProcessingError>,
+    ) -> Result<(), AppError> {
+        let job = &claim.job;
+        let error = match result {
+            Ok(()) => {
+                match self
+                    .db
+                    .complete_activity_job(job.id, claim.claim_token)
+                    .await?
                 {
-                    tracing::error!("Failed to save sensor data: {e}");
-                }
-
-                // Find and create segment efforts (only for GPX files currently)
-                if track_saved && let Some(ref gpx) = gpx_data {
-                    let matches = if let (Some(boundaries), Some(types)) =
-                        (&type_boundaries, &segment_types)
-                    {
-                        // Multi-sport activity: find all geometric matches, then filter by type
-                        match db.find_matching_segments_any_type(id).await {
-                            Ok(all_matches) => filter_multi_sport_matches(
-                                all_matches,
-                                &track_points,
-                                boundaries,
-                                types,
-                            ),
-                            Err(e) => {
-                                tracing::error!("Failed to find matching segments: {e}");
-                                vec![]
-                            }
-                        }
-                    } else {
-                        // Single-sport activity: filter by activity_type_id directly
-                        match db.find_matching_segments(id, activity_type_id).await {
-                            Ok(m) => m,
-                            Err(e) => {
-                                tracing::error!("Failed to find matching segments: {e}");
-                                vec![]
-                            }
-                        }
-                    };
-
-                    for segment_match in matches {
-                        process_segment_match(&db, gpx, uid, id, segment_match).await;
+                    Some(false) => {}
+                    Some(true) => {
+                        tracing::info!(job_id = %job.id, "Activity edited while processing, running again");
+                        self.wake.notify_one();
+                        return Ok(());
                     }
-                }
-
-                // Detect and save stopped segments
-                let stopped_segments = detect_stopped_segments(&track_points);
-                if !stopped_segments.is_empty() {
-                    tracing::info!(
-                        "Detected {} stopped segments in activity {}",
-                        stopped_segments.len(),
-                        id
-                    );
-                    if let Err(e) = db.save_stopped_segments(id, &stopped_segments).await {
-                        tracing::error!("Failed to save stopped segments: {e}");
+                    None => {
+                        tracing::warn!(job_id = %job.id, "Job was reclaimed, discarding result");
+                        return Ok(());
                     }
                 }
+                publish_event(
+                    &self.db,
+                    ProcessingEvent::Stage {
+                        activity_id: job.activity_id,
+                        stage: ProcessingStage::Done,
+                    },
+                )
+                .await;
+                return Ok(());
+            }
+            Err(ProcessingError::Reclaimed) => {
+                tracing::warn!(job_id = %job.id, "Job was reclaimed, stopping");
+                return Ok(());
+            }
+            Err(e) => e,
+        };
 
-                // Extract dig segments from multi-sport activities with DIG activity type
-                if let (Some(boundaries), Some(types)) = (&type_boundaries, &segment_types) {
-                    let dig_parts =
-                        extract_dig_parts_from_multi_sport(&track_points, boundaries, types);
-                    if !dig_parts.is_empty() {
-                        tracing::info!(
-                            "Found {} dig segments in multi-sport activity {}",
-                            dig_parts.len(),
-                            id
-                        );
-                        if let Err(e) = db.save_dig_parts_batch(id, &dig_parts).await {
-                            tracing::error!("Failed to save dig segments: {e}");
-                        }
-                    }
-                }
-            });
-            tx.send(id).unwrap();
+        let retry_at = (!error.is_permanent() && job.attempts < job.max_attempts)
+            .then(|| OffsetDateTime::now_utc() + retry_backoff(job.attempts));
+
+        let message = error.to_string();
+        let detail = error.detail();
+        tracing::error!(
+            job_id = %job.id,
+            activity_id = %job.activity_id,
+            attempt = job.attempts,
+            kind = ?error.kind(),
+            will_retry = retry_at.is_some(),
+            detail = detail.as_deref().unwrap_or_default(),
+            "Activity job failed: {message}"
+        );
+
+        let record = ProcessingErrorRecord {
+            kind: error.kind(),
+            message: &message,
+            detail: detail.as_deref(),
+        };
+        let Some(stage) = self
+            .db
+            .fail_activity_job(job.id, claim.claim_token, &record, retry_at)
+            .await?
+        else {
+            tracing::warn!(job_id = %job.id, "Job was reclaimed, discarding its error");
+            return Ok(());
+        };
+        publish_event(
+            &self.db,
+            ProcessingEvent::Failed {
+                activity_id: job.activity_id,
+                stage,
+                kind: record.kind,
+                error: message,
+                will_retry: retry_at.is_some(),
+            },
+        )
+        .await;
+        Ok(())
+    }
+
+    async fn run_job(&self, progress: &JobProgress<'_>) -> Result<(), ProcessingError> {
+        let job = progress.job;
+        // Read before the activity, so an edit in between rejects this run's efforts
+        let Some(match_generation) = self
+            .db
+            .get_activity_match_generation(job.activity_id)
+            .await
+            .map_err(ProcessingError::database("load the activity"))?
+        else {
+            tracing::info!(activity_id = %job.activity_id, "Activity deleted, skipping job");
+            return Ok(());
+        };
+        let activity = self
+            .db
+            .get_activity(job.activity_id)
+            .await
+            .map_err(ProcessingError::database("load the activity"))?;
+        let Some(activity) = activity else {
+            tracing::info!(activity_id = %job.activity_id, "Activity deleted, skipping job");
+            return Ok(());
+        };
+
+        progress.enter(ProcessingStage::Parsing).await?;
+
+        let bytes = self
+            .store
+            .get_file(&activity.object_store_path)
+            .await
+            .map_err(ProcessingError::FileUnavailable)?;
+        let file_type = FileType::detect_from_bytes(&bytes);
+        let cleaning = self.cleaning;
+        let elevation = self.elevation.clone();
+
+        // Parsing, cleaning and scoring are CPU bound, keep them off the async runtime.
+        // A panic on the rayon pool would abort the process, so catch it there.
+        let (tx, rx) = oneshot::channel();
+        self.pool.spawn(move || {
+            let result = std::panic::catch_unwind(|| {
+                parse_and_score(file_type, bytes, &cleaning, &elevation)
+            })
+            .unwrap_or(Err(ProcessingError::ParserPanicked));
+            let _ = tx.send(result);
         });
+        let mut rx = std::pin::pin!(rx);
+        let (parsed, scores, elevation_source) = loop {
+            tokio::select! {
+                result = &mut rx => break result.map_err(|_| ProcessingError::ParserPanicked)??,
+                _ = tokio::time::sleep(HEARTBEAT_INTERVAL) => progress.keep_alive().await,
+            }
+        };
+
+        process_activity(
+            &self.db,
+            progress,
+            &activity,
+            match_generation,
+            parsed,
+            scores,
+            elevation_source,
+        )
+        .await
+    }
+}
+
+/// Reports a running job's progress: persists the stage it is in, keeps
+/// non-fatal errors, publishes events to SSE subscribers, and keeps the job's
+/// claim fresh while a stage runs.
+struct JobProgress<'a> {
+    db: &'a Database,
+    job: &'a ActivityJob,
+    claim_token: Uuid,
+    last_heartbeat: Mutex<Instant>,
+}
+
+impl<'a> JobProgress<'a> {
+    fn new(db: &'a Database, claim: &'a ClaimedActivityJob) -> Self {
+        Self {
+            db,
+            job: &claim.job,
+            claim_token: claim.claim_token,
+            last_heartbeat: Mutex::new(Instant::now()),
+        }
+    }
+
+    async fn enter(&self, stage: ProcessingStage) -> Result<(), ProcessingError> {
+        // Entering a stage refreshes the claim too, and stops a job that
+        // another worker has taken over
+        let held = self
+            .db
+            .set_activity_job_stage(self.job.id, self.claim_token, stage)
+            .await
+            .map_err(ProcessingError::database("update processing progress"))?;
+        if !held {
+            return Err(ProcessingError::Reclaimed);
+        }
+        *self.last_heartbeat.lock().unwrap() = Instant::now();
+        self.publish(ProcessingEvent::Stage {
+            activity_id: self.job.activity_id,
+            stage,
+        })
+        .await;
         Ok(())
     }
+
+    /// Refresh the job's claim if [`HEARTBEAT_INTERVAL`] has passed since the
+    /// last refresh. Called between units of work within a stage, so a long
+    /// file or a track crossing many segments isn't reclaimed mid-way.
+    async fn keep_alive(&self) {
+        {
+            let mut last = self.last_heartbeat.lock().unwrap();
+            if last.elapsed() < HEARTBEAT_INTERVAL {
+                return;
+            }
+            *last = Instant::now();
+        }
+        match self
+            .db
+            .heartbeat_activity_job(self.job.id, self.claim_token)
+            .await
+        {
+            Ok(true) => {}
+            // The next stage change stops the job
+            Ok(false) => tracing::warn!(job_id = %self.job.id, "Job was reclaimed"),
+            Err(e) => tracing::warn!(job_id = %self.job.id, "Failed to refresh job claim: {e}"),
+        }
+    }
+
+    /// Keep an error that skipped part of a stage without failing the job.
+    async fn record_error(&self, stage: ProcessingStage, error: ProcessingError) {
+        let message = error.to_string();
+        let detail = error.detail();
+        tracing::error!(
+            job_id = %self.job.id,
+            ?stage,
+            detail = detail.as_deref().unwrap_or_default(),
+            "{message}"
+        );
+        let record = ProcessingErrorRecord {
+            kind: error.kind(),
+            message: &message,
+            detail: detail.as_deref(),
+        };
+        if let Err(e) = self
+            .db
+            .record_activity_processing_error(self.job, stage, &record)
+            .await
+        {
+            tracing::error!(job_id = %self.job.id, "Failed to record processing error: {e}");
+        }
+    }
+
+    async fn publish(&self, event: ProcessingEvent) {
+        publish_event(self.db, event).await;
+    }
+}
+
+/// Announce a processing event to SSE subscribers on every API instance.
+/// Progress events are best effort, so failing to send one is only logged.
+async fn publish_event(db: &Database, event: ProcessingEvent) {
+    if let Err(e) = db.publish_processing_event(&event).await {
+        tracing::warn!(activity_id = %event.activity_id(), "Failed to publish processing event: {e}");
+    }
+}
+
+/// Delay before the next attempt after `attempts` failed attempts.
+fn retry_backoff(attempts: i32) -> time::Duration {
+    let exponent = attempts.saturating_sub(1).clamp(0, 16) as u32;
+    let seconds = BACKOFF_BASE_SECONDS
+        .saturating_mul(1 << exponent)
+        .min(BACKOFF_MAX_SECONDS);
+    time::Duration::seconds(seconds)
+}
+
+/// Parse the file, clean its track and correct its elevation where terrain
+/// data covers it. Scores, the stored track and segment timing all use the
+/// resulting points.
+fn parse_and_score(
+    file_type: FileType,
+    bytes: Bytes,
+    cleaning: &TrackCleaning,
+    elevation: &ElevationService,
+) -> Result<(ParsedActivity, Scores, ElevationSource), ProcessingError> {
+    let mut parsed =
+        file_parsers::parse_activity_file(file_type, bytes).map_err(ProcessingError::Parse)?;
+    let report = cleaning.clean(&mut parsed);
+    if report.outliers + report.stationary > 0 {
+        tracing::debug!(
+            outliers = report.outliers,
+            stationary = report.stationary,
+            "Removed points while cleaning track"
+        );
+    }
+    let elevation_source = elevation.correct_track(&mut parsed.track_points);
+    let scores = scoring::score_activity(&parsed, cleaning.elevation_hysteresis_meters);
+    Ok((parsed, scores, elevation_source))
+}
+
+/// Run the processing pipeline for a parsed activity.
+///
+/// Each step overwrites or skips what an earlier attempt already wrote, so the
+/// whole pipeline can be re-run safely after a failure.
+async fn process_activity(
+    db: &Database,
+    progress: &JobProgress<'_>,
+    activity: &Activity,
+    match_generation: i32,
+    parsed: ParsedActivity,
+    scores: Scores,
+    elevation_source: ElevationSource,
+) -> Result<(), ProcessingError> {
+    let uid = activity.user_id;
+    let id = activity.id;
+    let ParsedActivity {
+        track_points,
+        sensor_data,
+        sport_segments: _,
+    } = parsed;
+
+    progress.enter(ProcessingStage::StoringGeometry).await?;
+
+    db.save_scores(uid, id, scores)
+        .await
+        .map_err(ProcessingError::database("save scores"))?;
+    db.set_activity_elevation_source(id, elevation_source)
+        .await
+        .map_err(ProcessingError::database("save the elevation source"))?;
+
+    // Nothing else to do for an activity without GPS points
+    if track_points.is_empty() {
+        return Ok(());
+    }
+
+    // Save track geometry with elevation and timestamps
+    db.save_track_geometry_with_data(uid, id, &track_points)
+        .await
+        .map_err(ProcessingError::database("save the track"))?;
+
+    // Save sensor data if present
+    if sensor_data.has_any_data() {
+        db.save_sensor_data(id, &sensor_data)
+            .await
+            .map_err(ProcessingError::database("save sensor data"))?;
+    }
+
+    // Detect and save stopped segments
+    let stopped_segments = detect_stopped_segments(&track_points);
+    if !stopped_segments.is_empty() {
+        tracing::info!(
+            "Detected {} stopped segments in activity {}",
+            stopped_segments.len(),
+            id
+        );
+    }
+    db.save_stopped_segments(id, &stopped_segments)
+        .await
+        .map_err(ProcessingError::database("save stops"))?;
+
+    // Extract dig segments from multi-sport activities with DIG activity type
+    if let (Some(boundaries), Some(types)) = (&activity.type_boundaries, &activity.segment_types) {
+        let dig_parts = extract_dig_parts_from_multi_sport(&track_points, boundaries, types);
+        if !dig_parts.is_empty() {
+            tracing::info!(
+                "Found {} dig segments in multi-sport activity {}",
+                dig_parts.len(),
+                id
+            );
+            db.save_dig_parts_batch(id, &dig_parts)
+                .await
+                .map_err(ProcessingError::database("save dig segments"))?;
+        }
+    }
+
+    progress.enter(ProcessingStage::MatchingSegments).await?;
+
+    // Find and create segment efforts, one per traversal (laps)
+    let matches = if let (Some(boundaries), Some(types)) =
+        (&activity.type_boundaries, &activity.segment_types)
+    {
+        // Multi-sport activity: find all geometric matches, then filter by type
+        let candidates = db
+            .find_matching_segments_any_type(id)
+            .await
+            .map_err(ProcessingError::database("look up matching segments"))?;
+        let all_matches = candidates
+            .into_iter()
+            .flat_map(|(candidate, type_id)| {
+                candidate
+                    .traversals(&track_points)
+                    .into_iter()
+                    .map(move |m| (m, type_id))
+            })
+            .collect();
+        filter_multi_sport_matches(all_matches, &track_points, boundaries, types)
+    } else {
+        // Single-sport activity: filter by activity_type_id directly
+        db.find_matching_segments(id, activity.activity_type_id)
+            .await
+            .map_err(ProcessingError::database("look up matching segments"))?
+            .iter()
+            .flat_map(|c| c.traversals(&track_points))
+            .collect()
+    };
+
+    for segment_match in matches {
+        progress.keep_alive().await;
+        let segment_id = segment_match.segment_id;
+        match create_effort_for_match(db, &track_points, uid, id, match_generation, segment_match)
+            .await
+        {
+            Ok(Some(effort)) => {
+                progress
+                    .publish(ProcessingEvent::SegmentMatched {
+                        activity_id: id,
+                        segment_id,
+                        effort_id: effort.id,
+                        elapsed_time_seconds: effort.elapsed_time_seconds,
+                    })
+                    .await
+            }
+            Ok(None) => {}
+            // The other segments can still get their efforts
+            Err(e @ ProcessingError::SegmentEffort { .. }) => {
+                progress
+                    .record_error(ProcessingStage::MatchingSegments, e)
+                    .await
+            }
+            // Without the segment to check against, the job is retried
+            Err(e) => return Err(e),
+        }
+    }
+
+    progress.enter(ProcessingStage::Achievements).await?;
+
+    // Covers efforts a failed earlier attempt created but never scored; PR and
+    // crown updates are no-ops for efforts that were already counted
+    let efforts = db
+        .get_activity_segment_efforts(id)
+        .await
+        .map_err(ProcessingError::database("load segment efforts"))?;
+    for effort in efforts {
+        progress.keep_alive().await;
+        match update_effort_achievements(
+            db,
+            effort.segment_id,
+            uid,
+            effort.effort_id,
+            effort.elapsed_time_seconds,
+            progress.job.notify,
+        )
+        .await
+        {
+            Ok(true) if !effort.is_personal_record => {
+                progress
+                    .publish(ProcessingEvent::PersonalRecord {
+                        activity_id: id,
+                        segment_id: effort.segment_id,
+                        effort_id: effort.effort_id,
+                        elapsed_time_seconds: effort.elapsed_time_seconds,
+                    })
+                    .await;
+                // Imported activities set PRs silently
+                if progress.job.notify
+                    && let Err(e) = achievements_service::notify_personal_record(
+                        db,
+                        effort.segment_id,
+                        uid,
+                        effort.effort_id,
+                        effort.elapsed_time_seconds,
+                    )
+                    .await
+                {
+                    tracing::warn!("Failed to notify {uid} of PR: {e}");
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                progress
+                    .record_error(
+                        ProcessingStage::Achievements,
+                        ProcessingError::Achievements {
+                            segment_id: effort.segment_id,
+                            source: e,
+                        },
+                    )
+                    .await
+            }
+        }
+    }
+
+    Ok(())
 }
 
 /// Detected stopped segment with timestamps
@@ -242,7 +792,6 @@ pub struct DetectedStoppedSegment {
 /// - Speed < 1 m/s (essentially not moving)
 /// - Duration > 30 seconds
 fn detect_stopped_segments(points: &[TrackPointData]) -> Vec<DetectedStoppedSegment> {
-    const SPEED_THRESHOLD_MPS: f64 = 1.0; // 1 m/s = 3.6 km/h
     const MIN_STOPPED_DURATION_SECS: f64 = 30.0;
 
     let mut segments = Vec::new();
@@ -287,7 +836,7 @@ fn detect_stopped_segments(points: &[TrackPointData]) -> Vec<DetectedStoppedSegm
 
         let speed = distance / time_diff;
 
-        if speed < SPEED_THRESHOLD_MPS {
+        if speed < STOPPED_SPEED_THRESHOLD_MPS {
             // Moving slowly or stopped
             if stop_start.is_none() {
                 stop_start = Some((i - 1, *prev_time));
@@ -384,49 +933,112 @@ fn haversine_distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
     EARTH_RADIUS_METERS * c
 }
 
-/// Process a single segment match: extract timing and create effort
-async fn process_segment_match(
+/// Process a single segment traversal outside the activity pipeline (segment
+/// backfills): create the effort, then update PRs and crowns. Returns true if a
+/// new effort was created; failing to update PRs and crowns is only logged.
+/// PRs on a newly created segment are found in old activities and aren't
+/// announced.
+pub(crate) async fn process_segment_match(
+    db: &Database,
+    track_points: &[TrackPointData],
+    user_id: Uuid,
+    activity_id: Uuid,
+    match_generation: i32,
+    segment_match: SegmentMatch,
+) -> Result<bool, AppError> {
+    let Some(effort) = create_effort_for_match(
+        db,
+        track_points,
+        user_id,
+        activity_id,
+        match_generation,
+        segment_match,
+    )
+    .await
+    .map_err(ProcessingError::into_source)?
+    else {
+        return Ok(false);
+    };
+
+    if let Err(e) = update_effort_achievements(
+        db,
+        effort.segment_id,
+        user_id,
+        effort.id,
+        effort.elapsed_time_seconds,
+        true,
+    )
+    .await
+    {
+        tracing::error!("Failed to update personal records: {e}");
+    }
+    Ok(true)
+}
+
+/// Check path conformance, extract timing and create the effort for a segment
+/// traversal. Returns None when the traversal already has an effort, doesn't
+/// qualify for one, or the activity was edited after `match_generation` was read.
+async fn create_effort_for_match(
     db: &Database,
-    gpx: &gpx::Gpx,
+    track_points: &[TrackPointData],
     user_id: Uuid,
     activity_id: Uuid,
+    match_generation: i32,
     segment_match: SegmentMatch,
-) {
-    // Check if effort already exists (idempotency)
-    match db
-        .segment_effort_exists(segment_match.segment_id, activity_id)
+) -> Result<Option<SegmentEffort>, ProcessingError> {
+    let segment_id = segment_match.segment_id;
+    let effort_error = |source| ProcessingError::SegmentEffort { segment_id, source };
+
+    // Check if an effort already exists for this traversal (idempotency)
+    if db
+        .segment_effort_exists(
+            segment_match.segment_id,
+            activity_id,
+            segment_match.start_fraction,
+            segment_match.end_fraction,
+        )
         .await
+        .map_err(effort_error)?
     {
-        Ok(true) => {
-            tracing::debug!(
-                "Effort already exists for segment {} and activity {}",
-                segment_match.segment_id,
-                activity_id
-            );
-            return;
-        }
-        Ok(false) => {}
-        Err(e) => {
-            tracing::error!("Failed to check effort existence: {e}");
-            return;
-        }
+        tracing::debug!(
+            "Effort already exists for segment {} and activity {} at {:.3}-{:.3}",
+            segment_match.segment_id,
+            activity_id,
+            segment_match.start_fraction,
+            segment_match.end_fraction
+        );
+        return Ok(None);
     }
 
-    // Extract timing from GPX
-    let timing = match segment_matching::extract_timing_from_gpx(
-        gpx,
+    // Reject endpoint matches where the track doesn't actually follow the segment
+    if !segment_matching::verify_match_conformance(
+        db,
+        segment_match.segment_id,
+        activity_id,
+        user_id,
+        track_points,
         segment_match.start_fraction,
         segment_match.end_fraction,
-    ) {
-        Some(t) => t,
-        None => {
-            tracing::warn!(
-                "Could not extract timing for segment {} on activity {}",
-                segment_match.segment_id,
-                activity_id
-            );
-            return;
-        }
+    )
+    .await
+    .map_err(ProcessingError::database(
+        "load a segment to check the path against",
+    ))? {
+        return Ok(None);
+    }
+
+    // Extract timing from the parsed track points (works for every file format)
+    let Some(timing) = segment_matching::extract_timing(
+        track_points,
+        segment_match.start_fraction,
+        segment_match.end_fraction,
+    ) else {
+        tracing::warn!(
+            "Could not extract timing for segment {} on activity {}",
+            segment_match.segment_id,
+            activity_id
+        );
+        return Ok(None);
     };
 
     // Calculate average speed: distance / time
@@ -436,8 +1048,7 @@ async fn process_segment_match(
         None
     };
 
-    // Create the effort
-    match db
+    let Some(effort) = db
         .create_segment_effort(
             segment_match.segment_id,
             activity_id,
@@ -449,53 +1060,67 @@ async fn process_segment_match(
             None, // max_speed_mps
             Some(segment_match.start_fraction),
             Some(segment_match.end_fraction),
+            Some(match_generation),
         )
         .await
+        .map_err(effort_error)?
+    else {
+        tracing::debug!(
+            "Activity {activity_id} was edited while matching, skipping segment {}",
+            segment_match.segment_id
+        );
+        return Ok(None);
+    };
+
+    tracing::info!(
+        "Created segment effort {} for segment {} with time {:.1}s (moving: {:.1}s)",
+        effort.id,
+        segment_match.segment_id,
+        timing.elapsed_time_seconds,
+        timing.moving_time_seconds
+    );
+
+    // Update effort count
+    if let Err(e) = db
+        .increment_segment_effort_count(segment_match.segment_id)
+        .await
     {
-        Ok(effort) => {
-            tracing::info!(
-                "Created segment effort {} for segment {} with time {:.1}s (moving: {:.1}s)",
-                effort.id,
-                segment_match.segment_id,
-                timing.elapsed_time_seconds,
-                timing.moving_time_seconds
-            );
-            // Update effort count
-            if let Err(e) = db
-                .increment_segment_effort_count(segment_match.segment_id)
-                .await
-            {
-                tracing::error!("Failed to increment effort count: {e}");
-            }
-            // Update personal records
-            if let Err(e) = db
-                .update_personal_records(segment_match.segment_id, user_id)
-                .await
-            {
-                tracing::error!("Failed to update personal records: {e}");
-            }
-            // Check and award achievements (KOM/QOM)
-            if let Err(e) = achievements_service::process_achievements(
-                db,
-                segment_match.segment_id,
-                user_id,
-                effort.id,
-                timing.elapsed_time_seconds,
-            )
-            .await
-            {
-                tracing::error!("Failed to process achievements: {e}");
-            }
-        }
-        Err(e) => {
-            tracing::error!("Failed to create segment effort: {e}");
-        }
+        tracing::error!("Failed to increment effort count: {e}");
     }
+
+    Ok(Some(effort))
+}
+
+/// Update the user's personal record and KOM/QOM holders on a segment after an
+/// effort, notifying crown changes with `notify`. Returns true if the effort
+/// is now the user's PR.
+async fn update_effort_achievements(
+    db: &Database,
+    segment_id: Uuid,
+    user_id: Uuid,
+    effort_id: Uuid,
+    elapsed_time_seconds: f64,
+    notify: bool,
+) -> Result<bool, AppError> {
+    let pr_effort = db.update_personal_records(segment_id, user_id).await?;
+
+    // Check and award achievements (KOM/QOM)
+    achievements_service::process_achievements(
+        db,
+        segment_id,
+        user_id,
+        effort_id,
+        elapsed_time_seconds,
+        notify,
+    )
+    .await?;
+
+    Ok(pr_effort == Some(effort_id))
 }
 
 /// For multi-sport activities, filter segment matches to only include those where
 /// the segment's activity type matches the activity type at that position on the track.
-fn filter_multi_sport_matches(
+pub(crate) fn filter_multi_sport_matches(
     all_matches: Vec<(SegmentMatch, Uuid)>,
     track_points: &[TrackPointData],
     type_boundaries: &[OffsetDateTime],
@@ -1010,4 +1635,35 @@ mod tests {
         let result = filter_multi_sport_matches(matches, &points, &boundaries, &segment_types);
         assert_eq!(result.len(), 1);
     }
+
+    #[test]
+    fn test_retry_backoff_doubles_and_caps() {
+        assert_eq!(retry_backoff(1), Duration::seconds(30));
+        assert_eq!(retry_backoff(2), Duration::seconds(60));
+        assert_eq!(retry_backoff(3), Duration::seconds(120));
+        assert_eq!(retry_backoff(10), Duration::hours(1));
+        assert_eq!(retry_backoff(i32::MAX), Duration::hours(1));
+    }
+
+    #[test]
+    fn test_parse_errors_are_permanent_and_user_facing() {
+        let error = ProcessingError::Parse(ParseError::GpxError("missing trk".into()));
+        assert!(error.is_permanent());
+        assert_eq!(error.kind(), ProcessingErrorKind::ParseFailed);
+        assert_eq!(
+            error.to_string(),
+            "Your file could not be parsed: Failed to parse GPX file: missing trk"
+        );
+        assert_eq!(error.detail(), None);
+    }
+
+    #[test]
+    fn test_database_errors_are_retried_with_detail() {
+        let error =
+            ProcessingError::database("save scores")(AppError::InvalidInput("bad score".into()));
+        assert!(!error.is_permanent());
+        assert_eq!(error.kind(), ProcessingErrorKind::Database);
+        assert_eq!(error.to_string(), "Could not save scores");
+        assert_eq!(error.detail().as_deref(), Some("Invalid input: bad score"));
+    }
 }
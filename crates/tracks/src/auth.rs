@@ -261,8 +261,8 @@ pub async fn register(
     req.validate().map_err(|e| {
         let messages: Vec<String> = e
             .field_errors()
-            .into_iter()
-            .flat_map(|(_, errors)| {
+            .values()
+            .flat_map(|errors| {
                 errors
                     .iter()
                     .filter_map(|e| e.message.as_ref().map(|m| m.to_string()))
@@ -312,8 +312,8 @@ pub async fn login(
     req.validate().map_err(|e| {
         let messages: Vec<String> = e
             .field_errors()
-            .into_iter()
-            .flat_map(|(_, errors)| {
+            .values()
+            .flat_map(|errors| {
                 errors
                     .iter()
                     .filter_map(|e| e.message.as_ref().map(|m| m.to_string()))
@@ -0,0 +1,94 @@
+//! Import a bulk export archive (e.g. a Strava export zip) for a user.
+//!
+//! Run with:
+//! ```
+//! cargo run -p tracks --bin import_archive -- <user-id> <export.zip> [--private]
+//! ```
+//!
+//! Uses the same `DATABASE_URL` and object store settings as the server.
+//! Imported activities are queued in the database and processed by a running
+//! server's workers. Useful for archives larger than the HTTP upload limit.
+
+use std::{env, fs::File, io::BufReader, path::Path, process::ExitCode};
+
+use sqlx::postgres::PgPoolOptions;
+use tracing_subscriber::EnvFilter;
+use tracks::{
+    activity_import::ActivityImporter,
+    activity_queue::ActivityQueue,
+    database::Database,
+    models::{ImportItemStatus, JobStatus},
+    object_store_service::ObjectStoreService,
+};
+use uuid::Uuid;
+
+const USAGE: &str = "usage: import_archive <user-id> <export.zip> [--private]";
+
+#[tokio::main]
+async fn main() -> anyhow::Result<ExitCode> {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .init();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let visibility = if args.iter().any(|a| a == "--private") {
+        "private"
+    } else {
+        "public"
+    };
+    let positional: Vec<&String> = args.iter().filter(|a| !a.starts_with("--")).collect();
+    let [user_id, archive_path] = positional[..] else {
+        eprintln!("{USAGE}");
+        return Ok(ExitCode::FAILURE);
+    };
+    let Ok(user_id) = user_id.parse::<Uuid>() else {
+        eprintln!("Invalid user id: {user_id}\n{USAGE}");
+        return Ok(ExitCode::FAILURE);
+    };
+
+    let database_url =
+        env::var("DATABASE_URL").unwrap_or_else(|_| "postgres://docker:pg@0.0.0.0".to_string());
+    let pool = PgPoolOptions::new()
+        .max_connections(2)
+        .connect(&database_url)
+        .await?;
+    let db = Database::new(pool);
+    let store = ObjectStoreService::from_env();
+    let aq = ActivityQueue::new(db.clone(), store.clone())?;
+
+    let source_name = Path::new(archive_path)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| archive_path.clone());
+    let archive = BufReader::new(File::open(archive_path)?);
+
+    let import = db.create_activity_import(user_id, &source_name).await?;
+    println!("Importing {source_name} (import {})", import.id);
+
+    ActivityImporter::new(db.clone(), store, aq)
+        .run(&import, archive, visibility)
+        .await;
+
+    let Some(import) = db.get_activity_import(import.id).await? else {
+        anyhow::bail!("Import {} disappeared", import.id);
+    };
+    for item in db.get_activity_import_items(import.id).await? {
+        if item.status == ImportItemStatus::Failed {
+            println!(
+                "  failed: {}: {}",
+                item.file_name,
+                item.message.unwrap_or_default()
+            );
+        }
+    }
+    println!(
+        "{} imported, {} duplicates, {} failed",
+        import.imported, import.duplicates, import.failed
+    );
+
+    if import.status == JobStatus::Failed {
+        eprintln!("Import stopped: {}", import.last_error.unwrap_or_default());
+        return Ok(ExitCode::FAILURE);
+    }
+    Ok(ExitCode::SUCCESS)
+}
@@ -0,0 +1,238 @@
+//! Transparent decompression of uploaded activity files.
+//!
+//! Device exports are often gzipped (`ride.fit.gz`) and single files are
+//! sometimes uploaded zipped. The original bytes are stored as uploaded;
+//! `decompress` unwraps them for type detection and parsing. Output is capped
+//! at `MAX_DECOMPRESSED_BYTES` so a small upload can't expand into gigabytes.
+
+use std::io::{Cursor, Read};
+
+use bytes::Bytes;
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+
+/// Largest activity file accepted once decompressed.
+pub const MAX_DECOMPRESSED_BYTES: u64 = 64 * 1024 * 1024;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+/// Compression wrapping an activity file, detected from its leading bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zip,
+}
+
+impl Compression {
+    pub fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if bytes.starts_with(ZIP_MAGIC) {
+            Compression::Zip
+        } else {
+            Compression::None
+        }
+    }
+
+    /// MIME type of the compressed container, if any.
+    pub fn as_mime_str(self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("application/gzip"),
+            Compression::Zip => Some("application/zip"),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DecompressError {
+    #[error("file is larger than {} MB once decompressed", MAX_DECOMPRESSED_BYTES / 1024 / 1024)]
+    TooLarge,
+    #[error("invalid gzip data: {0}")]
+    Gzip(std::io::Error),
+    #[error("invalid zip archive: {0}")]
+    Zip(String),
+    #[error("zip archive contains no activity file")]
+    NoActivityFile,
+    #[error("zip archive contains more than one activity file")]
+    MultipleActivityFiles,
+}
+
+/// Return the activity file inside a gzip or zip wrapper, or the bytes
+/// unchanged when they aren't compressed. A zip must hold a single activity
+/// file, which may itself be gzipped.
+pub fn decompress(bytes: Bytes) -> Result<Bytes, DecompressError> {
+    decompress_with_limit(bytes, MAX_DECOMPRESSED_BYTES)
+}
+
+fn decompress_with_limit(bytes: Bytes, limit: u64) -> Result<Bytes, DecompressError> {
+    match Compression::detect(&bytes) {
+        Compression::None => Ok(bytes),
+        Compression::Gzip => gunzip(&bytes, limit).map(Bytes::from),
+        Compression::Zip => {
+            let inner = unzip_single(bytes, limit)?;
+            if Compression::detect(&inner) == Compression::Gzip {
+                gunzip(&inner, limit).map(Bytes::from)
+            } else {
+                Ok(Bytes::from(inner))
+            }
+        }
+    }
+}
+
+fn gunzip(bytes: &[u8], limit: u64) -> Result<Vec<u8>, DecompressError> {
+    read_limited(GzDecoder::new(bytes), limit).map_err(|e| match e {
+        ReadError::TooLarge => DecompressError::TooLarge,
+        ReadError::Io(e) => DecompressError::Gzip(e),
+    })
+}
+
+fn unzip_single(bytes: Bytes, limit: u64) -> Result<Vec<u8>, DecompressError> {
+    let mut archive =
+        ZipArchive::new(Cursor::new(bytes)).map_err(|e| DecompressError::Zip(e.to_string()))?;
+
+    // Skip directories and the metadata macOS adds when zipping
+    let files: Vec<String> = archive
+        .file_names()
+        .filter(|name| !name.ends_with('/') && !name.starts_with("__MACOSX/"))
+        .map(str::to_string)
+        .collect();
+    let activity_files: Vec<&String> = files.iter().filter(|name| is_activity_file(name)).collect();
+
+    let name = match (activity_files.as_slice(), files.as_slice()) {
+        ([name], _) => *name,
+        ([], [name]) => name,
+        ([], _) => return Err(DecompressError::NoActivityFile),
+        _ => return Err(DecompressError::MultipleActivityFiles),
+    };
+
+    let entry = archive
+        .by_name(name)
+        .map_err(|e| DecompressError::Zip(e.to_string()))?;
+    read_limited(entry, limit).map_err(|e| match e {
+        ReadError::TooLarge => DecompressError::TooLarge,
+        ReadError::Io(e) => DecompressError::Zip(e.to_string()),
+    })
+}
+
+/// Whether a file name looks like a FIT, GPX or TCX file, optionally gzipped.
+pub fn is_activity_file(name: &str) -> bool {
+    let name = name.to_lowercase();
+    let name = name.strip_suffix(".gz").unwrap_or(&name);
+    [".fit", ".gpx", ".tcx"]
+        .iter()
+        .any(|ext| name.ends_with(ext))
+}
+
+enum ReadError {
+    TooLarge,
+    Io(std::io::Error),
+}
+
+/// Read at most `limit` bytes, failing rather than truncating.
+fn read_limited(reader: impl Read, limit: u64) -> Result<Vec<u8>, ReadError> {
+    let mut bytes = Vec::new();
+    reader
+        .take(limit + 1)
+        .read_to_end(&mut bytes)
+        .map_err(ReadError::Io)?;
+    if bytes.len() as u64 > limit {
+        return Err(ReadError::TooLarge);
+    }
+    Ok(bytes)
+}
+
+/// Builders for compressed test fixtures, shared with the import tests.
+#[cfg(test)]
+pub(crate) mod test_fixtures {
+    use std::io::{Cursor, Write};
+
+    use zip::{ZipWriter, write::SimpleFileOptions};
+
+    pub(crate) fn gzip(content: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(content).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    pub(crate) fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in files {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(content).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_fixtures::{gzip, zip};
+    use super::*;
+
+    const GPX: &[u8] = b"<?xml version=\"1.0\"?><gpx version=\"1.1\"></gpx>";
+
+    #[test]
+    fn test_decompress_passes_raw_files_through() {
+        assert_eq!(Compression::detect(GPX), Compression::None);
+        assert_eq!(decompress(Bytes::from_static(GPX)).unwrap(), GPX);
+    }
+
+    #[test]
+    fn test_decompress_gzip() {
+        let gz = gzip(GPX);
+        assert_eq!(Compression::detect(&gz), Compression::Gzip);
+        assert_eq!(decompress(Bytes::from(gz)).unwrap(), GPX);
+    }
+
+    #[test]
+    fn test_decompress_zip() {
+        let zipped = zip(&[("__MACOSX/._ride.gpx", b"junk"), ("ride.gpx", GPX)]);
+        assert_eq!(Compression::detect(&zipped), Compression::Zip);
+        assert_eq!(decompress(Bytes::from(zipped)).unwrap(), GPX);
+
+        // A lone file is used whatever it is named
+        let zipped = zip(&[("export", GPX)]);
+        assert_eq!(decompress(Bytes::from(zipped)).unwrap(), GPX);
+
+        // Gzipped files inside the zip are unwrapped too
+        let zipped = zip(&[("ride.gpx.gz", &gzip(GPX))]);
+        assert_eq!(decompress(Bytes::from(zipped)).unwrap(), GPX);
+    }
+
+    #[test]
+    fn test_decompress_zip_needs_one_activity_file() {
+        let zipped = zip(&[("a.gpx", GPX), ("b.gpx", GPX)]);
+        assert!(matches!(
+            decompress(Bytes::from(zipped)),
+            Err(DecompressError::MultipleActivityFiles)
+        ));
+
+        let zipped = zip(&[("notes.txt", b"hi"), ("readme.md", b"hi")]);
+        assert!(matches!(
+            decompress(Bytes::from(zipped)),
+            Err(DecompressError::NoActivityFile)
+        ));
+    }
+
+    #[test]
+    fn test_decompress_refuses_bombs() {
+        let zeros = vec![0u8; 4096];
+        let gz = Bytes::from(gzip(&zeros));
+        assert!(matches!(
+            decompress_with_limit(gz.clone(), 4095),
+            Err(DecompressError::TooLarge)
+        ));
+        assert_eq!(decompress_with_limit(gz, 4096).unwrap().len(), 4096);
+
+        let zipped = Bytes::from(zip(&[("ride.fit", &zeros)]));
+        assert!(matches!(
+            decompress_with_limit(zipped, 1024),
+            Err(DecompressError::TooLarge)
+        ));
+    }
+}
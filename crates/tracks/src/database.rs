@@ -1,19 +1,76 @@
 use crate::errors::AppError;
+use crate::leaderboard_cache::{CacheBucket, EffortPlacement};
 use crate::models::{
-    Achievement, AchievementHolder, AchievementType, AchievementWithSegment, Activity,
-    ActivityAliasRow, ActivitySegmentEffort, ActivityTypeRow, ActivityWithStats, AgeGroup,
-    CountryStats, CrownCountEntry, DateRangeFilter, DistanceLeaderEntry, GenderFilter,
-    LeaderboardEntry, LeaderboardFilters, LeaderboardScope, ResolvedActivityType, Scores, Segment,
-    SegmentEffort, Team, TeamInvitation, TeamInvitationWithDetails, TeamJoinRequest,
-    TeamJoinRequestWithUser, TeamMember, TeamMembership, TeamRole, TeamSummary, TeamVisibility,
-    TeamWithMembership, UpdateDemographicsRequest, User, UserWithDemographics, WeightClass,
+    AchievementCounts, AchievementHolder, AchievementType, AchievementWithSegment, Activity,
+    ActivityAliasRow, ActivityImport, ActivityImportItem, ActivityJob, ActivityProcessingError,
+    ActivitySegmentEffort, ActivityTypeRow, ActivityWithStats, AgeGroup, CountryStats, CrownChange,
+    CrownCountEntry, DateRangeFilter, DistanceLeaderEntry, ElevationSource, Gender, GenderFilter,
+    ImportItemStatus, JobStatus, LeaderboardEntry, LeaderboardFilters, LeaderboardScope,
+    ProcessingErrorKind, ProcessingEvent, ProcessingStage, ResolvedActivityType, Scores, Segment,
+    SegmentBackfillJob, SegmentEffort, SegmentGeometry, SegmentMatchRejection, SegmentVersion,
+    Team, TeamInvitation, TeamInvitationWithDetails, TeamJoinRequest, TeamJoinRequestWithUser,
+    TeamMember, TeamMembership, TeamRole, TeamSummary, TeamVisibility, TeamWithMembership,
+    UpdateDemographicsRequest, User, UserWithDemographics, Visibility, WeightClass,
 };
 use crate::query_builder::QueryBuilder;
-use crate::segment_matching::{ActivityMatch, SegmentMatch};
+use crate::segment_edit_service::SegmentRebuild;
+use crate::segment_matching::{
+    ActivityMatch, DEFAULT_MATCH_TOLERANCE_METERS, MatchRejection, SegmentCandidate,
+};
 use serde::Serialize;
-use sqlx::PgPool;
+use sqlx::{FromRow, PgPool};
 use uuid::Uuid;
 
+/// A processing error as written to activity_processing_errors.
+pub struct ProcessingErrorRecord<'a> {
+    pub kind: ProcessingErrorKind,
+    pub message: &'a str,
+    pub detail: Option<&'a str>,
+}
+
+/// Changes to an activity. `None` leaves a field as it is.
+#[derive(Debug, Default)]
+pub struct ActivityUpdate<'a> {
+    pub name: Option<&'a str>,
+    pub activity_type_id: Option<Uuid>,
+    pub visibility: Option<&'a str>,
+    /// New multi-sport boundaries and per-part types; `Some(None)` makes the
+    /// activity single-sport again.
+    pub multi_sport: Option<Option<(&'a [time::OffsetDateTime], &'a [Uuid])>>,
+}
+
+/// An updated activity. When the update changed which segment types the
+/// activity is matched against, its efforts were removed and `needs_rematch`
+/// is set: the activity has to go through the processing pipeline again.
+#[derive(Debug)]
+pub struct UpdatedActivity {
+    pub activity: Activity,
+    pub needs_rematch: bool,
+    /// Segments the activity had efforts on before the update
+    pub invalidated_segments: Vec<Uuid>,
+    /// Segments whose leaderboards and crowns the activity's efforts joined or
+    /// left because its visibility changed
+    pub reranked_segments: Vec<Uuid>,
+}
+
+/// A backfill claimed by a worker. The token identifies this claim: once the
+/// job is requeued and claimed again, updates made with it are ignored.
+#[derive(Debug, sqlx::FromRow)]
+pub struct ClaimedSegmentBackfill {
+    #[sqlx(flatten)]
+    pub job: SegmentBackfillJob,
+    pub claim_token: Uuid,
+}
+
+/// An activity job claimed by a worker. The token identifies this claim: once
+/// the job is requeued and claimed again, updates made with it are ignored.
+#[derive(Debug, sqlx::FromRow)]
+pub struct ClaimedActivityJob {
+    #[sqlx(flatten)]
+    pub job: ActivityJob,
+    pub claim_token: Uuid,
+}
+
 /// A segment that is similar to a proposed new segment.
 /// Used for duplicate detection when creating segments.
 #[derive(Debug, Clone, Serialize, sqlx::FromRow)]
@@ -23,6 +80,24 @@ pub struct SimilarSegment {
     pub distance_meters: f64,
 }
 
+/// A user's best leaderboard entry, the entries above and below it, and the
+/// number of entries on the leaderboard.
+pub type LeaderboardPositionEntries = (
+    LeaderboardEntry,
+    Vec<LeaderboardEntry>,
+    Vec<LeaderboardEntry>,
+    i64,
+);
+
+/// ID and key columns of a `leaderboard_cache` row.
+type CachedLeaderboardRow = (Uuid, String, Option<Gender>, Option<String>);
+
+/// A row of `leaderboard_cache`.
+struct CachedLeaderboard {
+    id: Uuid,
+    bucket: CacheBucket,
+}
+
 #[derive(Clone)]
 pub struct Database {
     pool: PgPool,
@@ -33,13 +108,20 @@ impl Database {
         Self { pool }
     }
 
-    pub async fn save_activity(&self, activity: &Activity) -> Result<(), AppError> {
-        sqlx::query(
+    /// Insert an activity, remembering the hash of its file for duplicate
+    /// detection. Fails with [`AppError::DuplicateActivity`] if the user
+    /// already has an activity with the same file.
+    pub async fn save_activity(
+        &self,
+        activity: &Activity,
+        file_hash: Option<&str>,
+    ) -> Result<(), AppError> {
+        let result = sqlx::query(
             r#"
             INSERT INTO activities (id, user_id, activity_type_id, name,
                                     object_store_path, started_at, submitted_at, visibility,
-                                    type_boundaries, segment_types)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+                                    type_boundaries, segment_types, file_hash)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
             "#,
         )
         .bind(activity.id)
@@ -52,10 +134,25 @@ impl Database {
         .bind(&activity.visibility)
         .bind(&activity.type_boundaries)
         .bind(&activity.segment_types)
+        .bind(file_hash)
         .execute(&self.pool)
-        .await?;
-
-        Ok(())
+        .await;
+
+        match result {
+            Ok(_) => Ok(()),
+            // The same file was saved by a concurrent upload since the
+            // duplicate check
+            Err(sqlx::Error::Database(e))
+                if e.constraint() == Some("idx_activities_user_file_hash") =>
+            {
+                let existing = self
+                    .find_activity_by_file_hash(activity.user_id, file_hash.unwrap_or_default())
+                    .await?
+                    .ok_or(AppError::Internal)?;
+                Err(AppError::DuplicateActivity(existing))
+            }
+            Err(e) => Err(e.into()),
+        }
     }
 
     // ========================================================================
@@ -66,7 +163,7 @@ impl Database {
     pub async fn list_activity_types(&self) -> Result<Vec<ActivityTypeRow>, AppError> {
         let types: Vec<ActivityTypeRow> = sqlx::query_as(
             r#"
-            SELECT id, name, is_builtin, created_by, created_at
+            SELECT id, name, is_builtin, created_by, created_at, match_tolerance_meters
             FROM activity_types
             ORDER BY is_builtin DESC, name ASC
             "#,
@@ -81,7 +178,7 @@ impl Database {
     pub async fn get_activity_type(&self, id: Uuid) -> Result<Option<ActivityTypeRow>, AppError> {
         let activity_type: Option<ActivityTypeRow> = sqlx::query_as(
             r#"
-            SELECT id, name, is_builtin, created_by, created_at
+            SELECT id, name, is_builtin, created_by, created_at, match_tolerance_meters
             FROM activity_types
             WHERE id = $1
             "#,
@@ -131,7 +228,7 @@ impl Database {
     pub async fn get_types_for_alias(&self, alias: &str) -> Result<Vec<ActivityTypeRow>, AppError> {
         let types: Vec<ActivityTypeRow> = sqlx::query_as(
             r#"
-            SELECT t.id, t.name, t.is_builtin, t.created_by, t.created_at
+            SELECT t.id, t.name, t.is_builtin, t.created_by, t.created_at, t.match_tolerance_meters
             FROM activity_types t
             JOIN activity_aliases a ON a.activity_type_id = t.id
             WHERE a.alias = $1
@@ -150,16 +247,18 @@ impl Database {
         &self,
         name: &str,
         created_by: Uuid,
+        match_tolerance_meters: f64,
     ) -> Result<ActivityTypeRow, AppError> {
         let activity_type: ActivityTypeRow = sqlx::query_as(
             r#"
-            INSERT INTO activity_types (name, is_builtin, created_by, created_at)
-            VALUES ($1, false, $2, NOW())
-            RETURNING id, name, is_builtin, created_by, created_at
+            INSERT INTO activity_types (name, is_builtin, created_by, created_at, match_tolerance_meters)
+            VALUES ($1, false, $2, NOW(), $3)
+            RETURNING id, name, is_builtin, created_by, created_at, match_tolerance_meters
             "#,
         )
         .bind(name)
         .bind(created_by)
+        .bind(match_tolerance_meters)
         .fetch_one(&self.pool)
         .await?;
 
@@ -215,9 +314,22 @@ impl Database {
             r#"
             SELECT a.id, a.user_id, a.activity_type_id, a.name, a.object_store_path,
                    a.started_at, a.submitted_at, a.visibility, a.type_boundaries, a.segment_types,
-                   s.distance, s.duration, s.elevation_gain
+                   a.elevation_source,
+                   s.distance, s.duration, s.elevation_gain,
+                   s.moving_time, s.max_speed, s.avg_speed,
+                   s.elevation_loss, s.max_elevation, s.min_elevation,
+                   s.avg_heart_rate, s.max_heart_rate, s.avg_cadence, s.max_cadence,
+                   s.avg_power, s.max_power,
+                   j.status AS processing_status,
+                   CASE WHEN j.status = 'failed' THEN j.last_error END AS processing_error
             FROM activities a
             LEFT JOIN scores s ON s.activity_id = a.id
+            LEFT JOIN LATERAL (
+                SELECT status, last_error FROM activity_jobs
+                WHERE activity_id = a.id
+                ORDER BY created_at DESC
+                LIMIT 1
+            ) j ON TRUE
             WHERE a.id = $1 AND a.deleted_at IS NULL
             "#,
         )
@@ -245,10 +357,12 @@ impl Database {
         Ok(activities)
     }
 
-    /// Get user activities with filtering, sorting, and pagination.
+    /// Get user activities with filtering, sorting, and pagination. Viewers
+    /// other than the user only get the activities visible to them.
     pub async fn get_user_activities_filtered(
         &self,
         user_id: Uuid,
+        viewer: Option<Uuid>,
         params: &crate::handlers::UserActivitiesQuery,
     ) -> Result<Vec<Activity>, AppError> {
         use crate::models::{DateRangeFilter, VisibilityFilter};
@@ -262,6 +376,15 @@ impl Database {
         qb.add_param_condition("a.user_id = ");
         qb.add_condition("a.deleted_at IS NULL");
 
+        // Visibility for anyone but the owner
+        let other_viewer = viewer.filter(|&viewer| viewer != user_id);
+        if other_viewer.is_some() {
+            let viewer_idx = qb.next_param_idx();
+            qb.add_condition(activity_visible_to(viewer_idx));
+        } else if viewer.is_none() {
+            qb.add_condition("a.visibility = 'public'");
+        }
+
         // Activity type filter
         if params.activity_type_id.is_some() {
             qb.add_param_condition("a.activity_type_id = ");
@@ -276,13 +399,16 @@ impl Database {
             &params.end_date,
         );
 
-        // Visibility filter (only the owner sees their activities, so all visibilities are visible)
+        // Visibility filter
         if let Some(vis) = &params.visibility {
             match vis {
                 VisibilityFilter::All => (),
                 VisibilityFilter::Public => {
                     let _ = qb.add_condition("a.visibility = 'public'");
                 }
+                VisibilityFilter::Followers => {
+                    let _ = qb.add_condition("a.visibility = 'followers'");
+                }
                 VisibilityFilter::Private => {
                     let _ = qb.add_condition("a.visibility = 'private'");
                 }
@@ -308,6 +434,11 @@ impl Database {
             ActivitySortBy::Oldest => "a.submitted_at ASC",
             ActivitySortBy::Distance => "COALESCE(s.distance, 0) DESC",
             ActivitySortBy::Duration => "COALESCE(s.duration, 0) DESC",
+            ActivitySortBy::MovingTime => "COALESCE(s.moving_time, 0) DESC",
+            ActivitySortBy::ElevationGain => "COALESCE(s.elevation_gain, 0) DESC",
+            ActivitySortBy::AvgSpeed => "COALESCE(s.avg_speed, 0) DESC",
+            ActivitySortBy::MaxSpeed => "COALESCE(s.max_speed, 0) DESC",
+            ActivitySortBy::AvgPower => "COALESCE(s.avg_power, 0) DESC",
         };
 
         // Build the final query
@@ -334,6 +465,11 @@ impl Database {
         // user_id is always first
         q = q.bind(user_id);
 
+        // Visibility viewer
+        if let Some(viewer) = other_viewer {
+            q = q.bind(viewer);
+        }
+
         // Activity type filter
         if let Some(type_id) = params.activity_type_id {
             q = q.bind(type_id);
@@ -362,40 +498,164 @@ impl Database {
         Ok(activities)
     }
 
+    /// Update an activity. If its type or multi-sport parts change, its
+    /// segment efforts are removed in the same transaction and the PR flags and
+    /// crowns they held are recomputed from the remaining efforts. Its match
+    /// generation is bumped too, so efforts a running job found for the old
+    /// version aren't recorded after the removal.
     pub async fn update_activity(
         &self,
         id: Uuid,
-        name: Option<&str>,
-        activity_type_id: Option<Uuid>,
-        visibility: Option<&str>,
-    ) -> Result<Option<Activity>, AppError> {
-        let activity = sqlx::query_as(
+        update: &ActivityUpdate<'_>,
+    ) -> Result<Option<UpdatedActivity>, AppError> {
+        let mut tx = self.pool.begin().await?;
+
+        let before: Option<Activity> = sqlx::query_as(
+            r#"
+            SELECT id, user_id, activity_type_id, name, object_store_path,
+                   started_at, submitted_at, visibility, type_boundaries, segment_types
+            FROM activities
+            WHERE id = $1 AND deleted_at IS NULL
+            FOR UPDATE
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(before) = before else {
+            return Ok(None);
+        };
+
+        let (type_boundaries, segment_types) = match update.multi_sport {
+            Some(Some((boundaries, types))) => (Some(boundaries.to_vec()), Some(types.to_vec())),
+            Some(None) => (None, None),
+            None => (before.type_boundaries.clone(), before.segment_types.clone()),
+        };
+
+        let activity: Activity = sqlx::query_as(
             r#"
             UPDATE activities
             SET name = COALESCE($2, name),
                 activity_type_id = COALESCE($3, activity_type_id),
-                visibility = COALESCE($4, visibility)
-            WHERE id = $1 AND deleted_at IS NULL
+                visibility = COALESCE($4, visibility),
+                type_boundaries = $5,
+                segment_types = $6
+            WHERE id = $1
             RETURNING id, user_id, activity_type_id, name, object_store_path,
                       started_at, submitted_at, visibility, type_boundaries, segment_types
             "#,
         )
         .bind(id)
-        .bind(name)
-        .bind(activity_type_id)
-        .bind(visibility)
-        .fetch_optional(&self.pool)
+        .bind(update.name)
+        .bind(update.activity_type_id)
+        .bind(update.visibility)
+        .bind(type_boundaries)
+        .bind(segment_types)
+        .fetch_one(&mut *tx)
         .await?;
 
-        Ok(activity)
+        let needs_rematch = activity.activity_type_id != before.activity_type_id
+            || activity.type_boundaries != before.type_boundaries
+            || activity.segment_types != before.segment_types;
+
+        let invalidated_segments = if needs_rematch {
+            // Jobs already matching the old version stop recording efforts
+            sqlx::query(
+                "UPDATE activities SET match_generation = match_generation + 1 WHERE id = $1",
+            )
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+            Self::clear_activity_efforts(&mut tx, &activity).await?
+        } else {
+            Vec::new()
+        };
+
+        // Followers-only activities' efforts aren't ranked on leaderboards.
+        // Efforts found by re-matching are ranked as they are saved.
+        let ranked = |a: &Activity| a.visibility != Visibility::Followers.as_str();
+        let reranked_segments = if !needs_rematch && ranked(&before) != ranked(&activity) {
+            Self::rerank_activity_efforts(&mut tx, id, ranked(&activity)).await?
+        } else {
+            Vec::new()
+        };
+
+        tx.commit().await?;
+
+        Ok(Some(UpdatedActivity {
+            activity,
+            needs_rematch,
+            invalidated_segments,
+            reranked_segments,
+        }))
+    }
+
+    /// Remove an activity's segment efforts and match rejections, then
+    /// recompute the owner's PR flags and the crowns on the affected segments.
+    /// Returns the affected segments.
+    async fn clear_activity_efforts(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        activity: &Activity,
+    ) -> Result<Vec<Uuid>, AppError> {
+        Self::lock_activity_leaderboard_caches(tx, activity.id).await?;
+        let mut segment_ids: Vec<Uuid> = sqlx::query_scalar(
+            "DELETE FROM segment_efforts WHERE activity_id = $1 RETURNING segment_id",
+        )
+        .bind(activity.id)
+        .fetch_all(&mut **tx)
+        .await?;
+        segment_ids.sort();
+        segment_ids.dedup();
+
+        sqlx::query("DELETE FROM segment_match_rejections WHERE activity_id = $1")
+            .bind(activity.id)
+            .execute(&mut **tx)
+            .await?;
+
+        if segment_ids.is_empty() {
+            return Ok(segment_ids);
+        }
+
+        // The owner's fastest remaining effort on each segment is their PR
+        sqlx::query(
+            r#"
+            UPDATE segment_efforts e
+            SET is_personal_record = (e.id = best.id)
+            FROM (
+                SELECT DISTINCT ON (segment_id) segment_id, id
+                FROM segment_efforts
+                WHERE user_id = $1 AND segment_id = ANY($2)
+                ORDER BY segment_id, elapsed_time_seconds ASC, started_at ASC
+            ) best
+            WHERE e.user_id = $1 AND e.segment_id = best.segment_id
+            "#,
+        )
+        .bind(activity.user_id)
+        .bind(&segment_ids)
+        .execute(&mut **tx)
+        .await?;
+
+        // Crowns move to the next fastest effort for now. Holders aren't
+        // notified: re-matching usually hands the crowns straight back.
+        for &segment_id in &segment_ids {
+            Self::rebuild_segment_crowns(tx, segment_id).await?;
+        }
+
+        Ok(segment_ids)
     }
 
     pub async fn delete_activity(&self, id: Uuid) -> Result<bool, AppError> {
+        let mut tx = self.pool.begin().await?;
+
+        // The efforts and their cached leaderboard entries go with the activity
+        Self::lock_activity_leaderboard_caches(&mut tx, id).await?;
+
         let result = sqlx::query("DELETE FROM activities WHERE id = $1")
             .bind(id)
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await?;
 
+        tx.commit().await?;
         Ok(result.rows_affected() > 0)
     }
 
@@ -438,8 +698,30 @@ impl Database {
     ) -> Result<(), AppError> {
         sqlx::query(
             r#"
-            INSERT INTO scores (user_id, activity_id, distance, duration, elevation_gain, created_at)
-            VALUES ($1, $2, $3, $4, $5, $6)
+            INSERT INTO scores (
+                user_id, activity_id, distance, duration, elevation_gain,
+                moving_time, max_speed, avg_speed,
+                elevation_loss, max_elevation, min_elevation,
+                avg_heart_rate, max_heart_rate, avg_cadence, max_cadence,
+                avg_power, max_power, created_at
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18)
+            ON CONFLICT (activity_id) DO UPDATE
+            SET distance = EXCLUDED.distance,
+                duration = EXCLUDED.duration,
+                elevation_gain = EXCLUDED.elevation_gain,
+                moving_time = EXCLUDED.moving_time,
+                max_speed = EXCLUDED.max_speed,
+                avg_speed = EXCLUDED.avg_speed,
+                elevation_loss = EXCLUDED.elevation_loss,
+                max_elevation = EXCLUDED.max_elevation,
+                min_elevation = EXCLUDED.min_elevation,
+                avg_heart_rate = EXCLUDED.avg_heart_rate,
+                max_heart_rate = EXCLUDED.max_heart_rate,
+                avg_cadence = EXCLUDED.avg_cadence,
+                max_cadence = EXCLUDED.max_cadence,
+                avg_power = EXCLUDED.avg_power,
+                max_power = EXCLUDED.max_power
             "#,
         )
         .bind(uid)
@@ -447,6 +729,18 @@ impl Database {
         .bind(scores.distance)
         .bind(scores.duration)
         .bind(scores.elevation_gain)
+        .bind(scores.stats.moving_time)
+        .bind(scores.stats.max_speed)
+        .bind(scores.stats.avg_speed)
+        .bind(scores.stats.elevation_loss)
+        .bind(scores.stats.max_elevation)
+        .bind(scores.stats.min_elevation)
+        .bind(scores.stats.avg_heart_rate)
+        .bind(scores.stats.max_heart_rate)
+        .bind(scores.stats.avg_cadence)
+        .bind(scores.stats.max_cadence)
+        .bind(scores.stats.avg_power)
+        .bind(scores.stats.max_power)
         .bind(time::OffsetDateTime::now_utc())
         .execute(&self.pool)
         .await?;
@@ -454,6 +748,21 @@ impl Database {
         Ok(())
     }
 
+    /// Record where an activity's stored track gets its elevation from.
+    pub async fn set_activity_elevation_source(
+        &self,
+        activity_id: Uuid,
+        source: ElevationSource,
+    ) -> Result<(), AppError> {
+        sqlx::query("UPDATE activities SET elevation_source = $2 WHERE id = $1")
+            .bind(activity_id)
+            .bind(source)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
     // Auth-related methods
 
     pub async fn get_user_by_email(&self, email: &str) -> Result<Option<User>, AppError> {
@@ -597,7 +906,7 @@ impl Database {
             SELECT s.id, s.creator_id, u.name as creator_name, s.name, s.description, s.activity_type_id,
                    s.distance_meters, s.elevation_gain_meters, s.elevation_loss_meters,
                    s.average_grade, s.max_grade, s.climb_category,
-                   s.visibility, s.created_at
+                   s.visibility, s.version, s.created_at
             FROM segments s
             JOIN users u ON u.id = s.creator_id
             WHERE s.id = $1 AND s.deleted_at IS NULL
@@ -621,7 +930,7 @@ impl Database {
                 SELECT s.id, s.creator_id, u.name as creator_name, s.name, s.description, s.activity_type_id,
                        s.distance_meters, s.elevation_gain_meters, s.elevation_loss_meters,
                        s.average_grade, s.max_grade, s.climb_category,
-                       s.visibility, s.created_at
+                       s.visibility, s.version, s.created_at
                 FROM segments s
                 JOIN users u ON u.id = s.creator_id
                 WHERE s.deleted_at IS NULL AND s.visibility = 'public' AND s.activity_type_id = $1
@@ -639,7 +948,7 @@ impl Database {
                 SELECT s.id, s.creator_id, u.name as creator_name, s.name, s.description, s.activity_type_id,
                        s.distance_meters, s.elevation_gain_meters, s.elevation_loss_meters,
                        s.average_grade, s.max_grade, s.climb_category,
-                       s.visibility, s.created_at
+                       s.visibility, s.version, s.created_at
                 FROM segments s
                 JOIN users u ON u.id = s.creator_id
                 WHERE s.deleted_at IS NULL AND s.visibility = 'public'
@@ -728,7 +1037,7 @@ impl Database {
             SELECT s.id, s.creator_id, u.name as creator_name, s.name, s.description, s.activity_type_id,
                    s.distance_meters, s.elevation_gain_meters, s.elevation_loss_meters,
                    s.average_grade, s.max_grade, s.climb_category,
-                   s.visibility, s.created_at
+                   s.visibility, s.version, s.created_at
             FROM segments s
             JOIN users u ON u.id = s.creator_id
             WHERE {where_clause}
@@ -770,7 +1079,7 @@ impl Database {
             SELECT s.id, s.creator_id, u.name as creator_name, s.name, s.description, s.activity_type_id,
                    s.distance_meters, s.elevation_gain_meters, s.elevation_loss_meters,
                    s.average_grade, s.max_grade, s.climb_category,
-                   s.visibility, s.created_at
+                   s.visibility, s.version, s.created_at
             FROM segments s
             JOIN users u ON u.id = s.creator_id
             WHERE s.creator_id = $1 AND s.deleted_at IS NULL
@@ -784,6 +1093,23 @@ impl Database {
         Ok(segments)
     }
 
+    /// The activity's match generation, see `update_activity`. None if the
+    /// activity doesn't exist.
+    pub async fn get_activity_match_generation(
+        &self,
+        activity_id: Uuid,
+    ) -> Result<Option<i32>, AppError> {
+        let generation: Option<(i32,)> =
+            sqlx::query_as("SELECT match_generation FROM activities WHERE id = $1")
+                .bind(activity_id)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(generation.map(|(generation,)| generation))
+    }
+
+    /// Record a segment effort. With `match_generation`, the effort is only
+    /// recorded if the activity hasn't been edited since the matching started;
+    /// returns None otherwise.
     #[allow(clippy::too_many_arguments)]
     pub async fn create_segment_effort(
         &self,
@@ -797,8 +1123,24 @@ impl Database {
         max_speed_mps: Option<f64>,
         start_fraction: Option<f64>,
         end_fraction: Option<f64>,
-    ) -> Result<SegmentEffort, AppError> {
-        let effort = sqlx::query_as(
+        match_generation: Option<i32>,
+    ) -> Result<Option<SegmentEffort>, AppError> {
+        let mut tx = self.pool.begin().await?;
+
+        if let Some(expected) = match_generation {
+            // FOR SHARE waits for an edit in progress, which clears the
+            // activity's efforts when it commits
+            let current: Option<(i32,)> =
+                sqlx::query_as("SELECT match_generation FROM activities WHERE id = $1 FOR SHARE")
+                    .bind(activity_id)
+                    .fetch_optional(&mut *tx)
+                    .await?;
+            if current != Some((expected,)) {
+                return Ok(None);
+            }
+        }
+
+        let effort: SegmentEffort = sqlx::query_as(
             r#"
             WITH inserted AS (
                 INSERT INTO segment_efforts (
@@ -841,18 +1183,22 @@ impl Database {
         .bind(max_speed_mps)
         .bind(start_fraction)
         .bind(end_fraction)
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *tx)
         .await?;
 
-        Ok(effort)
+        Self::add_effort_to_leaderboard_caches(&mut tx, segment_id, effort.id).await?;
+        tx.commit().await?;
+
+        Ok(Some(effort))
     }
 
+    /// The fastest efforts on a segment, leaving out followers-only activities.
     pub async fn get_segment_efforts(
         &self,
         segment_id: Uuid,
         limit: i64,
     ) -> Result<Vec<SegmentEffort>, AppError> {
-        let efforts: Vec<SegmentEffort> = sqlx::query_as(
+        let query = format!(
             r#"
             SELECT se.id, se.segment_id, se.activity_id, se.user_id,
                    u.name AS user_name,
@@ -862,15 +1208,17 @@ impl Database {
                    se.start_fraction, se.end_fraction
             FROM segment_efforts se
             JOIN users u ON u.id = se.user_id
-            WHERE se.segment_id = $1
-            ORDER BY se.elapsed_time_seconds ASC
+            JOIN activities a ON a.id = se.activity_id
+            WHERE se.segment_id = $1 AND {LEADERBOARD_ACTIVITY_CONDITION}
+            ORDER BY se.elapsed_time_seconds ASC, se.started_at ASC, se.id ASC
             LIMIT $2
-            "#,
-        )
-        .bind(segment_id)
-        .bind(limit)
-        .fetch_all(&self.pool)
-        .await?;
+            "#
+        );
+        let efforts: Vec<SegmentEffort> = sqlx::query_as(&query)
+            .bind(segment_id)
+            .bind(limit)
+            .fetch_all(&self.pool)
+            .await?;
 
         Ok(efforts)
     }
@@ -1099,40 +1447,45 @@ impl Database {
 
     // Segment matching methods
 
-    /// Find segments that the activity track passes through.
-    /// Uses PostGIS to check if track passes within 50m of both segment endpoints
-    /// and verifies direction (start before end along the track).
+    /// Find segments whose start and end points the activity track passes near.
+    /// Uses PostGIS to check if track passes within 50m of both segment endpoints.
     ///
     /// For single-sport activities: filters by activity_type_id directly.
     /// For multi-sport activities: finds all geometric matches, then filters by
     /// the activity type at each segment's position on the track.
+    ///
+    /// These are endpoint candidates only; callers expand them into individual
+    /// traversals (laps) with `SegmentCandidate::traversals` and must still run the
+    /// path conformance check before creating an effort.
     pub async fn find_matching_segments(
         &self,
         activity_id: Uuid,
         activity_type_id: Uuid,
-    ) -> Result<Vec<SegmentMatch>, AppError> {
+    ) -> Result<Vec<SegmentCandidate>, AppError> {
         #[derive(sqlx::FromRow)]
-        struct MatchRow {
+        struct CandidateRow {
             id: Uuid,
             distance_meters: f64,
-            start_pos: f64,
-            end_pos: f64,
+            start_lat: f64,
+            start_lon: f64,
+            end_lat: f64,
+            end_lon: f64,
         }
 
-        let rows: Vec<MatchRow> = sqlx::query_as(
+        let rows: Vec<CandidateRow> = sqlx::query_as(
             r#"
             SELECT s.id,
                    s.distance_meters,
-                   ST_LineLocatePoint(t.geo::geometry, s.start_point::geometry) as start_pos,
-                   ST_LineLocatePoint(t.geo::geometry, s.end_point::geometry) as end_pos
+                   ST_Y(s.start_point::geometry) as start_lat,
+                   ST_X(s.start_point::geometry) as start_lon,
+                   ST_Y(s.end_point::geometry) as end_lat,
+                   ST_X(s.end_point::geometry) as end_lon
             FROM segments s
             JOIN tracks t ON t.activity_id = $1
             WHERE s.deleted_at IS NULL
               AND s.activity_type_id = $2
               AND ST_DWithin(t.geo, s.start_point, 50)
               AND ST_DWithin(t.geo, s.end_point, 50)
-              AND ST_LineLocatePoint(t.geo::geometry, s.start_point::geometry)
-                  < ST_LineLocatePoint(t.geo::geometry, s.end_point::geometry)
             "#,
         )
         .bind(activity_id)
@@ -1142,11 +1495,11 @@ impl Database {
 
         Ok(rows
             .into_iter()
-            .map(|r| SegmentMatch {
+            .map(|r| SegmentCandidate {
                 segment_id: r.id,
                 distance_meters: r.distance_meters,
-                start_fraction: r.start_pos,
-                end_fraction: r.end_pos,
+                start_point: (r.start_lat, r.start_lon),
+                end_point: (r.end_lat, r.end_lon),
             })
             .collect())
     }
@@ -1157,30 +1510,32 @@ impl Database {
     pub async fn find_matching_segments_any_type(
         &self,
         activity_id: Uuid,
-    ) -> Result<Vec<(SegmentMatch, Uuid)>, AppError> {
+    ) -> Result<Vec<(SegmentCandidate, Uuid)>, AppError> {
         #[derive(sqlx::FromRow)]
-        struct MatchRow {
+        struct CandidateRow {
             id: Uuid,
             activity_type_id: Uuid,
             distance_meters: f64,
-            start_pos: f64,
-            end_pos: f64,
+            start_lat: f64,
+            start_lon: f64,
+            end_lat: f64,
+            end_lon: f64,
         }
 
-        let rows: Vec<MatchRow> = sqlx::query_as(
+        let rows: Vec<CandidateRow> = sqlx::query_as(
             r#"
             SELECT s.id,
                    s.activity_type_id,
                    s.distance_meters,
-                   ST_LineLocatePoint(t.geo::geometry, s.start_point::geometry) as start_pos,
-                   ST_LineLocatePoint(t.geo::geometry, s.end_point::geometry) as end_pos
+                   ST_Y(s.start_point::geometry) as start_lat,
+                   ST_X(s.start_point::geometry) as start_lon,
+                   ST_Y(s.end_point::geometry) as end_lat,
+                   ST_X(s.end_point::geometry) as end_lon
             FROM segments s
             JOIN tracks t ON t.activity_id = $1
             WHERE s.deleted_at IS NULL
               AND ST_DWithin(t.geo, s.start_point, 50)
               AND ST_DWithin(t.geo, s.end_point, 50)
-              AND ST_LineLocatePoint(t.geo::geometry, s.start_point::geometry)
-                  < ST_LineLocatePoint(t.geo::geometry, s.end_point::geometry)
             "#,
         )
         .bind(activity_id)
@@ -1191,11 +1546,11 @@ impl Database {
             .into_iter()
             .map(|r| {
                 (
-                    SegmentMatch {
+                    SegmentCandidate {
                         segment_id: r.id,
                         distance_meters: r.distance_meters,
-                        start_fraction: r.start_pos,
-                        end_fraction: r.end_pos,
+                        start_point: (r.start_lat, r.start_lon),
+                        end_point: (r.end_lat, r.end_lon),
                     },
                     r.activity_type_id,
                 )
@@ -1203,21 +1558,162 @@ impl Database {
             .collect())
     }
 
-    /// Check if a segment effort already exists for a given segment and activity.
+    /// Get a segment's line as (lat, lon) pairs in travel order, for path conformance checks.
+    pub async fn get_segment_points(&self, segment_id: Uuid) -> Result<Vec<(f64, f64)>, AppError> {
+        let rows: Vec<(f64, f64)> = sqlx::query_as(
+            r#"
+            SELECT ST_Y(dp.geom) as lat, ST_X(dp.geom) as lon
+            FROM segments s,
+            LATERAL ST_DumpPoints(s.geo::geometry) AS dp(path, geom)
+            WHERE s.id = $1
+            ORDER BY dp.path[1]
+            "#,
+        )
+        .bind(segment_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows)
+    }
+
+    /// Get the path conformance tolerance for a segment, from its activity type.
+    pub async fn get_segment_match_tolerance(&self, segment_id: Uuid) -> Result<f64, AppError> {
+        let row: Option<(f64,)> = sqlx::query_as(
+            r#"
+            SELECT t.match_tolerance_meters
+            FROM segments s
+            JOIN activity_types t ON t.id = s.activity_type_id
+            WHERE s.id = $1
+            "#,
+        )
+        .bind(segment_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map_or(DEFAULT_MATCH_TOLERANCE_METERS, |(t,)| t))
+    }
+
+    /// Record why one traversal of a segment by an activity was rejected.
+    /// Replaces any earlier rejection overlapping the same part of the track.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn record_segment_match_rejection(
+        &self,
+        segment_id: Uuid,
+        activity_id: Uuid,
+        user_id: Uuid,
+        start_fraction: f64,
+        end_fraction: f64,
+        rejection: &MatchRejection,
+        tolerance_meters: f64,
+    ) -> Result<(), AppError> {
+        let frechet_distance_meters = match rejection {
+            MatchRejection::PathDeviation {
+                frechet_distance_meters,
+                ..
+            } => Some(*frechet_distance_meters),
+            MatchRejection::InsufficientPoints => None,
+        };
+
+        self.clear_segment_match_rejection(segment_id, activity_id, start_fraction, end_fraction)
+            .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO segment_match_rejections
+                (segment_id, activity_id, user_id, reason, frechet_distance_meters,
+                 tolerance_meters, start_fraction, end_fraction)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#,
+        )
+        .bind(segment_id)
+        .bind(activity_id)
+        .bind(user_id)
+        .bind(rejection.reason())
+        .bind(frechet_distance_meters)
+        .bind(tolerance_meters)
+        .bind(start_fraction)
+        .bind(end_fraction)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Remove a stale rejection once this traversal of the segment has been accepted.
+    /// Rejections recorded before laps were tracked have no fractions and always overlap.
+    pub async fn clear_segment_match_rejection(
+        &self,
+        segment_id: Uuid,
+        activity_id: Uuid,
+        start_fraction: f64,
+        end_fraction: f64,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+            DELETE FROM segment_match_rejections
+            WHERE segment_id = $1 AND activity_id = $2
+              AND (start_fraction IS NULL OR end_fraction IS NULL
+                   OR (start_fraction < $4 AND end_fraction > $3))
+            "#,
+        )
+        .bind(segment_id)
+        .bind(activity_id)
+        .bind(start_fraction)
+        .bind(end_fraction)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Get rejected matches for a segment, most recent first.
+    pub async fn get_segment_match_rejections(
+        &self,
+        segment_id: Uuid,
+    ) -> Result<Vec<SegmentMatchRejection>, AppError> {
+        let rejections: Vec<SegmentMatchRejection> = sqlx::query_as(
+            r#"
+            SELECT r.id, r.segment_id, r.activity_id, r.user_id, u.name as user_name,
+                   r.reason, r.frechet_distance_meters, r.tolerance_meters, r.created_at,
+                   r.start_fraction, r.end_fraction
+            FROM segment_match_rejections r
+            JOIN users u ON u.id = r.user_id
+            JOIN activities a ON a.id = r.activity_id
+            WHERE r.segment_id = $1 AND a.deleted_at IS NULL
+            ORDER BY r.created_at DESC
+            "#,
+        )
+        .bind(segment_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rejections)
+    }
+
+    /// Check if a segment effort already exists for this traversal of a segment.
+    /// An activity can hold several efforts on one segment (laps), so only an
+    /// effort overlapping the given fraction range counts. Efforts recorded
+    /// without fractions cover the whole activity.
     pub async fn segment_effort_exists(
         &self,
         segment_id: Uuid,
         activity_id: Uuid,
+        start_fraction: f64,
+        end_fraction: f64,
     ) -> Result<bool, AppError> {
         let row: Option<(i32,)> = sqlx::query_as(
             r#"
             SELECT 1 FROM segment_efforts
             WHERE segment_id = $1 AND activity_id = $2
+              AND (start_fraction IS NULL OR end_fraction IS NULL
+                   OR (start_fraction < $4 AND end_fraction > $3))
             LIMIT 1
             "#,
         )
         .bind(segment_id)
         .bind(activity_id)
+        .bind(start_fraction)
+        .bind(end_fraction)
         .fetch_optional(&self.pool)
         .await?;
 
@@ -1242,11 +1738,12 @@ impl Database {
 
     /// Update personal records for a user on a segment.
     /// Marks the fastest effort as PR and clears PR flag from all others.
+    /// Returns the PR effort, if the user has any effort on the segment.
     pub async fn update_personal_records(
         &self,
         segment_id: Uuid,
         user_id: Uuid,
-    ) -> Result<(), AppError> {
+    ) -> Result<Option<Uuid>, AppError> {
         // Clear all PR flags for this user on this segment
         sqlx::query(
             r#"
@@ -1261,7 +1758,7 @@ impl Database {
         .await?;
 
         // Set PR flag on the fastest effort
-        sqlx::query(
+        let pr: Option<(Uuid,)> = sqlx::query_as(
             r#"
             UPDATE segment_efforts
             SET is_personal_record = TRUE
@@ -1271,14 +1768,38 @@ impl Database {
                 ORDER BY elapsed_time_seconds ASC
                 LIMIT 1
             )
+            RETURNING id
             "#,
         )
         .bind(segment_id)
         .bind(user_id)
-        .execute(&self.pool)
+        .fetch_optional(&self.pool)
         .await?;
 
-        Ok(())
+        Ok(pr.map(|(id,)| id))
+    }
+
+    /// Fastest time of the user's other efforts on a segment, if they have any.
+    pub async fn get_best_other_effort_time(
+        &self,
+        segment_id: Uuid,
+        user_id: Uuid,
+        effort_id: Uuid,
+    ) -> Result<Option<f64>, AppError> {
+        let best: Option<f64> = sqlx::query_scalar(
+            r#"
+            SELECT MIN(elapsed_time_seconds)
+            FROM segment_efforts
+            WHERE segment_id = $1 AND user_id = $2 AND id <> $3
+            "#,
+        )
+        .bind(segment_id)
+        .bind(user_id)
+        .bind(effort_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(best)
     }
 
     /// Get all activities with their track geometry for reprocessing.
@@ -1302,65 +1823,425 @@ impl Database {
         Ok(rows)
     }
 
-    /// Find all activities that match a specific segment.
-    /// Returns activity_id, user_id, and the fractional positions along each track.
+    /// Find all activities whose track passes near both endpoints of a segment.
+    /// Callers expand each activity into individual traversals from its track points.
     pub async fn find_matching_activities_for_segment(
         &self,
         segment_id: Uuid,
     ) -> Result<Vec<ActivityMatch>, AppError> {
-        #[derive(sqlx::FromRow)]
-        struct MatchRow {
-            activity_id: Uuid,
-            user_id: Uuid,
-            start_pos: f64,
-            end_pos: f64,
-        }
-
-        let rows: Vec<MatchRow> = sqlx::query_as(
-            r#"
-            SELECT t.activity_id,
-                   t.user_id,
-                   ST_LineLocatePoint(t.geo::geometry, s.start_point::geometry) as start_pos,
-                   ST_LineLocatePoint(t.geo::geometry, s.end_point::geometry) as end_pos
-            FROM segments s
-            JOIN tracks t ON ST_DWithin(t.geo, s.start_point, 50)
-                         AND ST_DWithin(t.geo, s.end_point, 50)
-            JOIN activities a ON a.id = t.activity_id
-            WHERE s.id = $1
-              AND s.deleted_at IS NULL
-              AND a.deleted_at IS NULL
-              AND a.activity_type_id = s.activity_type_id
-              AND ST_LineLocatePoint(t.geo::geometry, s.start_point::geometry)
-                  < ST_LineLocatePoint(t.geo::geometry, s.end_point::geometry)
-            "#,
-        )
-        .bind(segment_id)
-        .fetch_all(&self.pool)
-        .await?;
+        let query = activities_near_endpoints_query(
+            "SELECT activity_type_id, start_point, end_point
+             FROM segments
+             WHERE id = $1 AND deleted_at IS NULL",
+        );
+        let rows: Vec<(Uuid, Uuid)> = sqlx::query_as(&query)
+            .bind(segment_id)
+            .fetch_all(&self.pool)
+            .await?;
 
         Ok(rows
             .into_iter()
-            .map(|r| ActivityMatch {
-                activity_id: r.activity_id,
-                user_id: r.user_id,
-                start_fraction: r.start_pos,
-                end_fraction: r.end_pos,
+            .map(|(activity_id, user_id)| ActivityMatch {
+                activity_id,
+                user_id,
             })
             .collect())
     }
 
-    // Segment star methods
+    // Segment version methods
 
-    /// Star a segment for a user.
-    pub async fn star_segment(&self, user_id: Uuid, segment_id: Uuid) -> Result<(), AppError> {
-        sqlx::query(
-            r#"
-            INSERT INTO segment_stars (user_id, segment_id, created_at)
-            VALUES ($1, $2, NOW())
-            ON CONFLICT (user_id, segment_id) DO NOTHING
-            "#,
-        )
-        .bind(user_id)
+    /// Find activities of a type whose track passes near both given endpoints,
+    /// for matching against a segment geometry that isn't stored yet. Like
+    /// [`Self::find_matching_activities_for_segment`], multi-sport activities
+    /// with a part of the type are included.
+    pub async fn find_activities_near_endpoints(
+        &self,
+        activity_type_id: Uuid,
+        start_wkt: &str,
+        end_wkt: &str,
+    ) -> Result<Vec<ActivityMatch>, AppError> {
+        let query = activities_near_endpoints_query(
+            "SELECT $1::uuid AS activity_type_id,
+                    ST_GeogFromText($2) AS start_point,
+                    ST_GeogFromText($3) AS end_point",
+        );
+        let rows: Vec<(Uuid, Uuid)> = sqlx::query_as(&query)
+            .bind(activity_type_id)
+            .bind(start_wkt)
+            .bind(end_wkt)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(activity_id, user_id)| ActivityMatch {
+                activity_id,
+                user_id,
+            })
+            .collect())
+    }
+
+    /// Get a segment's line as (lat, lon, elevation) points, in order.
+    pub async fn get_segment_line(
+        &self,
+        segment_id: Uuid,
+    ) -> Result<Vec<(f64, f64, Option<f64>)>, AppError> {
+        let rows: Vec<(f64, f64, Option<f64>)> = sqlx::query_as(
+            r#"
+            SELECT ST_Y(dp.geom) as lat, ST_X(dp.geom) as lon, ST_Z(dp.geom) as ele
+            FROM segments s,
+            LATERAL ST_DumpPoints(s.geo::geometry) AS dp(path, geom)
+            WHERE s.id = $1
+            ORDER BY dp.path[1]
+            "#,
+        )
+        .bind(segment_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows)
+    }
+
+    /// Get the geometry history of a segment, newest first.
+    pub async fn get_segment_versions(
+        &self,
+        segment_id: Uuid,
+    ) -> Result<Vec<SegmentVersion>, AppError> {
+        let versions: Vec<SegmentVersion> = sqlx::query_as(
+            r#"
+            SELECT segment_id, version, distance_meters, elevation_gain_meters,
+                   elevation_loss_meters, average_grade, max_grade, climb_category,
+                   created_by, created_at
+            FROM segment_versions
+            WHERE segment_id = $1
+            ORDER BY version DESC
+            "#,
+        )
+        .bind(segment_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(versions)
+    }
+
+    /// Store a new geometry version for a segment and replace its efforts, match
+    /// rejections, PR flags and crowns with the ones `rebuild` computes against
+    /// that geometry, all in one transaction.
+    ///
+    /// The segment is locked before `rebuild` runs. New efforts reference the
+    /// segment, so ones the processing queue saves meanwhile wait for the edit
+    /// to commit instead of being deleted along with the old efforts.
+    ///
+    /// Returns the new version number, the crowns that changed hands and the
+    /// rebuild.
+    pub async fn apply_segment_geometry_edit(
+        &self,
+        segment_id: Uuid,
+        editor_id: Uuid,
+        geometry: &SegmentGeometry,
+        rebuild: impl AsyncFnOnce() -> Result<SegmentRebuild, AppError>,
+    ) -> Result<(i32, Vec<CrownChange>, SegmentRebuild), AppError> {
+        let mut tx = self.pool.begin().await?;
+
+        // Lock the segment so concurrent edits get consecutive versions and
+        // new efforts wait
+        let (current,): (i32,) = sqlx::query_as(
+            "SELECT version FROM segments WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+        )
+        .bind(segment_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(AppError::NotFound)?;
+        let version = current + 1;
+
+        let rebuild = rebuild().await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO segment_versions (
+                segment_id, version, geo, distance_meters,
+                elevation_gain_meters, elevation_loss_meters,
+                average_grade, max_grade, climb_category, created_by
+            )
+            VALUES ($1, $2, ST_GeogFromText($3), $4, $5, $6, $7, $8, $9, $10)
+            "#,
+        )
+        .bind(segment_id)
+        .bind(version)
+        .bind(&geometry.geo_wkt)
+        .bind(geometry.distance_meters)
+        .bind(geometry.elevation_gain)
+        .bind(geometry.elevation_loss)
+        .bind(geometry.average_grade)
+        .bind(geometry.max_grade)
+        .bind(geometry.climb_category)
+        .bind(editor_id)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            UPDATE segments
+            SET geo = ST_GeogFromText($2),
+                start_point = ST_GeogFromText($3),
+                end_point = ST_GeogFromText($4),
+                distance_meters = $5,
+                elevation_gain_meters = $6,
+                elevation_loss_meters = $7,
+                average_grade = $8,
+                max_grade = $9,
+                climb_category = $10,
+                version = $11,
+                updated_at = NOW()
+            WHERE id = $1
+            "#,
+        )
+        .bind(segment_id)
+        .bind(&geometry.geo_wkt)
+        .bind(&geometry.start_wkt)
+        .bind(&geometry.end_wkt)
+        .bind(geometry.distance_meters)
+        .bind(geometry.elevation_gain)
+        .bind(geometry.elevation_loss)
+        .bind(geometry.average_grade)
+        .bind(geometry.max_grade)
+        .bind(geometry.climb_category)
+        .bind(version)
+        .execute(&mut *tx)
+        .await?;
+
+        // Efforts and rejections against the old line no longer mean anything
+        Self::lock_leaderboard_cache(&mut tx, segment_id).await?;
+        sqlx::query("DELETE FROM leaderboard_cache WHERE segment_id = $1")
+            .bind(segment_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM segment_efforts WHERE segment_id = $1")
+            .bind(segment_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM segment_match_rejections WHERE segment_id = $1")
+            .bind(segment_id)
+            .execute(&mut *tx)
+            .await?;
+
+        let efforts = &rebuild.efforts;
+        sqlx::query(
+            r#"
+            INSERT INTO segment_efforts (
+                segment_id, activity_id, user_id, started_at, elapsed_time_seconds,
+                moving_time_seconds, average_speed_mps, start_fraction, end_fraction
+            )
+            SELECT $1, * FROM UNNEST(
+                $2::uuid[], $3::uuid[], $4::timestamptz[], $5::float8[],
+                $6::float8[], $7::float8[], $8::float8[], $9::float8[]
+            )
+            "#,
+        )
+        .bind(segment_id)
+        .bind(efforts.iter().map(|e| e.activity_id).collect::<Vec<_>>())
+        .bind(efforts.iter().map(|e| e.user_id).collect::<Vec<_>>())
+        .bind(efforts.iter().map(|e| e.started_at).collect::<Vec<_>>())
+        .bind(
+            efforts
+                .iter()
+                .map(|e| e.elapsed_time_seconds)
+                .collect::<Vec<_>>(),
+        )
+        .bind(
+            efforts
+                .iter()
+                .map(|e| e.moving_time_seconds)
+                .collect::<Vec<_>>(),
+        )
+        .bind(
+            efforts
+                .iter()
+                .map(|e| e.average_speed_mps)
+                .collect::<Vec<_>>(),
+        )
+        .bind(efforts.iter().map(|e| e.start_fraction).collect::<Vec<_>>())
+        .bind(efforts.iter().map(|e| e.end_fraction).collect::<Vec<_>>())
+        .execute(&mut *tx)
+        .await?;
+
+        let rejections = &rebuild.rejections;
+        sqlx::query(
+            r#"
+            INSERT INTO segment_match_rejections (
+                segment_id, activity_id, user_id, reason, frechet_distance_meters,
+                tolerance_meters, start_fraction, end_fraction
+            )
+            SELECT $1, activity_id, user_id, reason, frechet, $7, start_fraction, end_fraction
+            FROM UNNEST($2::uuid[], $3::uuid[], $4::text[], $5::float8[], $6::float8[], $8::float8[])
+                AS r(activity_id, user_id, reason, frechet, start_fraction, end_fraction)
+            "#,
+        )
+        .bind(segment_id)
+        .bind(rejections.iter().map(|r| r.activity_id).collect::<Vec<_>>())
+        .bind(rejections.iter().map(|r| r.user_id).collect::<Vec<_>>())
+        .bind(
+            rejections
+                .iter()
+                .map(|r| r.rejection.reason())
+                .collect::<Vec<_>>(),
+        )
+        .bind(
+            rejections
+                .iter()
+                .map(|r| match r.rejection {
+                    MatchRejection::PathDeviation {
+                        frechet_distance_meters,
+                        ..
+                    } => Some(frechet_distance_meters),
+                    MatchRejection::InsufficientPoints => None,
+                })
+                .collect::<Vec<_>>(),
+        )
+        .bind(rejections.iter().map(|r| r.start_fraction).collect::<Vec<_>>())
+        .bind(rebuild.tolerance_meters)
+        .bind(rejections.iter().map(|r| r.end_fraction).collect::<Vec<_>>())
+        .execute(&mut *tx)
+        .await?;
+
+        // Each user's fastest effort is their PR
+        sqlx::query(
+            r#"
+            UPDATE segment_efforts e
+            SET is_personal_record = (e.id = best.id)
+            FROM (
+                SELECT DISTINCT ON (user_id) user_id, id
+                FROM segment_efforts
+                WHERE segment_id = $1
+                ORDER BY user_id, elapsed_time_seconds ASC, started_at ASC
+            ) best
+            WHERE e.segment_id = $1 AND e.user_id = best.user_id
+            "#,
+        )
+        .bind(segment_id)
+        .execute(&mut *tx)
+        .await?;
+
+        let crown_changes = Self::rebuild_segment_crowns(&mut tx, segment_id).await?;
+
+        tx.commit().await?;
+
+        Ok((version, crown_changes, rebuild))
+    }
+
+    /// Recompute the KOM, QOM and course record holders of a segment from its
+    /// ranked efforts. A holder who keeps a crown keeps the original award (with
+    /// the effort updated); otherwise the old award is closed and a new one
+    /// opened.
+    async fn rebuild_segment_crowns(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        segment_id: Uuid,
+    ) -> Result<Vec<CrownChange>, AppError> {
+        let current: Vec<(Uuid, Uuid, AchievementType)> = sqlx::query_as(
+            r#"
+            SELECT id, user_id, achievement_type
+            FROM achievements
+            WHERE segment_id = $1 AND lost_at IS NULL
+              AND achievement_type IN ('kom', 'qom', 'course_record')
+            FOR UPDATE
+            "#,
+        )
+        .bind(segment_id)
+        .fetch_all(&mut **tx)
+        .await?;
+
+        let query = format!(
+            r#"
+            SELECT DISTINCT ON (achievement_type) achievement_type, user_id, effort_id
+            FROM (
+                SELECT (CASE u.gender WHEN 'male' THEN 'kom' ELSE 'qom' END)::achievement_type
+                           AS achievement_type,
+                       e.user_id, e.id AS effort_id, e.elapsed_time_seconds, e.started_at
+                FROM segment_efforts e
+                JOIN users u ON u.id = e.user_id
+                JOIN activities a ON a.id = e.activity_id
+                WHERE e.segment_id = $1 AND u.gender IN ('male', 'female')
+                  AND {LEADERBOARD_ACTIVITY_CONDITION}
+                UNION ALL
+                SELECT 'course_record'::achievement_type,
+                       e.user_id, e.id, e.elapsed_time_seconds, e.started_at
+                FROM segment_efforts e
+                JOIN activities a ON a.id = e.activity_id
+                WHERE e.segment_id = $1 AND {LEADERBOARD_ACTIVITY_CONDITION}
+            ) candidates
+            ORDER BY achievement_type, elapsed_time_seconds ASC, started_at ASC
+            "#
+        );
+        let fastest: Vec<(AchievementType, Uuid, Uuid)> = sqlx::query_as(&query)
+            .bind(segment_id)
+            .fetch_all(&mut **tx)
+            .await?;
+
+        let mut changes = Vec::new();
+        for achievement_type in [
+            AchievementType::Kom,
+            AchievementType::Qom,
+            AchievementType::CourseRecord,
+        ] {
+            let held = current.iter().find(|(_, _, t)| *t == achievement_type);
+            let best = fastest.iter().find(|(t, _, _)| *t == achievement_type);
+
+            match (held, best) {
+                (Some(&(id, holder, _)), Some(&(_, user_id, effort_id))) if holder == user_id => {
+                    sqlx::query("UPDATE achievements SET effort_id = $2 WHERE id = $1")
+                        .bind(id)
+                        .bind(effort_id)
+                        .execute(&mut **tx)
+                        .await?;
+                }
+                (None, None) => {}
+                _ => {
+                    if let Some(&(id, _, _)) = held {
+                        sqlx::query("UPDATE achievements SET lost_at = NOW() WHERE id = $1")
+                            .bind(id)
+                            .execute(&mut **tx)
+                            .await?;
+                    }
+                    if let Some(&(_, user_id, effort_id)) = best {
+                        sqlx::query(
+                            r#"
+                            INSERT INTO achievements
+                                (user_id, segment_id, effort_id, achievement_type, earned_at, created_at)
+                            VALUES ($1, $2, $3, $4, NOW(), NOW())
+                            "#,
+                        )
+                        .bind(user_id)
+                        .bind(segment_id)
+                        .bind(effort_id)
+                        .bind(achievement_type)
+                        .execute(&mut **tx)
+                        .await?;
+                    }
+                    changes.push(CrownChange {
+                        achievement_type,
+                        previous_holder_id: held.map(|&(_, holder, _)| holder),
+                        new_holder_id: best.map(|&(_, user_id, _)| user_id),
+                        effort_id: best.map(|&(_, _, effort_id)| effort_id),
+                    });
+                }
+            }
+        }
+
+        Ok(changes)
+    }
+
+    // Segment star methods
+
+    /// Star a segment for a user.
+    pub async fn star_segment(&self, user_id: Uuid, segment_id: Uuid) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+            INSERT INTO segment_stars (user_id, segment_id, created_at)
+            VALUES ($1, $2, NOW())
+            ON CONFLICT (user_id, segment_id) DO NOTHING
+            "#,
+        )
+        .bind(user_id)
         .bind(segment_id)
         .execute(&self.pool)
         .await?;
@@ -1412,7 +2293,7 @@ impl Database {
             SELECT s.id, s.creator_id, u.name as creator_name, s.name, s.description, s.activity_type_id,
                    s.distance_meters, s.elevation_gain_meters, s.elevation_loss_meters,
                    s.average_grade, s.max_grade, s.climb_category,
-                   s.visibility, s.created_at
+                   s.visibility, s.version, s.created_at
             FROM segments s
             JOIN segment_stars ss ON ss.segment_id = s.id
             JOIN users u ON u.id = s.creator_id
@@ -1533,7 +2414,7 @@ impl Database {
             SELECT s.id, s.creator_id, u.name as creator_name, s.name, s.description, s.activity_type_id,
                    s.distance_meters, s.elevation_gain_meters, s.elevation_loss_meters,
                    s.average_grade, s.max_grade, s.climb_category,
-                   s.visibility, s.created_at
+                   s.visibility, s.version, s.created_at
             FROM segments s
             JOIN users u ON u.id = s.creator_id
             WHERE s.deleted_at IS NULL
@@ -1561,78 +2442,23 @@ impl Database {
     // ========================================================================
 
     /// Get filtered leaderboard entries with user info and ranking.
-    /// Supports time scope, gender, and age group filtering.
+    /// Supports time scope, gender, and age group filtering. Leaderboards not
+    /// filtered by weight class or country are read from the leaderboard
+    /// cache, ranking and caching them first when they aren't cached yet.
     pub async fn get_filtered_leaderboard(
         &self,
         segment_id: Uuid,
         filters: &LeaderboardFilters,
     ) -> Result<(Vec<LeaderboardEntry>, i64), AppError> {
-        // Build the time filter based on scope
-        let time_filter = match filters.scope {
-            LeaderboardScope::AllTime => None,
-            LeaderboardScope::Year => Some("e.started_at >= NOW() - INTERVAL '1 year'"),
-            LeaderboardScope::Month => Some("e.started_at >= NOW() - INTERVAL '1 month'"),
-            LeaderboardScope::Week => Some("e.started_at >= NOW() - INTERVAL '1 week'"),
-        };
-
-        // Build the gender filter
-        let gender_filter = match filters.gender {
-            GenderFilter::All => None,
-            GenderFilter::Male => Some("u.gender = 'male'"),
-            GenderFilter::Female => Some("u.gender = 'female'"),
-        };
-
-        // Build the age filter based on current year and birth_year
-        let current_year = time::OffsetDateTime::now_utc().year();
-        let age_filter = filters.age_group.age_range().map(|(min_age, max_age)| {
-            let max_birth_year = current_year - min_age;
-            match max_age {
-                Some(max) => {
-                    let min_birth_year = current_year - max;
-                    format!("u.birth_year BETWEEN {min_birth_year} AND {max_birth_year}")
-                }
-                None => format!("u.birth_year <= {max_birth_year}"),
-            }
-        });
-
-        // Build the weight class filter
-        let weight_filter =
-            filters
-                .weight_class
-                .weight_range()
-                .map(|(min_kg, max_kg)| match (min_kg, max_kg) {
-                    (None, Some(max)) => format!("u.weight_kg < {max}"),
-                    (Some(min), Some(max)) => {
-                        format!("u.weight_kg >= {min} AND u.weight_kg < {max}")
-                    }
-                    (Some(min), None) => format!("u.weight_kg >= {min}"),
-                    (None, None) => unreachable!(),
-                });
-
-        // Build the country filter
-        let country_filter = filters
-            .country
-            .as_ref()
-            .map(|c| format!("u.country = '{}'", c.replace('\'', "''")));
-
-        // Build WHERE clauses
-        let mut where_clauses = vec!["e.segment_id = $1".to_string()];
-        if let Some(tf) = time_filter {
-            where_clauses.push(tf.to_string());
-        }
-        if let Some(gf) = gender_filter {
-            where_clauses.push(gf.to_string());
-        }
-        if let Some(af) = age_filter {
-            where_clauses.push(af);
-        }
-        if let Some(wf) = weight_filter {
-            where_clauses.push(wf);
-        }
-        if let Some(cf) = country_filter {
-            where_clauses.push(cf);
+        if let Some(bucket) = CacheBucket::for_filters(filters) {
+            let (cache_id, total_count) = self.cached_leaderboard(segment_id, &bucket).await?;
+            let entries = self
+                .get_cached_leaderboard_entries(cache_id, filters.offset, filters.limit)
+                .await?;
+            return Ok((entries, total_count));
         }
-        let where_clause = where_clauses.join(" AND ");
+
+        let where_clause = leaderboard_where_clause(filters);
 
         // Query for total count (for pagination)
         let count_query = format!(
@@ -1640,6 +2466,7 @@ impl Database {
             SELECT COUNT(DISTINCT e.id)
             FROM segment_efforts e
             JOIN users u ON u.id = e.user_id
+            JOIN activities a ON a.id = e.activity_id
             WHERE {where_clause}
             "#
         );
@@ -1664,6 +2491,7 @@ impl Database {
                     u.name as user_name
                 FROM segment_efforts e
                 JOIN users u ON u.id = e.user_id
+                JOIN activities a ON a.id = e.activity_id
                 WHERE {where_clause}
             ),
             ranked AS (
@@ -1676,8 +2504,8 @@ impl Database {
                     is_personal_record,
                     user_id,
                     user_name,
-                    ROW_NUMBER() OVER (ORDER BY elapsed_time_seconds ASC) as rank,
-                    FIRST_VALUE(elapsed_time_seconds) OVER (ORDER BY elapsed_time_seconds ASC) as leader_time
+                    ROW_NUMBER() OVER (ORDER BY elapsed_time_seconds ASC, started_at ASC, effort_id ASC) as rank,
+                    FIRST_VALUE(elapsed_time_seconds) OVER (ORDER BY elapsed_time_seconds ASC, started_at ASC, effort_id ASC) as leader_time
                 FROM filtered_efforts
             )
             SELECT
@@ -1707,88 +2535,101 @@ impl Database {
         Ok((entries, total_count.0))
     }
 
+    /// Get one effort's leaderboard entry, ranked under `filters`, and the
+    /// number of efforts on that leaderboard. `None` if the filters exclude it.
+    pub async fn get_leaderboard_entry(
+        &self,
+        segment_id: Uuid,
+        effort_id: Uuid,
+        filters: &LeaderboardFilters,
+    ) -> Result<Option<(LeaderboardEntry, i64)>, AppError> {
+        let where_clause = leaderboard_where_clause(filters);
+
+        let query = format!(
+            r#"
+            WITH ranked AS (
+                SELECT
+                    e.id as effort_id,
+                    e.elapsed_time_seconds,
+                    e.moving_time_seconds,
+                    e.average_speed_mps,
+                    e.started_at,
+                    e.is_personal_record,
+                    e.user_id,
+                    u.name as user_name,
+                    ROW_NUMBER() OVER (ORDER BY e.elapsed_time_seconds ASC, e.started_at ASC, e.id ASC) as rank,
+                    FIRST_VALUE(e.elapsed_time_seconds) OVER (ORDER BY e.elapsed_time_seconds ASC, e.started_at ASC, e.id ASC) as leader_time,
+                    COUNT(*) OVER () as total_count
+                FROM segment_efforts e
+                JOIN users u ON u.id = e.user_id
+                JOIN activities a ON a.id = e.activity_id
+                WHERE {where_clause}
+            )
+            SELECT
+                effort_id,
+                elapsed_time_seconds,
+                moving_time_seconds,
+                average_speed_mps,
+                started_at,
+                is_personal_record,
+                user_id,
+                user_name,
+                rank,
+                CASE WHEN rank > 1 THEN elapsed_time_seconds - leader_time ELSE NULL END as gap_seconds,
+                total_count
+            FROM ranked
+            WHERE effort_id = $2
+            "#
+        );
+
+        let row: Option<(LeaderboardEntry, i64)> = sqlx::query(&query)
+            .bind(segment_id)
+            .bind(effort_id)
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|row| -> Result<_, sqlx::Error> {
+                use sqlx::{FromRow, Row};
+                Ok((
+                    LeaderboardEntry::from_row(&row)?,
+                    row.try_get("total_count")?,
+                ))
+            })
+            .transpose()?;
+
+        Ok(row)
+    }
+
     /// Get the user's position in the leaderboard with surrounding entries.
+    /// Uses the leaderboard cache like [`Self::get_filtered_leaderboard`].
     pub async fn get_user_leaderboard_position(
         &self,
         segment_id: Uuid,
         user_id: Uuid,
         filters: &LeaderboardFilters,
         context_entries: i64,
-    ) -> Result<
-        Option<(
-            LeaderboardEntry,
-            Vec<LeaderboardEntry>,
-            Vec<LeaderboardEntry>,
-            i64,
-        )>,
-        AppError,
-    > {
-        // Build the time filter based on scope
-        let time_filter = match filters.scope {
-            LeaderboardScope::AllTime => None,
-            LeaderboardScope::Year => Some("e.started_at >= NOW() - INTERVAL '1 year'"),
-            LeaderboardScope::Month => Some("e.started_at >= NOW() - INTERVAL '1 month'"),
-            LeaderboardScope::Week => Some("e.started_at >= NOW() - INTERVAL '1 week'"),
-        };
-
-        // Build the gender filter
-        let gender_filter = match filters.gender {
-            GenderFilter::All => None,
-            GenderFilter::Male => Some("u.gender = 'male'"),
-            GenderFilter::Female => Some("u.gender = 'female'"),
-        };
-
-        // Build the age filter
-        let current_year = time::OffsetDateTime::now_utc().year();
-        let age_filter = filters.age_group.age_range().map(|(min_age, max_age)| {
-            let max_birth_year = current_year - min_age;
-            match max_age {
-                Some(max) => {
-                    let min_birth_year = current_year - max;
-                    format!("u.birth_year BETWEEN {min_birth_year} AND {max_birth_year}")
+    ) -> Result<Option<LeaderboardPositionEntries>, AppError> {
+        if let Some(bucket) = CacheBucket::for_filters(filters) {
+            let (cache_id, total_count) = self.cached_leaderboard(segment_id, &bucket).await?;
+            let context = context_entries.max(0);
+            let entries = match self
+                .get_cached_leaderboard_position(cache_id, user_id)
+                .await?
+            {
+                Some(position) => {
+                    let offset = (position - context).max(0);
+                    self.get_cached_leaderboard_entries(
+                        cache_id,
+                        offset,
+                        position + context + 1 - offset,
+                    )
+                    .await?
                 }
-                None => format!("u.birth_year <= {max_birth_year}"),
-            }
-        });
-
-        // Build the weight class filter
-        let weight_filter =
-            filters
-                .weight_class
-                .weight_range()
-                .map(|(min_kg, max_kg)| match (min_kg, max_kg) {
-                    (None, Some(max)) => format!("u.weight_kg < {max}"),
-                    (Some(min), Some(max)) => {
-                        format!("u.weight_kg >= {min} AND u.weight_kg < {max}")
-                    }
-                    (Some(min), None) => format!("u.weight_kg >= {min}"),
-                    (None, None) => unreachable!(),
-                });
-
-        // Build the country filter
-        let country_filter = filters
-            .country
-            .as_ref()
-            .map(|c| format!("u.country = '{}'", c.replace('\'', "''")));
-
-        // Build WHERE clauses
-        let mut where_clauses = vec!["e.segment_id = $1".to_string()];
-        if let Some(tf) = time_filter {
-            where_clauses.push(tf.to_string());
-        }
-        if let Some(gf) = gender_filter {
-            where_clauses.push(gf.to_string());
-        }
-        if let Some(af) = age_filter {
-            where_clauses.push(af);
+                None => Vec::new(),
+            };
+            return Ok(split_leaderboard_position(entries, user_id, total_count));
         }
-        if let Some(wf) = weight_filter {
-            where_clauses.push(wf);
-        }
-        if let Some(cf) = country_filter {
-            where_clauses.push(cf);
-        }
-        let where_clause = where_clauses.join(" AND ");
+
+        let where_clause = leaderboard_where_clause(filters);
 
         // First get the total count
         let count_query = format!(
@@ -1796,6 +2637,7 @@ impl Database {
             SELECT COUNT(DISTINCT e.id)
             FROM segment_efforts e
             JOIN users u ON u.id = e.user_id
+            JOIN activities a ON a.id = e.activity_id
             WHERE {where_clause}
             "#
         );
@@ -1820,6 +2662,7 @@ impl Database {
                     u.name as user_name
                 FROM segment_efforts e
                 JOIN users u ON u.id = e.user_id
+                JOIN activities a ON a.id = e.activity_id
                 WHERE {where_clause}
             ),
             ranked AS (
@@ -1832,12 +2675,12 @@ impl Database {
                     is_personal_record,
                     user_id,
                     user_name,
-                    ROW_NUMBER() OVER (ORDER BY elapsed_time_seconds ASC) as rank,
-                    FIRST_VALUE(elapsed_time_seconds) OVER (ORDER BY elapsed_time_seconds ASC) as leader_time
+                    ROW_NUMBER() OVER (ORDER BY elapsed_time_seconds ASC, started_at ASC, effort_id ASC) as rank,
+                    FIRST_VALUE(elapsed_time_seconds) OVER (ORDER BY elapsed_time_seconds ASC, started_at ASC, effort_id ASC) as leader_time
                 FROM filtered_efforts
             ),
             user_rank AS (
-                SELECT rank FROM ranked WHERE user_id = $2 ORDER BY elapsed_time_seconds LIMIT 1
+                SELECT rank FROM ranked WHERE user_id = $2 ORDER BY rank LIMIT 1
             )
             SELECT
                 effort_id,
@@ -1863,131 +2706,490 @@ impl Database {
             .fetch_all(&self.pool)
             .await?;
 
-        // Find user's entry and split into above/below
-        let user_entry_idx = entries.iter().position(|e| e.user_id == user_id);
-
-        match user_entry_idx {
-            Some(idx) => {
-                let user_entry = entries[idx].clone();
-                let entries_above = entries[..idx].to_vec();
-                let entries_below = entries[idx + 1..].to_vec();
-                Ok(Some((
-                    user_entry,
-                    entries_above,
-                    entries_below,
-                    total_count.0,
-                )))
-            }
-            None => Ok(None),
-        }
+        Ok(split_leaderboard_position(entries, user_id, total_count.0))
     }
 
     // ========================================================================
-    // User Demographics Methods
+    // Leaderboard Cache Methods
     // ========================================================================
 
-    /// Get a user with their demographics.
-    pub async fn get_user_with_demographics(
+    /// The ID and entry count of a cached leaderboard, ranking and caching it
+    /// first when it isn't cached yet.
+    async fn cached_leaderboard(
         &self,
-        user_id: Uuid,
-    ) -> Result<Option<UserWithDemographics>, AppError> {
-        let user: Option<UserWithDemographics> = sqlx::query_as(
-            r#"
-            SELECT id, email, name, created_at, gender, birth_year, weight_kg, country, region
-            FROM users
-            WHERE id = $1
+        segment_id: Uuid,
+        bucket: &CacheBucket,
+    ) -> Result<(Uuid, i64), AppError> {
+        let cached: Option<(Uuid, i32)> = sqlx::query_as(
+            r#"
+            SELECT id, entry_count
+            FROM leaderboard_cache
+            WHERE segment_id = $1
+              AND scope = $2
+              AND scope_value IS NULL
+              AND gender IS NOT DISTINCT FROM $3
+              AND age_group IS NOT DISTINCT FROM $4
+              AND expires_at > NOW()
             "#,
         )
-        .bind(user_id)
+        .bind(segment_id)
+        .bind(bucket.scope_key())
+        .bind(bucket.gender)
+        .bind(bucket.age_group_key())
         .fetch_optional(&self.pool)
         .await?;
 
-        Ok(user)
+        match cached {
+            Some((cache_id, entry_count)) => Ok((cache_id, entry_count.into())),
+            None => self.build_leaderboard_cache(segment_id, bucket).await,
+        }
     }
 
-    /// Update a user's demographics.
-    pub async fn update_user_demographics(
+    /// Entries of a cached leaderboard, from `offset` places below the top.
+    async fn get_cached_leaderboard_entries(
         &self,
-        user_id: Uuid,
-        req: &UpdateDemographicsRequest,
-    ) -> Result<UserWithDemographics, AppError> {
-        let user: UserWithDemographics = sqlx::query_as(
-            r#"
-            UPDATE users
-            SET gender = COALESCE($2, gender),
-                birth_year = COALESCE($3, birth_year),
-                weight_kg = COALESCE($4, weight_kg),
-                country = COALESCE($5, country),
-                region = COALESCE($6, region)
-            WHERE id = $1
-            RETURNING id, email, name, created_at, gender, birth_year, weight_kg, country, region
+        cache_id: Uuid,
+        offset: i64,
+        limit: i64,
+    ) -> Result<Vec<LeaderboardEntry>, AppError> {
+        let entries: Vec<LeaderboardEntry> = sqlx::query_as(
+            r#"
+            WITH page AS (
+                SELECT effort_id, elapsed_time_seconds, started_at
+                FROM leaderboard_cache_entries
+                WHERE cache_id = $1
+                ORDER BY elapsed_time_seconds ASC, started_at ASC, effort_id ASC
+                OFFSET $2
+                LIMIT $3
+            ),
+            leader AS (
+                SELECT MIN(elapsed_time_seconds) as leader_time
+                FROM leaderboard_cache_entries
+                WHERE cache_id = $1
+            ),
+            ranked AS (
+                SELECT
+                    page.*,
+                    $2 + ROW_NUMBER() OVER (
+                        ORDER BY page.elapsed_time_seconds ASC, page.started_at ASC, page.effort_id ASC
+                    ) as rank
+                FROM page
+            )
+            SELECT
+                r.effort_id,
+                r.elapsed_time_seconds,
+                e.moving_time_seconds,
+                e.average_speed_mps,
+                r.started_at,
+                e.is_personal_record,
+                e.user_id,
+                u.name as user_name,
+                r.rank,
+                CASE WHEN r.rank > 1 THEN r.elapsed_time_seconds - leader.leader_time ELSE NULL END as gap_seconds
+            FROM ranked r
+            CROSS JOIN leader
+            JOIN segment_efforts e ON e.id = r.effort_id
+            JOIN users u ON u.id = e.user_id
+            ORDER BY r.rank
+            "#,
+        )
+        .bind(cache_id)
+        .bind(offset.max(0))
+        .bind(limit.max(0))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(entries)
+    }
+
+    /// How many places below the top of a cached leaderboard the user's best
+    /// effort is, or `None` when they aren't on it.
+    async fn get_cached_leaderboard_position(
+        &self,
+        cache_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<i64>, AppError> {
+        let position: Option<i64> = sqlx::query_scalar(
+            r#"
+            WITH best AS (
+                SELECT c.elapsed_time_seconds, c.started_at, c.effort_id
+                FROM leaderboard_cache_entries c
+                JOIN segment_efforts e ON e.id = c.effort_id
+                WHERE c.cache_id = $1 AND e.user_id = $2
+                ORDER BY c.elapsed_time_seconds ASC, c.started_at ASC, c.effort_id ASC
+                LIMIT 1
+            )
+            SELECT (
+                SELECT COUNT(*)
+                FROM leaderboard_cache_entries c
+                WHERE c.cache_id = $1
+                  AND (c.elapsed_time_seconds, c.started_at, c.effort_id)
+                      < (best.elapsed_time_seconds, best.started_at, best.effort_id)
+            )
+            FROM best
             "#,
         )
+        .bind(cache_id)
         .bind(user_id)
-        .bind(req.gender)
-        .bind(req.birth_year)
-        .bind(req.weight_kg)
-        .bind(&req.country)
-        .bind(&req.region)
-        .fetch_one(&self.pool)
+        .fetch_optional(&self.pool)
         .await?;
 
-        Ok(user)
+        Ok(position)
+    }
+
+    /// Rank a segment leaderboard from scratch and cache it. Returns the ID
+    /// and entry count of the cached leaderboard.
+    async fn build_leaderboard_cache(
+        &self,
+        segment_id: Uuid,
+        bucket: &CacheBucket,
+    ) -> Result<(Uuid, i64), AppError> {
+        let mut tx = self.pool.begin().await?;
+        Self::lock_leaderboard_cache(&mut tx, segment_id).await?;
+
+        // Replacing the row drops the old entries with it
+        sqlx::query(
+            r#"
+            DELETE FROM leaderboard_cache
+            WHERE segment_id = $1
+              AND scope = $2
+              AND scope_value IS NULL
+              AND gender IS NOT DISTINCT FROM $3
+              AND age_group IS NOT DISTINCT FROM $4
+            "#,
+        )
+        .bind(segment_id)
+        .bind(bucket.scope_key())
+        .bind(bucket.gender)
+        .bind(bucket.age_group_key())
+        .execute(&mut *tx)
+        .await?;
+        let now = time::OffsetDateTime::now_utc();
+        let cache_id: Uuid = sqlx::query_scalar(
+            r#"
+            INSERT INTO leaderboard_cache (segment_id, scope, gender, age_group, computed_at, expires_at)
+            VALUES ($1, $2, $3, $4, NOW(), $5)
+            RETURNING id
+            "#,
+        )
+        .bind(segment_id)
+        .bind(bucket.scope_key())
+        .bind(bucket.gender)
+        .bind(bucket.age_group_key())
+        .bind(bucket.expires_at(None, now))
+        .fetch_one(&mut *tx)
+        .await?;
+
+        let where_clause = leaderboard_where_clause(&bucket.filters());
+        let query = format!(
+            r#"
+            WITH inserted AS (
+                INSERT INTO leaderboard_cache_entries (cache_id, effort_id, elapsed_time_seconds, started_at)
+                SELECT $2, e.id, e.elapsed_time_seconds, e.started_at
+                FROM segment_efforts e
+                JOIN users u ON u.id = e.user_id
+                JOIN activities a ON a.id = e.activity_id
+                WHERE {where_clause}
+                RETURNING started_at
+            )
+            SELECT COUNT(*), MIN(started_at) FROM inserted
+            "#
+        );
+        let (entry_count, oldest): (i64, Option<time::OffsetDateTime>) = sqlx::query_as(&query)
+            .bind(segment_id)
+            .bind(cache_id)
+            .fetch_one(&mut *tx)
+            .await?;
+
+        sqlx::query("UPDATE leaderboard_cache SET expires_at = $2 WHERE id = $1")
+            .bind(cache_id)
+            .bind(bucket.expires_at(oldest, now))
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok((cache_id, entry_count))
+    }
+
+    /// Hold the lock on a segment's cached leaderboards until the transaction
+    /// ends. Ranking a leaderboard from scratch takes it too, so it can't
+    /// miss an effort that is being added to or removed from the cache.
+    async fn lock_leaderboard_cache(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        segment_id: Uuid,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            "SELECT pg_advisory_xact_lock(hashtextextended('leaderboard_cache:' || $1::text, 0))",
+        )
+        .bind(segment_id)
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(())
+    }
+
+    /// A segment's unexpired cached leaderboards, locking them first.
+    async fn get_cached_leaderboards(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        segment_id: Uuid,
+    ) -> Result<Vec<CachedLeaderboard>, AppError> {
+        Self::lock_leaderboard_cache(tx, segment_id).await?;
+
+        let rows: Vec<CachedLeaderboardRow> = sqlx::query_as(
+            r#"
+            SELECT id, scope, gender, age_group
+            FROM leaderboard_cache
+            WHERE segment_id = $1 AND scope_value IS NULL AND expires_at > NOW()
+            "#,
+        )
+        .bind(segment_id)
+        .fetch_all(&mut **tx)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|(id, scope, gender, age_group)| {
+                let bucket = CacheBucket::from_key(&scope, gender, age_group.as_deref())?;
+                Some(CachedLeaderboard { id, bucket })
+            })
+            .collect())
+    }
+
+    /// Add a new effort to the cached leaderboards of its segment that it
+    /// belongs on. Efforts of followers-only activities are left off.
+    /// The leaderboards can only expire sooner.
+    async fn add_effort_to_leaderboard_caches(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        segment_id: Uuid,
+        effort_id: Uuid,
+    ) -> Result<(), AppError> {
+        let cached = Self::get_cached_leaderboards(tx, segment_id).await?;
+        if cached.is_empty() {
+            return Ok(());
+        }
+
+        let scope = |scope| scope_condition(scope).unwrap_or("TRUE");
+        let query = format!(
+            r#"
+            SELECT
+                e.started_at,
+                u.gender,
+                u.birth_year,
+                {} as in_year,
+                {} as in_month,
+                {} as in_week
+            FROM segment_efforts e
+            JOIN users u ON u.id = e.user_id
+            JOIN activities a ON a.id = e.activity_id
+            WHERE e.id = $1 AND {LEADERBOARD_ACTIVITY_CONDITION}
+            "#,
+            scope(LeaderboardScope::Year),
+            scope(LeaderboardScope::Month),
+            scope(LeaderboardScope::Week),
+        );
+        let Some(row) = sqlx::query(&query)
+            .bind(effort_id)
+            .fetch_optional(&mut **tx)
+            .await?
+        else {
+            return Ok(());
+        };
+        let started_at: time::OffsetDateTime = sqlx::Row::try_get(&row, "started_at")?;
+        let placement = EffortPlacement::from_row(&row)?;
+
+        let now = time::OffsetDateTime::now_utc();
+        for cached in cached {
+            if !cached.bucket.includes(&placement, now.year()) {
+                continue;
+            }
+            sqlx::query(
+                r#"
+                INSERT INTO leaderboard_cache_entries (cache_id, effort_id, elapsed_time_seconds, started_at)
+                SELECT $1, id, elapsed_time_seconds, started_at
+                FROM segment_efforts
+                WHERE id = $2
+                ON CONFLICT DO NOTHING
+                "#,
+            )
+            .bind(cached.id)
+            .bind(effort_id)
+            .execute(&mut **tx)
+            .await?;
+            sqlx::query(
+                "UPDATE leaderboard_cache SET expires_at = LEAST(expires_at, $2) WHERE id = $1",
+            )
+            .bind(cached.id)
+            .bind(cached.bucket.expires_at(Some(started_at), now))
+            .execute(&mut **tx)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Hold the locks on the cached leaderboards of the segments an
+    /// activity's efforts are on, before deleting them. Their entries go
+    /// with the efforts.
+    async fn lock_activity_leaderboard_caches(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        activity_id: Uuid,
+    ) -> Result<(), AppError> {
+        // Always lock segments in the same order
+        let segment_ids: Vec<Uuid> = sqlx::query_scalar(
+            "SELECT DISTINCT segment_id FROM segment_efforts WHERE activity_id = $1 ORDER BY segment_id",
+        )
+        .bind(activity_id)
+        .fetch_all(&mut **tx)
+        .await?;
+        for segment_id in segment_ids {
+            Self::lock_leaderboard_cache(tx, segment_id).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Put an activity's efforts on the cached leaderboards, or take them off,
+    /// after it stopped or started being followers-only, and announce the
+    /// change to live leaderboards. The crowns of the segments involved are
+    /// recomputed; holders aren't notified, as the rider may change the
+    /// visibility back. Returns the segments involved.
+    async fn rerank_activity_efforts(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        activity_id: Uuid,
+        ranked: bool,
+    ) -> Result<Vec<Uuid>, AppError> {
+        Self::lock_activity_leaderboard_caches(tx, activity_id).await?;
+        let efforts: Vec<(Uuid, Uuid)> =
+            sqlx::query_as("SELECT id, segment_id FROM segment_efforts WHERE activity_id = $1")
+                .bind(activity_id)
+                .fetch_all(&mut **tx)
+                .await?;
+        if efforts.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        if ranked {
+            for &(effort_id, segment_id) in &efforts {
+                Self::add_effort_to_leaderboard_caches(tx, segment_id, effort_id).await?;
+            }
+        } else {
+            let effort_ids: Vec<Uuid> = efforts.iter().map(|&(effort_id, _)| effort_id).collect();
+            sqlx::query("DELETE FROM leaderboard_cache_entries WHERE effort_id = ANY($1)")
+                .bind(&effort_ids)
+                .execute(&mut **tx)
+                .await?;
+        }
+
+        // Same payloads as the segment_efforts triggers send
+        sqlx::query(
+            r#"
+            SELECT pg_notify(
+                'segment_leaderboards',
+                json_build_object('segment_id', segment_id, $2::TEXT, array_agg(id))::text
+            )
+            FROM segment_efforts
+            WHERE activity_id = $1
+            GROUP BY segment_id
+            "#,
+        )
+        .bind(activity_id)
+        .bind(if ranked {
+            "effort_ids"
+        } else {
+            "removed_effort_ids"
+        })
+        .execute(&mut **tx)
+        .await?;
+
+        let mut segment_ids: Vec<Uuid> =
+            efforts.iter().map(|&(_, segment_id)| segment_id).collect();
+        segment_ids.sort();
+        segment_ids.dedup();
+        for &segment_id in &segment_ids {
+            Self::rebuild_segment_crowns(tx, segment_id).await?;
+        }
+
+        Ok(segment_ids)
+    }
+
+    /// Drop the cached gender and age group leaderboards of every segment a
+    /// user has ridden, after their demographics changed.
+    async fn invalidate_demographic_leaderboards(&self, user_id: Uuid) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+            DELETE FROM leaderboard_cache
+            WHERE (gender IS NOT NULL OR age_group IS NOT NULL)
+              AND segment_id IN (SELECT segment_id FROM segment_efforts WHERE user_id = $1)
+            "#,
+        )
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
     }
 
     // ========================================================================
-    // Achievement Methods
+    // User Demographics Methods
     // ========================================================================
 
-    /// Create or update an achievement (crown).
-    pub async fn create_achievement(
+    /// Get a user with their demographics.
+    pub async fn get_user_with_demographics(
         &self,
         user_id: Uuid,
-        segment_id: Uuid,
-        effort_id: Option<Uuid>,
-        achievement_type: AchievementType,
-        effort_count: Option<i32>,
-    ) -> Result<Achievement, AppError> {
-        let achievement: Achievement = sqlx::query_as(
+    ) -> Result<Option<UserWithDemographics>, AppError> {
+        let user: Option<UserWithDemographics> = sqlx::query_as(
             r#"
-            INSERT INTO achievements (id, user_id, segment_id, effort_id, achievement_type, effort_count, earned_at, created_at)
-            VALUES (gen_random_uuid(), $1, $2, $3, $4, $5, NOW(), NOW())
-            RETURNING id, user_id, segment_id, effort_id, achievement_type, earned_at, lost_at, effort_count, created_at
+            SELECT id, email, name, created_at, gender, birth_year, weight_kg, country, region
+            FROM users
+            WHERE id = $1
             "#,
         )
         .bind(user_id)
-        .bind(segment_id)
-        .bind(effort_id)
-        .bind(achievement_type)
-        .bind(effort_count)
-        .fetch_one(&self.pool)
+        .fetch_optional(&self.pool)
         .await?;
 
-        Ok(achievement)
+        Ok(user)
     }
 
-    /// Mark an achievement as lost (dethroned).
-    pub async fn dethrone_achievement(
+    /// Update a user's demographics.
+    pub async fn update_user_demographics(
         &self,
-        segment_id: Uuid,
-        achievement_type: AchievementType,
-    ) -> Result<Option<Achievement>, AppError> {
-        let achievement: Option<Achievement> = sqlx::query_as(
+        user_id: Uuid,
+        req: &UpdateDemographicsRequest,
+    ) -> Result<UserWithDemographics, AppError> {
+        let user: UserWithDemographics = sqlx::query_as(
             r#"
-            UPDATE achievements
-            SET lost_at = NOW()
-            WHERE segment_id = $1 AND achievement_type = $2 AND lost_at IS NULL
-            RETURNING id, user_id, segment_id, effort_id, achievement_type, earned_at, lost_at, effort_count, created_at
+            UPDATE users
+            SET gender = COALESCE($2, gender),
+                birth_year = COALESCE($3, birth_year),
+                weight_kg = COALESCE($4, weight_kg),
+                country = COALESCE($5, country),
+                region = COALESCE($6, region)
+            WHERE id = $1
+            RETURNING id, email, name, created_at, gender, birth_year, weight_kg, country, region
             "#,
         )
-        .bind(segment_id)
-        .bind(achievement_type)
-        .fetch_optional(&self.pool)
+        .bind(user_id)
+        .bind(req.gender)
+        .bind(req.birth_year)
+        .bind(req.weight_kg)
+        .bind(&req.country)
+        .bind(&req.region)
+        .fetch_one(&self.pool)
         .await?;
 
-        Ok(achievement)
+        if req.gender.is_some() || req.birth_year.is_some() {
+            self.invalidate_demographic_leaderboards(user_id).await?;
+        }
+
+        Ok(user)
     }
 
+    // ========================================================================
+    // Achievement Methods
+    // ========================================================================
+
     /// Get the current holder of an achievement for a segment.
     pub async fn get_current_achievement_holder(
         &self,
@@ -2017,55 +3219,296 @@ impl Database {
         Ok(holder)
     }
 
-    /// Get all achievements for a user.
-    pub async fn get_user_achievements(
+    /// Award a time-based crown to an effort if it beats the current holder's
+    /// time, dethroning them. A holder whose effort is gone or no longer ranked
+    /// is beaten by any time, and efforts that aren't ranked on leaderboards
+    /// can't take crowns. Returns the change of holder, if any.
+    ///
+    /// The comparison and the award run in one transaction under the
+    /// segment's leaderboard lock, so two efforts finishing at once can't
+    /// both take the crown, nor can a visibility change slip in between.
+    pub async fn award_fastest_effort(
         &self,
+        segment_id: Uuid,
         user_id: Uuid,
-        include_lost: bool,
-    ) -> Result<Vec<AchievementWithSegment>, AppError> {
-        let lost_filter = if include_lost {
-            ""
-        } else {
-            "AND a.lost_at IS NULL"
-        };
+        effort_id: Uuid,
+        elapsed_time_seconds: f64,
+        achievement_type: AchievementType,
+    ) -> Result<Option<CrownChange>, AppError> {
+        let mut tx = self.pool.begin().await?;
+        Self::lock_leaderboard_cache(&mut tx, segment_id).await?;
+
         let query = format!(
             r#"
-            SELECT
-                a.id,
-                a.user_id,
-                a.segment_id,
-                a.effort_id,
-                a.achievement_type,
-                a.earned_at,
-                a.lost_at,
-                a.effort_count,
-                s.name as segment_name,
-                s.distance_meters as segment_distance_meters,
-                s.activity_type_id as segment_activity_type_id
-            FROM achievements a
-            JOIN segments s ON s.id = a.segment_id
-            WHERE a.user_id = $1 {lost_filter}
-            ORDER BY a.earned_at DESC
+            SELECT 1
+            FROM segment_efforts e
+            JOIN activities a ON a.id = e.activity_id
+            WHERE e.id = $1 AND {LEADERBOARD_ACTIVITY_CONDITION}
             "#
         );
-
-        let achievements: Vec<AchievementWithSegment> = sqlx::query_as(&query)
-            .bind(user_id)
-            .fetch_all(&self.pool)
+        let ranked: Option<i32> = sqlx::query_scalar(&query)
+            .bind(effort_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        if ranked.is_none() {
+            return Ok(None);
+        }
+
+        let query = format!(
+            r#"
+            SELECT ach.id, ach.user_id, e.elapsed_time_seconds
+            FROM achievements ach
+            LEFT JOIN (
+                segment_efforts e
+                JOIN activities a ON a.id = e.activity_id AND {LEADERBOARD_ACTIVITY_CONDITION}
+            ) ON e.id = ach.effort_id
+            WHERE ach.segment_id = $1 AND ach.achievement_type = $2 AND ach.lost_at IS NULL
+            "#
+        );
+        let current: Option<(Uuid, Uuid, Option<f64>)> = sqlx::query_as(&query)
+            .bind(segment_id)
+            .bind(achievement_type)
+            .fetch_optional(&mut *tx)
+            .await?;
+        if let Some((_, _, Some(holder_time))) = current
+            && elapsed_time_seconds >= holder_time
+        {
+            return Ok(None);
+        }
+
+        if let Some((id, _, _)) = current {
+            sqlx::query("UPDATE achievements SET lost_at = NOW() WHERE id = $1")
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        sqlx::query(
+            r#"
+            INSERT INTO achievements (id, user_id, segment_id, effort_id, achievement_type, earned_at, created_at)
+            VALUES (gen_random_uuid(), $1, $2, $3, $4, NOW(), NOW())
+            "#,
+        )
+        .bind(user_id)
+        .bind(segment_id)
+        .bind(effort_id)
+        .bind(achievement_type)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(Some(CrownChange {
+            achievement_type,
+            previous_holder_id: current.map(|(_, holder, _)| holder),
+            new_holder_id: Some(user_id),
+            effort_id: Some(effort_id),
+        }))
+    }
+
+    /// Get the holder of an achievement, current or former. A time-based
+    /// crown whose effort isn't ranked on leaderboards has no holder to show,
+    /// so a followers-only rider's name and time don't reach live leaderboards.
+    pub async fn get_achievement_holder(
+        &self,
+        achievement_id: Uuid,
+    ) -> Result<Option<AchievementHolder>, AppError> {
+        let query = format!(
+            r#"
+            SELECT
+                ach.user_id,
+                u.name as user_name,
+                ach.achievement_type,
+                ach.earned_at,
+                e.elapsed_time_seconds,
+                ach.effort_count
+            FROM achievements ach
+            JOIN users u ON u.id = ach.user_id
+            LEFT JOIN segment_efforts e ON e.id = ach.effort_id
+            LEFT JOIN activities a ON a.id = e.activity_id
+            WHERE ach.id = $1
+              AND (ach.effort_id IS NULL OR ({LEADERBOARD_ACTIVITY_CONDITION}))
+            "#
+        );
+        let holder: Option<AchievementHolder> = sqlx::query_as(&query)
+            .bind(achievement_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(holder)
+    }
+
+    /// Recompute the Local Legend of a segment: the athlete with the most
+    /// ranked efforts started within `window`. The current holder keeps the
+    /// title on a tie. Returns the change of holder, if any.
+    pub async fn refresh_local_legend(
+        &self,
+        segment_id: Uuid,
+        window: time::Duration,
+    ) -> Result<Option<CrownChange>, AppError> {
+        let mut tx = self.pool.begin().await?;
+
+        // Serialize recomputation per segment. NO KEY UPDATE doesn't block
+        // effort inserts referencing the segment.
+        let exists: Option<(Uuid,)> =
+            sqlx::query_as("SELECT id FROM segments WHERE id = $1 FOR NO KEY UPDATE")
+                .bind(segment_id)
+                .fetch_optional(&mut *tx)
+                .await?;
+        if exists.is_none() {
+            return Ok(None);
+        }
+
+        let current: Option<(Uuid, Uuid)> = sqlx::query_as(
+            r#"
+            SELECT id, user_id
+            FROM achievements
+            WHERE segment_id = $1 AND achievement_type = 'local_legend' AND lost_at IS NULL
+            "#,
+        )
+        .bind(segment_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let query = format!(
+            r#"
+            SELECT e.user_id, COUNT(*) AS efforts
+            FROM segment_efforts e
+            JOIN activities a ON a.id = e.activity_id
+            WHERE e.segment_id = $1 AND e.started_at >= NOW() - $2::interval
+              AND {LEADERBOARD_ACTIVITY_CONDITION}
+            GROUP BY e.user_id
+            ORDER BY efforts DESC, (e.user_id = $3) DESC, MAX(e.started_at) ASC
+            LIMIT 1
+            "#
+        );
+        let leader: Option<(Uuid, i64)> = sqlx::query_as(&query)
+            .bind(segment_id)
+            .bind(window)
+            .bind(current.map(|(_, user_id)| user_id))
+            .fetch_optional(&mut *tx)
+            .await?;
+
+        let change = match (current, leader) {
+            (None, None) => None,
+            (Some((id, holder)), Some((user_id, efforts))) if holder == user_id => {
+                sqlx::query("UPDATE achievements SET effort_count = $2 WHERE id = $1")
+                    .bind(id)
+                    .bind(efforts as i32)
+                    .execute(&mut *tx)
+                    .await?;
+                None
+            }
+            (current, leader) => {
+                if let Some((id, _)) = current {
+                    sqlx::query("UPDATE achievements SET lost_at = NOW() WHERE id = $1")
+                        .bind(id)
+                        .execute(&mut *tx)
+                        .await?;
+                }
+                if let Some((user_id, efforts)) = leader {
+                    sqlx::query(
+                        r#"
+                        INSERT INTO achievements
+                            (user_id, segment_id, achievement_type, effort_count, earned_at, created_at)
+                        VALUES ($1, $2, 'local_legend', $3, NOW(), NOW())
+                        "#,
+                    )
+                    .bind(user_id)
+                    .bind(segment_id)
+                    .bind(efforts as i32)
+                    .execute(&mut *tx)
+                    .await?;
+                }
+                Some(CrownChange {
+                    achievement_type: AchievementType::LocalLegend,
+                    previous_holder_id: current.map(|(_, holder)| holder),
+                    new_holder_id: leader.map(|(user_id, _)| user_id),
+                    effort_id: None,
+                })
+            }
+        };
+
+        tx.commit().await?;
+        Ok(change)
+    }
+
+    /// Segments whose Local Legend may need recomputing: those with a current
+    /// holder or with efforts inside `window`.
+    pub async fn get_local_legend_segments(
+        &self,
+        window: time::Duration,
+    ) -> Result<Vec<Uuid>, AppError> {
+        let ids: Vec<(Uuid,)> = sqlx::query_as(
+            r#"
+            SELECT segment_id
+            FROM achievements
+            WHERE achievement_type = 'local_legend' AND lost_at IS NULL
+            UNION
+            SELECT DISTINCT segment_id
+            FROM segment_efforts
+            WHERE started_at >= NOW() - $1::interval
+            "#,
+        )
+        .bind(window)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(ids.into_iter().map(|(id,)| id).collect())
+    }
+
+    /// Get all achievements for a user.
+    pub async fn get_user_achievements(
+        &self,
+        user_id: Uuid,
+        include_lost: bool,
+    ) -> Result<Vec<AchievementWithSegment>, AppError> {
+        let lost_filter = if include_lost {
+            ""
+        } else {
+            "AND a.lost_at IS NULL"
+        };
+        let query = format!(
+            r#"
+            SELECT
+                a.id,
+                a.user_id,
+                a.segment_id,
+                a.effort_id,
+                a.achievement_type,
+                a.earned_at,
+                a.lost_at,
+                a.effort_count,
+                s.name as segment_name,
+                s.distance_meters as segment_distance_meters,
+                s.activity_type_id as segment_activity_type_id
+            FROM achievements a
+            JOIN segments s ON s.id = a.segment_id
+            WHERE a.user_id = $1 {lost_filter}
+            ORDER BY a.earned_at DESC
+            "#
+        );
+
+        let achievements: Vec<AchievementWithSegment> = sqlx::query_as(&query)
+            .bind(user_id)
+            .fetch_all(&self.pool)
             .await?;
 
         Ok(achievements)
     }
 
-    /// Get achievement counts for a user.
-    pub async fn get_user_achievement_counts(&self, user_id: Uuid) -> Result<(i64, i64), AppError> {
-        let counts: (i64, i64) = sqlx::query_as(
+    /// Count the crowns a user currently holds.
+    pub async fn get_user_achievement_counts(
+        &self,
+        user_id: Uuid,
+    ) -> Result<AchievementCounts, AppError> {
+        let counts: AchievementCounts = sqlx::query_as(
             r#"
             SELECT
-                COUNT(*) FILTER (WHERE achievement_type = 'kom' AND lost_at IS NULL),
-                COUNT(*) FILTER (WHERE achievement_type = 'qom' AND lost_at IS NULL)
+                COUNT(*) FILTER (WHERE achievement_type = 'kom') AS kom_count,
+                COUNT(*) FILTER (WHERE achievement_type = 'qom') AS qom_count,
+                COUNT(*) FILTER (WHERE achievement_type = 'course_record') AS course_record_count,
+                COUNT(*) FILTER (WHERE achievement_type = 'local_legend') AS local_legend_count
             FROM achievements
-            WHERE user_id = $1
+            WHERE user_id = $1 AND lost_at IS NULL
             "#,
         )
         .bind(user_id)
@@ -2092,9 +3535,10 @@ impl Database {
                     user_id,
                     COUNT(*) FILTER (WHERE achievement_type = 'kom') as kom_count,
                     COUNT(*) FILTER (WHERE achievement_type = 'qom') as qom_count,
+                    COUNT(*) FILTER (WHERE achievement_type = 'course_record') as course_record_count,
                     COUNT(*) as total_crowns
                 FROM achievements
-                WHERE lost_at IS NULL
+                WHERE lost_at IS NULL AND achievement_type <> 'local_legend'
                 GROUP BY user_id
             )
             SELECT
@@ -2102,6 +3546,7 @@ impl Database {
                 u.name as user_name,
                 cc.kom_count,
                 cc.qom_count,
+                cc.course_record_count,
                 cc.total_crowns,
                 ROW_NUMBER() OVER (ORDER BY cc.total_crowns DESC, cc.kom_count DESC) as rank
             FROM crown_counts cc
@@ -2179,8 +3624,9 @@ impl Database {
         // Start at index 3 since $1 and $2 are used for LIMIT and OFFSET
         let mut qb = QueryBuilder::with_start_index(3);
 
-        // Base condition: only active crowns
+        // Base condition: only active crowns (Local Legend rewards effort count, not speed)
         qb.add_condition("a.lost_at IS NULL");
+        qb.add_condition("a.achievement_type <> 'local_legend'");
 
         // Time scope filter on the effort that earned the crown
         match scope {
@@ -2260,6 +3706,7 @@ impl Database {
                     a.user_id,
                     COUNT(*) FILTER (WHERE a.achievement_type = 'kom') as kom_count,
                     COUNT(*) FILTER (WHERE a.achievement_type = 'qom') as qom_count,
+                    COUNT(*) FILTER (WHERE a.achievement_type = 'course_record') as course_record_count,
                     COUNT(*) as total_crowns
                 FROM achievements a
                 JOIN users u ON u.id = a.user_id
@@ -2274,6 +3721,7 @@ impl Database {
                 u.name as user_name,
                 cc.kom_count,
                 cc.qom_count,
+                cc.course_record_count,
                 cc.total_crowns,
                 ROW_NUMBER() OVER (ORDER BY cc.total_crowns DESC, cc.kom_count DESC) as rank
             FROM crown_counts cc
@@ -3065,7 +4513,7 @@ impl Database {
         Ok(counts)
     }
 
-    /// Get a user profile with follow counts.
+    /// Get a user profile with follow counts and the crowns they hold.
     pub async fn get_user_profile(
         &self,
         user_id: Uuid,
@@ -3084,7 +4532,12 @@ impl Database {
         .fetch_optional(&self.pool)
         .await?;
 
-        Ok(profile)
+        let Some(mut profile) = profile else {
+            return Ok(None);
+        };
+        profile.achievements = self.get_user_achievement_counts(user_id).await?;
+
+        Ok(Some(profile))
     }
 
     /// Get a user by ID (basic info only).
@@ -3127,6 +4580,10 @@ impl Database {
                 s.distance,
                 s.duration,
                 s.elevation_gain,
+                s.moving_time, s.max_speed, s.avg_speed,
+                s.elevation_loss, s.max_elevation, s.min_elevation,
+                s.avg_heart_rate, s.max_heart_rate, s.avg_cadence, s.max_cadence,
+                s.avg_power, s.max_power,
                 COALESCE(a.kudos_count, 0) as kudos_count,
                 COALESCE(a.comment_count, 0) as comment_count
             FROM activities a
@@ -3135,7 +4592,7 @@ impl Database {
             WHERE a.user_id IN (
                 SELECT following_id FROM follows WHERE follower_id = $1
             )
-            AND a.visibility = 'public'
+            AND a.visibility IN ('public', 'followers')
             AND a.deleted_at IS NULL
             ORDER BY a.submitted_at DESC
             LIMIT $2 OFFSET $3
@@ -3174,8 +4631,8 @@ impl Database {
             "a.user_id IN (SELECT following_id FROM follows WHERE follower_id = ${subquery_idx})"
         ));
 
-        // Always require public visibility and not deleted
-        qb.add_condition("a.visibility = 'public'");
+        // Always require visibility to followers and not deleted
+        qb.add_condition("a.visibility IN ('public', 'followers')");
         qb.add_condition("a.deleted_at IS NULL");
 
         // Optional activity type filter
@@ -3203,6 +4660,10 @@ impl Database {
                 s.distance,
                 s.duration,
                 s.elevation_gain,
+                s.moving_time, s.max_speed, s.avg_speed,
+                s.elevation_loss, s.max_elevation, s.min_elevation,
+                s.avg_heart_rate, s.max_heart_rate, s.avg_cadence, s.max_cadence,
+                s.avg_power, s.max_power,
                 COALESCE(a.kudos_count, 0) as kudos_count,
                 COALESCE(a.comment_count, 0) as comment_count
             FROM activities a
@@ -3249,7 +4710,8 @@ impl Database {
     /// - If `user_id` is None (anonymous), only public activities are returned.
     /// - If `user_id` is Some and `mine_only` is true, only that user's activities are returned.
     /// - If `user_id` is Some and `mine_only` is false, returns public activities,
-    ///   the user's own private activities, and activities shared with teams the user is a member of.
+    ///   the user's own private activities, followers-only activities of users they
+    ///   follow, and activities shared with teams the user is a member of.
     pub async fn get_activities_by_date(
         &self,
         date: time::Date,
@@ -3274,6 +4736,10 @@ impl Database {
                         s.distance,
                         s.duration,
                         s.elevation_gain,
+                        s.moving_time, s.max_speed, s.avg_speed,
+                        s.elevation_loss, s.max_elevation, s.min_elevation,
+                        s.avg_heart_rate, s.max_heart_rate, s.avg_cadence, s.max_cadence,
+                        s.avg_power, s.max_power,
                         COALESCE(a.kudos_count, 0) as kudos_count,
                         COALESCE(a.comment_count, 0) as comment_count
                     FROM activities a
@@ -3293,9 +4759,9 @@ impl Database {
                 .fetch_all(&self.pool)
                 .await?
             }
-            // Authenticated user sees public + own private + team-shared
+            // Authenticated user sees public + own private + followed + team-shared
             (Some(uid), false) => {
-                sqlx::query_as(
+                sqlx::query_as(&format!(
                     r#"
                     SELECT
                         a.id,
@@ -3308,6 +4774,10 @@ impl Database {
                         s.distance,
                         s.duration,
                         s.elevation_gain,
+                        s.moving_time, s.max_speed, s.avg_speed,
+                        s.elevation_loss, s.max_elevation, s.min_elevation,
+                        s.avg_heart_rate, s.max_heart_rate, s.avg_cadence, s.max_cadence,
+                        s.avg_power, s.max_power,
                         COALESCE(a.kudos_count, 0) as kudos_count,
                         COALESCE(a.comment_count, 0) as comment_count
                     FROM activities a
@@ -3315,19 +4785,12 @@ impl Database {
                     LEFT JOIN scores s ON a.id = s.activity_id
                     WHERE DATE(COALESCE(a.started_at, a.submitted_at)) = $1
                     AND a.deleted_at IS NULL
-                    AND (
-                        a.visibility = 'public'
-                        OR a.user_id = $2
-                        OR (a.visibility = 'teams_only' AND EXISTS (
-                            SELECT 1 FROM activity_teams at
-                            JOIN team_memberships tm ON tm.team_id = at.team_id
-                            WHERE at.activity_id = a.id AND tm.user_id = $2
-                        ))
-                    )
+                    AND {visible}
                     ORDER BY COALESCE(a.started_at, a.submitted_at) DESC
                     LIMIT $3 OFFSET $4
                     "#,
-                )
+                    visible = activity_visible_to(2),
+                ))
                 .bind(date)
                 .bind(uid)
                 .bind(limit)
@@ -3350,6 +4813,10 @@ impl Database {
                         s.distance,
                         s.duration,
                         s.elevation_gain,
+                        s.moving_time, s.max_speed, s.avg_speed,
+                        s.elevation_loss, s.max_elevation, s.min_elevation,
+                        s.avg_heart_rate, s.max_heart_rate, s.avg_cadence, s.max_cadence,
+                        s.avg_power, s.max_power,
                         COALESCE(a.kudos_count, 0) as kudos_count,
                         COALESCE(a.comment_count, 0) as comment_count
                     FROM activities a
@@ -3579,7 +5046,8 @@ impl Database {
             r#"
             INSERT INTO notifications (id, user_id, notification_type, actor_id, target_type, target_id, message, created_at)
             VALUES (gen_random_uuid(), $1, $2, $3, $4, $5, $6, NOW())
-            RETURNING id, user_id, notification_type, actor_id, target_type, target_id, message, read_at, created_at
+            RETURNING id, user_id, notification_type, actor_id, target_type, target_id, message,
+                      effort_id, read_at, created_at
             "#,
         )
         .bind(user_id)
@@ -3594,6 +5062,37 @@ impl Database {
         Ok(notification)
     }
 
+    /// Create a notification about a segment effort, such as a crown changing
+    /// hands or a new PR. The notification targets the segment.
+    pub async fn create_effort_notification(
+        &self,
+        user_id: Uuid,
+        notification_type: &str,
+        actor_id: Option<Uuid>,
+        segment_id: Uuid,
+        effort_id: Option<Uuid>,
+        message: &str,
+    ) -> Result<crate::models::Notification, AppError> {
+        let notification: crate::models::Notification = sqlx::query_as(
+            r#"
+            INSERT INTO notifications (id, user_id, notification_type, actor_id, target_type, target_id, message, effort_id, created_at)
+            VALUES (gen_random_uuid(), $1, $2, $3, 'segment', $4, $5, $6, NOW())
+            RETURNING id, user_id, notification_type, actor_id, target_type, target_id, message,
+                      effort_id, read_at, created_at
+            "#,
+        )
+        .bind(user_id)
+        .bind(notification_type)
+        .bind(actor_id)
+        .bind(segment_id)
+        .bind(message)
+        .bind(effort_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(notification)
+    }
+
     /// Get notifications for a user.
     pub async fn get_notifications(
         &self,
@@ -3612,10 +5111,16 @@ impl Database {
                 n.target_type,
                 n.target_id,
                 n.message,
+                sg.name as segment_name,
+                n.effort_id,
+                e.activity_id as effort_activity_id,
+                e.elapsed_time_seconds as effort_elapsed_time_seconds,
                 n.read_at,
                 n.created_at
             FROM notifications n
             LEFT JOIN users u ON u.id = n.actor_id
+            LEFT JOIN segments sg ON n.target_type = 'segment' AND sg.id = n.target_id
+            LEFT JOIN segment_efforts e ON e.id = n.effort_id
             WHERE n.user_id = $1
             ORDER BY n.created_at DESC
             LIMIT $2 OFFSET $3
@@ -3630,6 +5135,42 @@ impl Database {
         Ok(notifications)
     }
 
+    /// Get a single notification with its actor and effort details.
+    pub async fn get_notification(
+        &self,
+        notification_id: Uuid,
+    ) -> Result<Option<crate::models::NotificationWithActor>, AppError> {
+        let notification = sqlx::query_as(
+            r#"
+            SELECT
+                n.id,
+                n.user_id,
+                n.notification_type,
+                n.actor_id,
+                u.name as actor_name,
+                n.target_type,
+                n.target_id,
+                n.message,
+                sg.name as segment_name,
+                n.effort_id,
+                e.activity_id as effort_activity_id,
+                e.elapsed_time_seconds as effort_elapsed_time_seconds,
+                n.read_at,
+                n.created_at
+            FROM notifications n
+            LEFT JOIN users u ON u.id = n.actor_id
+            LEFT JOIN segments sg ON n.target_type = 'segment' AND sg.id = n.target_id
+            LEFT JOIN segment_efforts e ON e.id = n.effort_id
+            WHERE n.id = $1
+            "#,
+        )
+        .bind(notification_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(notification)
+    }
+
     /// Get unread notification count for a user.
     pub async fn get_unread_notification_count(&self, user_id: Uuid) -> Result<i64, AppError> {
         let count: (i64,) = sqlx::query_as(
@@ -4603,6 +6144,10 @@ impl Database {
                 s.distance,
                 s.duration,
                 s.elevation_gain,
+                s.moving_time, s.max_speed, s.avg_speed,
+                s.elevation_loss, s.max_elevation, s.min_elevation,
+                s.avg_heart_rate, s.max_heart_rate, s.avg_cadence, s.max_cadence,
+                s.avg_power, s.max_power,
                 COALESCE(a.kudos_count, 0) as kudos_count,
                 COALESCE(a.comment_count, 0) as comment_count
             FROM activities a
@@ -4635,7 +6180,7 @@ impl Database {
             SELECT s.id, s.creator_id, u.name as creator_name, s.name, s.description, s.activity_type_id,
                    s.distance_meters, s.elevation_gain_meters, s.elevation_loss_meters,
                    s.average_grade, s.max_grade, s.climb_category,
-                   s.visibility, s.created_at
+                   s.visibility, s.version, s.created_at
             FROM segments s
             JOIN segment_teams st ON st.segment_id = s.id
             JOIN users u ON u.id = s.creator_id
@@ -4674,6 +6219,10 @@ impl Database {
                 s.distance,
                 s.duration,
                 s.elevation_gain,
+                s.moving_time, s.max_speed, s.avg_speed,
+                s.elevation_loss, s.max_elevation, s.min_elevation,
+                s.avg_heart_rate, s.max_heart_rate, s.avg_cadence, s.max_cadence,
+                s.avg_power, s.max_power,
                 COALESCE(a.kudos_count, 0) as kudos_count,
                 COALESCE(a.comment_count, 0) as comment_count
             FROM activities a
@@ -4753,12 +6302,18 @@ impl Database {
     // Stopped Segment / Dig Tagging Methods
     // ========================================================================
 
-    /// Save detected stopped segments for an activity.
+    /// Save detected stopped segments for an activity, replacing any from an
+    /// earlier processing attempt.
     pub async fn save_stopped_segments(
         &self,
         activity_id: Uuid,
         segments: &[crate::activity_queue::DetectedStoppedSegment],
     ) -> Result<(), AppError> {
+        sqlx::query("DELETE FROM activity_stopped_segments WHERE activity_id = $1")
+            .bind(activity_id)
+            .execute(&self.pool)
+            .await?;
+
         for segment in segments {
             sqlx::query(
                 r#"
@@ -4849,6 +6404,7 @@ impl Database {
 
     /// Save dig segments extracted from multi-sport activities.
     /// Used when processing activities with DIG activity type segments.
+    /// Parts already saved by an earlier processing attempt are skipped.
     pub async fn save_dig_parts_batch(
         &self,
         activity_id: Uuid,
@@ -4859,8 +6415,11 @@ impl Database {
                 r#"
                 INSERT INTO activity_dig_parts
                     (activity_id, start_time, end_time, duration_seconds)
-                VALUES ($1, $2, $3, $4)
-                ON CONFLICT DO NOTHING
+                SELECT $1, $2, $3, $4
+                WHERE NOT EXISTS (
+                    SELECT 1 FROM activity_dig_parts
+                    WHERE activity_id = $1 AND start_time = $2 AND end_time = $3
+                )
                 "#,
             )
             .bind(activity_id)
@@ -5142,6 +6701,38 @@ impl Database {
         Ok(())
     }
 
+    /// Get the raw sensor arrays for an activity, parallel to its track points.
+    #[allow(clippy::type_complexity)]
+    pub async fn get_activity_sensor_arrays(
+        &self,
+        activity_id: Uuid,
+    ) -> Result<Option<crate::file_parsers::SensorData>, AppError> {
+        let row: Option<(
+            Option<Vec<Option<i32>>>,
+            Option<Vec<Option<i32>>>,
+            Option<Vec<Option<i32>>>,
+            Option<Vec<Option<f64>>>,
+        )> = sqlx::query_as(
+            r#"
+            SELECT heart_rates, cadences, powers, temperatures
+            FROM activity_sensor_data
+            WHERE activity_id = $1
+            "#,
+        )
+        .bind(activity_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(|(heart_rates, cadences, powers, temperatures)| {
+            crate::file_parsers::SensorData {
+                heart_rates: heart_rates.unwrap_or_default(),
+                cadences: cadences.unwrap_or_default(),
+                powers: powers.unwrap_or_default(),
+                temperatures: temperatures.unwrap_or_default(),
+            }
+        }))
+    }
+
     /// Get sensor data for an activity, including calculated distances from track geometry.
     #[allow(clippy::type_complexity)]
     pub async fn get_sensor_data(
@@ -5233,28 +6824,1043 @@ impl Database {
     }
 
     // ========================================================================
-    // Recovery Methods
+    // Activity Job Methods
     // ========================================================================
 
-    /// Find activities that have been uploaded but not fully processed.
-    /// These are activities with an object_store_path but no corresponding track geometry.
-    pub async fn find_orphaned_activities(
+    /// Queue an activity for processing. Returns the existing job if the activity
+    /// already has one queued or in progress. `notify` is whether the rider
+    /// hears about PRs the job finds.
+    pub async fn enqueue_activity_job(
+        &self,
+        activity_id: Uuid,
+        user_id: Uuid,
+        notify: bool,
+    ) -> Result<ActivityJob, AppError> {
+        loop {
+            let inserted: Option<ActivityJob> = sqlx::query_as(
+                r#"
+                INSERT INTO activity_jobs (activity_id, user_id, notify)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (activity_id) WHERE status IN ('queued', 'processing') DO NOTHING
+                RETURNING id, activity_id, user_id, status, stage, attempts, max_attempts, last_error, last_error_kind,
+                          run_after, notify, created_at, updated_at, finished_at
+                "#,
+            )
+            .bind(activity_id)
+            .bind(user_id)
+            .bind(notify)
+            .fetch_optional(&self.pool)
+            .await?;
+
+            if let Some(job) = inserted {
+                return Ok(job);
+            }
+
+            let existing: Option<ActivityJob> = sqlx::query_as(
+                r#"
+                SELECT id, activity_id, user_id, status, stage, attempts, max_attempts, last_error, last_error_kind,
+                       run_after, notify, created_at, updated_at, finished_at
+                FROM activity_jobs
+                WHERE activity_id = $1 AND status IN ('queued', 'processing')
+                "#,
+            )
+            .bind(activity_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+            // Otherwise the live job finished in the meantime: insert again
+            if let Some(job) = existing {
+                return Ok(job);
+            }
+        }
+    }
+
+    /// Queue an edited activity to be matched again. A queued job will see the
+    /// edit when it starts; a job already processing is flagged to run again
+    /// once it finishes, since it loaded the activity before the edit.
+    pub async fn enqueue_activity_rematch(
         &self,
-    ) -> Result<Vec<crate::models::OrphanedActivity>, AppError> {
-        let rows = sqlx::query_as::<_, crate::models::OrphanedActivity>(
+        activity_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<ActivityJob, AppError> {
+        loop {
+            let job = self
+                .enqueue_activity_job(activity_id, user_id, true)
+                .await?;
+            if job.status != JobStatus::Processing {
+                return Ok(job);
+            }
+
+            let flagged: Option<ActivityJob> = sqlx::query_as(
+                r#"
+                UPDATE activity_jobs
+                SET rerun = TRUE, updated_at = NOW()
+                WHERE id = $1 AND status = 'processing'
+                RETURNING id, activity_id, user_id, status, stage, attempts, max_attempts, last_error, last_error_kind,
+                          run_after, notify, created_at, updated_at, finished_at
+                "#,
+            )
+            .bind(job.id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+            // Otherwise the job finished in the meantime: queue a new one
+            if let Some(job) = flagged {
+                return Ok(job);
+            }
+        }
+    }
+
+    /// Claim the next runnable job, marking it processing and counting the attempt.
+    /// Uses SKIP LOCKED so concurrent workers (and server instances) never claim the same job.
+    pub async fn claim_activity_job(&self) -> Result<Option<ClaimedActivityJob>, AppError> {
+        let job: Option<ClaimedActivityJob> = sqlx::query_as(
             r#"
-            SELECT a.id, a.user_id, a.activity_type_id, a.object_store_path,
-                   a.type_boundaries, a.segment_types
-            FROM activities a
-            LEFT JOIN tracks t ON a.id = t.activity_id
-            WHERE t.id IS NULL
-              AND a.deleted_at IS NULL
-            ORDER BY a.submitted_at ASC
+            UPDATE activity_jobs
+            SET status = 'processing',
+                attempts = attempts + 1,
+                rerun = FALSE,
+                claim_token = gen_random_uuid(),
+                locked_at = NOW(),
+                updated_at = NOW()
+            WHERE id = (
+                SELECT id FROM activity_jobs
+                WHERE status = 'queued' AND run_after <= NOW()
+                ORDER BY run_after ASC
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, activity_id, user_id, status, stage, attempts, max_attempts, last_error, last_error_kind,
+                      run_after, notify, created_at, updated_at, finished_at, claim_token
             "#,
         )
-        .fetch_all(&self.pool)
+        .fetch_optional(&self.pool)
         .await?;
 
-        Ok(rows)
+        Ok(job)
+    }
+
+    /// Mark a job as successfully finished. A job whose activity was edited
+    /// while it ran goes back to the queue instead, as a fresh run. Returns
+    /// whether the job was requeued, or None, leaving the job alone, if the
+    /// claim is no longer held.
+    pub async fn complete_activity_job(
+        &self,
+        job_id: Uuid,
+        claim_token: Uuid,
+    ) -> Result<Option<bool>, AppError> {
+        let requeued: Option<(bool,)> = sqlx::query_as(
+            r#"
+            UPDATE activity_jobs
+            SET status = CASE WHEN rerun THEN 'queued'::job_status ELSE 'succeeded'::job_status END,
+                stage = CASE WHEN rerun THEN 'queued'::processing_stage ELSE 'done'::processing_stage END,
+                attempts = CASE WHEN rerun THEN 0 ELSE attempts END,
+                run_after = CASE WHEN rerun THEN NOW() ELSE run_after END,
+                last_error = NULL,
+                last_error_kind = NULL,
+                claim_token = NULL,
+                locked_at = NULL,
+                updated_at = NOW(),
+                finished_at = CASE WHEN rerun THEN NULL ELSE NOW() END,
+                rerun = FALSE
+            WHERE id = $1 AND claim_token = $2 AND status = 'processing'
+            RETURNING status = 'queued'
+            "#,
+        )
+        .bind(job_id)
+        .bind(claim_token)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(requeued.map(|(requeued,)| requeued))
     }
+
+    /// Record a failed attempt and its error against the stage the job was in.
+    /// With `retry_at` the job goes back to the queue until then; without it the
+    /// job is marked failed for good. Returns the stage the attempt failed in,
+    /// or None, leaving the job alone, if the claim is no longer held.
+    pub async fn fail_activity_job(
+        &self,
+        job_id: Uuid,
+        claim_token: Uuid,
+        error: &ProcessingErrorRecord<'_>,
+        retry_at: Option<time::OffsetDateTime>,
+    ) -> Result<Option<ProcessingStage>, AppError> {
+        let stage: Option<(ProcessingStage,)> = sqlx::query_as(
+            r#"
+            WITH failed AS (
+                UPDATE activity_jobs
+                SET status = CASE WHEN $3::timestamptz IS NULL
+                                  THEN 'failed'::job_status
+                                  ELSE 'queued'::job_status END,
+                    run_after = COALESCE($3, run_after),
+                    rerun = FALSE,
+                    last_error = $2,
+                    last_error_kind = $4,
+                    claim_token = NULL,
+                    locked_at = NULL,
+                    updated_at = NOW(),
+                    finished_at = CASE WHEN $3::timestamptz IS NULL THEN NOW() ELSE NULL END
+                WHERE id = $1 AND claim_token = $6 AND status = 'processing'
+                RETURNING id, activity_id, stage, attempts
+            )
+            INSERT INTO activity_processing_errors
+                (job_id, activity_id, stage, attempt, fatal, kind, message, detail)
+            SELECT id, activity_id, stage, attempts, TRUE, $4, $2, $5
+            FROM failed
+            RETURNING stage
+            "#,
+        )
+        .bind(job_id)
+        .bind(error.message)
+        .bind(retry_at)
+        .bind(error.kind)
+        .bind(error.detail)
+        .bind(claim_token)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(stage.map(|(stage,)| stage))
+    }
+
+    /// Move a job on to the next pipeline stage; also refreshes its claim.
+    /// Returns false if the claim is no longer held.
+    pub async fn set_activity_job_stage(
+        &self,
+        job_id: Uuid,
+        claim_token: Uuid,
+        stage: ProcessingStage,
+    ) -> Result<bool, AppError> {
+        let result = sqlx::query(
+            r#"
+            UPDATE activity_jobs
+            SET stage = $3, locked_at = NOW(), updated_at = NOW()
+            WHERE id = $1 AND claim_token = $2 AND status = 'processing'
+            "#,
+        )
+        .bind(job_id)
+        .bind(claim_token)
+        .bind(stage)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Refresh a running job's claim so the reaper doesn't take it for abandoned.
+    /// Returns false if the claim is no longer held.
+    pub async fn heartbeat_activity_job(
+        &self,
+        job_id: Uuid,
+        claim_token: Uuid,
+    ) -> Result<bool, AppError> {
+        let result = sqlx::query(
+            r#"
+            UPDATE activity_jobs
+            SET locked_at = NOW()
+            WHERE id = $1 AND claim_token = $2 AND status = 'processing'
+            "#,
+        )
+        .bind(job_id)
+        .bind(claim_token)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Record an error that skipped part of a stage without failing the job.
+    pub async fn record_activity_processing_error(
+        &self,
+        job: &ActivityJob,
+        stage: ProcessingStage,
+        error: &ProcessingErrorRecord<'_>,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+            INSERT INTO activity_processing_errors
+                (job_id, activity_id, stage, attempt, fatal, kind, message, detail)
+            VALUES ($1, $2, $3, $4, FALSE, $5, $6, $7)
+            "#,
+        )
+        .bind(job.id)
+        .bind(job.activity_id)
+        .bind(stage)
+        .bind(job.attempts)
+        .bind(error.kind)
+        .bind(error.message)
+        .bind(error.detail)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Announce a processing event to every API instance, see
+    /// [`crate::processing_stream`].
+    pub async fn publish_processing_event(&self, event: &ProcessingEvent) -> Result<(), AppError> {
+        let payload = serde_json::to_string(event).map_err(|_| AppError::Internal)?;
+        sqlx::query("SELECT pg_notify($1, $2)")
+            .bind(crate::processing_stream::PROCESSING_CHANNEL)
+            .bind(payload)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get the most recent processing job for an activity.
+    pub async fn get_latest_activity_job(
+        &self,
+        activity_id: Uuid,
+    ) -> Result<Option<ActivityJob>, AppError> {
+        let job: Option<ActivityJob> = sqlx::query_as(
+            r#"
+            SELECT id, activity_id, user_id, status, stage, attempts, max_attempts, last_error, last_error_kind,
+                   run_after, notify, created_at, updated_at, finished_at
+            FROM activity_jobs
+            WHERE activity_id = $1
+            ORDER BY created_at DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(activity_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(job)
+    }
+
+    /// Get the errors recorded for a job, newest first.
+    pub async fn get_activity_processing_errors(
+        &self,
+        job_id: Uuid,
+    ) -> Result<Vec<ActivityProcessingError>, AppError> {
+        let errors: Vec<ActivityProcessingError> = sqlx::query_as(
+            r#"
+            SELECT id, job_id, stage, attempt, fatal, kind, message, detail, created_at
+            FROM activity_processing_errors
+            WHERE job_id = $1
+            ORDER BY created_at DESC
+            "#,
+        )
+        .bind(job_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(errors)
+    }
+
+    /// Return jobs whose worker died mid-way (claim not refreshed for `stale_after`)
+    /// to the queue, or fail them if they are out of attempts. Returns how many were reset.
+    pub async fn requeue_stale_activity_jobs(
+        &self,
+        stale_after: time::Duration,
+    ) -> Result<u64, AppError> {
+        let result = sqlx::query(
+            r#"
+            WITH stale AS (
+                UPDATE activity_jobs
+                SET status = CASE WHEN attempts >= max_attempts
+                                  THEN 'failed'::job_status
+                                  ELSE 'queued'::job_status END,
+                    last_error = 'Processing was interrupted',
+                    last_error_kind = 'interrupted',
+                    run_after = NOW(),
+                    claim_token = NULL,
+                    locked_at = NULL,
+                    updated_at = NOW(),
+                    finished_at = CASE WHEN attempts >= max_attempts THEN NOW() ELSE NULL END
+                WHERE status = 'processing'
+                  AND locked_at < NOW() - $1::interval
+                RETURNING id, activity_id, stage, attempts, last_error
+            )
+            INSERT INTO activity_processing_errors
+                (job_id, activity_id, stage, attempt, fatal, kind, message, detail)
+            SELECT id, activity_id, stage, attempts, TRUE, 'interrupted', last_error,
+                   'Job was still processing when its claim expired'
+            FROM stale
+            "#,
+        )
+        .bind(stale_after)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// List a user's processing jobs, newest first, optionally filtered by status.
+    pub async fn list_activity_jobs(
+        &self,
+        user_id: Uuid,
+        status: Option<JobStatus>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<ActivityJob>, AppError> {
+        let jobs: Vec<ActivityJob> = sqlx::query_as(
+            r#"
+            SELECT id, activity_id, user_id, status, stage, attempts, max_attempts, last_error, last_error_kind,
+                   run_after, notify, created_at, updated_at, finished_at
+            FROM activity_jobs
+            WHERE user_id = $1
+              AND ($2::job_status IS NULL OR status = $2)
+            ORDER BY created_at DESC
+            LIMIT $3 OFFSET $4
+            "#,
+        )
+        .bind(user_id)
+        .bind(status)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(jobs)
+    }
+
+    /// Put a user's failed job back on the queue with a fresh set of attempts.
+    /// Returns None if the job doesn't exist, isn't theirs, isn't failed, or the
+    /// activity already has another live job.
+    pub async fn retry_activity_job(
+        &self,
+        job_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<ActivityJob>, AppError> {
+        let job: Option<ActivityJob> = sqlx::query_as(
+            r#"
+            UPDATE activity_jobs j
+            SET status = 'queued',
+                stage = 'queued',
+                attempts = 0,
+                run_after = NOW(),
+                last_error = NULL,
+                last_error_kind = NULL,
+                updated_at = NOW(),
+                finished_at = NULL
+            WHERE j.id = $1
+              AND j.user_id = $2
+              AND j.status = 'failed'
+              AND NOT EXISTS (
+                  SELECT 1 FROM activity_jobs other
+                  WHERE other.activity_id = j.activity_id
+                    AND other.status IN ('queued', 'processing')
+              )
+            RETURNING id, activity_id, user_id, status, stage, attempts, max_attempts, last_error, last_error_kind,
+                      run_after, notify, created_at, updated_at, finished_at
+            "#,
+        )
+        .bind(job_id)
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(job)
+    }
+
+    // ========================================================================
+    // Segment Backfill Job Methods
+    // ========================================================================
+
+    /// Queue a backfill for a segment. If one is already queued or running, it
+    /// is returned instead.
+    pub async fn enqueue_segment_backfill(
+        &self,
+        segment_id: Uuid,
+        requested_by: Uuid,
+    ) -> Result<SegmentBackfillJob, AppError> {
+        let inserted: Option<SegmentBackfillJob> = sqlx::query_as(
+            r#"
+            INSERT INTO segment_backfill_jobs (segment_id, requested_by)
+            VALUES ($1, $2)
+            ON CONFLICT (segment_id) WHERE status IN ('queued', 'processing') DO NOTHING
+            RETURNING id, segment_id, requested_by, status, activities_total, activities_checked,
+                      efforts_created, failures, last_error, created_at, updated_at, finished_at
+            "#,
+        )
+        .bind(segment_id)
+        .bind(requested_by)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        if let Some(job) = inserted {
+            return Ok(job);
+        }
+
+        let existing: SegmentBackfillJob = sqlx::query_as(
+            r#"
+            SELECT id, segment_id, requested_by, status, activities_total, activities_checked,
+                   efforts_created, failures, last_error, created_at, updated_at, finished_at
+            FROM segment_backfill_jobs
+            WHERE segment_id = $1 AND status IN ('queued', 'processing')
+            "#,
+        )
+        .bind(segment_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(existing)
+    }
+
+    /// Claim the oldest queued backfill. Progress counters restart from zero,
+    /// since a requeued job runs again from the beginning.
+    pub async fn claim_segment_backfill(&self) -> Result<Option<ClaimedSegmentBackfill>, AppError> {
+        let job: Option<ClaimedSegmentBackfill> = sqlx::query_as(
+            r#"
+            UPDATE segment_backfill_jobs
+            SET status = 'processing',
+                activities_total = NULL,
+                activities_checked = 0,
+                efforts_created = 0,
+                failures = 0,
+                claim_token = gen_random_uuid(),
+                locked_at = NOW(),
+                updated_at = NOW()
+            WHERE id = (
+                SELECT id FROM segment_backfill_jobs
+                WHERE status = 'queued'
+                ORDER BY created_at ASC
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, segment_id, requested_by, status, activities_total, activities_checked,
+                      efforts_created, failures, last_error, created_at, updated_at, finished_at,
+                      claim_token
+            "#,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(job)
+    }
+
+    /// Record a running backfill's progress; also refreshes its claim.
+    /// Returns false if the claim is no longer held.
+    pub async fn update_segment_backfill_progress(
+        &self,
+        job_id: Uuid,
+        claim_token: Uuid,
+        activities_total: i32,
+        activities_checked: i32,
+        efforts_created: i32,
+        failures: i32,
+    ) -> Result<bool, AppError> {
+        let result = sqlx::query(
+            r#"
+            UPDATE segment_backfill_jobs
+            SET activities_total = $3,
+                activities_checked = $4,
+                efforts_created = $5,
+                failures = $6,
+                locked_at = NOW(),
+                updated_at = NOW()
+            WHERE id = $1 AND claim_token = $2 AND status = 'processing'
+            "#,
+        )
+        .bind(job_id)
+        .bind(claim_token)
+        .bind(activities_total)
+        .bind(activities_checked)
+        .bind(efforts_created)
+        .bind(failures)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Finish a backfill, successfully or with the error that stopped it.
+    /// Returns false, leaving the job alone, if the claim is no longer held.
+    pub async fn finish_segment_backfill(
+        &self,
+        job_id: Uuid,
+        claim_token: Uuid,
+        error: Option<&str>,
+    ) -> Result<bool, AppError> {
+        let result = sqlx::query(
+            r#"
+            UPDATE segment_backfill_jobs
+            SET status = CASE WHEN $3::text IS NULL THEN 'succeeded'::job_status
+                              ELSE 'failed'::job_status END,
+                last_error = $3,
+                claim_token = NULL,
+                locked_at = NULL,
+                updated_at = NOW(),
+                finished_at = NOW()
+            WHERE id = $1 AND claim_token = $2 AND status = 'processing'
+            "#,
+        )
+        .bind(job_id)
+        .bind(claim_token)
+        .bind(error)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Put backfills whose worker stopped refreshing their claim back on the queue.
+    pub async fn requeue_stale_segment_backfills(
+        &self,
+        stale_after: time::Duration,
+    ) -> Result<u64, AppError> {
+        let result = sqlx::query(
+            r#"
+            UPDATE segment_backfill_jobs
+            SET status = 'queued',
+                claim_token = NULL,
+                locked_at = NULL,
+                updated_at = NOW()
+            WHERE status = 'processing'
+              AND locked_at < NOW() - $1::interval
+            "#,
+        )
+        .bind(stale_after)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Get the most recent backfill of a segment.
+    pub async fn get_latest_segment_backfill(
+        &self,
+        segment_id: Uuid,
+    ) -> Result<Option<SegmentBackfillJob>, AppError> {
+        let job: Option<SegmentBackfillJob> = sqlx::query_as(
+            r#"
+            SELECT id, segment_id, requested_by, status, activities_total, activities_checked,
+                   efforts_created, failures, last_error, created_at, updated_at, finished_at
+            FROM segment_backfill_jobs
+            WHERE segment_id = $1
+            ORDER BY created_at DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(segment_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(job)
+    }
+
+    /// Whether a user is a site admin.
+    pub async fn is_admin(&self, user_id: Uuid) -> Result<bool, AppError> {
+        let is_admin: Option<(bool,)> = sqlx::query_as("SELECT is_admin FROM users WHERE id = $1")
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(is_admin.is_some_and(|(admin,)| admin))
+    }
+
+    // ========================================================================
+    // Activity Import Methods
+    // ========================================================================
+
+    /// Record a new bulk import for a user.
+    pub async fn create_activity_import(
+        &self,
+        user_id: Uuid,
+        source_name: &str,
+    ) -> Result<ActivityImport, AppError> {
+        let import: ActivityImport = sqlx::query_as(
+            r#"
+            INSERT INTO activity_imports (user_id, source_name, locked_at)
+            VALUES ($1, $2, NOW())
+            RETURNING id, user_id, source_name, status, files_total, imported, duplicates,
+                      failed, last_error, created_at, updated_at, finished_at
+            "#,
+        )
+        .bind(user_id)
+        .bind(source_name)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(import)
+    }
+
+    /// Mark an import as running once its archive has been read.
+    pub async fn start_activity_import(
+        &self,
+        import_id: Uuid,
+        files_total: i32,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+            UPDATE activity_imports
+            SET status = 'processing', files_total = $2, locked_at = NOW(), updated_at = NOW()
+            WHERE id = $1
+            "#,
+        )
+        .bind(import_id)
+        .bind(files_total)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Record the outcome of one file and bump the import's counters; also
+    /// refreshes the import's heartbeat.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn record_activity_import_item(
+        &self,
+        import_id: Uuid,
+        file_name: &str,
+        external_id: Option<&str>,
+        activity_name: Option<&str>,
+        status: ImportItemStatus,
+        activity_id: Option<Uuid>,
+        message: Option<&str>,
+    ) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO activity_import_items
+                (import_id, file_name, external_id, activity_name, status, activity_id, message)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+        )
+        .bind(import_id)
+        .bind(file_name)
+        .bind(external_id)
+        .bind(activity_name)
+        .bind(status)
+        .bind(activity_id)
+        .bind(message)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            r#"
+            UPDATE activity_imports
+            SET imported = imported + ($2 = 'imported')::int,
+                duplicates = duplicates + ($2 = 'duplicate')::int,
+                failed = failed + ($2 = 'failed')::int,
+                locked_at = NOW(),
+                updated_at = NOW()
+            WHERE id = $1
+            "#,
+        )
+        .bind(import_id)
+        .bind(status)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Finish an import, successfully or with the error that stopped it. An
+    /// import already failed as stale keeps that result.
+    pub async fn finish_activity_import(
+        &self,
+        import_id: Uuid,
+        error: Option<&str>,
+    ) -> Result<(), AppError> {
+        sqlx::query(
+            r#"
+            UPDATE activity_imports
+            SET status = CASE WHEN $2::text IS NULL THEN 'succeeded'::job_status
+                              ELSE 'failed'::job_status END,
+                last_error = $2,
+                locked_at = NULL,
+                updated_at = NOW(),
+                finished_at = NOW()
+            WHERE id = $1 AND status IN ('queued', 'processing')
+            "#,
+        )
+        .bind(import_id)
+        .bind(error)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Fail unfinished imports whose process stopped refreshing their heartbeat
+    /// for `stale_after`. The archive only lived in that process's memory, so
+    /// they can't be resumed.
+    pub async fn fail_stale_activity_imports(
+        &self,
+        stale_after: time::Duration,
+    ) -> Result<u64, AppError> {
+        let result = sqlx::query(
+            r#"
+            UPDATE activity_imports
+            SET status = 'failed',
+                last_error = 'Interrupted: the server running the import stopped',
+                locked_at = NULL,
+                updated_at = NOW(),
+                finished_at = NOW()
+            WHERE status IN ('queued', 'processing')
+              AND locked_at < NOW() - $1::interval
+            "#,
+        )
+        .bind(stale_after)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn get_activity_import(
+        &self,
+        import_id: Uuid,
+    ) -> Result<Option<ActivityImport>, AppError> {
+        let import: Option<ActivityImport> = sqlx::query_as(
+            r#"
+            SELECT id, user_id, source_name, status, files_total, imported, duplicates,
+                   failed, last_error, created_at, updated_at, finished_at
+            FROM activity_imports
+            WHERE id = $1
+            "#,
+        )
+        .bind(import_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(import)
+    }
+
+    /// Get the per-file report of an import, in the order files were processed.
+    pub async fn get_activity_import_items(
+        &self,
+        import_id: Uuid,
+    ) -> Result<Vec<ActivityImportItem>, AppError> {
+        let items: Vec<ActivityImportItem> = sqlx::query_as(
+            r#"
+            SELECT id, import_id, file_name, external_id, activity_name, status, activity_id,
+                   message, created_at
+            FROM activity_import_items
+            WHERE import_id = $1
+            ORDER BY created_at ASC
+            "#,
+        )
+        .bind(import_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(items)
+    }
+
+    /// Find a user's activity that started within a second of `started_at`.
+    pub async fn find_activity_started_at(
+        &self,
+        user_id: Uuid,
+        started_at: time::OffsetDateTime,
+    ) -> Result<Option<Uuid>, AppError> {
+        let id: Option<Uuid> = sqlx::query_scalar(
+            r#"
+            SELECT id FROM activities
+            WHERE user_id = $1
+              AND deleted_at IS NULL
+              AND started_at BETWEEN $2 - INTERVAL '1 second' AND $2 + INTERVAL '1 second'
+            LIMIT 1
+            "#,
+        )
+        .bind(user_id)
+        .bind(started_at)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(id)
+    }
+
+    /// Find a user's activity uploaded from a file with this hash.
+    pub async fn find_activity_by_file_hash(
+        &self,
+        user_id: Uuid,
+        file_hash: &str,
+    ) -> Result<Option<Uuid>, AppError> {
+        let id: Option<Uuid> = sqlx::query_scalar(
+            r#"
+            SELECT id FROM activities
+            WHERE user_id = $1 AND file_hash = $2 AND deleted_at IS NULL
+            ORDER BY submitted_at
+            LIMIT 1
+            "#,
+        )
+        .bind(user_id)
+        .bind(file_hash)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(id)
+    }
+
+    /// Find a user's processed activities whose track was recorded at some
+    /// point between `started_at` and `ended_at`, using the timestamps stored
+    /// in the track's M dimension.
+    pub async fn find_activities_overlapping(
+        &self,
+        user_id: Uuid,
+        started_at: time::OffsetDateTime,
+        ended_at: time::OffsetDateTime,
+    ) -> Result<Vec<Uuid>, AppError> {
+        let ids: Vec<Uuid> = sqlx::query_scalar(
+            r#"
+            SELECT a.id
+            FROM activities a
+            JOIN tracks t ON t.activity_id = a.id
+            WHERE a.user_id = $1
+              AND a.deleted_at IS NULL
+              AND a.started_at <= $3
+              AND ST_M(ST_StartPoint(t.geo::geometry)) > 0
+              AND to_timestamp(ST_M(ST_StartPoint(t.geo::geometry))) <= $3
+              AND to_timestamp(ST_M(ST_EndPoint(t.geo::geometry))) >= $2
+            ORDER BY a.started_at
+            "#,
+        )
+        .bind(user_id)
+        .bind(started_at)
+        .bind(ended_at)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(ids)
+    }
+}
+
+/// WHERE clause selecting a segment's efforts (`e`, joined with their user
+/// `u` and activity `a`) that are on the leaderboard under `filters`. The
+/// segment ID is `$1`. Efforts from followers-only activities aren't ranked,
+/// so leaderboards and their cache are the same for every viewer.
+fn leaderboard_where_clause(filters: &LeaderboardFilters) -> String {
+    // Build the time filter based on scope
+    let time_filter = scope_condition(filters.scope);
+
+    // Build the gender filter
+    let gender_filter = match filters.gender {
+        GenderFilter::All => None,
+        GenderFilter::Male => Some("u.gender = 'male'"),
+        GenderFilter::Female => Some("u.gender = 'female'"),
+    };
+
+    // Build the age filter based on current year and birth_year
+    let current_year = time::OffsetDateTime::now_utc().year();
+    let age_filter = filters.age_group.age_range().map(|(min_age, max_age)| {
+        let max_birth_year = current_year - min_age;
+        match max_age {
+            Some(max) => {
+                let min_birth_year = current_year - max;
+                format!("u.birth_year BETWEEN {min_birth_year} AND {max_birth_year}")
+            }
+            None => format!("u.birth_year <= {max_birth_year}"),
+        }
+    });
+
+    // Build the weight class filter
+    let weight_filter =
+        filters
+            .weight_class
+            .weight_range()
+            .map(|(min_kg, max_kg)| match (min_kg, max_kg) {
+                (None, Some(max)) => format!("u.weight_kg < {max}"),
+                (Some(min), Some(max)) => {
+                    format!("u.weight_kg >= {min} AND u.weight_kg < {max}")
+                }
+                (Some(min), None) => format!("u.weight_kg >= {min}"),
+                (None, None) => unreachable!(),
+            });
+
+    // Build the country filter
+    let country_filter = filters
+        .country
+        .as_ref()
+        .map(|c| format!("u.country = '{}'", c.replace('\'', "''")));
+
+    // Build WHERE clauses
+    let mut where_clauses = vec![
+        "e.segment_id = $1".to_string(),
+        LEADERBOARD_ACTIVITY_CONDITION.to_string(),
+    ];
+    if let Some(tf) = time_filter {
+        where_clauses.push(tf.to_string());
+    }
+    if let Some(gf) = gender_filter {
+        where_clauses.push(gf.to_string());
+    }
+    if let Some(af) = age_filter {
+        where_clauses.push(af);
+    }
+    if let Some(wf) = weight_filter {
+        where_clauses.push(wf);
+    }
+    if let Some(cf) = country_filter {
+        where_clauses.push(cf);
+    }
+    where_clauses.join(" AND ")
+}
+
+/// Condition on an activity `a` having its efforts ranked on leaderboards and
+/// eligible for crowns. Followers-only activities are left off, as ranking them
+/// would show their riders and times to everyone.
+const LEADERBOARD_ACTIVITY_CONDITION: &str = "a.visibility <> 'followers' AND a.deleted_at IS NULL";
+
+/// Condition on a segment effort `e` being within a leaderboard time scope.
+fn scope_condition(scope: LeaderboardScope) -> Option<&'static str> {
+    match scope {
+        LeaderboardScope::AllTime => None,
+        LeaderboardScope::Year => Some("e.started_at >= NOW() - INTERVAL '1 year'"),
+        LeaderboardScope::Month => Some("e.started_at >= NOW() - INTERVAL '1 month'"),
+        LeaderboardScope::Week => Some("e.started_at >= NOW() - INTERVAL '1 week'"),
+    }
+}
+
+/// Query listing (activity ID, user ID) of the activities whose track passes
+/// near both endpoints selected by `endpoints`, which yields one row of
+/// `activity_type_id`, `start_point` and `end_point`. Multi-sport activities
+/// with a part of that type are included; callers keep only the traversals
+/// ridden as it.
+fn activities_near_endpoints_query(endpoints: &str) -> String {
+    format!(
+        r#"
+        WITH endpoints AS ({endpoints})
+        SELECT t.activity_id, t.user_id
+        FROM endpoints ep
+        JOIN tracks t ON ST_DWithin(t.geo, ep.start_point, 50)
+                     AND ST_DWithin(t.geo, ep.end_point, 50)
+        JOIN activities a ON a.id = t.activity_id
+        WHERE a.deleted_at IS NULL
+          AND (a.activity_type_id = ep.activity_type_id
+               OR ep.activity_type_id = ANY(a.segment_types))
+        "#
+    )
+}
+
+/// Split the entries around a user's position on a leaderboard at their best
+/// entry. `None` when they aren't on it.
+fn split_leaderboard_position(
+    entries: Vec<LeaderboardEntry>,
+    user_id: Uuid,
+    total_count: i64,
+) -> Option<LeaderboardPositionEntries> {
+    let idx = entries.iter().position(|e| e.user_id == user_id)?;
+    let user_entry = entries[idx].clone();
+    let entries_above = entries[..idx].to_vec();
+    let entries_below = entries[idx + 1..].to_vec();
+    Some((user_entry, entries_above, entries_below, total_count))
+}
+
+/// Condition on an activity `a` being visible to the user bound as
+/// `$viewer_param`: it is public or theirs, followers-only and they follow
+/// its owner, or shared with a team they are in.
+fn activity_visible_to(viewer_param: usize) -> String {
+    format!(
+        r#"(
+            a.visibility = 'public'
+            OR a.user_id = ${viewer_param}
+            OR (a.visibility = 'followers' AND EXISTS (
+                SELECT 1 FROM follows f
+                WHERE f.follower_id = ${viewer_param} AND f.following_id = a.user_id
+            ))
+            OR (a.visibility = 'teams_only' AND EXISTS (
+                SELECT 1 FROM activity_teams at
+                JOIN team_memberships tm ON tm.team_id = at.team_id
+                WHERE at.activity_id = a.id AND tm.user_id = ${viewer_param}
+            ))
+        )"#
+    )
 }
@@ -0,0 +1,252 @@
+//! Duplicate activity detection.
+//!
+//! The same ride is often uploaded twice, for example from both the head unit
+//! and the phone, and each copy would otherwise create its own segment efforts
+//! and count twice on the leaderboards. An upload is a duplicate of one of the
+//! user's activities when:
+//!
+//! - the file is identical (same SHA-256 of the decompressed content),
+//! - the activity starts within a second of it, or
+//! - its track was recorded over mostly the same time span and follows the
+//!   same path throughout that span.
+//!
+//! The track comparison only sees activities that have finished processing,
+//! since it reads their geometry from the `tracks` table. Both tracks are
+//! resampled to at most [`MAX_COMPARED_POINTS`] before the O(n*m) comparison,
+//! which runs on the blocking thread pool.
+
+use std::sync::Arc;
+
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{
+    database::Database,
+    errors::AppError,
+    file_parsers::ParsedActivity,
+    models::TrackPointData,
+    segment_matching::{discrete_frechet_distance, haversine_distance},
+};
+
+/// Two recordings of the same activity stay within this distance of each other.
+/// Generous enough for a phone and a head unit with different GPS quality.
+pub const DUPLICATE_TOLERANCE_METERS: f64 = 50.0;
+
+/// Share of the shorter recording's duration the two must have in common.
+const MIN_TIME_OVERLAP: f64 = 0.8;
+
+/// Upper bound on points per track in the comparison; long rides are compared
+/// at a wider spacing instead.
+pub const MAX_COMPARED_POINTS: usize = 2000;
+
+/// Smallest spacing tracks are resampled to.
+const MIN_RESAMPLE_SPACING_METERS: f64 = 5.0;
+
+/// Hex SHA-256 of an activity file's decompressed content.
+pub fn file_hash(contents: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(contents))
+}
+
+/// Find the user's existing activity that this upload duplicates, if any.
+/// `parsed` is `None` when the file couldn't be parsed, in which case only the
+/// file hash is compared.
+pub async fn find_duplicate_activity(
+    db: &Database,
+    user_id: Uuid,
+    file_hash: &str,
+    parsed: Option<&ParsedActivity>,
+) -> Result<Option<Uuid>, AppError> {
+    if let Some(existing) = db.find_activity_by_file_hash(user_id, file_hash).await? {
+        return Ok(Some(existing));
+    }
+
+    let Some(points) = parsed.map(|p| p.track_points.as_slice()) else {
+        return Ok(None);
+    };
+    let Some((started_at, ended_at)) = time_range(points) else {
+        return Ok(None);
+    };
+
+    if let Some(existing) = db.find_activity_started_at(user_id, started_at).await? {
+        return Ok(Some(existing));
+    }
+
+    let candidates = db
+        .find_activities_overlapping(user_id, started_at, ended_at)
+        .await?;
+    if candidates.is_empty() {
+        return Ok(None);
+    }
+    let points: Arc<[TrackPointData]> = points.into();
+    for candidate in candidates {
+        let Some(candidate_points) = db.get_track_points(candidate).await? else {
+            continue;
+        };
+        let points = points.clone();
+        let same =
+            tokio::task::spawn_blocking(move || is_same_recording(&points, &candidate_points))
+                .await
+                .map_err(|_| AppError::Internal)?;
+        if same {
+            return Ok(Some(candidate));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Whether two tracks record the same activity: they overlap in time for most
+/// of the shorter one, and over that overlap follow the same path in the same
+/// order.
+pub fn is_same_recording(a: &[TrackPointData], b: &[TrackPointData]) -> bool {
+    let (Some((a_start, a_end)), Some((b_start, b_end))) = (time_range(a), time_range(b)) else {
+        return false;
+    };
+
+    let start = a_start.max(b_start);
+    let end = a_end.min(b_end);
+    let shorter = (a_end - a_start).min(b_end - b_start);
+    if end <= start || (end - start) < shorter * MIN_TIME_OVERLAP {
+        return false;
+    }
+
+    let clip = |points: &[TrackPointData]| -> Vec<(f64, f64)> {
+        points
+            .iter()
+            .filter(|p| p.timestamp.is_some_and(|t| t >= start && t <= end))
+            .map(|p| (p.lat, p.lon))
+            .collect()
+    };
+    let (a, b) = (clip(a), clip(b));
+    if a.len() < 2 || b.len() < 2 {
+        return false;
+    }
+
+    // Resampled points can fall up to half the spacing apart along the path
+    let spacing = (path_length(&a).max(path_length(&b)) / (MAX_COMPARED_POINTS - 1) as f64)
+        .max(MIN_RESAMPLE_SPACING_METERS);
+    discrete_frechet_distance(&resample(&a, spacing), &resample(&b, spacing))
+        <= DUPLICATE_TOLERANCE_METERS + spacing / 2.0
+}
+
+fn path_length(points: &[(f64, f64)]) -> f64 {
+    points
+        .windows(2)
+        .map(|w| haversine_distance(w[0].0, w[0].1, w[1].0, w[1].1))
+        .sum()
+}
+
+/// Points every `spacing` meters along a line, from its first point to its
+/// last. Fills in sparse stretches and thins out dense ones, so a line of
+/// length L has at most L / spacing + 2 points.
+fn resample(points: &[(f64, f64)], spacing: f64) -> Vec<(f64, f64)> {
+    let (Some(&first), Some(&last)) = (points.first(), points.last()) else {
+        return Vec::new();
+    };
+    let mut out = vec![first];
+    // Distance from the start of the current step to the next point out
+    let mut next = spacing;
+    for w in points.windows(2) {
+        let (a, b) = (w[0], w[1]);
+        let step = haversine_distance(a.0, a.1, b.0, b.1);
+        while next < step {
+            let t = next / step;
+            out.push((a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t));
+            next += spacing;
+        }
+        next -= step;
+    }
+    out.push(last);
+    out
+}
+
+/// First and last timestamp of a track, if it has at least two timed points
+/// spanning some time.
+fn time_range(points: &[TrackPointData]) -> Option<(time::OffsetDateTime, time::OffsetDateTime)> {
+    let start = points.iter().find_map(|p| p.timestamp)?;
+    let end = points.iter().rev().find_map(|p| p.timestamp)?;
+    (end > start).then_some((start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{Duration, OffsetDateTime};
+
+    use super::*;
+
+    /// A track heading north, one point every 10 s.
+    fn track(start_offset_secs: i64, points: usize, lon_shift: f64) -> Vec<TrackPointData> {
+        let start = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        (0..points)
+            .map(|i| {
+                let secs = start_offset_secs + i as i64 * 10;
+                TrackPointData {
+                    // ~100 m per 10 s, counted from the shared clock so
+                    // tracks that start late are on the same path
+                    lat: 45.0 + secs as f64 * 0.00009,
+                    lon: 7.0 + lon_shift,
+                    elevation: None,
+                    timestamp: Some(start + Duration::seconds(secs)),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_file_hash() {
+        assert_eq!(file_hash(b"<gpx/>"), file_hash(b"<gpx/>"));
+        assert_ne!(file_hash(b"<gpx/>"), file_hash(b"<gpx />"));
+        assert_eq!(file_hash(b"").len(), 64);
+    }
+
+    #[test]
+    fn test_same_recording_from_two_devices() {
+        // The phone started 30 s late and drifts ~15 m east
+        let head_unit = track(0, 60, 0.0);
+        let phone = track(30, 57, 0.0002);
+        assert!(is_same_recording(&head_unit, &phone));
+        assert!(is_same_recording(&phone, &head_unit));
+    }
+
+    #[test]
+    fn test_different_path_is_not_duplicate() {
+        // Same time, a parallel road ~400 m away
+        assert!(!is_same_recording(&track(0, 60, 0.0), &track(0, 60, 0.005)));
+    }
+
+    #[test]
+    fn test_little_time_overlap_is_not_duplicate() {
+        // Second ride starts as the first is finishing
+        assert!(!is_same_recording(&track(0, 60, 0.0), &track(500, 60, 0.0)));
+        // No overlap at all
+        assert!(!is_same_recording(
+            &track(0, 60, 0.0),
+            &track(1000, 60, 0.0)
+        ));
+    }
+
+    #[test]
+    fn test_long_tracks_are_resampled() {
+        // 300 km, well over the point limit
+        let head_unit = track(0, 3_000, 0.0);
+        let phone = track(30, 2_997, 0.0002);
+        assert!(is_same_recording(&head_unit, &phone));
+        assert!(!is_same_recording(&head_unit, &track(0, 3_000, 0.005)));
+
+        let line: Vec<(f64, f64)> = head_unit.iter().map(|p| (p.lat, p.lon)).collect();
+        let spacing = path_length(&line) / (MAX_COMPARED_POINTS - 1) as f64;
+        let resampled = resample(&line, spacing);
+        assert!(resampled.len() <= MAX_COMPARED_POINTS + 1);
+        assert_eq!(resampled.first(), line.first());
+        assert_eq!(resampled.last(), line.last());
+    }
+
+    #[test]
+    fn test_untimed_tracks_are_not_compared() {
+        let mut a = track(0, 60, 0.0);
+        for p in &mut a {
+            p.timestamp = None;
+        }
+        assert!(!is_same_recording(&a, &a.clone()));
+    }
+}
@@ -0,0 +1,710 @@
+//! Elevation from a digital elevation model (DEM).
+//!
+//! Devices without a barometer record GPS elevation, which can be off by tens
+//! of meters and jumps around, so elevation gain and climb categories derived
+//! from it are unreliable. When `ELEVATION_TILES_DIR` points at a directory of
+//! DEM tiles, track and segment elevations are replaced with terrain elevation.
+//! The directory may hold SRTM `.hgt` tiles (named like `N45E007.hgt`, 1 or 3
+//! arc-second) and single-band GeoTIFFs (`.tif`/`.tiff`) in latitude and
+//! longitude, of any extent and name.
+//!
+//! A line is only corrected when every point falls on terrain the tiles
+//! cover; otherwise it keeps the device's elevation, so a track never mixes
+//! the two.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use tiff::{
+    ColorType,
+    decoder::{Decoder, DecodingResult},
+    tags::Tag,
+};
+
+use crate::models::{ElevationSource, TrackPointData};
+
+/// Value SRTM uses for cells without data.
+const HGT_VOID: i16 = -32768;
+
+/// Tiles kept in memory. A 1 arc-second tile is about 25 MB.
+const MAX_CACHED_TILES: usize = 16;
+
+/// GeoTIFF keys in the `GeoKeyDirectoryTag`.
+const GT_MODEL_TYPE_GEO_KEY: u16 = 1024;
+const GT_RASTER_TYPE_GEO_KEY: u16 = 1025;
+/// `GTModelTypeGeoKey` value for projected coordinates, which aren't supported.
+const MODEL_TYPE_PROJECTED: u16 = 1;
+/// `GTRasterTypeGeoKey` value for samples at the tie point rather than
+/// covering the area to its south-east.
+const RASTER_PIXEL_IS_POINT: u16 = 2;
+
+/// Looks up terrain elevation in local DEM tiles, loading each tile on first
+/// use. Cheap to clone; clones share the tile cache.
+#[derive(Clone, Debug, Default)]
+pub struct ElevationService {
+    dir: Option<PathBuf>,
+    index: Arc<OnceLock<TileIndex>>,
+    tiles: Arc<Mutex<TileCache>>,
+}
+
+impl ElevationService {
+    /// Read tiles from `dir`.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: Some(dir.into()),
+            ..Default::default()
+        }
+    }
+
+    /// A service without tiles, which never corrects anything.
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Read tiles from `ELEVATION_TILES_DIR`, or disabled when it isn't set.
+    pub fn from_env() -> Self {
+        match std::env::var("ELEVATION_TILES_DIR") {
+            Ok(dir) if !dir.is_empty() => {
+                tracing::info!(%dir, "Correcting elevation from DEM tiles");
+                Self::new(dir)
+            }
+            _ => Self::disabled(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.dir.is_some()
+    }
+
+    /// Terrain elevation in meters at a point, interpolated between the
+    /// surrounding cells. `None` where there is no tile or only voids.
+    pub fn elevation_at(&self, lat: f64, lon: f64) -> Option<f64> {
+        let index = self.index()?;
+        let cell = (lat.floor() as i32, lon.floor() as i32);
+        // Where tiles overlap, a void in one falls through to the next
+        index
+            .cells
+            .get(&cell)?
+            .iter()
+            .filter(|&&i| index.entries[i].bounds.contains(lat, lon))
+            .find_map(|&i| self.tile(index, i)?.elevation_at(lat, lon))
+    }
+
+    /// Terrain elevations for a line of (lat, lon) points, or `None` unless
+    /// the tiles cover every point.
+    pub fn line_elevations(
+        &self,
+        points: impl IntoIterator<Item = (f64, f64)>,
+    ) -> Option<Vec<f64>> {
+        if !self.is_enabled() {
+            return None;
+        }
+        points
+            .into_iter()
+            .map(|(lat, lon)| self.elevation_at(lat, lon))
+            .collect()
+    }
+
+    /// Replace a track's elevations with terrain elevation. Returns the
+    /// source the track's elevation now comes from; when the tiles don't
+    /// cover the whole track it is left as recorded.
+    pub fn correct_track(&self, points: &mut [TrackPointData]) -> ElevationSource {
+        if points.is_empty() {
+            return ElevationSource::Device;
+        }
+        let Some(elevations) = self.line_elevations(points.iter().map(|p| (p.lat, p.lon))) else {
+            return ElevationSource::Device;
+        };
+        for (point, elevation) in points.iter_mut().zip(elevations) {
+            point.elevation = Some(elevation);
+        }
+        ElevationSource::Corrected
+    }
+
+    /// The tiles in the directory, found on first use.
+    fn index(&self) -> Option<&TileIndex> {
+        let dir = self.dir.as_ref()?;
+        Some(self.index.get_or_init(|| TileIndex::scan(dir)))
+    }
+
+    fn tile(&self, index: &TileIndex, i: usize) -> Option<Arc<DemTile>> {
+        let slot = self.tiles.lock().unwrap_or_else(|e| e.into_inner()).slot(i);
+        // Read outside the cache lock; only lookups of this tile wait for it
+        slot.get_or_init(|| index.entries[i].load().map(Arc::new))
+            .clone()
+    }
+}
+
+/// A tile, loaded once by whichever lookup gets there first. `None` when the
+/// file couldn't be read.
+type TileSlot = Arc<OnceLock<Option<Arc<DemTile>>>>;
+
+/// The most recently used tiles, by their position in the [`TileIndex`].
+#[derive(Debug, Default)]
+struct TileCache {
+    slots: HashMap<usize, (TileSlot, u64)>,
+    /// Bumped on every lookup, to tell which tile was used least recently
+    clock: u64,
+}
+
+impl TileCache {
+    /// The slot for a tile, evicting the least recently used tile to make
+    /// room for a new one.
+    fn slot(&mut self, i: usize) -> TileSlot {
+        self.clock += 1;
+        if let Some((slot, used)) = self.slots.get_mut(&i) {
+            *used = self.clock;
+            return slot.clone();
+        }
+
+        if self.slots.len() >= MAX_CACHED_TILES
+            && let Some(&oldest) = self
+                .slots
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(i, _)| i)
+        {
+            self.slots.remove(&oldest);
+        }
+        let slot = TileSlot::default();
+        self.slots.insert(i, (slot.clone(), self.clock));
+        slot
+    }
+}
+
+/// Latitude and longitude extent of a tile.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Bounds {
+    south: f64,
+    west: f64,
+    north: f64,
+    east: f64,
+}
+
+impl Bounds {
+    fn contains(&self, lat: f64, lon: f64) -> bool {
+        (self.south..=self.north).contains(&lat) && (self.west..=self.east).contains(&lon)
+    }
+
+    /// The whole-degree cells, by their south-west corner, the tile touches.
+    fn cells(&self) -> impl Iterator<Item = (i32, i32)> {
+        let lats = self.south.floor() as i32..=self.north.floor() as i32;
+        let lons = self.west.floor() as i32..=self.east.floor() as i32;
+        lats.flat_map(move |lat| lons.clone().map(move |lon| (lat, lon)))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TileFormat {
+    Hgt,
+    GeoTiff,
+}
+
+/// A tile file found in the directory, not yet read.
+#[derive(Debug)]
+struct TileEntry {
+    path: PathBuf,
+    format: TileFormat,
+    bounds: Bounds,
+}
+
+impl TileEntry {
+    fn load(&self) -> Option<DemTile> {
+        let path = &self.path;
+        let tile = match self.format {
+            TileFormat::Hgt => std::fs::read(path)
+                .map_err(|e| e.to_string())
+                .and_then(|bytes| {
+                    DemTile::from_hgt(self.bounds, &bytes)
+                        .ok_or_else(|| "unexpected size".to_string())
+                }),
+            TileFormat::GeoTiff => read_geotiff(path),
+        };
+        tile.inspect_err(|e| tracing::warn!(path = %path.display(), "Ignoring DEM tile: {e}"))
+            .ok()
+    }
+}
+
+/// The tiles in the directory and the whole-degree cells each one touches.
+#[derive(Debug, Default)]
+struct TileIndex {
+    entries: Vec<TileEntry>,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+impl TileIndex {
+    /// Find the tiles in `dir`. GeoTIFFs are placed from their georeferencing,
+    /// which only needs their header read.
+    fn scan(dir: &Path) -> Self {
+        let mut paths: Vec<PathBuf> = match std::fs::read_dir(dir) {
+            Ok(entries) => entries.filter_map(|e| Some(e.ok()?.path())).collect(),
+            Err(e) => {
+                tracing::warn!(dir = %dir.display(), "Failed to list DEM tiles: {e}");
+                Vec::new()
+            }
+        };
+        // Deterministic order for overlapping tiles
+        paths.sort();
+
+        let mut index = Self::default();
+        for path in paths {
+            let Some(entry) = tile_entry(&path) else {
+                continue;
+            };
+            for cell in entry.bounds.cells() {
+                index
+                    .cells
+                    .entry(cell)
+                    .or_default()
+                    .push(index.entries.len());
+            }
+            index.entries.push(entry);
+        }
+        tracing::info!(tiles = index.entries.len(), "Found DEM tiles");
+        index
+    }
+}
+
+fn tile_entry(path: &Path) -> Option<TileEntry> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    let (format, bounds) = match extension.as_str() {
+        "hgt" => {
+            let (lat, lon) = parse_tile_name(path.file_stem()?.to_str()?)?;
+            let (south, west) = (lat as f64, lon as f64);
+            let bounds = Bounds {
+                south,
+                west,
+                north: south + 1.0,
+                east: west + 1.0,
+            };
+            (TileFormat::Hgt, bounds)
+        }
+        "tif" | "tiff" => {
+            let bounds = File::open(path)
+                .map_err(|e| e.to_string())
+                .and_then(|file| {
+                    let mut decoder =
+                        Decoder::new(BufReader::new(file)).map_err(|e| e.to_string())?;
+                    Ok(geotiff_grid(&mut decoder)?.bounds())
+                });
+            match bounds {
+                Ok(bounds) => (TileFormat::GeoTiff, bounds),
+                Err(e) => {
+                    tracing::warn!(path = %path.display(), "Ignoring DEM tile: {e}");
+                    return None;
+                }
+            }
+        }
+        _ => return None,
+    };
+    Some(TileEntry {
+        path: path.to_path_buf(),
+        format,
+        bounds,
+    })
+}
+
+/// South-west corner of the tile named like `N45E007`, in any case.
+fn parse_tile_name(name: &str) -> Option<(i32, i32)> {
+    let name = name.to_ascii_uppercase();
+    if name.len() != 7 || !name.is_ascii() {
+        return None;
+    }
+    let lat: i32 = name[1..3].parse().ok()?;
+    let lon: i32 = name[4..7].parse().ok()?;
+    let lat = match &name[0..1] {
+        "N" => lat,
+        "S" => -lat,
+        _ => return None,
+    };
+    let lon = match &name[3..4] {
+        "E" => lon,
+        "W" => -lon,
+        _ => return None,
+    };
+    Some((lat, lon))
+}
+
+/// Where a tile's samples sit. Samples run in rows from north to south.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Grid {
+    /// Position of the north-west sample
+    north: f64,
+    west: f64,
+    /// Degrees between neighbouring samples
+    lat_step: f64,
+    lon_step: f64,
+    width: usize,
+    height: usize,
+    /// Whether each sample covers the area around it, rather than the
+    /// samples on the tile's edges lying on its bounds
+    pixel_is_area: bool,
+}
+
+impl Grid {
+    fn bounds(&self) -> Bounds {
+        // Samples covering an area reach half a step past the outermost ones
+        let (lat_margin, lon_margin) = if self.pixel_is_area {
+            (self.lat_step / 2.0, self.lon_step / 2.0)
+        } else {
+            (0.0, 0.0)
+        };
+        Bounds {
+            south: self.north - (self.height - 1) as f64 * self.lat_step - lat_margin,
+            west: self.west - lon_margin,
+            north: self.north + lat_margin,
+            east: self.west + (self.width - 1) as f64 * self.lon_step + lon_margin,
+        }
+    }
+}
+
+/// Read the georeferencing of a GeoTIFF in latitude and longitude.
+fn geotiff_grid<R: std::io::Read + std::io::Seek>(
+    decoder: &mut Decoder<R>,
+) -> Result<Grid, String> {
+    let (width, height) = decoder.dimensions().map_err(|e| e.to_string())?;
+    let (width, height) = (width as usize, height as usize);
+    if width < 2 || height < 2 {
+        return Err("tile is smaller than 2x2 samples".to_string());
+    }
+
+    let keys = decoder
+        .find_tag_unsigned_vec::<u16>(Tag::GeoKeyDirectoryTag)
+        .map_err(|e| e.to_string())?
+        .unwrap_or_default();
+    // A header of four values, then (key, location, count, value) entries
+    let geo_key = |id: u16| {
+        keys.get(4..)?
+            .chunks_exact(4)
+            .find(|entry| entry[0] == id && entry[1] == 0)
+            .map(|entry| entry[3])
+    };
+    if geo_key(GT_MODEL_TYPE_GEO_KEY) == Some(MODEL_TYPE_PROJECTED) {
+        return Err("tile is projected, not in latitude and longitude".to_string());
+    }
+    let pixel_is_area = geo_key(GT_RASTER_TYPE_GEO_KEY) != Some(RASTER_PIXEL_IS_POINT);
+
+    let scale = decoder
+        .get_tag_f64_vec(Tag::ModelPixelScaleTag)
+        .map_err(|_| "tile has no pixel scale".to_string())?;
+    let tiepoint = decoder
+        .get_tag_f64_vec(Tag::ModelTiepointTag)
+        .map_err(|_| "tile has no tie point".to_string())?;
+    let (&[lon_step, lat_step, ..], &[i, j, _, x, y, ..]) = (&scale[..], &tiepoint[..]) else {
+        return Err("tile has a malformed pixel scale or tie point".to_string());
+    };
+    if lon_step <= 0.0 || lat_step <= 0.0 {
+        return Err("tile has a malformed pixel scale".to_string());
+    }
+
+    // The tie point is the corner of its sample when samples cover an area
+    let centre = if pixel_is_area { 0.5 } else { 0.0 };
+    Ok(Grid {
+        north: y + (j - centre) * lat_step,
+        west: x - (i - centre) * lon_step,
+        lat_step,
+        lon_step,
+        width,
+        height,
+        pixel_is_area,
+    })
+}
+
+fn read_geotiff(path: &Path) -> Result<DemTile, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mut decoder = Decoder::new(BufReader::new(file)).map_err(|e| e.to_string())?;
+    let grid = geotiff_grid(&mut decoder)?;
+
+    match decoder.colortype().map_err(|e| e.to_string())? {
+        ColorType::Gray(_) => {}
+        other => return Err(format!("tile has {other:?} samples, not a single band")),
+    }
+    let void = match decoder.get_tag_ascii_string(Tag::GdalNodata) {
+        Ok(value) => value.trim_end_matches('\0').trim().parse().ok(),
+        Err(_) => None,
+    };
+
+    let samples = match decoder.read_image().map_err(|e| e.to_string())? {
+        DecodingResult::I16(samples) => Samples::I16(samples),
+        DecodingResult::U8(samples) => Samples::widen(samples),
+        DecodingResult::I8(samples) => Samples::widen(samples),
+        DecodingResult::U16(samples) => Samples::widen(samples),
+        DecodingResult::I32(samples) => Samples::F32(samples.iter().map(|&s| s as f32).collect()),
+        DecodingResult::U32(samples) => Samples::F32(samples.iter().map(|&s| s as f32).collect()),
+        DecodingResult::F32(samples) => Samples::F32(samples),
+        DecodingResult::F64(samples) => Samples::F32(samples.iter().map(|&s| s as f32).collect()),
+        _ => return Err("tile has an unsupported sample type".to_string()),
+    };
+    if samples.len() != grid.width * grid.height {
+        return Err("tile has the wrong number of samples".to_string());
+    }
+    Ok(DemTile {
+        grid,
+        samples,
+        void,
+    })
+}
+
+/// Elevations as stored, kept as 16-bit integers where the tile has them.
+#[derive(Debug)]
+enum Samples {
+    I16(Vec<i16>),
+    F32(Vec<f32>),
+}
+
+impl Samples {
+    fn widen<T: Into<f32> + Copy>(samples: Vec<T>) -> Self {
+        Self::F32(samples.iter().map(|&s| s.into()).collect())
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Self::I16(samples) => samples.len(),
+            Self::F32(samples) => samples.len(),
+        }
+    }
+
+    fn get(&self, i: usize) -> f64 {
+        match self {
+            Self::I16(samples) => samples[i] as f64,
+            Self::F32(samples) => samples[i] as f64,
+        }
+    }
+}
+
+/// One loaded tile: a grid of elevations in meters.
+#[derive(Debug)]
+struct DemTile {
+    grid: Grid,
+    samples: Samples,
+    /// Value marking cells without data
+    void: Option<f64>,
+}
+
+impl DemTile {
+    /// An SRTM tile: a square grid of big-endian 16-bit elevations covering
+    /// one degree, rows running from north to south. Edge rows and columns
+    /// overlap the neighbouring tiles.
+    fn from_hgt(bounds: Bounds, bytes: &[u8]) -> Option<Self> {
+        let size = (bytes.len() / 2).isqrt();
+        if size < 2 || size * size * 2 != bytes.len() {
+            return None;
+        }
+        let samples = bytes
+            .chunks_exact(2)
+            .map(|b| i16::from_be_bytes([b[0], b[1]]))
+            .collect();
+        let step = 1.0 / (size - 1) as f64;
+        Some(Self {
+            grid: Grid {
+                north: bounds.north,
+                west: bounds.west,
+                lat_step: step,
+                lon_step: step,
+                width: size,
+                height: size,
+                pixel_is_area: false,
+            },
+            samples: Samples::I16(samples),
+            void: Some(HGT_VOID as f64),
+        })
+    }
+
+    fn sample(&self, row: usize, col: usize) -> Option<f64> {
+        let value = self.samples.get(row * self.grid.width + col);
+        (!value.is_nan() && Some(value) != self.void).then_some(value)
+    }
+
+    /// Bilinear interpolation between the four surrounding samples, ignoring
+    /// voids.
+    fn elevation_at(&self, lat: f64, lon: f64) -> Option<f64> {
+        let grid = &self.grid;
+        let (rows, cols) = ((grid.height - 1) as f64, (grid.width - 1) as f64);
+        let y = ((grid.north - lat) / grid.lat_step).clamp(0.0, rows);
+        let x = ((lon - grid.west) / grid.lon_step).clamp(0.0, cols);
+
+        let row = (y.floor() as usize).min(grid.height - 2);
+        let col = (x.floor() as usize).min(grid.width - 2);
+        let (dy, dx) = (y - row as f64, x - col as f64);
+
+        let corners = [
+            (row, col, (1.0 - dy) * (1.0 - dx)),
+            (row, col + 1, (1.0 - dy) * dx),
+            (row + 1, col, dy * (1.0 - dx)),
+            (row + 1, col + 1, dy * dx),
+        ];
+        let (sum, weight) = corners
+            .iter()
+            .filter_map(|&(r, c, w)| self.sample(r, c).map(|e| (e * w, w)))
+            .fold((0.0, 0.0), |(s, tw), (e, w)| (s + e, tw + w));
+
+        // All four corners void, or the point sits on a void corner
+        (weight > 1e-9).then(|| sum / weight)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 3x3 tile at N45E007 rising 10 m per sample to the east, with the
+    /// north-east corner void.
+    fn tile_bytes() -> Vec<u8> {
+        let samples: [i16; 9] = [0, 10, HGT_VOID, 0, 10, 20, 0, 10, 20];
+        samples.iter().flat_map(|s| s.to_be_bytes()).collect()
+    }
+
+    fn service_with_tile() -> (ElevationService, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("dem-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("N45E007.hgt"), tile_bytes()).unwrap();
+        (ElevationService::new(&dir), dir)
+    }
+
+    #[test]
+    fn test_parse_tile_name() {
+        assert_eq!(parse_tile_name("N45E007"), Some((45, 7)));
+        assert_eq!(parse_tile_name("s34w071"), Some((-34, -71)));
+        assert_eq!(parse_tile_name("N45E07"), None);
+        assert_eq!(parse_tile_name("X45E007"), None);
+    }
+
+    #[test]
+    fn test_elevation_at() {
+        let (service, dir) = service_with_tile();
+
+        // Samples sit on the corners and centre of the tile
+        assert_eq!(service.elevation_at(45.0, 7.0), Some(0.0));
+        assert_eq!(service.elevation_at(45.5, 7.5), Some(10.0));
+        // Halfway between the 0 m and 10 m columns
+        assert_eq!(service.elevation_at(45.25, 7.25), Some(5.0));
+        // Next to the void corner the remaining samples are used
+        assert!(service.elevation_at(45.99, 7.99).is_some_and(|e| e > 10.0));
+        // No tile for this degree
+        assert_eq!(service.elevation_at(46.5, 7.5), None);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_correct_track_needs_full_coverage() {
+        let (service, dir) = service_with_tile();
+        let point = |lat, lon| TrackPointData {
+            lat,
+            lon,
+            elevation: Some(500.0),
+            timestamp: None,
+        };
+
+        let mut covered = vec![point(45.0, 7.0), point(45.5, 7.5)];
+        assert_eq!(
+            service.correct_track(&mut covered),
+            ElevationSource::Corrected
+        );
+        assert_eq!(covered[0].elevation, Some(0.0));
+        assert_eq!(covered[1].elevation, Some(10.0));
+
+        let mut partly = vec![point(45.5, 7.5), point(46.5, 7.5)];
+        assert_eq!(service.correct_track(&mut partly), ElevationSource::Device);
+        assert!(partly.iter().all(|p| p.elevation == Some(500.0)));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_disabled_service_corrects_nothing() {
+        let mut points = vec![TrackPointData {
+            lat: 45.5,
+            lon: 7.5,
+            elevation: Some(500.0),
+            timestamp: None,
+        }];
+        assert_eq!(
+            ElevationService::disabled().correct_track(&mut points),
+            ElevationSource::Device
+        );
+        assert_eq!(points[0].elevation, Some(500.0));
+    }
+
+    #[test]
+    fn test_malformed_tile_is_ignored() {
+        let bounds = Bounds {
+            south: 45.0,
+            west: 7.0,
+            north: 46.0,
+            east: 8.0,
+        };
+        assert!(DemTile::from_hgt(bounds, &[0; 7]).is_none());
+        assert!(DemTile::from_hgt(bounds, &[0; 10]).is_none());
+    }
+
+    /// A 3x2 GeoTIFF of 0.5 degree samples covering the area from 45.0N
+    /// 7.0E to 46.0N 8.5E, rising 100 m per sample to the east, with a void
+    /// in the south-east.
+    fn write_geotiff(path: &Path) {
+        use tiff::encoder::{TiffEncoder, colortype::Gray32Float};
+
+        let mut encoder = TiffEncoder::new(File::create(path).unwrap()).unwrap();
+        let mut image = encoder.new_image::<Gray32Float>(3, 2).unwrap();
+        let dir = image.encoder();
+        dir.write_tag(Tag::ModelPixelScaleTag, &[0.5, 0.5, 0.0][..])
+            .unwrap();
+        dir.write_tag(Tag::ModelTiepointTag, &[0.0, 0.0, 0.0, 7.0, 46.0, 0.0][..])
+            .unwrap();
+        // Geographic, pixel is area
+        let keys: [u16; 12] = [1, 1, 0, 2, 1024, 0, 1, 2, 1025, 0, 1, 1];
+        dir.write_tag(Tag::GeoKeyDirectoryTag, &keys[..]).unwrap();
+        dir.write_tag(Tag::GdalNodata, "-9999").unwrap();
+        image
+            .write_data(&[100.0, 200.0, 300.0, 100.0, 200.0, -9999.0])
+            .unwrap();
+    }
+
+    #[test]
+    fn test_geotiff_tile() {
+        let dir = std::env::temp_dir().join(format!("dem-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        write_geotiff(&dir.join("copernicus_dem.tif"));
+        let service = ElevationService::new(&dir);
+
+        // Sample centres sit a quarter degree inside the tile's corners
+        assert_eq!(service.elevation_at(45.75, 7.25), Some(100.0));
+        assert_eq!(service.elevation_at(45.75, 7.5), Some(150.0));
+        // Past the outermost centres the edge samples are used
+        assert_eq!(service.elevation_at(45.95, 7.01), Some(100.0));
+        // Next to the void the remaining samples are used
+        assert!(
+            service
+                .elevation_at(45.3, 8.2)
+                .is_some_and(|e| e > 200.0 && e < 300.0)
+        );
+        assert_eq!(service.elevation_at(45.25, 8.25), None);
+        // Outside the tile
+        assert_eq!(service.elevation_at(45.5, 8.6), None);
+        assert_eq!(service.elevation_at(46.1, 7.5), None);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_tile_cache_evicts_least_recently_used() {
+        let mut cache = TileCache::default();
+        let first = cache.slot(0);
+        for i in 1..MAX_CACHED_TILES {
+            cache.slot(i);
+        }
+        // Using the first tile again keeps it over the second
+        assert!(Arc::ptr_eq(&cache.slot(0), &first));
+        cache.slot(MAX_CACHED_TILES);
+
+        assert_eq!(cache.slots.len(), MAX_CACHED_TILES);
+        assert!(cache.slots.contains_key(&0));
+        assert!(!cache.slots.contains_key(&1));
+        assert!(cache.slots.contains_key(&MAX_CACHED_TILES));
+    }
+}
@@ -7,6 +7,7 @@ use serde::Serialize;
 use serde_json::json;
 use thiserror::Error;
 use tracing::error;
+use uuid::Uuid;
 
 use crate::database::SimilarSegment;
 
@@ -41,6 +42,9 @@ pub enum AppError {
 
     #[error("Similar segments already exist")]
     SimilarSegmentsExist(Vec<SimilarSegment>),
+
+    #[error("Activity already uploaded as {0}")]
+    DuplicateActivity(Uuid),
 }
 
 /// Summary of a similar segment for conflict responses.
@@ -67,6 +71,13 @@ impl IntoResponse for AppError {
                 }));
                 (StatusCode::CONFLICT, body).into_response()
             }
+            AppError::DuplicateActivity(existing) => {
+                let body = Json(json!({
+                    "error": "Activity already uploaded",
+                    "duplicate_of": existing,
+                }));
+                (StatusCode::CONFLICT, body).into_response()
+            }
             _ => {
                 let (status, error_message) = match &self {
                     AppError::Database(e) => {
@@ -89,7 +100,9 @@ impl IntoResponse for AppError {
                         error!("Queue error: {e}");
                         (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error")
                     }
-                    AppError::SimilarSegmentsExist(_) => unreachable!(),
+                    AppError::SimilarSegmentsExist(_) | AppError::DuplicateActivity(_) => {
+                        unreachable!()
+                    }
                 };
 
                 let body = Json(json!({
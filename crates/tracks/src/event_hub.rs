@@ -0,0 +1,261 @@
+//! Fan-out of Postgres notifications to the streams connected to this instance.
+//!
+//! The real-time streams ([`crate::processing_stream`],
+//! [`crate::notification_stream`] and [`crate::leaderboard_stream`]) each
+//! listen on a Postgres channel and push what they hear to their server-sent
+//! event clients. An [`EventHub`] holds what they have in common: the channel
+//! listener, which reconnects after losing the database, the broadcast channel
+//! the events go out on, and the count of open streams per key, so a payload
+//! nobody is watching can be dropped before any work is done for it. Each
+//! stream module decides what its payloads mean and which events a stream
+//! shows.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    hash::Hash,
+    sync::{Arc, Mutex},
+    time::Duration as StdDuration,
+};
+
+use serde::de::DeserializeOwned;
+use sqlx::{PgPool, postgres::PgListener};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Wait before listening again after losing the database connection.
+const RECONNECT_DELAY: StdDuration = StdDuration::from_secs(5);
+
+/// Broadcasts events of type `T` to subscriptions keyed by `K`. Cheap to
+/// clone; clones share subscribers.
+pub struct EventHub<K, T> {
+    pool: PgPool,
+    channel: &'static str,
+    events: broadcast::Sender<T>,
+    /// Open streams per key
+    subscribers: Arc<Mutex<HashMap<K, usize>>>,
+    /// Whether a stream subscribed with a key shows an event
+    shows: fn(&K, &T) -> bool,
+}
+
+impl<K, T> Clone for EventHub<K, T> {
+    fn clone(&self) -> Self {
+        Self {
+            pool: self.pool.clone(),
+            channel: self.channel,
+            events: self.events.clone(),
+            subscribers: self.subscribers.clone(),
+            shows: self.shows,
+        }
+    }
+}
+
+/// A subscription missed events because it fell more than the hub's capacity
+/// behind.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lagged;
+
+impl<K, T> EventHub<K, T>
+where
+    K: Eq + Hash + Clone + Send + 'static,
+    T: Clone + Send + 'static,
+{
+    /// A hub for the Postgres `channel`, buffering `capacity` events per
+    /// subscriber before a slow one starts missing them.
+    pub fn new(
+        pool: PgPool,
+        channel: &'static str,
+        capacity: usize,
+        shows: fn(&K, &T) -> bool,
+    ) -> Self {
+        let (events, _) = broadcast::channel(capacity);
+        Self {
+            pool,
+            channel,
+            events,
+            subscribers: Default::default(),
+            shows,
+        }
+    }
+
+    /// Spawn the channel listener on the current runtime. Every payload is
+    /// parsed as `P` and handed to `handle`, one at a time.
+    pub fn start<P, F, Fut>(&self, handle: F)
+    where
+        P: DeserializeOwned + Send,
+        F: Fn(P) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send,
+    {
+        let (pool, channel) = (self.pool.clone(), self.channel);
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = listen(&pool, channel, &handle).await {
+                    tracing::error!(channel, "Event listener failed: {e}");
+                }
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        });
+    }
+
+    /// Open a stream of the events shown to `key`.
+    pub fn subscribe(&self, key: K) -> Subscription<K, T> {
+        *self.lock_subscribers().entry(key.clone()).or_default() += 1;
+        Subscription {
+            key,
+            receiver: self.events.subscribe(),
+            hub: self.clone(),
+        }
+    }
+
+    /// Whether any stream is subscribed with `key`.
+    pub fn is_watched(&self, key: &K) -> bool {
+        self.lock_subscribers().contains_key(key)
+    }
+
+    /// The keys of the open streams that match `filter`.
+    pub fn watched(&self, filter: impl Fn(&K) -> bool) -> Vec<K> {
+        self.lock_subscribers()
+            .keys()
+            .filter(|key| filter(key))
+            .cloned()
+            .collect()
+    }
+
+    /// Send an event to every open stream that shows it.
+    pub fn send(&self, event: T) {
+        // Only fails when every stream closed in the meantime
+        let _ = self.events.send(event);
+    }
+
+    fn lock_subscribers(&self) -> std::sync::MutexGuard<'_, HashMap<K, usize>> {
+        self.subscribers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+async fn listen<P, F, Fut>(pool: &PgPool, channel: &str, handle: &F) -> Result<(), sqlx::Error>
+where
+    P: DeserializeOwned,
+    F: Fn(P) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let mut listener = PgListener::connect_with(pool).await?;
+    listener.listen(channel).await?;
+    tracing::info!(channel, "Listening for events");
+
+    loop {
+        let message = listener.recv().await?;
+        match serde_json::from_str::<P>(message.payload()) {
+            Ok(payload) => handle(payload).await,
+            Err(e) => tracing::warn!(channel, "Ignoring malformed payload: {e}"),
+        }
+    }
+}
+
+/// An open stream. Dropping it unsubscribes.
+pub struct Subscription<K, T>
+where
+    K: Eq + Hash + Clone + Send + 'static,
+    T: Clone + Send + 'static,
+{
+    key: K,
+    receiver: broadcast::Receiver<T>,
+    hub: EventHub<K, T>,
+}
+
+impl<K, T> Subscription<K, T>
+where
+    K: Eq + Hash + Clone + Send + 'static,
+    T: Clone + Send + 'static,
+{
+    /// Wait for the next event the stream shows. `None` once the hub is gone.
+    pub async fn recv(&mut self) -> Option<Result<T, Lagged>> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(event) if (self.hub.shows)(&self.key, &event) => return Some(Ok(event)),
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!(channel = self.hub.channel, skipped, "Event stream lagged");
+                    return Some(Err(Lagged));
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+impl<K, T> Drop for Subscription<K, T>
+where
+    K: Eq + Hash + Clone + Send + 'static,
+    T: Clone + Send + 'static,
+{
+    fn drop(&mut self) {
+        let mut subscribers = self.hub.lock_subscribers();
+        if let Some(count) = subscribers.get_mut(&self.key) {
+            *count -= 1;
+            if *count == 0 {
+                subscribers.remove(&self.key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    const CAPACITY: usize = 4;
+
+    /// Events addressed to a key
+    fn hub() -> EventHub<Uuid, (Uuid, u32)> {
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .connect_lazy("postgres://localhost/unused")
+            .unwrap();
+        EventHub::new(pool, "test", CAPACITY, |key, (to, _)| to == key)
+    }
+
+    #[tokio::test]
+    async fn test_subscription_only_sees_events_it_shows() {
+        let hub = hub();
+        let (mine, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut subscription = hub.subscribe(mine);
+
+        hub.send((other, 1));
+        hub.send((mine, 2));
+
+        assert_eq!(subscription.recv().await, Some(Ok((mine, 2))));
+    }
+
+    #[tokio::test]
+    async fn test_lagged_subscription_is_told() {
+        let hub = hub();
+        let key = Uuid::new_v4();
+        let mut subscription = hub.subscribe(key);
+
+        for i in 0..=CAPACITY as u32 {
+            hub.send((key, i));
+        }
+
+        assert_eq!(subscription.recv().await, Some(Err(Lagged)));
+        assert_eq!(subscription.recv().await, Some(Ok((key, 1))));
+    }
+
+    #[tokio::test]
+    async fn test_dropping_subscriptions_unsubscribes() {
+        let hub = hub();
+        let (first_key, second_key) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let first = hub.subscribe(first_key);
+        let second = hub.subscribe(first_key);
+        let third = hub.subscribe(second_key);
+        assert_eq!(hub.lock_subscribers().get(&first_key), Some(&2));
+        assert_eq!(hub.watched(|key| *key == second_key), [second_key]);
+
+        drop(first);
+        assert!(hub.is_watched(&first_key));
+        drop(second);
+        assert!(!hub.is_watched(&first_key));
+        drop(third);
+        assert!(hub.lock_subscribers().is_empty());
+    }
+}
@@ -3,13 +3,18 @@
 //! This module provides unified parsing for different activity file formats,
 //! extracting both track geometry and sensor data (heart rate, cadence, power).
 
-use bytes::Buf as _;
 use bytes::Bytes;
+use quick_xml::{
+    events::Event,
+    name::{Namespace, ResolveResult},
+    reader::NsReader,
+};
 use serde::Serialize;
 use std::io::BufReader;
 use time::OffsetDateTime;
 use uuid::Uuid;
 
+use crate::compression::{self, DecompressError};
 use crate::models::TrackPointData;
 use crate::object_store_service::FileType;
 
@@ -66,11 +71,11 @@ impl SensorData {
     }
 }
 
-/// Sport segment extracted from a FIT file's Session messages.
-/// Each session represents a distinct sport within a multi-sport activity.
+/// Sport segment extracted from a FIT file's Session messages or a TCX file's
+/// Activity elements. Each represents a distinct sport within a multi-sport activity.
 #[derive(Debug, Clone, Serialize)]
 pub struct FitSportSegment {
-    /// The sport type as a string (e.g., "running", "cycling")
+    /// The sport type as a string (e.g., "running", "cycling", or "biking" for TCX)
     pub sport: String,
     /// Sub-sport for more specific categorization (e.g., "mountain" for MTB)
     pub sub_sport: Option<String>,
@@ -89,7 +94,7 @@ pub struct ParsedActivity {
     pub track_points: Vec<TrackPointData>,
     /// Sensor data parallel to track points
     pub sensor_data: SensorData,
-    /// Sport segments detected from FIT Session messages (FIT files only)
+    /// Sport segments detected from FIT Session messages or TCX Activities
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub sport_segments: Vec<FitSportSegment>,
 }
@@ -110,16 +115,21 @@ pub enum ParseError {
     TcxError(String),
     #[error("Failed to parse FIT file: {0}")]
     FitError(String),
+    #[error("Failed to decompress file: {0}")]
+    DecompressError(#[from] DecompressError),
     #[error("Unsupported file type: {0:?}")]
     UnsupportedFileType(FileType),
 }
 
 /// Parse an activity file based on its detected type.
+/// Gzipped or zipped files are decompressed first.
 /// For FileType::Other, attempts to detect the format from the bytes.
 pub fn parse_activity_file(
     file_type: FileType,
     bytes: Bytes,
 ) -> Result<ParsedActivity, ParseError> {
+    let bytes = compression::decompress(bytes)?;
+
     // If type is Other, try to detect from bytes
     let actual_type = if file_type == FileType::Other {
         FileType::detect_from_bytes(&bytes)
@@ -137,7 +147,26 @@ pub fn parse_activity_file(
 
 /// Parse a GPX file, extracting track points and sensor data from Garmin TrackPointExtension.
 pub fn parse_gpx(bytes: Bytes) -> Result<ParsedActivity, ParseError> {
-    let gpx = gpx::read(bytes.reader()).map_err(|e| ParseError::GpxError(e.to_string()))?;
+    let gpx = gpx::read(&bytes[..]).map_err(|e| ParseError::GpxError(e.to_string()))?;
+
+    // The gpx crate skips extensions, so read them in a second pass. Both passes
+    // see track points in document order; if they disagree, drop the sensor
+    // data rather than attach it to the wrong points.
+    let point_count: usize = gpx
+        .tracks
+        .iter()
+        .flat_map(|t| &t.segments)
+        .map(|s| s.points.len())
+        .sum();
+    let mut extensions = read_gpx_extensions(&bytes);
+    if extensions.len() != point_count {
+        tracing::warn!(
+            "GPX extensions found for {} of {point_count} track points, ignoring them",
+            extensions.len()
+        );
+        extensions.clear();
+    }
+    let mut extensions = extensions.into_iter();
 
     let mut track_points = Vec::new();
     let mut sensor_data = SensorData::default();
@@ -165,12 +194,12 @@ pub fn parse_gpx(bytes: Bytes) -> Result<ParsedActivity, ParseError> {
                     timestamp,
                 });
 
-                // Extract sensor data from Garmin TrackPointExtension if present
-                let (hr, cad, temp) = extract_gpx_extensions(pt);
-                sensor_data.heart_rates.push(hr);
-                sensor_data.cadences.push(cad);
-                sensor_data.powers.push(None); // GPX typically doesn't have power
-                sensor_data.temperatures.push(temp);
+                // Sensor data from the point's extensions, if any
+                let ext = extensions.next().unwrap_or_default();
+                sensor_data.heart_rates.push(ext.heart_rate);
+                sensor_data.cadences.push(ext.cadence);
+                sensor_data.powers.push(ext.power);
+                sensor_data.temperatures.push(ext.temperature);
             }
         }
     }
@@ -182,26 +211,120 @@ pub fn parse_gpx(bytes: Bytes) -> Result<ParsedActivity, ParseError> {
     })
 }
 
-/// Extract sensor data from GPX track point extensions.
-/// Looks for Garmin TrackPointExtension v1/v2 namespace elements.
-fn extract_gpx_extensions(pt: &gpx::Waypoint) -> (Option<i32>, Option<i32>, Option<f64>) {
-    // GPX extensions are stored as raw XML in the gpx crate
-    // We need to look for elements like:
-    // <gpxtpx:hr>150</gpxtpx:hr>
-    // <gpxtpx:cad>80</gpxtpx:cad>
-    // <gpxtpx:atemp>22.0</gpxtpx:atemp>
+const GARMIN_TPX_V1: &[u8] = b"http://www.garmin.com/xmlschemas/TrackPointExtension/v1";
+const GARMIN_TPX_V2: &[u8] = b"http://www.garmin.com/xmlschemas/TrackPointExtension/v2";
+const GARMIN_POWER_V1: &[u8] = b"http://www.garmin.com/xmlschemas/PowerExtension/v1";
+const CLUETRUST_GPXDATA: &[u8] = b"http://www.cluetrust.com/XML/GPXDATA/1/0";
+const GPX_1_1: &[u8] = b"http://www.topografix.com/GPX/1/1";
+
+/// Sensor values from one GPX track point's `<extensions>`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct GpxPointExtensions {
+    heart_rate: Option<i32>,
+    cadence: Option<i32>,
+    power: Option<i32>,
+    temperature: Option<f64>,
+}
 
-    // The gpx crate doesn't expose extensions directly in a structured way,
-    // so we'll need to check if there's extension data available.
-    // For now, return None values - this can be enhanced later with XML parsing
-    // if the gpx crate exposes the raw extension XML.
+#[derive(Debug, Clone, Copy)]
+enum GpxSensorField {
+    HeartRate,
+    Cadence,
+    Power,
+    Temperature,
+}
 
-    // Note: The gpx crate's Waypoint struct has an `extensions` field but it may not
-    // be fully populated depending on the crate version. This is a known limitation.
+impl GpxSensorField {
+    /// Map an extension element to the sensor it carries:
+    /// - Garmin TrackPointExtension v1/v2: `hr`, `cad`, `atemp`
+    /// - Garmin PowerExtension: `PowerInWatts`
+    /// - Cluetrust gpxdata: `hr`, `cadence`, `temp`
+    /// - `power` without a namespace (or in the GPX one), as Strava and Wahoo write it
+    fn classify(namespace: &ResolveResult, local_name: &[u8]) -> Option<Self> {
+        let namespace = match namespace {
+            ResolveResult::Bound(Namespace(ns)) => Some(*ns),
+            ResolveResult::Unbound => None,
+            ResolveResult::Unknown(_) => return None,
+        };
+
+        match (namespace, local_name) {
+            (Some(GARMIN_TPX_V1 | GARMIN_TPX_V2), b"hr") => Some(Self::HeartRate),
+            (Some(GARMIN_TPX_V1 | GARMIN_TPX_V2), b"cad") => Some(Self::Cadence),
+            (Some(GARMIN_TPX_V1 | GARMIN_TPX_V2), b"atemp") => Some(Self::Temperature),
+            (Some(GARMIN_POWER_V1), b"PowerInWatts") => Some(Self::Power),
+            (Some(CLUETRUST_GPXDATA), b"hr") => Some(Self::HeartRate),
+            (Some(CLUETRUST_GPXDATA), b"cadence") => Some(Self::Cadence),
+            (Some(CLUETRUST_GPXDATA), b"temp") => Some(Self::Temperature),
+            (None | Some(GPX_1_1), b"power") => Some(Self::Power),
+            _ => None,
+        }
+    }
+}
 
-    let _ = pt; // Acknowledge we receive the point but can't extract extensions with current crate
+impl GpxPointExtensions {
+    fn set(&mut self, field: GpxSensorField, text: &str) {
+        let Ok(value) = text.trim().parse::<f64>() else {
+            return;
+        };
+        if !value.is_finite() {
+            return;
+        }
+        match field {
+            GpxSensorField::HeartRate => self.heart_rate = Some(value.round() as i32),
+            GpxSensorField::Cadence => self.cadence = Some(value.round() as i32),
+            GpxSensorField::Power => self.power = Some(value.round() as i32),
+            GpxSensorField::Temperature => self.temperature = Some(value),
+        }
+    }
+}
+
+/// Read the sensor extensions of every `<trkpt>` in document order, one entry
+/// per point. Returns an empty list if the XML can't be read.
+fn read_gpx_extensions(bytes: &[u8]) -> Vec<GpxPointExtensions> {
+    let mut reader = NsReader::from_reader(bytes);
+    let mut buf = Vec::new();
+    let mut points = Vec::new();
+    let mut current: Option<GpxPointExtensions> = None;
+    let mut field: Option<GpxSensorField> = None;
+
+    loop {
+        let (namespace, event) = match reader.read_resolved_event_into(&mut buf) {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                tracing::warn!("Failed to read GPX extensions: {e}");
+                return Vec::new();
+            }
+        };
 
-    (None, None, None)
+        match event {
+            Event::Start(e) if e.local_name().as_ref() == b"trkpt" => {
+                current = Some(GpxPointExtensions::default());
+            }
+            Event::Empty(e) if e.local_name().as_ref() == b"trkpt" => {
+                points.push(GpxPointExtensions::default());
+            }
+            Event::End(e) if e.local_name().as_ref() == b"trkpt" => {
+                points.extend(current.take());
+                field = None;
+            }
+            Event::Start(e) if current.is_some() => {
+                field = GpxSensorField::classify(&namespace, e.local_name().as_ref());
+            }
+            Event::Text(text) => {
+                if let (Some(point), Some(field)) = (current.as_mut(), field)
+                    && let Ok(text) = text.decode()
+                {
+                    point.set(field, &text);
+                }
+            }
+            Event::End(_) => field = None,
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    points
 }
 
 /// Parse a TCX (Training Center XML) file.
@@ -215,10 +338,13 @@ pub fn parse_tcx(bytes: Bytes) -> Result<ParsedActivity, ParseError> {
 
     let mut track_points = Vec::new();
     let mut sensor_data = SensorData::default();
+    let mut sport_segments: Vec<FitSportSegment> = Vec::new();
 
     // activities is Option<Activities>
     if let Some(ref activities) = tcx_data.activities {
         for activity in &activities.activities {
+            let first_point = track_points.len();
+
             for lap in &activity.laps {
                 // Laps have tracks as Vec<Track>
                 for track in &lap.tracks {
@@ -253,16 +379,56 @@ pub fn parse_tcx(bytes: Bytes) -> Result<ParsedActivity, ParseError> {
                     }
                 }
             }
+
+            // Each Activity carries its own Sport; a brick workout is several of them
+            let sport = activity.sport.to_lowercase();
+            let total_elapsed_time: f64 = activity.laps.iter().map(|l| l.total_time_seconds).sum();
+            match sport_segments.last_mut() {
+                // Consecutive activities of the same sport are one segment
+                Some(previous) if previous.sport == sport => {
+                    previous.total_elapsed_time =
+                        Some(previous.total_elapsed_time.unwrap_or(0.0) + total_elapsed_time);
+                }
+                _ => {
+                    let start_time = track_points[first_point..]
+                        .iter()
+                        .find_map(|pt| pt.timestamp)
+                        .or_else(|| {
+                            OffsetDateTime::parse(
+                                activity.id.trim(),
+                                &time::format_description::well_known::Rfc3339,
+                            )
+                            .ok()
+                        });
+                    sport_segments.push(FitSportSegment {
+                        activity_type_id: map_tcx_sport_to_activity_type(&sport),
+                        sport,
+                        sub_sport: None,
+                        start_time,
+                        total_elapsed_time: Some(total_elapsed_time),
+                    });
+                }
+            }
         }
     }
 
     Ok(ParsedActivity {
         track_points,
         sensor_data,
-        sport_segments: Vec::new(),
+        sport_segments,
     })
 }
 
+/// Map a TCX `Sport` attribute to our activity type UUID.
+/// TCX only distinguishes running, biking and everything else.
+fn map_tcx_sport_to_activity_type(sport: &str) -> Uuid {
+    match sport.to_lowercase().as_str() {
+        "running" => activity_type_ids::RUN,
+        "biking" => activity_type_ids::ROAD,
+        _ => activity_type_ids::UNKNOWN,
+    }
+}
+
 /// Convert chrono DateTime<Utc> to time OffsetDateTime
 fn chrono_to_offset_datetime_utc(dt: &chrono::DateTime<chrono::Utc>) -> OffsetDateTime {
     // Get components from chrono
@@ -370,7 +536,7 @@ pub fn parse_fit(bytes: Bytes) -> Result<ParsedActivity, ParseError> {
     }
 
     // Sort sport segments by start time
-    sport_segments.sort_by(|a, b| a.start_time.cmp(&b.start_time));
+    sport_segments.sort_by_key(|a| a.start_time);
 
     Ok(ParsedActivity {
         track_points,
@@ -435,7 +601,9 @@ fn map_fit_sport_to_activity_type(sport: &str, sub_sport: Option<&str>) -> Uuid
             match sub_sport_lower.as_deref() {
                 Some("mountain") | Some("downhill") | Some("trail") => activity_type_ids::MTB,
                 Some("road") | Some("track_cycling") => activity_type_ids::ROAD,
-                Some("gravel") | Some("cyclocross") => activity_type_ids::GRAVEL,
+                Some("gravel") | Some("gravel_cycling") | Some("cyclocross") => {
+                    activity_type_ids::GRAVEL
+                }
                 _ => activity_type_ids::ROAD, // Default cycling to road
             }
         }
@@ -519,6 +687,132 @@ mod tests {
         assert!(data.has_temperature());
     }
 
+    #[test]
+    fn test_parse_gpx_reads_sensor_extensions() {
+        let gpx = r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx version="1.1" creator="test"
+     xmlns="http://www.topografix.com/GPX/1/1"
+     xmlns:gpxtpx="http://www.garmin.com/xmlschemas/TrackPointExtension/v1"
+     xmlns:ns3="http://www.garmin.com/xmlschemas/TrackPointExtension/v2"
+     xmlns:pwr="http://www.garmin.com/xmlschemas/PowerExtension/v1"
+     xmlns:gpxdata="http://www.cluetrust.com/XML/GPXDATA/1/0">
+  <trk><trkseg>
+    <trkpt lat="45.0" lon="7.0"><ele>100</ele><time>2024-01-01T10:00:00Z</time>
+      <extensions><gpxtpx:TrackPointExtension>
+        <gpxtpx:atemp>21.5</gpxtpx:atemp><gpxtpx:hr>140</gpxtpx:hr><gpxtpx:cad>85</gpxtpx:cad>
+      </gpxtpx:TrackPointExtension><pwr:PowerInWatts>250</pwr:PowerInWatts></extensions>
+    </trkpt>
+    <trkpt lat="45.001" lon="7.0"><time>2024-01-01T10:00:05Z</time>
+      <extensions><power>310</power>
+        <ns3:TrackPointExtension><ns3:hr>142</ns3:hr></ns3:TrackPointExtension>
+      </extensions>
+    </trkpt>
+    <trkpt lat="45.002" lon="7.0"><time>2024-01-01T10:00:10Z</time></trkpt>
+    <trkpt lat="45.003" lon="7.0"><time>2024-01-01T10:00:15Z</time>
+      <extensions><gpxdata:hr>150</gpxdata:hr><gpxdata:cadence>90</gpxdata:cadence><gpxdata:temp>19</gpxdata:temp></extensions>
+    </trkpt>
+  </trkseg></trk>
+</gpx>"#;
+
+        let parsed = parse_gpx(Bytes::from(gpx)).unwrap();
+        let sensors = &parsed.sensor_data;
+
+        assert_eq!(parsed.track_points.len(), 4);
+        assert_eq!(
+            sensors.heart_rates,
+            vec![Some(140), Some(142), None, Some(150)]
+        );
+        assert_eq!(sensors.cadences, vec![Some(85), None, None, Some(90)]);
+        assert_eq!(sensors.powers, vec![Some(250), Some(310), None, None]);
+        assert_eq!(
+            sensors.temperatures,
+            vec![Some(21.5), None, None, Some(19.0)]
+        );
+    }
+
+    #[test]
+    fn test_parse_gpx_without_extensions() {
+        let gpx = r#"<?xml version="1.0"?>
+<gpx version="1.1" creator="test" xmlns="http://www.topografix.com/GPX/1/1">
+  <trk><trkseg><trkpt lat="45.0" lon="7.0"/><trkpt lat="45.001" lon="7.0"/></trkseg></trk>
+</gpx>"#;
+
+        let parsed = parse_gpx(Bytes::from(gpx)).unwrap();
+        assert_eq!(parsed.track_points.len(), 2);
+        assert_eq!(parsed.sensor_data.heart_rates, vec![None, None]);
+        assert!(!parsed.sensor_data.has_any_data());
+    }
+
+    /// A TCX activity with one lap covering `points` trackpoints 10s apart.
+    fn tcx_activity(sport: &str, start_minute: u32, points: u32) -> String {
+        let trackpoints: String = (0..points)
+            .map(|i| {
+                format!(
+                    "<Trackpoint><Time>2024-01-01T10:{start_minute:02}:{:02}Z</Time><Position><LatitudeDegrees>45.0{i}</LatitudeDegrees><LongitudeDegrees>7.0</LongitudeDegrees></Position></Trackpoint>",
+                    i * 10
+                )
+            })
+            .collect();
+        format!(
+            r#"<Activity Sport="{sport}"><Id>2024-01-01T10:{start_minute:02}:00Z</Id><Lap StartTime="2024-01-01T10:{start_minute:02}:00Z"><TotalTimeSeconds>{}</TotalTimeSeconds><DistanceMeters>0</DistanceMeters><Calories>0</Calories><Track>{trackpoints}</Track></Lap></Activity>"#,
+            (points - 1) * 10
+        )
+    }
+
+    #[test]
+    fn test_parse_tcx_detects_sport_segments() {
+        let tcx = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<TrainingCenterDatabase xmlns="http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2">
+  <Activities>{}{}{}{}</Activities>
+</TrainingCenterDatabase>"#,
+            tcx_activity("Running", 0, 3),
+            tcx_activity("Biking", 10, 4),
+            tcx_activity("Biking", 20, 2),
+            tcx_activity("Running", 30, 3),
+        );
+
+        let parsed = parse_tcx(Bytes::from(tcx)).unwrap();
+        assert_eq!(parsed.track_points.len(), 12);
+
+        let segments = &parsed.sport_segments;
+        let types: Vec<Uuid> = segments.iter().map(|s| s.activity_type_id).collect();
+        assert_eq!(
+            types,
+            vec![
+                activity_type_ids::RUN,
+                activity_type_ids::ROAD,
+                activity_type_ids::RUN
+            ]
+        );
+        assert_eq!(segments[1].sport, "biking");
+        assert_eq!(segments[1].start_time, parsed.track_points[3].timestamp);
+        // The two consecutive rides are merged
+        assert_eq!(segments[1].total_elapsed_time, Some(40.0));
+    }
+
+    #[test]
+    fn test_parse_compressed_file_matches_raw() {
+        let tcx = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<TrainingCenterDatabase xmlns="http://www.garmin.com/xmlschemas/TrainingCenterDatabase/v2">
+  <Activities>{}</Activities>
+</TrainingCenterDatabase>"#,
+            tcx_activity("Biking", 0, 4),
+        );
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, tcx.as_bytes()).unwrap();
+        let gz = encoder.finish().unwrap();
+
+        assert_eq!(FileType::detect_from_bytes(&gz), FileType::Other);
+        let raw = parse_activity_file(FileType::Other, Bytes::from(tcx)).unwrap();
+        let compressed = parse_activity_file(FileType::Other, Bytes::from(gz)).unwrap();
+        assert_eq!(
+            serde_json::to_value(&compressed).unwrap(),
+            serde_json::to_value(&raw).unwrap()
+        );
+    }
+
     #[test]
     fn test_file_type_detection() {
         // Test FIT magic bytes
@@ -95,10 +95,18 @@ pub async fn get_segment_achievements(
     let qom = db
         .get_current_achievement_holder(id, AchievementType::Qom)
         .await?;
+    let course_record = db
+        .get_current_achievement_holder(id, AchievementType::CourseRecord)
+        .await?;
+    let local_legend = db
+        .get_current_achievement_holder(id, AchievementType::LocalLegend)
+        .await?;
 
     Ok(Json(SegmentAchievements {
         segment_id: id,
         kom,
         qom,
+        course_record,
+        local_legend,
     }))
 }
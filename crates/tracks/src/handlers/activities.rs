@@ -13,13 +13,17 @@ use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::{
-    activity_queue::{ActivityQueue, ActivitySubmission},
+    achievements_service,
+    activity_export::{ActivityExport, ExportFormat},
+    activity_queue::ActivityQueue,
     auth::{AuthUser, OptionalAuthUser},
-    database::Database,
+    compression,
+    database::{ActivityUpdate, Database},
+    duplicate_detection,
     errors::AppError,
     file_parsers::{FitSportSegment, parse_activity_file},
     models::{
-        Activity, ActivitySortBy, ActivityWithStats, DateRangeFilter, FeedActivity,
+        Activity, ActivitySortBy, ActivityWithStats, DateRangeFilter, FeedActivity, Visibility,
         VisibilityFilter,
     },
     object_store_service::{FileType, ObjectStoreService},
@@ -61,7 +65,7 @@ pub struct PreviewTrackPoint {
     pub time: Option<String>,
 }
 
-/// Sport segment detected from FIT file Session messages.
+/// Sport segment detected from FIT Session messages or TCX Activities.
 #[derive(Debug, Serialize, ToSchema)]
 pub struct PreviewSportSegment {
     /// The sport type as a string (e.g., "running", "cycling")
@@ -100,7 +104,7 @@ pub struct PreviewActivityResponse {
     pub points: Vec<PreviewTrackPoint>,
     /// Geographic bounds of the track
     pub bounds: TrackBounds,
-    /// Sport segments detected from FIT file (empty for GPX/TCX)
+    /// Sport segments detected from FIT or TCX files (empty for GPX)
     pub sport_segments: Vec<PreviewSportSegment>,
     /// Whether the track has timestamp data (needed for multi-sport)
     pub has_timestamps: bool,
@@ -125,11 +129,19 @@ pub struct UploadQuery {
 }
 
 /// Activity update request.
+///
+/// Changing the activity type or the multi-sport parts re-runs segment matching
+/// for the activity.
 #[derive(Debug, Deserialize, ToSchema)]
 pub struct UpdateActivityRequest {
     pub name: Option<String>,
     pub activity_type_id: Option<Uuid>,
     pub visibility: Option<String>,
+    /// Multi-sport: ISO-8601 timestamps marking segment boundaries. Send empty
+    /// lists for both multi-sport fields to make the activity single-sport.
+    pub type_boundaries: Option<Vec<String>>,
+    /// Multi-sport: activity type UUIDs for each segment
+    pub segment_types: Option<Vec<Uuid>>,
 }
 
 /// User activities query parameters with filtering and pagination.
@@ -184,7 +196,7 @@ pub struct ActivitiesByDateQuery {
     params(
         ("activity_type_id" = Uuid, Query, description = "Activity type ID"),
         ("name" = String, Query, description = "Activity name"),
-        ("visibility" = Option<String>, Query, description = "Visibility: public, private, or teams_only"),
+        ("visibility" = Option<String>, Query, description = "Visibility: public, followers, private, or teams_only"),
         ("team_ids" = Option<String>, Query, description = "Comma-separated team IDs for teams_only visibility"),
         ("type_boundaries" = Option<String>, Query, description = "Comma-separated ISO-8601 timestamps for multi-sport segment boundaries"),
         ("segment_types" = Option<String>, Query, description = "Comma-separated activity type UUIDs for multi-sport segments")
@@ -193,7 +205,8 @@ pub struct ActivitiesByDateQuery {
     responses(
         (status = 200, description = "Activity created successfully", body = Activity),
         (status = 400, description = "Invalid input"),
-        (status = 401, description = "Unauthorized")
+        (status = 401, description = "Unauthorized"),
+        (status = 409, description = "The user already has this activity; `duplicate_of` is its ID")
     ),
     security(
         ("bearer_auth" = [])
@@ -211,6 +224,8 @@ pub async fn new_activity(
     let activity_id = Uuid::new_v4();
     let name = params.name;
     let activity_type_id = params.activity_type_id;
+    let visibility = params.visibility.unwrap_or_else(|| "public".to_string());
+    validate_visibility(&visibility)?;
 
     let (mime_hdr, file_bytes) =
         {
@@ -237,6 +252,11 @@ pub async fn new_activity(
             (mime_hdr, file_bytes.freeze())
         };
 
+    // Gzipped or zipped uploads are parsed from their content, but the
+    // original is what gets stored
+    let contents = compression::decompress(file_bytes.clone())
+        .map_err(|e| AppError::InvalidInput(format!("Failed to decompress file: {e}")))?;
+
     let mut file_type = mime_hdr.map_or(FileType::Other, |ct| {
         let mime = Mime::from(ct);
         FileType::from(mime)
@@ -245,7 +265,19 @@ pub async fn new_activity(
     // If MIME type didn't give us a specific format (e.g., octet-stream),
     // detect the actual file type from the content
     if file_type == FileType::Other {
-        file_type = FileType::detect_from_bytes(&file_bytes);
+        file_type = FileType::detect_from_bytes(&contents);
+    }
+
+    // Parse the activity file to extract the start time from track data
+    let file_hash = duplicate_detection::file_hash(&contents);
+    let parsed = parse_activity_file(file_type, contents).ok();
+
+    // Refuse a second copy of an activity the user already has
+    if let Some(existing) =
+        duplicate_detection::find_duplicate_activity(&db, user_id, &file_hash, parsed.as_ref())
+            .await?
+    {
+        return Err(AppError::DuplicateActivity(existing));
     }
 
     // Store the file in object store
@@ -273,10 +305,9 @@ pub async fn new_activity(
             .collect()
     });
 
-    // Parse the activity file to extract the start time from track data
     let now = time::OffsetDateTime::now_utc();
-    let started_at = parse_activity_file(file_type, file_bytes.clone())
-        .ok()
+    let started_at = parsed
+        .as_ref()
         .and_then(|parsed| parsed.started_at())
         .unwrap_or(now);
 
@@ -288,7 +319,7 @@ pub async fn new_activity(
         started_at,
         submitted_at: now,
         object_store_path,
-        visibility: params.visibility.unwrap_or_else(|| "public".to_string()),
+        visibility,
         type_boundaries,
         segment_types,
     };
@@ -296,18 +327,20 @@ pub async fn new_activity(
     // Save activity to database BEFORE submitting to queue to avoid race condition.
     // The queue worker inserts scores with activity_id as a foreign key, so the
     // activity row must exist first.
-    db.save_activity(&activity).await?;
+    if let Err(e) = db.save_activity(&activity, Some(&file_hash)).await {
+        // A concurrent upload of the same file was saved first; drop this copy
+        if let AppError::DuplicateActivity(_) = e
+            && let Err(delete_error) = store.delete_file(&activity.object_store_path).await
+        {
+            tracing::warn!(
+                "Failed to delete duplicate upload {}: {delete_error}",
+                activity.id
+            );
+        }
+        return Err(e);
+    }
 
-    aq.submit(ActivitySubmission {
-        user_id,
-        activity_id: activity.id,
-        file_type,
-        bytes: file_bytes,
-        activity_type_id: activity.activity_type_id,
-        type_boundaries: activity.type_boundaries.clone(),
-        segment_types: activity.segment_types.clone(),
-    })
-    .map_err(AppError::Queue)?;
+    aq.submit(user_id, activity.id).await?;
 
     // Share with teams if team_ids provided
     if let Some(team_ids_str) = &params.team_ids {
@@ -370,6 +403,9 @@ pub async fn preview_activity(
             (mime_hdr, file_bytes.freeze())
         };
 
+    let file_bytes = compression::decompress(file_bytes)
+        .map_err(|e| AppError::InvalidInput(format!("Failed to decompress file: {e}")))?;
+
     // Detect file type
     let mut file_type = mime_hdr.map_or(FileType::Other, |ct| {
         let mime = Mime::from(ct);
@@ -451,6 +487,39 @@ pub async fn preview_activity(
     }))
 }
 
+/// Whether `viewer` may see an activity owned by `owner_id`. Handlers answer
+/// 404 when not, so hidden activities don't leak their existence.
+pub async fn can_view_activity(
+    db: &Database,
+    viewer: Option<Uuid>,
+    activity_id: Uuid,
+    owner_id: Uuid,
+    visibility: &str,
+) -> Result<bool, AppError> {
+    if visibility == Visibility::Public.as_str() {
+        return Ok(true);
+    }
+    let Some(viewer) = viewer else {
+        return Ok(false);
+    };
+    if viewer == owner_id {
+        return Ok(true);
+    }
+    match visibility.parse() {
+        Ok(Visibility::Followers) => db.is_following(viewer, owner_id).await,
+        Ok(Visibility::TeamsOnly) => db.user_has_activity_team_access(viewer, activity_id).await,
+        _ => Ok(false),
+    }
+}
+
+/// Check an activity visibility given in a request.
+fn validate_visibility(visibility: &str) -> Result<(), AppError> {
+    visibility
+        .parse::<Visibility>()
+        .map(|_| ())
+        .map_err(AppError::InvalidInput)
+}
+
 /// Get an activity by ID.
 #[utoipa::path(
     get,
@@ -475,24 +544,9 @@ pub async fn get_activity(
         .ok_or(AppError::NotFound)?;
 
     // Check visibility-based access control
-    let has_access = match activity.visibility.as_str() {
-        "public" => true,
-        "private" => claims.as_ref().is_some_and(|c| c.sub == activity.user_id),
-        "teams_only" => {
-            if let Some(ref c) = claims {
-                // Owner always has access
-                if c.sub == activity.user_id {
-                    true
-                } else {
-                    // Check team membership
-                    db.user_has_activity_team_access(c.sub, id).await?
-                }
-            } else {
-                false
-            }
-        }
-        _ => false,
-    };
+    let viewer = claims.as_ref().map(|c| c.sub);
+    let has_access =
+        can_view_activity(&db, viewer, id, activity.user_id, &activity.visibility).await?;
 
     if has_access {
         Ok(Json(activity))
@@ -502,7 +556,7 @@ pub async fn get_activity(
     }
 }
 
-/// Update an activity.
+/// Update an activity. Only its owner and site admins may edit it.
 #[utoipa::path(
     patch,
     path = "/activities/{id}",
@@ -513,25 +567,100 @@ pub async fn get_activity(
     request_body = UpdateActivityRequest,
     responses(
         (status = 200, description = "Activity updated successfully", body = Activity),
+        (status = 400, description = "Invalid visibility or multi-sport parts"),
+        (status = 401, description = "Unauthorized"),
         (status = 404, description = "Activity not found")
+    ),
+    security(
+        ("bearer_auth" = [])
     )
 )]
 pub async fn update_activity(
     Extension(db): Extension<Database>,
+    Extension(aq): Extension<ActivityQueue>,
+    AuthUser(claims): AuthUser,
     Path(id): Path<Uuid>,
     Json(req): Json<UpdateActivityRequest>,
 ) -> Result<Json<Activity>, AppError> {
-    let activity = db
-        .update_activity(
-            id,
-            req.name.as_deref(),
-            req.activity_type_id,
-            req.visibility.as_deref(),
-        )
+    let activity = db.get_activity(id).await?.ok_or(AppError::NotFound)?;
+    // Return 404 to avoid leaking existence
+    if activity.user_id != claims.sub && !db.is_admin(claims.sub).await? {
+        return Err(AppError::NotFound);
+    }
+
+    if let Some(visibility) = &req.visibility {
+        validate_visibility(visibility)?;
+    }
+    let type_boundaries = req
+        .type_boundaries
+        .as_deref()
+        .map(parse_type_boundaries)
+        .transpose()?;
+    let multi_sport = match (&type_boundaries, &req.segment_types) {
+        (None, None) => None,
+        (Some(boundaries), Some(types)) if boundaries.is_empty() && types.is_empty() => Some(None),
+        (Some(boundaries), Some(types)) => {
+            validate_multi_sport(boundaries, types)?;
+            Some(Some((boundaries.as_slice(), types.as_slice())))
+        }
+        _ => {
+            return Err(AppError::InvalidInput(
+                "type_boundaries and segment_types must be updated together".to_string(),
+            ));
+        }
+    };
+
+    let update = ActivityUpdate {
+        name: req.name.as_deref(),
+        activity_type_id: req.activity_type_id,
+        visibility: req.visibility.as_deref(),
+        multi_sport,
+    };
+    let updated = db
+        .update_activity(id, &update)
         .await?
         .ok_or(AppError::NotFound)?;
 
-    Ok(Json(activity))
+    if updated.needs_rematch {
+        aq.resubmit(&updated).await?;
+    }
+    // Local Legend counts only ranked efforts
+    for &segment_id in &updated.reranked_segments {
+        if let Err(e) = achievements_service::update_local_legend(&db, segment_id).await {
+            tracing::warn!("Failed to update Local Legend for segment {segment_id}: {e}");
+        }
+    }
+
+    Ok(Json(updated.activity))
+}
+
+fn parse_type_boundaries(boundaries: &[String]) -> Result<Vec<time::OffsetDateTime>, AppError> {
+    boundaries
+        .iter()
+        .map(|ts| {
+            time::OffsetDateTime::parse(ts.trim(), &time::format_description::well_known::Rfc3339)
+                .map_err(|_| AppError::InvalidInput(format!("Invalid type boundary: {ts}")))
+        })
+        .collect()
+}
+
+/// Boundaries `[start, b1, ..., end]` split the activity into one part per
+/// segment type.
+fn validate_multi_sport(
+    boundaries: &[time::OffsetDateTime],
+    types: &[Uuid],
+) -> Result<(), AppError> {
+    if boundaries.len() < 2 || types.len() != boundaries.len() - 1 {
+        return Err(AppError::InvalidInput(
+            "segment_types needs one entry per part between type_boundaries".to_string(),
+        ));
+    }
+    if boundaries.windows(2).any(|w| w[0] >= w[1]) {
+        return Err(AppError::InvalidInput(
+            "type_boundaries must be in increasing order".to_string(),
+        ));
+    }
+    Ok(())
 }
 
 /// Delete an activity.
@@ -558,7 +687,8 @@ pub async fn delete_activity(
     }
 }
 
-/// Get activities for a user.
+/// Get activities for a user. Other users only get the activities visible to
+/// them.
 #[utoipa::path(
     get,
     path = "/users/{id}/activities",
@@ -573,10 +703,12 @@ pub async fn delete_activity(
 )]
 pub async fn get_user_activities(
     Extension(db): Extension<Database>,
+    OptionalAuthUser(claims): OptionalAuthUser,
     Query(params): Query<UserActivitiesQuery>,
     Path(id): Path<Uuid>,
 ) -> Result<Json<Vec<Activity>>, AppError> {
-    let activities = db.get_user_activities_filtered(id, &params).await?;
+    let viewer = claims.map(|c| c.sub);
+    let activities = db.get_user_activities_filtered(id, viewer, &params).await?;
     Ok(Json(activities))
 }
 
@@ -602,28 +734,20 @@ pub async fn download_gpx_file(
     let activity = db.get_activity(id).await?.ok_or(AppError::NotFound)?;
 
     // Check visibility-based access control
-    let has_access = match activity.visibility.as_str() {
-        "public" => true,
-        "private" => claims.as_ref().is_some_and(|c| c.sub == activity.user_id),
-        "teams_only" => {
-            if let Some(ref c) = claims {
-                if c.sub == activity.user_id {
-                    true
-                } else {
-                    db.user_has_activity_team_access(c.sub, id).await?
-                }
-            } else {
-                false
-            }
-        }
-        _ => false,
-    };
+    let viewer = claims.as_ref().map(|c| c.sub);
+    let has_access =
+        can_view_activity(&db, viewer, id, activity.user_id, &activity.visibility).await?;
 
     if !has_access {
         return Err(AppError::NotFound);
     }
 
     let file_bytes = store.get_file(&activity.object_store_path).await?;
+    // Compressed uploads are stored as uploaded; serve the activity file itself
+    let file_bytes = compression::decompress(file_bytes).map_err(|e| {
+        tracing::error!(activity_id = %id, "Failed to decompress stored file: {e}");
+        AppError::Internal
+    })?;
 
     let mut headers = HeaderMap::new();
     headers.insert(header::CONTENT_TYPE, "application/gpx+xml".parse().unwrap());
@@ -637,6 +761,82 @@ pub async fn download_gpx_file(
     Ok((headers, file_bytes).into_response())
 }
 
+/// Query parameters for exporting an activity.
+#[derive(Debug, Deserialize, ToSchema, utoipa::IntoParams)]
+pub struct ExportActivityQuery {
+    /// File format to export: gpx, tcx or fit
+    pub format: ExportFormat,
+}
+
+/// Export an activity as GPX, TCX or FIT, whatever format it was uploaded in.
+/// The file is rebuilt from the stored track and sensor data.
+#[utoipa::path(
+    get,
+    path = "/activities/{id}/export",
+    tag = "activities",
+    params(
+        ("id" = Uuid, Path, description = "Activity ID"),
+        ExportActivityQuery
+    ),
+    responses(
+        (status = 200, description = "Activity file in the requested format"),
+        (status = 404, description = "Activity not found")
+    )
+)]
+pub async fn export_activity(
+    Extension(db): Extension<Database>,
+    OptionalAuthUser(claims): OptionalAuthUser,
+    Path(id): Path<Uuid>,
+    Query(query): Query<ExportActivityQuery>,
+) -> Result<Response, AppError> {
+    let activity = db.get_activity(id).await?.ok_or(AppError::NotFound)?;
+
+    // Check visibility-based access control
+    let viewer = claims.as_ref().map(|c| c.sub);
+    let has_access =
+        can_view_activity(&db, viewer, id, activity.user_id, &activity.visibility).await?;
+
+    if !has_access {
+        return Err(AppError::NotFound);
+    }
+
+    let track_points = db.get_track_points(id).await?.ok_or(AppError::NotFound)?;
+    let sensor_data = db.get_activity_sensor_arrays(id).await?.unwrap_or_default();
+
+    let export = ActivityExport::new(&activity, &track_points, &sensor_data);
+    let file_bytes = export.render(query.format);
+
+    // Keep the filename to characters that are safe in a header
+    let file_stem: String = activity
+        .name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == ' ' || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        query.format.content_type().parse().unwrap(),
+    );
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        format!(
+            "attachment; filename=\"{file_stem}.{}\"",
+            query.format.extension()
+        )
+        .parse()
+        .unwrap(),
+    );
+
+    Ok((headers, file_bytes).into_response())
+}
+
 /// Get track data for an activity.
 #[utoipa::path(
     get,
@@ -659,22 +859,9 @@ pub async fn get_activity_track(
     let activity = db.get_activity(id).await?.ok_or(AppError::NotFound)?;
 
     // Check visibility-based access control
-    let has_access = match activity.visibility.as_str() {
-        "public" => true,
-        "private" => claims.as_ref().is_some_and(|c| c.sub == activity.user_id),
-        "teams_only" => {
-            if let Some(ref c) = claims {
-                if c.sub == activity.user_id {
-                    true
-                } else {
-                    db.user_has_activity_team_access(c.sub, id).await?
-                }
-            } else {
-                false
-            }
-        }
-        _ => false,
-    };
+    let viewer = claims.as_ref().map(|c| c.sub);
+    let has_access =
+        can_view_activity(&db, viewer, id, activity.user_id, &activity.visibility).await?;
 
     if !has_access {
         return Err(AppError::NotFound);
@@ -745,22 +932,9 @@ pub async fn get_activity_segments(
     let activity = db.get_activity(id).await?.ok_or(AppError::NotFound)?;
 
     // Check visibility-based access control
-    let has_access = match activity.visibility.as_str() {
-        "public" => true,
-        "private" => claims.as_ref().is_some_and(|c| c.sub == activity.user_id),
-        "teams_only" => {
-            if let Some(ref c) = claims {
-                if c.sub == activity.user_id {
-                    true
-                } else {
-                    db.user_has_activity_team_access(c.sub, id).await?
-                }
-            } else {
-                false
-            }
-        }
-        _ => false,
-    };
+    let viewer = claims.as_ref().map(|c| c.sub);
+    let has_access =
+        can_view_activity(&db, viewer, id, activity.user_id, &activity.visibility).await?;
 
     if !has_access {
         return Err(AppError::NotFound);
@@ -1116,23 +1290,10 @@ pub async fn get_activity_sensor_data(
     // Get activity to check visibility
     let activity = db.get_activity(id).await?.ok_or(AppError::NotFound)?;
 
-    // Check visibility - public activities are visible to all, private only to owner
-    let is_owner = user
-        .as_ref()
-        .map(|u| u.sub == activity.user_id)
-        .unwrap_or(false);
-
-    if activity.visibility != "public" && !is_owner {
-        // Check if user has team access
-        let has_team_access = if let Some(ref u) = user {
-            db.user_has_activity_team_access(u.sub, id).await?
-        } else {
-            false
-        };
-
-        if !has_team_access {
-            return Err(AppError::NotFound);
-        }
+    // Check visibility-based access control
+    let viewer = user.as_ref().map(|u| u.sub);
+    if !can_view_activity(&db, viewer, id, activity.user_id, &activity.visibility).await? {
+        return Err(AppError::NotFound);
     }
 
     // Get sensor data - return empty response if no sensor data exists
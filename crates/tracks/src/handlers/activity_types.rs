@@ -9,7 +9,9 @@ use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 use uuid::Uuid;
 
-use crate::{auth::AuthUser, database::Database, errors::AppError, models::ActivityTypeRow};
+use crate::{
+    auth::AuthUser, database::Database, errors::AppError, models::ActivityTypeRow, segment_matching,
+};
 
 /// List all activity types (built-in and custom).
 #[utoipa::path(
@@ -78,7 +80,18 @@ pub async fn create_activity_type(
         ));
     }
 
-    let activity_type = db.create_activity_type(&name, claims.sub).await?;
+    let tolerance = req
+        .match_tolerance_meters
+        .unwrap_or(segment_matching::DEFAULT_MATCH_TOLERANCE_METERS);
+    if !(tolerance.is_finite() && tolerance > 0.0) {
+        return Err(AppError::InvalidInput(
+            "Match tolerance must be a positive number of meters".to_string(),
+        ));
+    }
+
+    let activity_type = db
+        .create_activity_type(&name, claims.sub, tolerance)
+        .await?;
     Ok(Json(activity_type))
 }
 
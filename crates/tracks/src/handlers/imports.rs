@@ -0,0 +1,133 @@
+//! Bulk activity import handlers.
+
+use std::io::Cursor;
+
+use axum::{
+    Extension,
+    extract::{Multipart, Path, Query},
+    http::StatusCode,
+    response::Json,
+};
+use bytes::BytesMut;
+use serde::Deserialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    activity_import::ActivityImporter,
+    activity_queue::ActivityQueue,
+    auth::AuthUser,
+    database::Database,
+    errors::AppError,
+    models::{ActivityImport, ActivityImportReport},
+    object_store_service::ObjectStoreService,
+};
+
+/// Activity import query parameters.
+#[derive(Debug, Deserialize, ToSchema, utoipa::IntoParams)]
+pub struct ImportActivitiesQuery {
+    /// Visibility for activities the archive doesn't give one for: public
+    /// (default), followers or private
+    pub visibility: Option<String>,
+}
+
+/// Import a bulk export archive (e.g. a Strava export zip).
+///
+/// The zip holds `activities.csv` plus the activity files it references
+/// (`.fit`, `.gpx`, `.tcx`, optionally gzipped). Activities are imported in
+/// the background; poll `/imports/{id}` for the per-file report.
+#[utoipa::path(
+    post,
+    path = "/imports",
+    tag = "activities",
+    params(ImportActivitiesQuery),
+    request_body(content_type = "multipart/form-data", description = "Export archive in the `file` field"),
+    responses(
+        (status = 202, description = "Import started", body = ActivityImport),
+        (status = 400, description = "No archive provided or invalid visibility"),
+        (status = 401, description = "Unauthorized")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn import_activities(
+    Extension(db): Extension<Database>,
+    Extension(store): Extension<ObjectStoreService>,
+    Extension(aq): Extension<ActivityQueue>,
+    AuthUser(claims): AuthUser,
+    Query(params): Query<ImportActivitiesQuery>,
+    mut multipart: Multipart,
+) -> Result<(StatusCode, Json<ActivityImport>), AppError> {
+    let visibility = params.visibility.unwrap_or_else(|| "public".to_string());
+    if !["public", "followers", "private"].contains(&visibility.as_str()) {
+        return Err(AppError::InvalidInput(
+            "visibility must be public, followers or private".to_string(),
+        ));
+    }
+
+    let mut archive = BytesMut::new();
+    let mut source_name = None;
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|_| AppError::InvalidInput("Failed to process multipart data".to_string()))?
+    {
+        if field.name() == Some("file") {
+            source_name = field.file_name().map(str::to_string);
+            let chunk = field
+                .bytes()
+                .await
+                .map_err(|_| AppError::InvalidInput("Failed to read file data".to_string()))?;
+            archive.extend(chunk);
+        } else {
+            tracing::warn!("Unexpected field: {:?}", field.name());
+        }
+    }
+    if archive.is_empty() {
+        return Err(AppError::InvalidInput("No file provided".to_string()));
+    }
+
+    let import = db
+        .create_activity_import(claims.sub, source_name.as_deref().unwrap_or("export.zip"))
+        .await?;
+
+    let importer = ActivityImporter::new(db, store, aq);
+    let archive = Cursor::new(archive.freeze());
+    let running = import.clone();
+    tokio::spawn(async move { importer.run(&running, archive, &visibility).await });
+
+    Ok((StatusCode::ACCEPTED, Json(import)))
+}
+
+/// Get an activity import with its per-file report.
+#[utoipa::path(
+    get,
+    path = "/imports/{id}",
+    tag = "activities",
+    params(
+        ("id" = Uuid, Path, description = "Import ID")
+    ),
+    responses(
+        (status = 200, description = "Import progress and per-file outcomes", body = ActivityImportReport),
+        (status = 401, description = "Unauthorized"),
+        (status = 404, description = "Import not found")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_activity_import(
+    Extension(db): Extension<Database>,
+    AuthUser(claims): AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ActivityImportReport>, AppError> {
+    let import = db
+        .get_activity_import(id)
+        .await?
+        .filter(|import| import.user_id == claims.sub)
+        .ok_or(AppError::NotFound)?;
+    let items = db.get_activity_import_items(id).await?;
+
+    Ok(Json(ActivityImportReport { import, items }))
+}
@@ -0,0 +1,215 @@
+//! Activity processing job handlers.
+
+use std::convert::Infallible;
+
+use axum::{
+    Extension,
+    extract::{Path, Query},
+    response::{
+        Json,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
+use futures_util::{Stream, StreamExt, stream};
+use serde::Deserialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::{
+    activity_queue::ActivityQueue,
+    auth::AuthUser,
+    database::Database,
+    errors::AppError,
+    models::{ActivityJob, ActivityProcessingStatus, JobStatus, ProcessingEvent, ProcessingStage},
+    processing_stream::ProcessingHub,
+};
+
+use super::pagination::default_limit;
+
+/// Query parameters for listing processing jobs.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ListJobsQuery {
+    /// Only return jobs in this state.
+    pub status: Option<JobStatus>,
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+    #[serde(default)]
+    pub offset: i64,
+}
+
+/// List the authenticated user's activity processing jobs.
+#[utoipa::path(
+    get,
+    path = "/jobs",
+    tag = "jobs",
+    params(
+        ("status" = Option<JobStatus>, Query, description = "Filter by job status: queued, processing, succeeded, failed"),
+        ("limit" = Option<i64>, Query, description = "Maximum number of jobs to return"),
+        ("offset" = Option<i64>, Query, description = "Number of jobs to skip")
+    ),
+    responses(
+        (status = 200, description = "Processing jobs, newest first", body = Vec<ActivityJob>),
+        (status = 401, description = "Unauthorized")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn list_jobs(
+    Extension(db): Extension<Database>,
+    AuthUser(claims): AuthUser,
+    Query(query): Query<ListJobsQuery>,
+) -> Result<Json<Vec<ActivityJob>>, AppError> {
+    let jobs = db
+        .list_activity_jobs(claims.sub, query.status, query.limit, query.offset)
+        .await?;
+    Ok(Json(jobs))
+}
+
+/// Retry a failed activity processing job.
+#[utoipa::path(
+    post,
+    path = "/jobs/{id}/retry",
+    tag = "jobs",
+    params(
+        ("id" = Uuid, Path, description = "Job ID")
+    ),
+    responses(
+        (status = 200, description = "Job queued for retry", body = ActivityJob),
+        (status = 401, description = "Unauthorized"),
+        (status = 404, description = "No failed job with this ID that can be retried")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn retry_job(
+    Extension(aq): Extension<ActivityQueue>,
+    AuthUser(claims): AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ActivityJob>, AppError> {
+    let job = aq.retry(id, claims.sub).await?.ok_or(AppError::NotFound)?;
+    Ok(Json(job))
+}
+
+/// Load the processing state of one of the user's activities.
+async fn load_processing_status(
+    db: &Database,
+    user_id: Uuid,
+    activity_id: Uuid,
+) -> Result<ActivityProcessingStatus, AppError> {
+    let activity = db
+        .get_activity(activity_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+
+    // Processing details are only for the owner; 404 avoids leaking existence
+    if activity.user_id != user_id {
+        return Err(AppError::NotFound);
+    }
+
+    let job = db.get_latest_activity_job(activity_id).await?;
+    let errors = match &job {
+        Some(job) => db.get_activity_processing_errors(job.id).await?,
+        None => Vec::new(),
+    };
+
+    // Activities processed before jobs were tracked have no job row
+    let (status, stage) = job
+        .as_ref()
+        .map_or((JobStatus::Succeeded, ProcessingStage::Done), |j| {
+            (j.status, j.stage)
+        });
+
+    Ok(ActivityProcessingStatus {
+        activity_id,
+        status,
+        stage,
+        job,
+        errors,
+    })
+}
+
+/// Get the processing pipeline stage and errors for an activity.
+#[utoipa::path(
+    get,
+    path = "/activities/{id}/processing",
+    tag = "jobs",
+    params(
+        ("id" = Uuid, Path, description = "Activity ID")
+    ),
+    responses(
+        (status = 200, description = "Processing status", body = ActivityProcessingStatus),
+        (status = 401, description = "Unauthorized"),
+        (status = 404, description = "Activity not found")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_activity_processing(
+    Extension(db): Extension<Database>,
+    AuthUser(claims): AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ActivityProcessingStatus>, AppError> {
+    let status = load_processing_status(&db, claims.sub, id).await?;
+    Ok(Json(status))
+}
+
+/// Stream processing progress for an activity as Server-Sent Events.
+///
+/// The first event (`status`) is the current processing status. After that the
+/// stream carries `stage`, `segment_matched`, `personal_record` and `failed`
+/// events, and closes once processing is done or has failed for good.
+#[utoipa::path(
+    get,
+    path = "/activities/{id}/processing/events",
+    tag = "jobs",
+    params(
+        ("id" = Uuid, Path, description = "Activity ID")
+    ),
+    responses(
+        (status = 200, description = "Event stream of processing progress", body = ProcessingEvent, content_type = "text/event-stream"),
+        (status = 401, description = "Unauthorized"),
+        (status = 404, description = "Activity not found")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn stream_activity_processing(
+    Extension(db): Extension<Database>,
+    Extension(hub): Extension<ProcessingHub>,
+    AuthUser(claims): AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
+    // Subscribe before reading the status so no transition falls in between
+    let subscription = hub.subscribe(id);
+    let status = load_processing_status(&db, claims.sub, id).await?;
+
+    let finished = matches!(status.status, JobStatus::Succeeded | JobStatus::Failed);
+    let initial = Event::default()
+        .event("status")
+        .json_data(&status)
+        .map_err(|_| AppError::Internal)?;
+
+    let updates = stream::unfold(
+        (subscription, finished),
+        |(mut subscription, finished)| async move {
+            if finished {
+                return None;
+            }
+            loop {
+                let event = subscription.recv().await?;
+                let done = event.is_terminal();
+                match Event::default().event(event.name()).json_data(&event) {
+                    Ok(sse) => return Some((Ok(sse), (subscription, done))),
+                    Err(e) => tracing::error!("Failed to serialize processing event: {e}"),
+                }
+            }
+        },
+    );
+
+    let events = stream::once(async { Ok(initial) }).chain(updates);
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
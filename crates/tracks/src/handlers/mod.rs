@@ -10,6 +10,8 @@ pub mod achievements;
 pub mod activities;
 pub mod activity_types;
 pub mod demographics;
+pub mod imports;
+pub mod jobs;
 pub mod leaderboards;
 pub mod segments;
 pub mod social;
@@ -24,17 +26,18 @@ pub use achievements::{
 };
 pub use activities::{
     __path_create_dig_parts, __path_delete_activity, __path_delete_dig_part,
-    __path_download_gpx_file, __path_get_activities_by_date, __path_get_activity,
-    __path_get_activity_segments, __path_get_activity_sensor_data, __path_get_activity_track,
-    __path_get_dig_parts, __path_get_dig_time, __path_get_stopped_segments,
-    __path_get_user_activities, __path_new_activity, __path_preview_activity,
-    __path_reprocess_dig_parts, __path_update_activity, ActivitiesByDateQuery,
-    PreviewActivityResponse, PreviewSportSegment, PreviewTrackPoint, ReprocessDigPartsResult,
-    TrackBounds, TrackData, TrackPoint, UpdateActivityRequest, UploadQuery, UserActivitiesQuery,
-    create_dig_parts, delete_activity, delete_dig_part, download_gpx_file, get_activities_by_date,
-    get_activity, get_activity_segments, get_activity_sensor_data, get_activity_track,
-    get_dig_parts, get_dig_time, get_stopped_segments, get_user_activities, new_activity,
-    preview_activity, reprocess_dig_parts, update_activity,
+    __path_download_gpx_file, __path_export_activity, __path_get_activities_by_date,
+    __path_get_activity, __path_get_activity_segments, __path_get_activity_sensor_data,
+    __path_get_activity_track, __path_get_dig_parts, __path_get_dig_time,
+    __path_get_stopped_segments, __path_get_user_activities, __path_new_activity,
+    __path_preview_activity, __path_reprocess_dig_parts, __path_update_activity,
+    ActivitiesByDateQuery, ExportActivityQuery, PreviewActivityResponse, PreviewSportSegment,
+    PreviewTrackPoint, ReprocessDigPartsResult, TrackBounds, TrackData, TrackPoint,
+    UpdateActivityRequest, UploadQuery, UserActivitiesQuery, create_dig_parts, delete_activity,
+    delete_dig_part, download_gpx_file, export_activity, get_activities_by_date, get_activity,
+    get_activity_segments, get_activity_sensor_data, get_activity_track, get_dig_parts,
+    get_dig_time, get_stopped_segments, get_user_activities, new_activity, preview_activity,
+    reprocess_dig_parts, update_activity,
 };
 pub use activity_types::{
     __path_create_activity_type, __path_get_activity_type, __path_list_activity_types,
@@ -45,6 +48,15 @@ pub use demographics::{
     __path_get_my_demographics, __path_update_my_demographics, get_my_demographics,
     update_my_demographics,
 };
+pub use imports::{
+    __path_get_activity_import, __path_import_activities, ImportActivitiesQuery,
+    get_activity_import, import_activities,
+};
+pub use jobs::{
+    __path_get_activity_processing, __path_list_jobs, __path_retry_job,
+    __path_stream_activity_processing, ListJobsQuery, get_activity_processing, list_jobs,
+    retry_job, stream_activity_processing,
+};
 pub use leaderboards::{
     __path_get_average_speed_leaderboard, __path_get_countries, __path_get_crown_leaderboard,
     __path_get_dig_percentage_leaderboard, __path_get_dig_time_leaderboard,
@@ -55,28 +67,33 @@ pub use leaderboards::{
 pub use segments::{
     __path_create_segment, __path_get_filtered_leaderboard, __path_get_leaderboard_position,
     __path_get_my_segment_efforts, __path_get_nearby_segments, __path_get_segment,
-    __path_get_segment_leaderboard, __path_get_segment_track, __path_get_starred_segment_efforts,
+    __path_get_segment_backfill, __path_get_segment_leaderboard, __path_get_segment_rejections,
+    __path_get_segment_track, __path_get_segment_versions, __path_get_starred_segment_efforts,
     __path_get_starred_segments, __path_is_segment_starred, __path_list_segments,
-    __path_preview_segment, __path_reprocess_segment, __path_star_segment, __path_unstar_segment,
+    __path_preview_segment, __path_reprocess_segment, __path_star_segment,
+    __path_stream_segment_leaderboard, __path_unstar_segment, __path_update_segment_geometry,
     ClimbCategoryFilter, CreateSegmentRequest, ListSegmentsQuery, NearbySegmentsQuery,
-    PreviewSegmentRequest, PreviewSegmentResponse, ReprocessResult, SegmentPoint, SegmentSortBy,
-    SegmentTrackData, SegmentTrackPoint, SegmentValidation, SortOrder, StarResponse,
-    create_segment, get_filtered_leaderboard, get_leaderboard_position, get_my_segment_efforts,
-    get_nearby_segments, get_segment, get_segment_leaderboard, get_segment_track,
-    get_starred_segment_efforts, get_starred_segments, is_segment_starred, list_segments,
-    preview_segment, reprocess_segment, star_segment, unstar_segment,
+    PreviewSegmentRequest, PreviewSegmentResponse, SegmentPoint, SegmentSortBy, SegmentTrackData,
+    SegmentTrackPoint, SegmentValidation, SortOrder, StarResponse, UpdateSegmentGeometryRequest,
+    UpdateSegmentGeometryResponse, create_segment, get_filtered_leaderboard,
+    get_leaderboard_position, get_my_segment_efforts, get_nearby_segments, get_segment,
+    get_segment_backfill, get_segment_leaderboard, get_segment_rejections, get_segment_track,
+    get_segment_versions, get_starred_segment_efforts, get_starred_segments, is_segment_starred,
+    list_segments, preview_segment, reprocess_segment, star_segment, stream_segment_leaderboard,
+    unstar_segment, update_segment_geometry,
 };
 pub use social::{
     __path_add_comment, __path_delete_comment, __path_follow_user, __path_get_comments,
     __path_get_feed, __path_get_follow_status, __path_get_followers, __path_get_following,
     __path_get_kudos_givers, __path_get_kudos_status, __path_get_notifications,
     __path_get_user_profile, __path_give_kudos, __path_mark_all_notifications_read,
-    __path_mark_notification_read, __path_remove_kudos, __path_unfollow_user, AddCommentRequest,
-    FeedQuery, FollowListQuery, FollowListResponse, FollowStatusResponse, KudosResponse,
-    KudosStatusResponse, NotificationsQuery, add_comment, delete_comment, follow_user,
-    get_comments, get_feed, get_follow_status, get_followers, get_following, get_kudos_givers,
-    get_kudos_status, get_notifications, get_user_profile, give_kudos, mark_all_notifications_read,
-    mark_notification_read, remove_kudos, unfollow_user,
+    __path_mark_notification_read, __path_remove_kudos, __path_stream_notifications,
+    __path_unfollow_user, AddCommentRequest, FeedQuery, FollowListQuery, FollowListResponse,
+    FollowStatusResponse, KudosResponse, KudosStatusResponse, NotificationsQuery, add_comment,
+    delete_comment, follow_user, get_comments, get_feed, get_follow_status, get_followers,
+    get_following, get_kudos_givers, get_kudos_status, get_notifications, get_user_profile,
+    give_kudos, mark_all_notifications_read, mark_notification_read, remove_kudos,
+    stream_notifications, unfollow_user,
 };
 pub use stats::{__path_get_stats, __path_health_check, get_stats, health_check};
 pub use teams::{
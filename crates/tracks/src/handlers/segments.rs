@@ -1,25 +1,36 @@
 //! Segment management handlers.
 
+use std::convert::Infallible;
+
 use axum::{
     Extension,
     extract::{Path, Query},
-    response::Json,
+    http::StatusCode,
+    response::{
+        Json,
+        sse::{Event, KeepAlive, Sse},
+    },
 };
+use futures_util::{Stream, StreamExt, stream};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 use uuid::Uuid;
 
 use crate::{
-    achievements_service,
     auth::{AuthUser, OptionalAuthUser},
     database::Database,
+    elevation::ElevationService,
     errors::AppError,
+    file_parsers::parse_activity_file,
+    leaderboard_stream::LeaderboardHub,
     models::{
-        LeaderboardFilters, LeaderboardFiltersResponse, LeaderboardPosition, LeaderboardResponse,
-        Segment, SegmentEffort, StarredSegmentEffort,
+        CrownChange, LeaderboardEvent, LeaderboardFilters, LeaderboardFiltersResponse,
+        LeaderboardPosition, LeaderboardResponse, Segment, SegmentBackfillJob, SegmentEffort,
+        SegmentGeometry, SegmentMatchRejection, SegmentVersion, StarredSegmentEffort,
     },
-    object_store_service::ObjectStoreService,
-    segment_matching,
+    object_store_service::{FileType, ObjectStoreService},
+    segment_backfill::SegmentBackfillQueue,
+    segment_edit_service,
 };
 
 use super::activities::TrackBounds;
@@ -47,7 +58,7 @@ fn default_visibility() -> String {
     "public".to_string()
 }
 
-#[derive(Debug, Deserialize, ToSchema)]
+#[derive(Debug, Clone, Deserialize, ToSchema)]
 pub struct SegmentPoint {
     pub lat: f64,
     pub lon: f64,
@@ -68,72 +79,13 @@ pub struct SegmentPoint {
 pub async fn create_segment(
     Extension(db): Extension<Database>,
     Extension(store): Extension<ObjectStoreService>,
+    Extension(backfill): Extension<SegmentBackfillQueue>,
+    Extension(elevation): Extension<ElevationService>,
     AuthUser(claims): AuthUser,
-    Json(req): Json<CreateSegmentRequest>,
+    Json(mut req): Json<CreateSegmentRequest>,
 ) -> Result<Json<Segment>, AppError> {
-    // Validation: minimum point count
-    const MIN_POINTS: usize = 10;
-    if req.points.len() < MIN_POINTS {
-        return Err(AppError::InvalidInput(format!(
-            "Segment must have at least {MIN_POINTS} points (got {})",
-            req.points.len()
-        )));
-    }
-
-    // Calculate distance early for validation
-    let distance_meters = calculate_total_distance(&req.points);
-
-    // Validation: minimum length (100m)
-    const MIN_LENGTH_METERS: f64 = 100.0;
-    if distance_meters < MIN_LENGTH_METERS {
-        return Err(AppError::InvalidInput(format!(
-            "Segment must be at least {MIN_LENGTH_METERS}m long (got {:.0}m)",
-            distance_meters
-        )));
-    }
-
-    // Validation: maximum length (50km)
-    const MAX_LENGTH_METERS: f64 = 50_000.0;
-    if distance_meters > MAX_LENGTH_METERS {
-        return Err(AppError::InvalidInput(format!(
-            "Segment must be at most {}km long (got {:.1}km)",
-            MAX_LENGTH_METERS / 1000.0,
-            distance_meters / 1000.0
-        )));
-    }
-
-    // Build WKT strings for PostGIS (include elevation if available)
-    let has_elevation = req.points.iter().any(|p| p.ele.is_some());
-    let coords: Vec<String> = req
-        .points
-        .iter()
-        .map(|p| {
-            if has_elevation {
-                format!("{} {} {}", p.lon, p.lat, p.ele.unwrap_or(0.0))
-            } else {
-                format!("{} {}", p.lon, p.lat)
-            }
-        })
-        .collect();
-    let geo_wkt = if has_elevation {
-        format!("LINESTRING Z({})", coords.join(", "))
-    } else {
-        format!("LINESTRING({})", coords.join(", "))
-    };
-
-    let start = &req.points[0];
-    let end = &req.points[req.points.len() - 1];
-    let start_wkt = format!("POINT({} {})", start.lon, start.lat);
-    let end_wkt = format!("POINT({} {})", end.lon, end.lat);
-
-    // Calculate elevation gain/loss
-    let (elevation_gain, elevation_loss) = calculate_elevation_change(&req.points);
-
-    // Calculate grade metrics
-    let (average_grade, max_grade) = calculate_grades(&req.points);
-
-    // Calculate climb category
-    let climb_category = calculate_climb_category(elevation_gain, distance_meters, average_grade);
+    let points = correct_segment_elevation(&elevation, std::mem::take(&mut req.points)).await?;
+    let geometry = build_segment_geometry(&points)?;
 
     let creator_id = claims.sub;
 
@@ -155,7 +107,7 @@ pub async fn create_segment(
 
     // Check for duplicate segments (same activity type, similar start/end points)
     let similar_segments = db
-        .find_similar_segments(activity_type_id, &start_wkt, &end_wkt)
+        .find_similar_segments(activity_type_id, &geometry.start_wkt, &geometry.end_wkt)
         .await?;
     if !similar_segments.is_empty() {
         return Err(AppError::SimilarSegmentsExist(similar_segments));
@@ -168,15 +120,15 @@ pub async fn create_segment(
             &req.name,
             req.description.as_deref(),
             activity_type_id,
-            &geo_wkt,
-            &start_wkt,
-            &end_wkt,
-            distance_meters,
-            elevation_gain,
-            elevation_loss,
-            average_grade,
-            max_grade,
-            climb_category,
+            &geometry.geo_wkt,
+            &geometry.start_wkt,
+            &geometry.end_wkt,
+            geometry.distance_meters,
+            geometry.elevation_gain,
+            geometry.elevation_loss,
+            geometry.average_grade,
+            geometry.max_grade,
+            geometry.climb_category,
             &req.visibility,
         )
         .await?;
@@ -195,11 +147,16 @@ pub async fn create_segment(
         {
             // Track not in database, try to save it
             if let Ok(file_bytes) = store.get_file(&activity.object_store_path).await
-                && let Ok(gpx) = gpx::read(std::io::BufReader::new(file_bytes.as_ref()))
-                && let Some(wkt) = build_track_wkt(&gpx)
+                && let Ok(parsed) =
+                    parse_activity_file(FileType::detect_from_bytes(&file_bytes), file_bytes)
+                && !parsed.track_points.is_empty()
             {
                 if let Err(e) = db
-                    .save_track_geometry(activity.user_id, source_id, &wkt)
+                    .save_track_geometry_with_data(
+                        activity.user_id,
+                        source_id,
+                        &parsed.track_points,
+                    )
                     .await
                 {
                     tracing::warn!(
@@ -212,101 +169,12 @@ pub async fn create_segment(
         }
     }
 
-    // Automatically find and create efforts for existing activities
-    let segment_id = segment.id;
-    match db.find_matching_activities_for_segment(segment_id).await {
-        Ok(matches) => {
-            tracing::info!(
-                "Found {} matching activities for segment {}",
-                matches.len(),
-                segment_id
-            );
-            for activity_match in matches {
-                // Get the activity to find its GPX file
-                let activity = match db.get_activity(activity_match.activity_id).await {
-                    Ok(Some(a)) => a,
-                    _ => continue,
-                };
-
-                // Fetch and parse the GPX
-                let file_bytes = match store.get_file(&activity.object_store_path).await {
-                    Ok(bytes) => bytes,
-                    Err(_) => continue,
-                };
-
-                let gpx: gpx::Gpx = match gpx::read(std::io::BufReader::new(file_bytes.as_ref())) {
-                    Ok(g) => g,
-                    Err(_) => continue,
-                };
-
-                // Extract timing
-                let timing = match segment_matching::extract_timing_from_gpx(
-                    &gpx,
-                    activity_match.start_fraction,
-                    activity_match.end_fraction,
-                ) {
-                    Some(t) => t,
-                    None => continue,
-                };
-
-                // Calculate average speed: distance / time
-                let average_speed_mps = if timing.elapsed_time_seconds > 0.0 {
-                    Some(distance_meters / timing.elapsed_time_seconds)
-                } else {
-                    None
-                };
-
-                // Create the effort
-                if let Ok(effort) = db
-                    .create_segment_effort(
-                        segment_id,
-                        activity_match.activity_id,
-                        activity_match.user_id,
-                        timing.started_at,
-                        timing.elapsed_time_seconds,
-                        Some(timing.moving_time_seconds),
-                        average_speed_mps,
-                        None, // max_speed_mps
-                        Some(activity_match.start_fraction),
-                        Some(activity_match.end_fraction),
-                    )
-                    .await
-                {
-                    tracing::info!(
-                        "Auto-created effort {} for segment {} from activity {} with time {:.1}s (moving: {:.1}s)",
-                        effort.id,
-                        segment_id,
-                        activity_match.activity_id,
-                        timing.elapsed_time_seconds,
-                        timing.moving_time_seconds
-                    );
-
-                    // Update effort count
-                    let _ = db.increment_segment_effort_count(segment_id).await;
-
-                    // Update personal records
-                    let _ = db
-                        .update_personal_records(segment_id, activity_match.user_id)
-                        .await;
-
-                    // Process achievements (KOM/QOM)
-                    if let Err(e) = achievements_service::process_achievements(
-                        &db,
-                        segment_id,
-                        activity_match.user_id,
-                        effort.id,
-                        timing.elapsed_time_seconds,
-                    )
-                    .await
-                    {
-                        tracing::error!("Failed to process achievements: {e}");
-                    }
-                }
-            }
-        }
-        Err(e) => {
-            tracing::warn!("Failed to find matching activities for new segment: {e}");
-        }
+    // Match existing activities against the new segment in the background
+    if let Err(e) = backfill.submit(segment.id, creator_id).await {
+        tracing::warn!(
+            "Failed to queue backfill for new segment {}: {e}",
+            segment.id
+        );
     }
 
     // Share with teams if team_ids provided
@@ -317,25 +185,112 @@ pub async fn create_segment(
     Ok(Json(segment))
 }
 
-/// Build a WKT LINESTRING from all track points
-fn build_track_wkt(gpx: &gpx::Gpx) -> Option<String> {
-    let mut coords: Vec<String> = Vec::new();
+/// Replace the points' elevation with terrain elevation when the DEM tiles
+/// cover the whole line, so gain and climb category don't depend on the
+/// creator's device. Otherwise the points are returned unchanged.
+async fn correct_segment_elevation(
+    elevation: &ElevationService,
+    mut points: Vec<SegmentPoint>,
+) -> Result<Vec<SegmentPoint>, AppError> {
+    if !elevation.is_enabled() {
+        return Ok(points);
+    }
 
-    for track in &gpx.tracks {
-        for seg in &track.segments {
-            for pt in &seg.points {
-                let lon = pt.point().x();
-                let lat = pt.point().y();
-                coords.push(format!("{lon} {lat}"));
-            }
+    // Tiles are read from disk on first use
+    let service = elevation.clone();
+    let coords: Vec<(f64, f64)> = points.iter().map(|p| (p.lat, p.lon)).collect();
+    let elevations = tokio::task::spawn_blocking(move || service.line_elevations(coords))
+        .await
+        .map_err(|e| {
+            tracing::error!("Elevation lookup failed: {e}");
+            AppError::Internal
+        })?;
+
+    if let Some(elevations) = elevations {
+        for (point, ele) in points.iter_mut().zip(elevations) {
+            point.ele = Some(ele);
         }
     }
+    Ok(points)
+}
 
-    if coords.len() < 2 {
-        return None;
+/// Validate a segment polyline and derive its WKT and stats.
+fn build_segment_geometry(points: &[SegmentPoint]) -> Result<SegmentGeometry, AppError> {
+    // Validation: minimum point count
+    const MIN_POINTS: usize = 10;
+    if points.len() < MIN_POINTS {
+        return Err(AppError::InvalidInput(format!(
+            "Segment must have at least {MIN_POINTS} points (got {})",
+            points.len()
+        )));
+    }
+
+    // Calculate distance early for validation
+    let distance_meters = calculate_total_distance(points);
+
+    // Validation: minimum length (100m)
+    const MIN_LENGTH_METERS: f64 = 100.0;
+    if distance_meters < MIN_LENGTH_METERS {
+        return Err(AppError::InvalidInput(format!(
+            "Segment must be at least {MIN_LENGTH_METERS}m long (got {:.0}m)",
+            distance_meters
+        )));
+    }
+
+    // Validation: maximum length (50km)
+    const MAX_LENGTH_METERS: f64 = 50_000.0;
+    if distance_meters > MAX_LENGTH_METERS {
+        return Err(AppError::InvalidInput(format!(
+            "Segment must be at most {}km long (got {:.1}km)",
+            MAX_LENGTH_METERS / 1000.0,
+            distance_meters / 1000.0
+        )));
     }
 
-    Some(format!("LINESTRING({})", coords.join(", ")))
+    // Build WKT strings for PostGIS (include elevation if available)
+    let has_elevation = points.iter().any(|p| p.ele.is_some());
+    let coords: Vec<String> = points
+        .iter()
+        .map(|p| {
+            if has_elevation {
+                format!("{} {} {}", p.lon, p.lat, p.ele.unwrap_or(0.0))
+            } else {
+                format!("{} {}", p.lon, p.lat)
+            }
+        })
+        .collect();
+    let geo_wkt = if has_elevation {
+        format!("LINESTRING Z({})", coords.join(", "))
+    } else {
+        format!("LINESTRING({})", coords.join(", "))
+    };
+
+    let start = &points[0];
+    let end = &points[points.len() - 1];
+    let start_wkt = format!("POINT({} {})", start.lon, start.lat);
+    let end_wkt = format!("POINT({} {})", end.lon, end.lat);
+
+    // Calculate elevation gain/loss
+    let (elevation_gain, elevation_loss) = calculate_elevation_change(points);
+
+    // Calculate grade metrics
+    let (average_grade, max_grade) = calculate_grades(points);
+
+    // Calculate climb category
+    let climb_category = calculate_climb_category(elevation_gain, distance_meters, average_grade);
+
+    Ok(SegmentGeometry {
+        geo_wkt,
+        start_wkt,
+        end_wkt,
+        points: points.iter().map(|p| (p.lat, p.lon)).collect(),
+        distance_meters,
+        elevation_gain,
+        elevation_loss,
+        average_grade,
+        max_grade,
+        climb_category,
+    })
 }
 
 fn calculate_total_distance(points: &[SegmentPoint]) -> f64 {
@@ -662,6 +617,168 @@ pub async fn get_my_segment_efforts(
     Ok(Json(efforts))
 }
 
+/// List activities whose match on this segment was rejected by the path
+/// conformance check, with the reason. Only the segment creator can see these.
+#[utoipa::path(
+    get,
+    path = "/segments/{id}/rejections",
+    tag = "segments",
+    params(
+        ("id" = Uuid, Path, description = "Segment ID")
+    ),
+    responses(
+        (status = 200, description = "Rejected matches on segment", body = Vec<SegmentMatchRejection>),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not the segment creator"),
+        (status = 404, description = "Segment not found"),
+        (status = 500, description = "Internal server error")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_segment_rejections(
+    Extension(db): Extension<Database>,
+    AuthUser(claims): AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vec<SegmentMatchRejection>>, AppError> {
+    let segment = db.get_segment(id).await?.ok_or(AppError::NotFound)?;
+    if segment.creator_id != claims.sub {
+        return Err(AppError::Forbidden);
+    }
+
+    let rejections = db.get_segment_match_rejections(id).await?;
+    Ok(Json(rejections))
+}
+
+/// New geometry for a segment: either a replacement polyline or a trim of the
+/// current one.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct UpdateSegmentGeometryRequest {
+    /// Replacement polyline; cannot be combined with trimming
+    pub points: Option<Vec<SegmentPoint>>,
+    /// Meters to cut from the start of the current line
+    pub trim_start_meters: Option<f64>,
+    /// Meters to cut from the end of the current line
+    pub trim_end_meters: Option<f64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UpdateSegmentGeometryResponse {
+    pub segment: Segment,
+    /// Activities checked against the new geometry
+    pub activities_checked: usize,
+    /// Efforts on the segment after the rebuild
+    pub efforts: usize,
+    /// KOM/QOM/course record crowns that changed hands
+    pub crown_changes: Vec<CrownChange>,
+}
+
+/// Edit a segment's geometry, creating a new version. All efforts, personal
+/// records and crowns on the segment are rebuilt against the new line, and
+/// riders whose crowns changed are notified. Only the segment creator can edit.
+#[utoipa::path(
+    patch,
+    path = "/segments/{id}/geometry",
+    tag = "segments",
+    params(
+        ("id" = Uuid, Path, description = "Segment ID")
+    ),
+    request_body = UpdateSegmentGeometryRequest,
+    responses(
+        (status = 200, description = "Segment geometry updated", body = UpdateSegmentGeometryResponse),
+        (status = 400, description = "Invalid input"),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not the segment creator"),
+        (status = 404, description = "Segment not found")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn update_segment_geometry(
+    Extension(db): Extension<Database>,
+    Extension(elevation): Extension<ElevationService>,
+    AuthUser(claims): AuthUser,
+    Path(id): Path<Uuid>,
+    Json(req): Json<UpdateSegmentGeometryRequest>,
+) -> Result<Json<UpdateSegmentGeometryResponse>, AppError> {
+    let segment = db.get_segment(id).await?.ok_or(AppError::NotFound)?;
+    if segment.creator_id != claims.sub {
+        return Err(AppError::Forbidden);
+    }
+
+    let trimming = req.trim_start_meters.is_some() || req.trim_end_meters.is_some();
+    let points = match (req.points, trimming) {
+        (Some(_), true) => {
+            return Err(AppError::InvalidInput(
+                "Provide either points or trim distances, not both".to_string(),
+            ));
+        }
+        (Some(points), false) => points,
+        (None, true) => {
+            let line = db.get_segment_line(id).await?;
+            // Segments created without elevation are stored with Z = 0
+            let has_elevation = line.iter().any(|(_, _, ele)| ele.is_some_and(|e| e != 0.0));
+            let line: Vec<_> = line
+                .into_iter()
+                .map(|(lat, lon, ele)| (lat, lon, ele.filter(|_| has_elevation)))
+                .collect();
+            segment_edit_service::trim_line(
+                &line,
+                req.trim_start_meters.unwrap_or(0.0),
+                req.trim_end_meters.unwrap_or(0.0),
+            )
+            .ok_or_else(|| {
+                AppError::InvalidInput("Trimming would remove the whole segment".to_string())
+            })?
+            .into_iter()
+            .map(|(lat, lon, ele)| SegmentPoint { lat, lon, ele })
+            .collect()
+        }
+        (None, false) => {
+            return Err(AppError::InvalidInput(
+                "Provide points or trim distances".to_string(),
+            ));
+        }
+    };
+
+    let points = correct_segment_elevation(&elevation, points).await?;
+    let geometry = build_segment_geometry(&points)?;
+    let outcome =
+        segment_edit_service::edit_segment_geometry(&db, &segment, claims.sub, &geometry).await?;
+
+    let segment = db.get_segment(id).await?.ok_or(AppError::NotFound)?;
+    Ok(Json(UpdateSegmentGeometryResponse {
+        segment,
+        activities_checked: outcome.activities_checked,
+        efforts: outcome.efforts,
+        crown_changes: outcome.crown_changes,
+    }))
+}
+
+/// List the geometry versions of a segment, newest first.
+#[utoipa::path(
+    get,
+    path = "/segments/{id}/versions",
+    tag = "segments",
+    params(
+        ("id" = Uuid, Path, description = "Segment ID")
+    ),
+    responses(
+        (status = 200, description = "Segment geometry versions", body = Vec<SegmentVersion>),
+        (status = 404, description = "Segment not found")
+    )
+)]
+pub async fn get_segment_versions(
+    Extension(db): Extension<Database>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vec<SegmentVersion>>, AppError> {
+    db.get_segment(id).await?.ok_or(AppError::NotFound)?;
+    let versions = db.get_segment_versions(id).await?;
+    Ok(Json(versions))
+}
+
 #[derive(Debug, Serialize, ToSchema)]
 pub struct SegmentTrackData {
     pub points: Vec<SegmentTrackPoint>,
@@ -807,21 +924,23 @@ pub struct SegmentValidation {
     )
 )]
 pub async fn preview_segment(
+    Extension(elevation): Extension<ElevationService>,
     Json(req): Json<PreviewSegmentRequest>,
 ) -> Result<Json<PreviewSegmentResponse>, AppError> {
     let mut errors = Vec::new();
+    let points = correct_segment_elevation(&elevation, req.points).await?;
 
     // Validation checks (same as create_segment but we collect all errors)
     const MIN_POINTS: usize = 10;
-    if req.points.len() < MIN_POINTS {
+    if points.len() < MIN_POINTS {
         errors.push(format!(
             "Segment must have at least {MIN_POINTS} points (got {})",
-            req.points.len()
+            points.len()
         ));
     }
 
     // Calculate distance
-    let distance_meters = calculate_total_distance(&req.points);
+    let distance_meters = calculate_total_distance(&points);
 
     // Validation: minimum length (100m)
     const MIN_LENGTH_METERS: f64 = 100.0;
@@ -843,10 +962,10 @@ pub async fn preview_segment(
     }
 
     // Calculate elevation metrics
-    let (elevation_gain, elevation_loss) = calculate_elevation_change(&req.points);
+    let (elevation_gain, elevation_loss) = calculate_elevation_change(&points);
 
     // Calculate grades
-    let (average_grade, max_grade) = calculate_grades(&req.points);
+    let (average_grade, max_grade) = calculate_grades(&points);
 
     // Calculate climb category
     let climb_category = calculate_climb_category(elevation_gain, distance_meters, average_grade);
@@ -858,7 +977,7 @@ pub async fn preview_segment(
         average_grade,
         max_grade,
         climb_category,
-        point_count: req.points.len(),
+        point_count: points.len(),
         validation: SegmentValidation {
             is_valid: errors.is_empty(),
             errors,
@@ -866,16 +985,22 @@ pub async fn preview_segment(
     }))
 }
 
-#[derive(Debug, Serialize, ToSchema)]
-pub struct ReprocessResult {
-    pub segment_id: Uuid,
-    pub activities_checked: usize,
-    pub efforts_created: usize,
+/// Only the segment creator and site admins may manage a segment's backfills.
+async fn require_segment_manager(
+    db: &Database,
+    segment: &Segment,
+    user_id: Uuid,
+) -> Result<(), AppError> {
+    if segment.creator_id == user_id || db.is_admin(user_id).await? {
+        Ok(())
+    } else {
+        Err(AppError::Forbidden)
+    }
 }
 
-/// Reprocess all activities to find matches for a specific segment.
-/// This is useful when a new segment is created and we want to find
-/// all existing activities that pass through it.
+/// Queue a background job matching all existing activities against a segment.
+/// This is useful when activities were uploaded before the segment existed or
+/// matching has changed. Poll `GET /segments/{id}/backfill` for progress.
 #[utoipa::path(
     post,
     path = "/segments/{id}/reprocess",
@@ -884,157 +1009,65 @@ pub struct ReprocessResult {
         ("id" = Uuid, Path, description = "Segment ID")
     ),
     responses(
-        (status = 200, description = "Reprocessing results", body = ReprocessResult),
-        (status = 404, description = "Segment not found"),
-        (status = 500, description = "Internal server error")
+        (status = 202, description = "Backfill queued (or already pending)", body = SegmentBackfillJob),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not the segment creator or an admin"),
+        (status = 404, description = "Segment not found")
+    ),
+    security(
+        ("bearer_auth" = [])
     )
 )]
 pub async fn reprocess_segment(
     Extension(db): Extension<Database>,
-    Extension(store): Extension<ObjectStoreService>,
+    Extension(backfill): Extension<SegmentBackfillQueue>,
+    AuthUser(claims): AuthUser,
     Path(segment_id): Path<Uuid>,
-) -> Result<Json<ReprocessResult>, AppError> {
-    // Verify segment exists
+) -> Result<(StatusCode, Json<SegmentBackfillJob>), AppError> {
     let segment = db
         .get_segment(segment_id)
         .await?
         .ok_or(AppError::NotFound)?;
+    require_segment_manager(&db, &segment, claims.sub).await?;
 
-    // Find all activities that match this segment
-    let matches = db.find_matching_activities_for_segment(segment_id).await?;
-
-    let activities_checked = matches.len();
-    let mut efforts_created = 0;
-
-    for activity_match in matches {
-        // Check if effort already exists
-        if db
-            .segment_effort_exists(segment_id, activity_match.activity_id)
-            .await?
-        {
-            continue;
-        }
-
-        // Get the activity to find its GPX file
-        let activity = match db.get_activity(activity_match.activity_id).await? {
-            Some(a) => a,
-            None => continue,
-        };
-
-        // Fetch and parse the GPX
-        let file_bytes = match store.get_file(&activity.object_store_path).await {
-            Ok(bytes) => bytes,
-            Err(e) => {
-                tracing::warn!(
-                    "Failed to fetch GPX for activity {}: {e}",
-                    activity_match.activity_id
-                );
-                continue;
-            }
-        };
-
-        let gpx: gpx::Gpx = match gpx::read(std::io::BufReader::new(file_bytes.as_ref())) {
-            Ok(g) => g,
-            Err(e) => {
-                tracing::warn!(
-                    "Failed to parse GPX for activity {}: {e}",
-                    activity_match.activity_id
-                );
-                continue;
-            }
-        };
-
-        // Extract timing
-        let timing = match segment_matching::extract_timing_from_gpx(
-            &gpx,
-            activity_match.start_fraction,
-            activity_match.end_fraction,
-        ) {
-            Some(t) => t,
-            None => {
-                tracing::warn!(
-                    "Could not extract timing for activity {} on segment {}",
-                    activity_match.activity_id,
-                    segment_id
-                );
-                continue;
-            }
-        };
-
-        // Calculate average speed: distance / time
-        let average_speed_mps = if timing.elapsed_time_seconds > 0.0 {
-            Some(segment.distance_meters / timing.elapsed_time_seconds)
-        } else {
-            None
-        };
-
-        // Create the effort
-        match db
-            .create_segment_effort(
-                segment_id,
-                activity_match.activity_id,
-                activity_match.user_id,
-                timing.started_at,
-                timing.elapsed_time_seconds,
-                Some(timing.moving_time_seconds),
-                average_speed_mps,
-                None, // max_speed_mps
-                Some(activity_match.start_fraction),
-                Some(activity_match.end_fraction),
-            )
-            .await
-        {
-            Ok(effort) => {
-                tracing::info!(
-                    "Created segment effort {} for segment {} from activity {} with time {:.1}s (moving: {:.1}s)",
-                    effort.id,
-                    segment_id,
-                    activity_match.activity_id,
-                    timing.elapsed_time_seconds,
-                    timing.moving_time_seconds
-                );
-                efforts_created += 1;
-
-                // Update effort count
-                if let Err(e) = db.increment_segment_effort_count(segment_id).await {
-                    tracing::error!("Failed to increment effort count: {e}");
-                }
-
-                // Update personal records
-                if let Err(e) = db
-                    .update_personal_records(segment_id, activity_match.user_id)
-                    .await
-                {
-                    tracing::error!("Failed to update personal records: {e}");
-                }
+    let job = backfill.submit(segment_id, claims.sub).await?;
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
 
-                // Process achievements (KOM/QOM)
-                if let Err(e) = achievements_service::process_achievements(
-                    &db,
-                    segment_id,
-                    activity_match.user_id,
-                    effort.id,
-                    timing.elapsed_time_seconds,
-                )
-                .await
-                {
-                    tracing::error!("Failed to process achievements: {e}");
-                }
-            }
-            Err(e) => {
-                tracing::error!(
-                    "Failed to create segment effort for activity {}: {e}",
-                    activity_match.activity_id
-                );
-            }
-        }
-    }
+/// Get the progress of a segment's most recent backfill.
+#[utoipa::path(
+    get,
+    path = "/segments/{id}/backfill",
+    tag = "segments",
+    params(
+        ("id" = Uuid, Path, description = "Segment ID")
+    ),
+    responses(
+        (status = 200, description = "Latest backfill job", body = SegmentBackfillJob),
+        (status = 401, description = "Unauthorized"),
+        (status = 403, description = "Not the segment creator or an admin"),
+        (status = 404, description = "Segment not found or never backfilled")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn get_segment_backfill(
+    Extension(db): Extension<Database>,
+    AuthUser(claims): AuthUser,
+    Path(segment_id): Path<Uuid>,
+) -> Result<Json<SegmentBackfillJob>, AppError> {
+    let segment = db
+        .get_segment(segment_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    require_segment_manager(&db, &segment, claims.sub).await?;
 
-    Ok(Json(ReprocessResult {
-        segment_id: segment.id,
-        activities_checked,
-        efforts_created,
-    }))
+    let job = db
+        .get_latest_segment_backfill(segment_id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    Ok(Json(job))
 }
 
 // Segment star handlers
@@ -1248,6 +1281,52 @@ pub async fn get_filtered_leaderboard(
     }))
 }
 
+/// Stream live updates to a segment leaderboard.
+///
+/// Takes the same filters as the filtered leaderboard; `limit` and `offset`
+/// are ignored. Each new effort that makes the filtered leaderboard arrives as
+/// an `effort` event with its rank, and each KOM, QOM or course record that
+/// changes hands as a `crown` event. Efforts leaving the segment arrive as a
+/// `removed` event, or a `reset` event when too many changed at once to list.
+#[utoipa::path(
+    get,
+    path = "/segments/{id}/leaderboard/stream",
+    tag = "segments",
+    params(
+        ("id" = Uuid, Path, description = "Segment ID"),
+        LeaderboardFilters
+    ),
+    responses(
+        (status = 200, description = "Event stream of leaderboard updates", body = LeaderboardEvent, content_type = "text/event-stream"),
+        (status = 404, description = "Segment not found")
+    )
+)]
+pub async fn stream_segment_leaderboard(
+    Extension(db): Extension<Database>,
+    Extension(hub): Extension<LeaderboardHub>,
+    Path(id): Path<Uuid>,
+    Query(filters): Query<LeaderboardFilters>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
+    db.get_segment(id).await?.ok_or(AppError::NotFound)?;
+
+    let subscription = hub.subscribe(id, filters);
+    let events = stream::unfold(subscription, |mut subscription| async move {
+        let event = subscription.recv().await?;
+        Some((event, subscription))
+    })
+    .filter_map(|event| async move {
+        match Event::default().event(event.name()).json_data(&event) {
+            Ok(sse) => Some(Ok(sse)),
+            Err(e) => {
+                tracing::error!("Failed to serialize leaderboard event: {e}");
+                None
+            }
+        }
+    });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
 /// Get the authenticated user's position in a segment leaderboard.
 #[utoipa::path(
     get,
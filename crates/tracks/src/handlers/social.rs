@@ -1,16 +1,28 @@
 //! Social feature handlers: follows, notifications, feed, kudos, and comments.
 
+use std::convert::Infallible;
+
 use axum::{
     Extension,
     extract::{Path, Query},
     http::StatusCode,
-    response::Json,
+    response::{
+        Json,
+        sse::{Event, KeepAlive, Sse},
+    },
 };
+use futures_util::{Stream, StreamExt, stream};
 use serde::{Deserialize, Serialize};
 use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
-use crate::{auth::AuthUser, database::Database, errors::AppError, models::DateRangeFilter};
+use crate::{
+    auth::AuthUser,
+    database::Database,
+    errors::AppError,
+    models::{DateRangeFilter, NotificationEvent},
+    notification_stream::NotificationHub,
+};
 
 use super::pagination::default_limit;
 
@@ -270,6 +282,52 @@ pub async fn get_notifications(
     }))
 }
 
+/// Stream the authenticated user's notifications as they are created.
+///
+/// The first event (`unread_count`) is the current unread count. After that
+/// the stream carries a `notification` event for each new notification and an
+/// `unread_count` event whenever notifications are read, from any device.
+#[utoipa::path(
+    get,
+    path = "/notifications/stream",
+    tag = "notifications",
+    responses(
+        (status = 200, description = "Event stream of notifications", body = NotificationEvent, content_type = "text/event-stream"),
+        (status = 401, description = "Unauthorized")
+    ),
+    security(
+        ("bearer_auth" = [])
+    )
+)]
+pub async fn stream_notifications(
+    Extension(db): Extension<Database>,
+    Extension(hub): Extension<NotificationHub>,
+    AuthUser(claims): AuthUser,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
+    // Subscribe before reading the count so no change falls in between
+    let subscription = hub.subscribe(claims.sub);
+    let unread_count = db.get_unread_notification_count(claims.sub).await?;
+    let initial = NotificationEvent::UnreadCount { unread_count };
+
+    let updates = stream::unfold(subscription, |mut subscription| async move {
+        let event = subscription.recv().await?;
+        Some((event, subscription))
+    });
+    let events = stream::once(async { initial })
+        .chain(updates)
+        .filter_map(|event| async move {
+            match Event::default().event(event.name()).json_data(&event) {
+                Ok(sse) => Some(Ok(sse)),
+                Err(e) => {
+                    tracing::error!("Failed to serialize notification event: {e}");
+                    None
+                }
+            }
+        });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
 /// Mark a notification as read.
 #[utoipa::path(
     put,
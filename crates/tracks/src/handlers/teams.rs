@@ -21,7 +21,7 @@ use crate::{
     },
 };
 
-use super::pagination::default_limit;
+use super::{activities::can_view_activity, pagination::default_limit};
 
 // ============================================================================
 // ============================================================================
@@ -862,13 +862,21 @@ pub async fn get_activity_teams(
         .await?
         .ok_or(AppError::NotFound)?;
 
-    // Only owner or team members can see sharing
-    let is_owner = activity.user_id == claims.sub;
+    // Only those who can see the activity or are in a team it's shared with
+    // can see sharing
     let has_team_access = db
         .user_has_activity_team_access(claims.sub, activity_id)
         .await?;
+    let can_view = can_view_activity(
+        &db,
+        Some(claims.sub),
+        activity_id,
+        activity.user_id,
+        &activity.visibility,
+    )
+    .await?;
 
-    if !is_owner && !has_team_access && activity.visibility != "public" {
+    if !has_team_access && !can_view {
         return Err(AppError::NotFound);
     }
 
@@ -0,0 +1,293 @@
+//! Materialized segment leaderboards.
+//!
+//! Ranking every effort on a busy segment is most of the cost of serving its
+//! leaderboard, so the common leaderboards (a time scope, optionally narrowed
+//! to one gender and one age group) are kept ranked in the database: one
+//! `leaderboard_cache` row per segment and [`CacheBucket`], and one
+//! `leaderboard_cache_entries` row per effort on it, indexed in leaderboard
+//! order. A leaderboard is built the first time it is requested. From then on
+//! new efforts are added to it as they are saved and removed efforts leave it
+//! with their rows, so requests only read a slice of the index. Rider names
+//! and PR flags are read from their tables. Leaderboards filtered by weight
+//! class or country are always ranked from scratch.
+//!
+//! A leaderboard also expires when it would go stale without any effort
+//! changing: when its oldest effort leaves a rolling time scope, when the new
+//! year moves riders into the next age group, and in any case [`MAX_AGE`]
+//! after it was built. A rider changing their gender or birth year drops the
+//! demographic leaderboards of the segments they have ridden.
+
+use sqlx::FromRow;
+use time::{Date, Duration, Month, OffsetDateTime};
+
+use crate::models::{
+    AgeGroup, Gender, GenderFilter, LeaderboardFilters, LeaderboardScope, WeightClass,
+};
+
+/// Longest a cached leaderboard is served, which bounds any drift from changes
+/// the cache isn't told about.
+pub const MAX_AGE: Duration = Duration::days(1);
+
+/// The filters a cached leaderboard is ranked under.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheBucket {
+    pub scope: LeaderboardScope,
+    pub gender: Option<Gender>,
+    pub age_group: Option<AgeGroup>,
+}
+
+/// What decides which of a segment's leaderboards an effort is on.
+#[derive(Debug, Clone, FromRow)]
+pub struct EffortPlacement {
+    pub gender: Option<Gender>,
+    pub birth_year: Option<i32>,
+    pub in_year: bool,
+    pub in_month: bool,
+    pub in_week: bool,
+}
+
+impl CacheBucket {
+    /// The bucket a leaderboard request is served from, or `None` when it
+    /// filters on something that isn't cached.
+    pub fn for_filters(filters: &LeaderboardFilters) -> Option<Self> {
+        if filters.weight_class != WeightClass::All || filters.country.is_some() {
+            return None;
+        }
+        Some(Self {
+            scope: filters.scope,
+            gender: match filters.gender {
+                GenderFilter::All => None,
+                GenderFilter::Male => Some(Gender::Male),
+                GenderFilter::Female => Some(Gender::Female),
+            },
+            age_group: (filters.age_group != AgeGroup::All).then_some(filters.age_group),
+        })
+    }
+
+    /// The bucket stored under a cache row's key, `None` for unknown keys.
+    pub fn from_key(scope: &str, gender: Option<Gender>, age_group: Option<&str>) -> Option<Self> {
+        let gender = match gender {
+            None | Some(Gender::Male) | Some(Gender::Female) => gender,
+            Some(_) => return None,
+        };
+        Some(Self {
+            scope: scope.parse().ok()?,
+            gender,
+            age_group: age_group.map(str::parse).transpose().ok()?,
+        })
+    }
+
+    /// Value of the row's `scope` column.
+    pub fn scope_key(&self) -> &'static str {
+        match self.scope {
+            LeaderboardScope::AllTime => "all_time",
+            LeaderboardScope::Year => "year",
+            LeaderboardScope::Month => "month",
+            LeaderboardScope::Week => "week",
+        }
+    }
+
+    /// Value of the row's `age_group` column.
+    pub fn age_group_key(&self) -> Option<&'static str> {
+        self.age_group.map(|group| match group {
+            AgeGroup::All => "all",
+            AgeGroup::Age18To24 => "18-24",
+            AgeGroup::Age25To29 => "25-29",
+            AgeGroup::Age30To34 => "30-34",
+            AgeGroup::Age35To39 => "35-39",
+            AgeGroup::Age40To49 => "40-49",
+            AgeGroup::Age50To59 => "50-59",
+            AgeGroup::Age60Plus => "60+",
+        })
+    }
+
+    /// Leaderboard filters selecting the bucket's efforts, all of them.
+    pub fn filters(&self) -> LeaderboardFilters {
+        LeaderboardFilters {
+            scope: self.scope,
+            gender: match self.gender {
+                Some(Gender::Male) => GenderFilter::Male,
+                Some(Gender::Female) => GenderFilter::Female,
+                _ => GenderFilter::All,
+            },
+            age_group: self.age_group.unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+
+    /// Whether an effort belongs on this leaderboard. Ages are counted the
+    /// way the leaderboard filters count them, from the birth year.
+    pub fn includes(&self, effort: &EffortPlacement, current_year: i32) -> bool {
+        let in_scope = match self.scope {
+            LeaderboardScope::AllTime => true,
+            LeaderboardScope::Year => effort.in_year,
+            LeaderboardScope::Month => effort.in_month,
+            LeaderboardScope::Week => effort.in_week,
+        };
+        let in_gender = self
+            .gender
+            .is_none_or(|gender| effort.gender == Some(gender));
+        let in_age_group = match self.age_group.and_then(|group| group.age_range()) {
+            None => true,
+            Some((min_age, max_age)) => effort.birth_year.is_some_and(|year| {
+                let age = current_year - year;
+                age >= min_age && max_age.is_none_or(|max| age <= max)
+            }),
+        };
+        in_scope && in_gender && in_age_group
+    }
+
+    /// When a leaderboard whose oldest effort started at `oldest`, up to date
+    /// at `now`, has to be ranked again from scratch.
+    pub fn expires_at(
+        &self,
+        oldest: Option<OffsetDateTime>,
+        now: OffsetDateTime,
+    ) -> OffsetDateTime {
+        let mut expires_at = now + MAX_AGE;
+        if let Some(window) = scope_window(self.scope)
+            && let Some(oldest) = oldest
+        {
+            expires_at = expires_at.min(oldest + window);
+        }
+        if self.age_group.is_some() {
+            let new_year = Date::from_calendar_date(now.year() + 1, Month::January, 1)
+                .expect("January 1st is a valid date")
+                .midnight()
+                .assume_utc();
+            expires_at = expires_at.min(new_year);
+        }
+        expires_at
+    }
+}
+
+/// Shortest length of a rolling scope's window. Never longer than the
+/// interval the leaderboard filters use, so rows expire early, not late.
+fn scope_window(scope: LeaderboardScope) -> Option<Duration> {
+    match scope {
+        LeaderboardScope::AllTime => None,
+        LeaderboardScope::Year => Some(Duration::days(365)),
+        LeaderboardScope::Month => Some(Duration::days(28)),
+        LeaderboardScope::Week => Some(Duration::weeks(1)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(year: i32, month: Month, day: u8, hour: u8) -> OffsetDateTime {
+        Date::from_calendar_date(year, month, day)
+            .unwrap()
+            .with_hms(hour, 0, 0)
+            .unwrap()
+            .assume_utc()
+    }
+
+    fn placement(gender: Gender, birth_year: i32) -> EffortPlacement {
+        EffortPlacement {
+            gender: Some(gender),
+            birth_year: Some(birth_year),
+            in_year: true,
+            in_month: true,
+            in_week: false,
+        }
+    }
+
+    #[test]
+    fn test_uncached_filters_bypass_the_cache() {
+        let filters = LeaderboardFilters {
+            gender: GenderFilter::Female,
+            age_group: AgeGroup::Age30To34,
+            ..Default::default()
+        };
+        let bucket = CacheBucket::for_filters(&filters).unwrap();
+        assert_eq!(bucket.gender, Some(Gender::Female));
+        assert_eq!(bucket.age_group, Some(AgeGroup::Age30To34));
+        assert_eq!(bucket.filters().gender, GenderFilter::Female);
+
+        let by_weight = LeaderboardFilters {
+            weight_class: WeightClass::Middleweight,
+            ..Default::default()
+        };
+        assert!(CacheBucket::for_filters(&by_weight).is_none());
+        let by_country = LeaderboardFilters {
+            country: Some("NZ".into()),
+            ..Default::default()
+        };
+        assert!(CacheBucket::for_filters(&by_country).is_none());
+    }
+
+    #[test]
+    fn test_bucket_keys_round_trip() {
+        let bucket = CacheBucket {
+            scope: LeaderboardScope::Month,
+            gender: Some(Gender::Male),
+            age_group: Some(AgeGroup::Age60Plus),
+        };
+        assert_eq!(
+            CacheBucket::from_key(bucket.scope_key(), bucket.gender, bucket.age_group_key()),
+            Some(bucket)
+        );
+        assert!(CacheBucket::from_key("decade", None, None).is_none());
+        assert!(CacheBucket::from_key("week", Some(Gender::Other), None).is_none());
+    }
+
+    #[test]
+    fn test_includes() {
+        let bucket = CacheBucket {
+            scope: LeaderboardScope::Month,
+            gender: Some(Gender::Female),
+            age_group: Some(AgeGroup::Age30To34),
+        };
+        assert!(bucket.includes(&placement(Gender::Female, 1993), 2026));
+        assert!(!bucket.includes(&placement(Gender::Male, 1993), 2026));
+        assert!(!bucket.includes(&placement(Gender::Female, 1990), 2026));
+
+        let week = CacheBucket {
+            scope: LeaderboardScope::Week,
+            gender: None,
+            age_group: None,
+        };
+        assert!(!week.includes(&placement(Gender::Male, 1990), 2026));
+        let mut unknown = placement(Gender::Male, 1990);
+        unknown.in_week = true;
+        unknown.birth_year = None;
+        assert!(week.includes(&unknown, 2026));
+        assert!(!bucket.includes(&unknown, 2026));
+    }
+
+    #[test]
+    fn test_expires_at() {
+        let now = utc(2026, Month::December, 20, 12);
+        let oldest = Some(utc(2026, Month::December, 14, 9));
+
+        let all_time = CacheBucket {
+            scope: LeaderboardScope::AllTime,
+            gender: None,
+            age_group: None,
+        };
+        assert_eq!(all_time.expires_at(oldest, now), now + MAX_AGE);
+
+        // The oldest effort leaves the week before the day is up
+        let week = CacheBucket {
+            scope: LeaderboardScope::Week,
+            ..all_time
+        };
+        assert_eq!(
+            week.expires_at(oldest, now),
+            utc(2026, Month::December, 21, 9)
+        );
+
+        // Age groups move on at the new year
+        let by_age = CacheBucket {
+            age_group: Some(AgeGroup::Age25To29),
+            ..all_time
+        };
+        let new_year_eve = utc(2026, Month::December, 31, 18);
+        assert_eq!(
+            by_age.expires_at(oldest, new_year_eve),
+            utc(2027, Month::January, 1, 0)
+        );
+    }
+}
@@ -0,0 +1,363 @@
+//! Live segment leaderboard updates.
+//!
+//! Triggers on `segment_efforts` and `achievements` announce efforts joining
+//! and leaving a segment and every KOM, QOM or course record changing hands on
+//! the `segment_leaderboards` Postgres channel (see migrations
+//! `027_segment_leaderboard_events.sql` and
+//! `034_segment_leaderboard_removals.sql`). Each API instance listens on that
+//! channel and turns changes to the segments its clients are watching into
+//! [`LeaderboardEvent`]s. A new effort is ranked once for every distinct set
+//! of [`LeaderboardFilters`] being watched on its segment and the result is
+//! sent to all the streams watching with those filters, so an effort outside
+//! a stream's filters is never sent to it. Ranking runs in a task per segment,
+//! off the listener, and keeps the order the segment's changes arrived in. A
+//! stream that falls behind is told to fetch the leaderboard again.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    database::Database,
+    event_hub::{EventHub, Subscription},
+    models::{
+        AchievementType, AgeGroup, GenderFilter, LeaderboardEvent, LeaderboardFilters,
+        LeaderboardScope, WeightClass,
+    },
+};
+
+/// Postgres channel the effort and crown triggers publish on.
+pub const LEADERBOARD_CHANNEL: &str = "segment_leaderboards";
+
+/// Updates buffered per subscriber before a slow client starts missing them.
+/// Backfills can create many efforts on a segment in a short time.
+const UPDATE_CHANNEL_CAPACITY: usize = 1024;
+
+/// Payload of a message on [`LEADERBOARD_CHANNEL`]: efforts a statement added
+/// to or removed from the segment, `reset` when it changed too many to list,
+/// or a crown that changed hands.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct ChangePayload {
+    segment_id: Uuid,
+    effort_ids: Vec<Uuid>,
+    removed_effort_ids: Vec<Uuid>,
+    reset: bool,
+    achievement_id: Option<Uuid>,
+}
+
+/// The filters that decide what a stream shows. Streams watching a segment
+/// with the same filters share the work of ranking its new efforts.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct StreamFilters {
+    scope: LeaderboardScope,
+    gender: GenderFilter,
+    age_group: AgeGroup,
+    weight_class: WeightClass,
+    country: Option<String>,
+}
+
+impl StreamFilters {
+    fn new(filters: LeaderboardFilters) -> Self {
+        Self {
+            scope: filters.scope,
+            gender: filters.gender,
+            age_group: filters.age_group,
+            weight_class: filters.weight_class,
+            country: filters.country,
+        }
+    }
+
+    fn filters(&self) -> LeaderboardFilters {
+        LeaderboardFilters {
+            scope: self.scope,
+            gender: self.gender,
+            age_group: self.age_group,
+            weight_class: self.weight_class,
+            country: self.country.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/// An event for the streams watching a segment: all of them, or only those
+/// watching with `filters`.
+#[derive(Debug)]
+struct Update {
+    segment_id: Uuid,
+    filters: Option<StreamFilters>,
+    event: LeaderboardEvent,
+}
+
+/// What a stream watches: a segment, through a set of filters.
+type StreamKey = (Uuid, StreamFilters);
+
+/// Changes waiting to be turned into updates, per segment. A segment is in
+/// here while its task runs.
+type Pending = HashMap<Uuid, VecDeque<ChangePayload>>;
+
+/// Forwards leaderboard changes to the clients connected to this instance.
+/// Cheap to clone; clones share subscribers.
+#[derive(Clone)]
+pub struct LeaderboardHub {
+    db: Database,
+    /// Changes on segments nobody watches are dropped
+    hub: EventHub<StreamKey, Arc<Update>>,
+    pending: Arc<Mutex<Pending>>,
+}
+
+impl LeaderboardHub {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            db: Database::new(pool.clone()),
+            hub: EventHub::new(pool, LEADERBOARD_CHANNEL, UPDATE_CHANNEL_CAPACITY, shows),
+            pending: Default::default(),
+        }
+    }
+
+    /// Spawn the channel listener on the current runtime.
+    pub fn start(&self) {
+        let hub = self.clone();
+        self.hub.start(move |change: ChangePayload| {
+            if !hub.watched_filters(change.segment_id).is_empty() {
+                hub.queue(change);
+            }
+            std::future::ready(())
+        });
+    }
+
+    /// Watch a segment's leaderboard as seen through `filters`.
+    pub fn subscribe(
+        &self,
+        segment_id: Uuid,
+        filters: LeaderboardFilters,
+    ) -> LeaderboardSubscription {
+        LeaderboardSubscription(
+            self.hub
+                .subscribe((segment_id, StreamFilters::new(filters))),
+        )
+    }
+
+    /// The distinct filters a segment is watched with.
+    fn watched_filters(&self, segment_id: Uuid) -> Vec<StreamFilters> {
+        self.hub
+            .watched(|(watched, _)| *watched == segment_id)
+            .into_iter()
+            .map(|(_, filters)| filters)
+            .collect()
+    }
+
+    /// Queue a change for its segment's task, starting the task if the
+    /// segment has none.
+    fn queue(&self, change: ChangePayload) {
+        let segment_id = change.segment_id;
+        {
+            let mut pending = self.lock_pending();
+            if let Some(queue) = pending.get_mut(&segment_id) {
+                queue.push_back(change);
+                return;
+            }
+            pending.insert(segment_id, VecDeque::from([change]));
+        }
+        let hub = self.clone();
+        tokio::spawn(async move { hub.process(segment_id).await });
+    }
+
+    /// Send the updates for a segment's queued changes in order, until none
+    /// are left.
+    async fn process(&self, segment_id: Uuid) {
+        loop {
+            let change = {
+                let mut pending = self.lock_pending();
+                match pending.get_mut(&segment_id).and_then(VecDeque::pop_front) {
+                    Some(change) => change,
+                    None => {
+                        pending.remove(&segment_id);
+                        return;
+                    }
+                }
+            };
+            let watched = self.watched_filters(segment_id);
+            if watched.is_empty() {
+                continue;
+            }
+            for update in self.updates_for(&change, &watched).await {
+                self.hub.send(Arc::new(update));
+            }
+        }
+    }
+
+    /// The updates a change makes to the leaderboards watched with `watched`.
+    async fn updates_for(&self, change: &ChangePayload, watched: &[StreamFilters]) -> Vec<Update> {
+        let segment_id = change.segment_id;
+        let to_all = |event| Update {
+            segment_id,
+            filters: None,
+            event,
+        };
+
+        if change.reset {
+            return vec![to_all(LeaderboardEvent::Reset)];
+        }
+
+        let mut updates = Vec::new();
+        if !change.removed_effort_ids.is_empty() {
+            updates.push(to_all(LeaderboardEvent::Removed {
+                effort_ids: change.removed_effort_ids.clone(),
+            }));
+        }
+        if let Some(achievement_id) = change.achievement_id {
+            // Each stream checks whether its gender filter shows the crown
+            match self.db.get_achievement_holder(achievement_id).await {
+                Ok(holder) => {
+                    updates.extend(holder.map(|holder| to_all(LeaderboardEvent::Crown { holder })))
+                }
+                Err(e) => tracing::warn!("Failed to load crown holder {achievement_id}: {e}"),
+            }
+        }
+        for &effort_id in &change.effort_ids {
+            for stream in watched {
+                match self
+                    .db
+                    .get_leaderboard_entry(segment_id, effort_id, &stream.filters())
+                    .await
+                {
+                    Ok(Some((entry, total_count))) => updates.push(Update {
+                        segment_id,
+                        filters: Some(stream.clone()),
+                        event: LeaderboardEvent::Effort { entry, total_count },
+                    }),
+                    Ok(None) => {}
+                    Err(e) => tracing::warn!("Failed to rank effort {effort_id}: {e}"),
+                }
+            }
+        }
+        updates
+    }
+
+    fn lock_pending(&self) -> std::sync::MutexGuard<'_, Pending> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// An open leaderboard stream. Dropping it unsubscribes.
+pub struct LeaderboardSubscription(Subscription<StreamKey, Arc<Update>>);
+
+impl LeaderboardSubscription {
+    /// Wait for the next update to the watched leaderboard. A stream that
+    /// missed updates gets a [`LeaderboardEvent::Reset`] instead.
+    pub async fn recv(&mut self) -> Option<LeaderboardEvent> {
+        Some(match self.0.recv().await? {
+            Ok(update) => update.event.clone(),
+            Err(_) => LeaderboardEvent::Reset,
+        })
+    }
+}
+
+/// Whether the stream watching with `key` shows an update.
+fn shows((segment_id, filters): &StreamKey, update: &Arc<Update>) -> bool {
+    if update.segment_id != *segment_id
+        || update
+            .filters
+            .as_ref()
+            .is_some_and(|update_filters| update_filters != filters)
+    {
+        return false;
+    }
+    match &update.event {
+        LeaderboardEvent::Crown { holder } => shows_crown(filters.gender, holder.achievement_type),
+        _ => true,
+    }
+}
+
+/// Whether a leaderboard filtered by `gender` shows this crown: the men's
+/// leaderboard has no QOM and the women's no KOM.
+fn shows_crown(gender: GenderFilter, crown: AchievementType) -> bool {
+    !matches!(
+        (gender, crown),
+        (GenderFilter::Male, AchievementType::Qom) | (GenderFilter::Female, AchievementType::Kom)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shows_crown() {
+        assert!(shows_crown(GenderFilter::All, AchievementType::Qom));
+        assert!(shows_crown(GenderFilter::Male, AchievementType::Kom));
+        assert!(shows_crown(
+            GenderFilter::Male,
+            AchievementType::CourseRecord
+        ));
+        assert!(!shows_crown(GenderFilter::Male, AchievementType::Qom));
+        assert!(!shows_crown(GenderFilter::Female, AchievementType::Kom));
+    }
+
+    fn test_hub() -> LeaderboardHub {
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .connect_lazy("postgres://localhost/unused")
+            .unwrap();
+        LeaderboardHub::new(pool)
+    }
+
+    #[tokio::test]
+    async fn test_subscription_skips_other_filters_and_segments() {
+        let hub = test_hub();
+        let segment = Uuid::new_v4();
+        let mut subscription = hub.subscribe(segment, LeaderboardFilters::default());
+        let removed = |segment_id, filters| {
+            Arc::new(Update {
+                segment_id,
+                filters,
+                event: LeaderboardEvent::Removed {
+                    effort_ids: vec![segment_id],
+                },
+            })
+        };
+
+        let women = StreamFilters::new(LeaderboardFilters {
+            gender: GenderFilter::Female,
+            ..Default::default()
+        });
+        hub.hub.send(removed(segment, Some(women)));
+        hub.hub.send(removed(Uuid::new_v4(), None));
+        hub.hub.send(removed(segment, None));
+
+        match subscription.recv().await {
+            Some(LeaderboardEvent::Removed { effort_ids }) => assert_eq!(effort_ids, [segment]),
+            other => panic!("unexpected event {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lagged_subscription_resets() {
+        let hub = test_hub();
+        let segment = Uuid::new_v4();
+        let mut subscription = hub.subscribe(segment, LeaderboardFilters::default());
+        for _ in 0..=UPDATE_CHANNEL_CAPACITY {
+            hub.hub.send(Arc::new(Update {
+                segment_id: segment,
+                filters: None,
+                event: LeaderboardEvent::Removed {
+                    effort_ids: Vec::new(),
+                },
+            }));
+        }
+
+        assert!(matches!(
+            subscription.recv().await,
+            Some(LeaderboardEvent::Reset)
+        ));
+        assert!(matches!(
+            subscription.recv().await,
+            Some(LeaderboardEvent::Removed { .. })
+        ));
+    }
+}
@@ -1,22 +1,36 @@
 pub mod achievements_service;
+pub mod activity_export;
+pub mod activity_import;
 pub mod activity_queue;
 pub mod auth;
+pub mod compression;
 pub mod database;
+pub mod duplicate_detection;
+pub mod elevation;
 pub mod errors;
+pub mod event_hub;
 pub mod file_parsers;
 pub mod handlers;
+pub mod leaderboard_cache;
+pub mod leaderboard_stream;
 pub mod models;
+pub mod notification_stream;
 pub mod object_store_service;
+pub mod processing_stream;
 pub mod query_builder;
 pub mod request_id;
 pub mod scoring;
+pub mod segment_backfill;
+pub mod segment_edit_service;
 pub mod segment_matching;
+pub mod track_cleaning;
 pub mod types;
 
 use std::env;
 
 use axum::{
     Extension, Router,
+    extract::DefaultBodyLimit,
     http::{HeaderValue, Method, header},
     middleware,
     routing::{get, post},
@@ -36,11 +50,13 @@ use crate::{
     activity_queue::ActivityQueue,
     auth::{login, me, register},
     database::Database,
+    elevation::ElevationService,
     handlers::{
         accept_invitation, add_comment, all_users, change_member_role, create_activity_type,
         create_dig_parts, create_segment, create_team, delete_activity, delete_comment,
-        delete_dig_part, delete_team, discover_teams, download_gpx_file, follow_user,
-        get_activities_by_date, get_activity, get_activity_segments, get_activity_sensor_data,
+        delete_dig_part, delete_team, discover_teams, download_gpx_file, export_activity,
+        follow_user, get_activities_by_date, get_activity, get_activity_import,
+        get_activity_processing, get_activity_segments, get_activity_sensor_data,
         get_activity_teams, get_activity_track, get_activity_type, get_average_speed_leaderboard,
         get_comments, get_countries, get_crown_leaderboard, get_dig_parts,
         get_dig_percentage_leaderboard, get_dig_time, get_dig_time_leaderboard,
@@ -48,21 +64,27 @@ use crate::{
         get_followers, get_following, get_global_dig_heatmap, get_invitation, get_join_requests,
         get_kudos_givers, get_kudos_status, get_leaderboard_position, get_my_achievements,
         get_my_demographics, get_my_segment_efforts, get_nearby_segments, get_notifications,
-        get_segment, get_segment_achievements, get_segment_leaderboard, get_segment_teams,
-        get_segment_track, get_starred_segment_efforts, get_starred_segments, get_stats,
-        get_stopped_segments, get_team, get_team_activities, get_team_activities_by_date,
-        get_team_dig_heatmap, get_team_invitations, get_team_leaderboard, get_team_segments,
-        get_user_achievements, get_user_activities, get_user_profile, give_kudos, health_check,
-        invite_to_team, is_segment_starred, join_team, leave_team, list_activity_types,
+        get_segment, get_segment_achievements, get_segment_backfill, get_segment_leaderboard,
+        get_segment_rejections, get_segment_teams, get_segment_track, get_segment_versions,
+        get_starred_segment_efforts, get_starred_segments, get_stats, get_stopped_segments,
+        get_team, get_team_activities, get_team_activities_by_date, get_team_dig_heatmap,
+        get_team_invitations, get_team_leaderboard, get_team_segments, get_user_achievements,
+        get_user_activities, get_user_profile, give_kudos, health_check, import_activities,
+        invite_to_team, is_segment_starred, join_team, leave_team, list_activity_types, list_jobs,
         list_my_teams, list_segments, list_team_members, mark_all_notifications_read,
         mark_notification_read, new_activity, new_user, preview_activity, preview_segment,
         remove_kudos, remove_team_member, reprocess_dig_parts, reprocess_segment,
-        resolve_activity_type, review_join_request, revoke_invitation, share_activity_with_teams,
-        share_segment_with_teams, star_segment, unfollow_user, unshare_activity_from_team,
-        unshare_segment_from_team, unstar_segment, update_activity, update_my_demographics,
-        update_team,
+        resolve_activity_type, retry_job, review_join_request, revoke_invitation,
+        share_activity_with_teams, share_segment_with_teams, star_segment,
+        stream_activity_processing, stream_notifications, stream_segment_leaderboard,
+        unfollow_user, unshare_activity_from_team, unshare_segment_from_team, unstar_segment,
+        update_activity, update_my_demographics, update_segment_geometry, update_team,
     },
+    leaderboard_stream::LeaderboardHub,
+    notification_stream::NotificationHub,
     object_store_service::ObjectStoreService,
+    processing_stream::ProcessingHub,
+    segment_backfill::SegmentBackfillQueue,
 };
 #[derive(OpenApi)]
 #[openapi(
@@ -85,6 +107,7 @@ use crate::{
         (name = "notifications", description = "Notification endpoints"),
         (name = "teams", description = "Team management endpoints"),
         (name = "stats", description = "Platform statistics"),
+        (name = "jobs", description = "Activity processing jobs"),
     ),
     paths(
         // Auth
@@ -102,6 +125,7 @@ use crate::{
         handlers::delete_activity,
         handlers::get_user_activities,
         handlers::download_gpx_file,
+        handlers::export_activity,
         handlers::get_activity_track,
         handlers::get_activity_segments,
         handlers::get_activities_by_date,
@@ -124,6 +148,10 @@ use crate::{
         handlers::list_segments,
         handlers::get_segment_leaderboard,
         handlers::get_my_segment_efforts,
+        handlers::get_segment_rejections,
+        handlers::get_segment_backfill,
+        handlers::update_segment_geometry,
+        handlers::get_segment_versions,
         handlers::get_segment_track,
         handlers::preview_segment,
         handlers::reprocess_segment,
@@ -134,7 +162,16 @@ use crate::{
         handlers::get_starred_segment_efforts,
         handlers::get_nearby_segments,
         handlers::get_filtered_leaderboard,
+        handlers::stream_segment_leaderboard,
         handlers::get_leaderboard_position,
+        // Processing jobs
+        handlers::list_jobs,
+        handlers::retry_job,
+        handlers::get_activity_processing,
+        handlers::stream_activity_processing,
+        // Bulk imports
+        handlers::import_activities,
+        handlers::get_activity_import,
         // Demographics
         handlers::get_my_demographics,
         handlers::update_my_demographics,
@@ -158,6 +195,7 @@ use crate::{
         handlers::get_user_profile,
         // Notifications
         handlers::get_notifications,
+        handlers::stream_notifications,
         handlers::mark_notification_read,
         handlers::mark_all_notifications_read,
         // Feed
@@ -219,6 +257,14 @@ use crate::{
             models::Activity,
             models::Segment,
             models::SegmentEffort,
+            models::SegmentMatchRejection,
+            models::SegmentVersion,
+            models::SegmentBackfillJob,
+            models::ActivityImport,
+            models::ActivityImportItem,
+            models::ImportItemStatus,
+            models::ActivityImportReport,
+            models::CrownChange,
             models::ActivityTypeRow,
             models::CreateActivityTypeRequest,
             // Visibility and enums
@@ -242,6 +288,7 @@ use crate::{
             models::LeaderboardResponse,
             models::LeaderboardFiltersResponse,
             models::LeaderboardPosition,
+            models::LeaderboardEvent,
             models::CountryStats,
             // Achievement types
             models::AchievementType,
@@ -253,6 +300,7 @@ use crate::{
             models::UserWithDemographics,
             models::UpdateDemographicsRequest,
             models::UserProfile,
+            models::AchievementCounts,
             models::UserSummary,
             // Global leaderboards
             models::CrownCountEntry,
@@ -267,9 +315,19 @@ use crate::{
             models::Notification,
             models::NotificationWithActor,
             models::NotificationsResponse,
+            models::NotificationEvent,
             // Feed types
             models::FeedActivity,
+            models::ActivityStats,
             models::FeedActivityWithTeams,
+            // Processing job types
+            models::JobStatus,
+            models::ActivityJob,
+            models::ProcessingStage,
+            models::ProcessingErrorKind,
+            models::ActivityProcessingError,
+            models::ActivityProcessingStatus,
+            models::ProcessingEvent,
             // Stopped/Dig segment types
             models::StoppedSegment,
             models::DigPart,
@@ -319,6 +377,8 @@ use crate::{
             handlers::UpdateActivityRequest,
             handlers::UserActivitiesQuery,
             handlers::ActivitiesByDateQuery,
+            handlers::ExportActivityQuery,
+            activity_export::ExportFormat,
             handlers::ResolveTypeQuery,
             handlers::ResolveTypeResponse,
             handlers::CreateSegmentRequest,
@@ -332,7 +392,8 @@ use crate::{
             handlers::PreviewSegmentRequest,
             handlers::PreviewSegmentResponse,
             handlers::SegmentValidation,
-            handlers::ReprocessResult,
+            handlers::UpdateSegmentGeometryRequest,
+            handlers::UpdateSegmentGeometryResponse,
             handlers::StarResponse,
             handlers::NearbySegmentsQuery,
             handlers::GetAchievementsQuery,
@@ -341,6 +402,8 @@ use crate::{
             handlers::FollowListQuery,
             handlers::FollowListResponse,
             handlers::NotificationsQuery,
+            handlers::ListJobsQuery,
+            handlers::ImportActivitiesQuery,
             handlers::FeedQuery,
             handlers::KudosResponse,
             handlers::KudosStatusResponse,
@@ -378,9 +441,18 @@ impl utoipa::Modify for SecurityAddon {
     }
 }
 
-pub fn create_router(pool: PgPool, store: ObjectStoreService) -> Router {
+#[allow(clippy::too_many_arguments)]
+pub fn create_router(
+    pool: PgPool,
+    store: ObjectStoreService,
+    aq: ActivityQueue,
+    backfill: SegmentBackfillQueue,
+    elevation: ElevationService,
+    notifications: NotificationHub,
+    leaderboards: LeaderboardHub,
+    processing: ProcessingHub,
+) -> Router {
     let db = Database::new(pool);
-    let aq = ActivityQueue::new(db.clone());
 
     // Parse CORS origins from environment variable (comma-separated)
     // Defaults to localhost:3000 for development
@@ -424,6 +496,12 @@ pub fn create_router(pool: PgPool, store: ObjectStoreService) -> Router {
         .route("/activities/{id}/track", get(get_activity_track))
         .route("/activities/{id}/segments", get(get_activity_segments))
         .route("/activities/{id}/download", get(download_gpx_file))
+        .route("/activities/{id}/export", get(export_activity))
+        .route("/activities/{id}/processing", get(get_activity_processing))
+        .route(
+            "/activities/{id}/processing/events",
+            get(stream_activity_processing),
+        )
         .route(
             "/activities/{id}/stopped-segments",
             get(get_stopped_segments),
@@ -472,6 +550,10 @@ pub fn create_router(pool: PgPool, store: ObjectStoreService) -> Router {
             "/segments/{id}/leaderboard/filtered",
             get(get_filtered_leaderboard),
         )
+        .route(
+            "/segments/{id}/leaderboard/stream",
+            get(stream_segment_leaderboard),
+        )
         .route(
             "/segments/{id}/leaderboard/position",
             get(get_leaderboard_position),
@@ -479,6 +561,13 @@ pub fn create_router(pool: PgPool, store: ObjectStoreService) -> Router {
         .route("/segments/{id}/achievements", get(get_segment_achievements))
         .route("/segments/{id}/my-efforts", get(get_my_segment_efforts))
         .route("/segments/{id}/reprocess", post(reprocess_segment))
+        .route("/segments/{id}/rejections", get(get_segment_rejections))
+        .route("/segments/{id}/backfill", get(get_segment_backfill))
+        .route(
+            "/segments/{id}/geometry",
+            axum::routing::patch(update_segment_geometry),
+        )
+        .route("/segments/{id}/versions", get(get_segment_versions))
         .route(
             "/segments/{id}/star",
             get(is_segment_starred)
@@ -515,8 +604,19 @@ pub fn create_router(pool: PgPool, store: ObjectStoreService) -> Router {
         .route("/users/{id}/following", get(get_following))
         // Notification routes
         .route("/notifications", get(get_notifications))
+        .route("/notifications/stream", get(stream_notifications))
         .route("/notifications/{id}/read", post(mark_notification_read))
         .route("/notifications/read-all", post(mark_all_notifications_read))
+        // Processing job routes
+        .route("/jobs", get(list_jobs))
+        .route("/jobs/{id}/retry", post(retry_job))
+        // Bulk import routes; archives may exceed the default body limit
+        .route(
+            "/imports",
+            post(import_activities)
+                .layer(DefaultBodyLimit::max(activity_import::MAX_ARCHIVE_BYTES)),
+        )
+        .route("/imports/{id}", get(get_activity_import))
         // Activity feed
         .route("/feed", get(get_feed))
         // Kudos routes
@@ -601,6 +701,11 @@ pub fn create_router(pool: PgPool, store: ObjectStoreService) -> Router {
         .layer(Extension(db))
         .layer(Extension(store))
         .layer(Extension(aq))
+        .layer(Extension(backfill))
+        .layer(Extension(elevation))
+        .layer(Extension(notifications))
+        .layer(Extension(leaderboards))
+        .layer(Extension(processing))
         .layer(cors)
         .layer(CompressionLayer::new())
         .layer(middleware::from_fn(request_id_middleware))
@@ -626,28 +731,44 @@ pub fn create_router(pool: PgPool, store: ObjectStoreService) -> Router {
 pub async fn run_server(pool: PgPool, store: ObjectStoreService, port: u16) -> anyhow::Result<()> {
     // Create core components
     let db = Database::new(pool.clone());
-    let aq = ActivityQueue::new(db.clone());
+    let elevation = ElevationService::from_env();
+    let aq = ActivityQueue::new(db, store.clone())?.with_elevation(elevation.clone());
 
-    // Recover orphaned activities (uploaded but not processed due to restart)
-    match db.find_orphaned_activities().await {
-        Ok(orphaned) if !orphaned.is_empty() => {
-            tracing::info!(
-                count = orphaned.len(),
-                "Found orphaned activities, reprocessing"
-            );
-            for activity in orphaned {
-                if let Err(e) = aq.reprocess_orphaned(activity, &store).await {
-                    tracing::error!("Failed to reprocess orphaned activity: {e}");
-                }
-            }
-        }
-        Ok(_) => {}
-        Err(e) => {
-            tracing::warn!("Failed to check for orphaned activities: {e}");
-        }
-    }
+    // Start the processing workers; jobs left over from a previous run are
+    // picked up from the activity_jobs table
+    let workers = env::var("ACTIVITY_QUEUE_WORKERS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(activity_queue::DEFAULT_WORKERS);
+    aq.start(workers);
+    tracing::info!(workers, "Started activity processing workers");
+
+    achievements_service::start_local_legend_sweep(Database::new(pool.clone()));
 
-    let app = create_router(pool, store);
+    let backfill = SegmentBackfillQueue::new(Database::new(pool.clone()));
+    backfill.start();
+
+    // Imports hold their archive in memory, so ones cut off by a restart can't resume
+    activity_import::start_stale_import_reaper(Database::new(pool.clone()));
+
+    // Push notifications created by any instance to clients connected here
+    let notifications = NotificationHub::new(pool.clone());
+    notifications.start();
+    let leaderboards = LeaderboardHub::new(pool.clone());
+    leaderboards.start();
+    let processing = ProcessingHub::new(pool.clone());
+    processing.start();
+
+    let app = create_router(
+        pool,
+        store,
+        aq,
+        backfill,
+        elevation,
+        notifications,
+        leaderboards,
+        processing,
+    );
 
     let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port)).await?;
 
@@ -26,19 +26,28 @@ impl User {
     }
 }
 
+/// Who can see an activity. Whatever the visibility, the owner can.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default, ToSchema)]
-#[serde(rename_all = "lowercase")]
+#[serde(rename_all = "snake_case")]
 pub enum Visibility {
+    /// Anyone, signed in or not
     #[default]
     Public,
+    /// Users following the owner
+    Followers,
+    /// Only the owner
     Private,
+    /// Members of the teams the activity is shared with
+    TeamsOnly,
 }
 
 impl Visibility {
     pub fn as_str(&self) -> &'static str {
         match self {
             Visibility::Public => "public",
+            Visibility::Followers => "followers",
             Visibility::Private => "private",
+            Visibility::TeamsOnly => "teams_only",
         }
     }
 }
@@ -49,7 +58,9 @@ impl std::str::FromStr for Visibility {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s.to_lowercase().as_str() {
             "public" => Ok(Visibility::Public),
+            "followers" => Ok(Visibility::Followers),
             "private" => Ok(Visibility::Private),
+            "teams_only" => Ok(Visibility::TeamsOnly),
             _ => Err(format!("unknown visibility: {s}")),
         }
     }
@@ -74,6 +85,20 @@ pub struct Activity {
     pub segment_types: Option<Vec<Uuid>>,
 }
 
+/// Where an activity's elevation comes from.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema,
+)]
+#[sqlx(type_name = "elevation_source", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ElevationSource {
+    /// As recorded by the device
+    #[default]
+    Device,
+    /// Replaced with terrain elevation from the DEM
+    Corrected,
+}
+
 /// Activity with optional statistics from scores table
 #[derive(Debug, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct ActivityWithStats {
@@ -89,10 +114,19 @@ pub struct ActivityWithStats {
     pub visibility: String,
     pub type_boundaries: Option<Vec<OffsetDateTime>>,
     pub segment_types: Option<Vec<Uuid>>,
+    /// Whether the elevation is the device's or corrected from terrain data
+    pub elevation_source: ElevationSource,
     // Stats from scores table
     pub distance: Option<f64>,
     pub duration: Option<f64>,
     pub elevation_gain: Option<f64>,
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub stats: ActivityStats,
+    /// State of the latest processing job, if any
+    pub processing_status: Option<JobStatus>,
+    /// Why processing failed, when the latest job failed for good
+    pub processing_error: Option<String>,
 }
 
 // ============================================================================
@@ -123,6 +157,8 @@ pub struct ActivityTypeRow {
     pub created_by: Option<Uuid>,
     #[serde(with = "rfc3339")]
     pub created_at: OffsetDateTime,
+    /// Max path deviation (Fréchet distance, meters) allowed for a segment effort
+    pub match_tolerance_meters: f64,
 }
 
 /// Row from the activity_aliases table
@@ -150,6 +186,8 @@ pub enum ResolvedActivityType {
 #[derive(Debug, Deserialize, ToSchema)]
 pub struct CreateActivityTypeRequest {
     pub name: String,
+    /// Segment matching tolerance in meters (defaults to 30)
+    pub match_tolerance_meters: Option<f64>,
 }
 
 /// Request to create an activity alias
@@ -177,11 +215,39 @@ pub struct ScoresRow {
     pub scores: Scores,
     pub created_at: OffsetDateTime,
 }
-#[derive(Debug, Clone, Default, sqlx::Type)]
+#[derive(Debug, Clone, Default, FromRow)]
 pub struct Scores {
     pub distance: f64,
+    /// Elapsed time from the first to the last timestamp, in seconds
     pub duration: f64,
     pub elevation_gain: f64,
+    #[sqlx(flatten)]
+    pub stats: ActivityStats,
+}
+
+/// Statistics beyond the core scores. Missing for activities processed before
+/// they were introduced, and sensor statistics are missing when the file
+/// didn't record that sensor.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct ActivityStats {
+    /// Time spent moving at 1 m/s or faster, in seconds
+    pub moving_time: Option<f64>,
+    /// Fastest speed held for at least 5 seconds, in meters per second
+    pub max_speed: Option<f64>,
+    /// Distance covered while moving over moving time, in meters per second
+    pub avg_speed: Option<f64>,
+    pub elevation_loss: Option<f64>,
+    pub max_elevation: Option<f64>,
+    pub min_elevation: Option<f64>,
+    /// Beats per minute
+    pub avg_heart_rate: Option<f64>,
+    pub max_heart_rate: Option<i32>,
+    /// Revolutions or steps per minute
+    pub avg_cadence: Option<f64>,
+    pub max_cadence: Option<i32>,
+    /// Watts
+    pub avg_power: Option<f64>,
+    pub max_power: Option<i32>,
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow, ToSchema)]
@@ -199,10 +265,44 @@ pub struct Segment {
     pub max_grade: Option<f64>,
     pub climb_category: Option<i32>,
     pub visibility: String,
+    /// Geometry version; starts at 1 and increases with each geometry edit
+    pub version: i32,
+    #[serde(with = "rfc3339")]
+    pub created_at: OffsetDateTime,
+}
+
+/// A past or current geometry of a segment.
+#[derive(Debug, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct SegmentVersion {
+    pub segment_id: Uuid,
+    pub version: i32,
+    pub distance_meters: f64,
+    pub elevation_gain_meters: Option<f64>,
+    pub elevation_loss_meters: Option<f64>,
+    pub average_grade: Option<f64>,
+    pub max_grade: Option<f64>,
+    pub climb_category: Option<i32>,
+    pub created_by: Uuid,
     #[serde(with = "rfc3339")]
     pub created_at: OffsetDateTime,
 }
 
+/// Segment line and the stats derived from it, ready to store.
+#[derive(Debug, Clone)]
+pub struct SegmentGeometry {
+    pub geo_wkt: String,
+    pub start_wkt: String,
+    pub end_wkt: String,
+    /// (lat, lon) of every point on the line
+    pub points: Vec<(f64, f64)>,
+    pub distance_meters: f64,
+    pub elevation_gain: Option<f64>,
+    pub elevation_loss: Option<f64>,
+    pub average_grade: Option<f64>,
+    pub max_grade: Option<f64>,
+    pub climb_category: Option<i32>,
+}
+
 #[derive(Debug, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct SegmentEffort {
     pub id: Uuid,
@@ -223,6 +323,25 @@ pub struct SegmentEffort {
     pub end_fraction: Option<f64>,
 }
 
+/// A segment match that was rejected because the track did not follow the segment.
+#[derive(Debug, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct SegmentMatchRejection {
+    pub id: Uuid,
+    pub segment_id: Uuid,
+    pub activity_id: Uuid,
+    pub user_id: Uuid,
+    pub user_name: Option<String>,
+    /// "path_deviation" or "insufficient_points"
+    pub reason: String,
+    pub frechet_distance_meters: Option<f64>,
+    pub tolerance_meters: f64,
+    #[serde(with = "rfc3339")]
+    pub created_at: OffsetDateTime,
+    /// Position of the rejected traversal on the activity track (fractional 0-1)
+    pub start_fraction: Option<f64>,
+    pub end_fraction: Option<f64>,
+}
+
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct SegmentWithStats {
     #[serde(flatten)]
@@ -302,7 +421,7 @@ impl std::str::FromStr for Gender {
 }
 
 /// Time scope for leaderboard filtering
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default, ToSchema)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default, ToSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum LeaderboardScope {
     #[default]
@@ -327,7 +446,7 @@ impl std::str::FromStr for LeaderboardScope {
 }
 
 /// Age group for demographic filtering (5-year brackets for younger, 10-year for older)
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default, ToSchema)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default, ToSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum AgeGroup {
     #[default]
@@ -383,7 +502,7 @@ impl std::str::FromStr for AgeGroup {
 }
 
 /// Gender filter for leaderboards (includes "all" option)
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default, ToSchema)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default, ToSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum GenderFilter {
     #[default]
@@ -406,7 +525,7 @@ impl std::str::FromStr for GenderFilter {
 }
 
 /// Weight class for leaderboard filtering
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default, ToSchema)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, Default, ToSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum WeightClass {
     #[default]
@@ -505,6 +624,7 @@ pub enum VisibilityFilter {
     #[default]
     All,
     Public,
+    Followers,
     Private,
     TeamsOnly,
 }
@@ -516,6 +636,7 @@ impl std::str::FromStr for VisibilityFilter {
         match s.to_lowercase().as_str() {
             "all" => Ok(VisibilityFilter::All),
             "public" => Ok(VisibilityFilter::Public),
+            "followers" => Ok(VisibilityFilter::Followers),
             "private" => Ok(VisibilityFilter::Private),
             "teams_only" => Ok(VisibilityFilter::TeamsOnly),
             _ => Err(format!("unknown visibility filter: {s}")),
@@ -532,6 +653,11 @@ pub enum ActivitySortBy {
     Oldest,
     Distance,
     Duration,
+    MovingTime,
+    ElevationGain,
+    AvgSpeed,
+    MaxSpeed,
+    AvgPower,
 }
 
 impl ActivitySortBy {
@@ -542,6 +668,11 @@ impl ActivitySortBy {
             ActivitySortBy::Oldest => "submitted_at ASC",
             ActivitySortBy::Distance => "distance_meters DESC NULLS LAST",
             ActivitySortBy::Duration => "duration_seconds DESC NULLS LAST",
+            ActivitySortBy::MovingTime => "moving_time DESC NULLS LAST",
+            ActivitySortBy::ElevationGain => "elevation_gain DESC NULLS LAST",
+            ActivitySortBy::AvgSpeed => "avg_speed DESC NULLS LAST",
+            ActivitySortBy::MaxSpeed => "max_speed DESC NULLS LAST",
+            ActivitySortBy::AvgPower => "avg_power DESC NULLS LAST",
         }
     }
 }
@@ -555,6 +686,11 @@ impl std::str::FromStr for ActivitySortBy {
             "oldest" => Ok(ActivitySortBy::Oldest),
             "distance" => Ok(ActivitySortBy::Distance),
             "duration" => Ok(ActivitySortBy::Duration),
+            "moving_time" => Ok(ActivitySortBy::MovingTime),
+            "elevation_gain" => Ok(ActivitySortBy::ElevationGain),
+            "avg_speed" => Ok(ActivitySortBy::AvgSpeed),
+            "max_speed" => Ok(ActivitySortBy::MaxSpeed),
+            "avg_power" => Ok(ActivitySortBy::AvgPower),
             _ => Err(format!("unknown activity sort: {s}")),
         }
     }
@@ -654,6 +790,41 @@ pub struct LeaderboardFiltersResponse {
     pub offset: i64,
 }
 
+/// Update pushed on GET /segments/{id}/leaderboard/stream.
+/// The SSE event name is the `type` field.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LeaderboardEvent {
+    /// A new effort entered the leaderboard at `entry.rank`; entries from that
+    /// rank down move one place down. `total_count` includes the new effort.
+    Effort {
+        entry: LeaderboardEntry,
+        total_count: i64,
+    },
+    /// Efforts left the segment's leaderboards because their activity was
+    /// deleted, edited or made followers-only. Entries below each move up a
+    /// place; efforts the client isn't showing can be ignored.
+    Removed { effort_ids: Vec<Uuid> },
+    /// Too many efforts changed at once to send one by one, e.g. when the
+    /// segment was edited, or the stream missed updates; the leaderboard
+    /// should be fetched again.
+    Reset,
+    /// A KOM, QOM or course record changed hands.
+    Crown { holder: AchievementHolder },
+}
+
+impl LeaderboardEvent {
+    /// SSE event name.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Effort { .. } => "effort",
+            Self::Removed { .. } => "removed",
+            Self::Reset => "reset",
+            Self::Crown { .. } => "crown",
+        }
+    }
+}
+
 /// User's position in the leaderboard with surrounding entries
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct LeaderboardPosition {
@@ -683,6 +854,8 @@ pub enum AchievementType {
     Kom,
     Qom,
     CourseRecord,
+    /// Most efforts on the segment over the last 90 days
+    LocalLegend,
 }
 
 impl std::fmt::Display for AchievementType {
@@ -691,6 +864,7 @@ impl std::fmt::Display for AchievementType {
             AchievementType::Kom => write!(f, "KOM"),
             AchievementType::Qom => write!(f, "QOM"),
             AchievementType::CourseRecord => write!(f, "Course Record"),
+            AchievementType::LocalLegend => write!(f, "Local Legend"),
         }
     }
 }
@@ -737,10 +911,12 @@ pub struct SegmentAchievements {
     pub segment_id: Uuid,
     pub kom: Option<AchievementHolder>,
     pub qom: Option<AchievementHolder>,
+    pub course_record: Option<AchievementHolder>,
+    pub local_legend: Option<AchievementHolder>,
 }
 
 /// Holder of an achievement with their details
-#[derive(Debug, Serialize, Deserialize, FromRow, ToSchema)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct AchievementHolder {
     pub user_id: Uuid,
     pub user_name: String,
@@ -751,6 +927,16 @@ pub struct AchievementHolder {
     pub effort_count: Option<i32>,
 }
 
+/// A crown that changed hands on a segment.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct CrownChange {
+    pub achievement_type: AchievementType,
+    pub previous_holder_id: Option<Uuid>,
+    pub new_holder_id: Option<Uuid>,
+    /// The new holder's effort; none for Local Legend, which counts efforts
+    pub effort_id: Option<Uuid>,
+}
+
 // ============================================================================
 // User Demographics Models
 // ============================================================================
@@ -791,6 +977,7 @@ pub struct CrownCountEntry {
     pub user_name: String,
     pub kom_count: i64,
     pub qom_count: i64,
+    pub course_record_count: i64,
     pub total_crowns: i64,
     pub rank: i64,
 }
@@ -907,6 +1094,18 @@ pub struct UserProfile {
     pub weight_kg: Option<f64>,
     pub country: Option<String>,
     pub region: Option<String>,
+    /// Crowns the user currently holds
+    #[sqlx(skip)]
+    pub achievements: AchievementCounts,
+}
+
+/// Number of crowns of each kind a user currently holds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct AchievementCounts {
+    pub kom_count: i64,
+    pub qom_count: i64,
+    pub course_record_count: i64,
+    pub local_legend_count: i64,
 }
 
 /// Summary of a user for follower/following lists
@@ -971,6 +1170,8 @@ pub struct Notification {
     pub target_type: Option<String>,
     pub target_id: Option<Uuid>,
     pub message: Option<String>,
+    /// Segment effort a crown or PR notification is about
+    pub effort_id: Option<Uuid>,
     #[serde(with = "rfc3339::option")]
     pub read_at: Option<OffsetDateTime>,
     #[serde(with = "rfc3339")]
@@ -978,7 +1179,7 @@ pub struct Notification {
 }
 
 /// Notification with actor details for display
-#[derive(Debug, Serialize, Deserialize, FromRow, ToSchema)]
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
 pub struct NotificationWithActor {
     pub id: Uuid,
     pub user_id: Uuid,
@@ -988,6 +1189,13 @@ pub struct NotificationWithActor {
     pub target_type: Option<String>,
     pub target_id: Option<Uuid>,
     pub message: Option<String>,
+    /// Name of the target segment, for crown and PR notifications
+    pub segment_name: Option<String>,
+    /// Segment effort that set the PR or took the crown
+    pub effort_id: Option<Uuid>,
+    /// Activity the effort belongs to
+    pub effort_activity_id: Option<Uuid>,
+    pub effort_elapsed_time_seconds: Option<f64>,
     #[serde(with = "rfc3339::option")]
     pub read_at: Option<OffsetDateTime>,
     #[serde(with = "rfc3339")]
@@ -1002,6 +1210,30 @@ pub struct NotificationsResponse {
     pub total_count: i64,
 }
 
+/// Event pushed on GET /notifications/stream.
+/// The SSE event name is the `type` field.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NotificationEvent {
+    /// A new notification, with the unread count including it.
+    Notification {
+        notification: Box<NotificationWithActor>,
+        unread_count: i64,
+    },
+    /// The unread count changed, e.g. because notifications were read.
+    UnreadCount { unread_count: i64 },
+}
+
+impl NotificationEvent {
+    /// SSE event name.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Notification { .. } => "notification",
+            Self::UnreadCount { .. } => "unread_count",
+        }
+    }
+}
+
 // ============================================================================
 // Activity Feed Models
 // ============================================================================
@@ -1020,6 +1252,9 @@ pub struct FeedActivity {
     pub distance: Option<f64>,
     pub duration: Option<f64>,
     pub elevation_gain: Option<f64>,
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub stats: ActivityStats,
     pub kudos_count: i32,
     pub comment_count: i32,
 }
@@ -1509,18 +1744,257 @@ pub struct ActivitySensorDataResponse {
 }
 
 // ============================================================================
-// Recovery Models
+// Activity Job Models
 // ============================================================================
 
-/// An activity that was uploaded but not fully processed (no track geometry).
-#[derive(Debug, sqlx::FromRow)]
-pub struct OrphanedActivity {
+/// State of an activity processing job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "job_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Processing,
+    Succeeded,
+    Failed,
+}
+
+/// Step of the activity processing pipeline a job is in (or stopped at).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "processing_stage", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessingStage {
+    Queued,
+    Parsing,
+    StoringGeometry,
+    MatchingSegments,
+    Achievements,
+    Done,
+}
+
+/// Category of an activity processing error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "processing_error_kind", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessingErrorKind {
+    /// The uploaded file could not be loaded from object storage
+    FileUnavailable,
+    /// The uploaded file is not a readable GPX, TCX or FIT file
+    ParseFailed,
+    /// Saving results failed; usually transient
+    Database,
+    /// The worker stopped (crash, restart) while processing
+    Interrupted,
+    Internal,
+}
+
+/// A durable activity processing job from the activity_jobs table.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct ActivityJob {
     pub id: Uuid,
+    pub activity_id: Uuid,
     pub user_id: Uuid,
-    pub activity_type_id: Uuid,
-    pub object_store_path: String,
-    pub type_boundaries: Option<Vec<time::OffsetDateTime>>,
-    pub segment_types: Option<Vec<Uuid>>,
+    pub status: JobStatus,
+    pub stage: ProcessingStage,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    /// User-facing message of the last failed attempt
+    pub last_error: Option<String>,
+    pub last_error_kind: Option<ProcessingErrorKind>,
+    /// Earliest time a queued job will be picked up
+    #[serde(with = "rfc3339")]
+    pub run_after: OffsetDateTime,
+    /// Whether the rider is notified of PRs the job finds; off for imported activities
+    #[serde(skip)]
+    pub notify: bool,
+    #[serde(with = "rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "rfc3339")]
+    pub updated_at: OffsetDateTime,
+    #[serde(with = "rfc3339::option")]
+    pub finished_at: Option<OffsetDateTime>,
+}
+
+/// A background job matching a segment against existing activities, from the
+/// segment_backfill_jobs table.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct SegmentBackfillJob {
+    pub id: Uuid,
+    pub segment_id: Uuid,
+    pub requested_by: Uuid,
+    pub status: JobStatus,
+    /// Candidate activities near the segment; unknown until the job starts
+    pub activities_total: Option<i32>,
+    pub activities_checked: i32,
+    pub efforts_created: i32,
+    /// Activities that could not be checked
+    pub failures: i32,
+    pub last_error: Option<String>,
+    #[serde(with = "rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "rfc3339")]
+    pub updated_at: OffsetDateTime,
+    #[serde(with = "rfc3339::option")]
+    pub finished_at: Option<OffsetDateTime>,
+}
+
+/// A bulk import of an activity export archive, from the activity_imports table.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct ActivityImport {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    /// Name of the uploaded archive
+    pub source_name: String,
+    pub status: JobStatus,
+    /// Activity files found in the archive; unknown until it has been read
+    pub files_total: Option<i32>,
+    pub imported: i32,
+    /// Files skipped because the user already has the activity
+    pub duplicates: i32,
+    pub failed: i32,
+    /// Why the import as a whole stopped
+    pub last_error: Option<String>,
+    #[serde(with = "rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(with = "rfc3339")]
+    pub updated_at: OffsetDateTime,
+    #[serde(with = "rfc3339::option")]
+    pub finished_at: Option<OffsetDateTime>,
+}
+
+/// Outcome of importing one file of an archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[sqlx(type_name = "import_item_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ImportItemStatus {
+    Imported,
+    Duplicate,
+    Failed,
+}
+
+/// One file of an activity import, from the activity_import_items table.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct ActivityImportItem {
+    pub id: Uuid,
+    pub import_id: Uuid,
+    /// Path of the file inside the archive
+    pub file_name: String,
+    /// Activity ID in the exporting service
+    pub external_id: Option<String>,
+    pub activity_name: Option<String>,
+    pub status: ImportItemStatus,
+    /// The created activity, or the existing one a duplicate matched
+    pub activity_id: Option<Uuid>,
+    /// Why the file failed or was skipped
+    pub message: Option<String>,
+    #[serde(with = "rfc3339")]
+    pub created_at: OffsetDateTime,
+}
+
+/// An activity import with its per-file report.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ActivityImportReport {
+    pub import: ActivityImport,
+    pub items: Vec<ActivityImportItem>,
+}
+
+/// An error captured while processing an activity.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow, ToSchema)]
+pub struct ActivityProcessingError {
+    pub id: Uuid,
+    pub job_id: Uuid,
+    pub stage: ProcessingStage,
+    /// Job attempt the error happened on
+    pub attempt: i32,
+    /// Fatal errors stopped the attempt; others only skipped part of a stage
+    pub fatal: bool,
+    pub kind: ProcessingErrorKind,
+    /// User-facing description of what went wrong
+    pub message: String,
+    /// Technical detail (underlying error chain)
+    pub detail: Option<String>,
+    #[serde(with = "rfc3339")]
+    pub created_at: OffsetDateTime,
+}
+
+/// Processing state of an activity, from GET /activities/{id}/processing.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct ActivityProcessingStatus {
+    pub activity_id: Uuid,
+    pub status: JobStatus,
+    pub stage: ProcessingStage,
+    /// Latest processing job; absent for activities processed before jobs were tracked
+    pub job: Option<ActivityJob>,
+    /// Errors from the latest job, newest first
+    pub errors: Vec<ActivityProcessingError>,
+}
+
+/// Progress event pushed on GET /activities/{id}/processing/events.
+/// The SSE event name is the `type` field.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProcessingEvent {
+    /// The pipeline moved to a new stage; `done` is the last event of a successful run.
+    Stage {
+        activity_id: Uuid,
+        stage: ProcessingStage,
+    },
+    /// A segment effort was created for the activity.
+    SegmentMatched {
+        activity_id: Uuid,
+        segment_id: Uuid,
+        effort_id: Uuid,
+        elapsed_time_seconds: f64,
+    },
+    /// An effort from the activity became the user's personal record on a segment.
+    PersonalRecord {
+        activity_id: Uuid,
+        segment_id: Uuid,
+        effort_id: Uuid,
+        elapsed_time_seconds: f64,
+    },
+    /// The attempt failed; without `will_retry` this is the last event.
+    Failed {
+        activity_id: Uuid,
+        stage: ProcessingStage,
+        kind: ProcessingErrorKind,
+        error: String,
+        will_retry: bool,
+    },
+}
+
+impl ProcessingEvent {
+    pub fn activity_id(&self) -> Uuid {
+        match self {
+            Self::Stage { activity_id, .. }
+            | Self::SegmentMatched { activity_id, .. }
+            | Self::PersonalRecord { activity_id, .. }
+            | Self::Failed { activity_id, .. } => *activity_id,
+        }
+    }
+
+    /// SSE event name.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Stage { .. } => "stage",
+            Self::SegmentMatched { .. } => "segment_matched",
+            Self::PersonalRecord { .. } => "personal_record",
+            Self::Failed { .. } => "failed",
+        }
+    }
+
+    /// Whether no more events will follow for this processing run.
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            Self::Stage {
+                stage: ProcessingStage::Done,
+                ..
+            } | Self::Failed {
+                will_retry: false,
+                ..
+            }
+        )
+    }
 }
 
 // ============================================================================
@@ -0,0 +1,145 @@
+//! Real-time notification delivery.
+//!
+//! A trigger on the `notifications` table announces every new notification and
+//! every change to what has been read on the `notifications` Postgres channel
+//! (see migration `026_notification_events.sql`). Each API instance listens on
+//! that channel and pushes the events to the clients connected to it, so a
+//! notification created by any instance, or by a processing worker, reaches
+//! the recipient wherever they are connected.
+//!
+//! Notifications sent while an instance is reconnecting to the database are
+//! not pushed; clients catch up with `GET /notifications`.
+
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    database::Database,
+    errors::AppError,
+    event_hub::{EventHub, Subscription},
+    models::NotificationEvent,
+};
+
+/// Postgres channel the notifications trigger publishes on.
+pub const NOTIFICATION_CHANNEL: &str = "notifications";
+
+/// Events buffered per subscriber before a slow client starts missing them.
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// Payload of a message on [`NOTIFICATION_CHANNEL`].
+#[derive(Debug, Deserialize)]
+struct ChangePayload {
+    user_id: Uuid,
+    /// Set for a new notification, absent when notifications were read
+    notification_id: Option<Uuid>,
+}
+
+/// Fans notification events out to the clients connected to this instance.
+/// Cheap to clone; clones share subscribers.
+#[derive(Clone)]
+pub struct NotificationHub {
+    db: Database,
+    /// Streams are keyed by user; events are only loaded for users with one
+    hub: EventHub<Uuid, (Uuid, NotificationEvent)>,
+}
+
+impl NotificationHub {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            db: Database::new(pool.clone()),
+            hub: EventHub::new(
+                pool,
+                NOTIFICATION_CHANNEL,
+                EVENT_CHANNEL_CAPACITY,
+                |user_id, (recipient, _)| recipient == user_id,
+            ),
+        }
+    }
+
+    /// Spawn the channel listener on the current runtime.
+    pub fn start(&self) {
+        let hub = self.clone();
+        self.hub.start(move |payload: ChangePayload| {
+            let hub = hub.clone();
+            async move {
+                if let Err(e) = hub.dispatch(payload).await {
+                    tracing::warn!("Failed to push notification event: {e}");
+                }
+            }
+        });
+    }
+
+    /// Subscribe to the events of one user.
+    pub fn subscribe(&self, user_id: Uuid) -> NotificationSubscription {
+        NotificationSubscription(self.hub.subscribe(user_id))
+    }
+
+    async fn dispatch(&self, payload: ChangePayload) -> Result<(), AppError> {
+        if !self.hub.is_watched(&payload.user_id) {
+            return Ok(());
+        }
+
+        let unread_count = self
+            .db
+            .get_unread_notification_count(payload.user_id)
+            .await?;
+        let notification = match payload.notification_id {
+            Some(id) => self.db.get_notification(id).await?,
+            None => None,
+        };
+        let event = match notification {
+            Some(notification) => NotificationEvent::Notification {
+                notification: Box::new(notification),
+                unread_count,
+            },
+            None => NotificationEvent::UnreadCount { unread_count },
+        };
+
+        self.hub.send((payload.user_id, event));
+        Ok(())
+    }
+}
+
+/// A user's open notification stream. Dropping it unsubscribes.
+pub struct NotificationSubscription(Subscription<Uuid, (Uuid, NotificationEvent)>);
+
+impl NotificationSubscription {
+    /// Wait for the user's next event. Missed events are skipped; the next
+    /// one carries the current unread count.
+    pub async fn recv(&mut self) -> Option<NotificationEvent> {
+        loop {
+            if let Ok((_, event)) = self.0.recv().await? {
+                return Some(event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hub() -> NotificationHub {
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .connect_lazy("postgres://localhost/unused")
+            .unwrap();
+        NotificationHub::new(pool)
+    }
+
+    #[tokio::test]
+    async fn test_subscription_only_sees_own_events() {
+        let hub = hub();
+        let (me, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut subscription = hub.subscribe(me);
+
+        let event = |unread_count| NotificationEvent::UnreadCount { unread_count };
+        hub.hub.send((other, event(5)));
+        hub.hub.send((me, event(2)));
+
+        match subscription.recv().await {
+            Some(NotificationEvent::UnreadCount { unread_count }) => assert_eq!(unread_count, 2),
+            other => panic!("unexpected event: {other:?}"),
+        }
+    }
+}
@@ -6,7 +6,7 @@ use object_store::{ObjectStore, PutOptions, local::LocalFileSystem, path::Path};
 use std::sync::Arc;
 use uuid::Uuid;
 
-use crate::errors::AppError;
+use crate::{compression::Compression, errors::AppError};
 
 #[non_exhaustive]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -23,9 +23,10 @@ impl From<Mime> for FileType {
                 "gpx" | "gpx+xml" => FileType::Gpx,
                 "vnd.garmin.tcx+xml" | "tcx+xml" | "tcx" => FileType::Tcx,
                 "vnd.ant.fit" | "fit" => FileType::Fit,
-                // Browsers often send activity files as octet-stream - caller must
-                // use detect_from_bytes() to determine actual type
-                "octet-stream" => FileType::Other,
+                // Browsers often send activity files as octet-stream, and
+                // compressed uploads as gzip/zip - caller must use
+                // detect_from_bytes() on the decompressed content
+                "octet-stream" | "gzip" | "x-gzip" | "zip" | "x-zip-compressed" => FileType::Other,
                 s => {
                     tracing::warn!("Unknown mime subtype: {s}");
                     FileType::Other
@@ -51,6 +52,7 @@ impl FileType {
 
     /// Detect file type from raw bytes by checking magic bytes/signatures.
     /// Used when MIME type is octet-stream and we need to determine actual format.
+    /// Compressed files must be unwrapped with `compression::decompress` first.
     pub fn detect_from_bytes(bytes: &[u8]) -> Self {
         // FIT files start with header size byte, then ".FIT" signature at offset 8-11
         if bytes.len() >= 12 {
@@ -99,6 +101,30 @@ pub struct ObjectStoreService {
 }
 
 impl ObjectStoreService {
+    /// Build the store configured by the environment: S3 when
+    /// `OBJECT_STORE_BACKEND=s3` (with `AWS_S3_BUCKET`, `AWS_REGION` and an
+    /// optional `AWS_S3_PREFIX`), otherwise the local directory in
+    /// `OBJECT_STORE_PATH` (default `./uploads`).
+    pub fn from_env() -> Self {
+        match std::env::var("OBJECT_STORE_BACKEND").as_deref() {
+            Ok("s3") => {
+                let bucket = std::env::var("AWS_S3_BUCKET")
+                    .expect("AWS_S3_BUCKET is required when using S3");
+                let region =
+                    std::env::var("AWS_REGION").expect("AWS_REGION is required when using S3");
+                let prefix = std::env::var("AWS_S3_PREFIX").ok();
+                tracing::info!(%bucket, %region, prefix = prefix.as_deref().unwrap_or(""), "Using S3 object store");
+                Self::new_s3(&bucket, &region, prefix.as_deref())
+            }
+            _ => {
+                let path =
+                    std::env::var("OBJECT_STORE_PATH").unwrap_or_else(|_| "./uploads".to_string());
+                tracing::info!(%path, "Using local object store");
+                Self::new_local(path)
+            }
+        }
+    }
+
     pub fn new_local(base_path: String) -> Self {
         // Ensure the directory exists before creating the LocalFileSystem
         std::fs::create_dir_all(&base_path).expect("Failed to create uploads directory");
@@ -148,10 +174,12 @@ impl ObjectStoreService {
         let mut opts = PutOptions::default();
 
         if self.set_content_type {
-            opts.attributes.insert(
-                object_store::Attribute::ContentType,
-                file_type.as_mime_str().into(),
-            );
+            // Compressed uploads are stored as uploaded
+            let content_type = Compression::detect(&content)
+                .as_mime_str()
+                .unwrap_or(file_type.as_mime_str());
+            opts.attributes
+                .insert(object_store::Attribute::ContentType, content_type.into());
         }
 
         self.store
@@ -0,0 +1,131 @@
+//! Real-time activity processing progress.
+//!
+//! Processing workers publish every [`ProcessingEvent`] on the
+//! `activity_processing` Postgres channel (see
+//! `Database::publish_processing_event`). Each API instance listens on that
+//! channel and pushes the events to the clients following the activity, so a
+//! client sees its job's progress whichever instance's worker runs it.
+//!
+//! Events published while an instance is reconnecting to the database are not
+//! pushed; clients catch up with `GET /activities/{id}/processing`.
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    event_hub::{EventHub, Subscription},
+    models::ProcessingEvent,
+};
+
+/// Postgres channel processing workers publish on.
+pub const PROCESSING_CHANNEL: &str = "activity_processing";
+
+/// Events buffered per subscriber before a slow client starts missing them.
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// Fans processing events out to the clients connected to this instance.
+/// Cheap to clone; clones share subscribers.
+#[derive(Clone)]
+pub struct ProcessingHub {
+    /// Streams are keyed by activity; events for other activities are dropped
+    hub: EventHub<Uuid, ProcessingEvent>,
+}
+
+impl ProcessingHub {
+    pub fn new(pool: PgPool) -> Self {
+        Self {
+            hub: EventHub::new(
+                pool,
+                PROCESSING_CHANNEL,
+                EVENT_CHANNEL_CAPACITY,
+                |activity_id, event| event.activity_id() == *activity_id,
+            ),
+        }
+    }
+
+    /// Spawn the channel listener on the current runtime.
+    pub fn start(&self) {
+        let hub = self.clone();
+        self.hub.start(move |event: ProcessingEvent| {
+            hub.dispatch(event);
+            std::future::ready(())
+        });
+    }
+
+    /// Subscribe to the progress of one activity.
+    pub fn subscribe(&self, activity_id: Uuid) -> ProcessingSubscription {
+        ProcessingSubscription(self.hub.subscribe(activity_id))
+    }
+
+    fn dispatch(&self, event: ProcessingEvent) {
+        if self.hub.is_watched(&event.activity_id()) {
+            self.hub.send(event);
+        }
+    }
+}
+
+/// An open processing stream for one activity. Dropping it unsubscribes.
+pub struct ProcessingSubscription(Subscription<Uuid, ProcessingEvent>);
+
+impl ProcessingSubscription {
+    /// Wait for the activity's next event. Missed events are skipped; the
+    /// client catches up from the job's state.
+    pub async fn recv(&mut self) -> Option<ProcessingEvent> {
+        loop {
+            if let Ok(event) = self.0.recv().await? {
+                return Some(event);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ProcessingStage;
+
+    fn hub() -> ProcessingHub {
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .connect_lazy("postgres://localhost/unused")
+            .unwrap();
+        ProcessingHub::new(pool)
+    }
+
+    fn stage(activity_id: Uuid, stage: ProcessingStage) -> ProcessingEvent {
+        ProcessingEvent::Stage { activity_id, stage }
+    }
+
+    #[tokio::test]
+    async fn test_subscription_only_sees_its_activity() {
+        let hub = hub();
+        let (mine, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut subscription = hub.subscribe(mine);
+        let _other_subscription = hub.subscribe(other);
+
+        hub.dispatch(stage(other, ProcessingStage::Parsing));
+        hub.dispatch(stage(mine, ProcessingStage::MatchingSegments));
+
+        match subscription.recv().await {
+            Some(ProcessingEvent::Stage { activity_id, stage }) => {
+                assert_eq!(activity_id, mine);
+                assert_eq!(stage, ProcessingStage::MatchingSegments);
+            }
+            other => panic!("unexpected event: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_events_round_trip_through_channel_payload() {
+        let event = ProcessingEvent::Failed {
+            activity_id: Uuid::new_v4(),
+            stage: ProcessingStage::Parsing,
+            kind: crate::models::ProcessingErrorKind::ParseFailed,
+            error: "Your file could not be parsed".into(),
+            will_retry: false,
+        };
+        let payload = serde_json::to_string(&event).unwrap();
+        let parsed: ProcessingEvent = serde_json::from_str(&payload).unwrap();
+        assert_eq!(parsed.activity_id(), event.activity_id());
+        assert!(parsed.is_terminal());
+    }
+}
@@ -1,10 +1,20 @@
+use std::collections::VecDeque;
+
 use geo::{Distance as _, Haversine, geometry::Point};
 use gpx::Gpx;
 
-use crate::models::{Scores, TrackPointData};
+use crate::{
+    file_parsers::ParsedActivity,
+    models::{ActivityStats, Scores, TrackPointData},
+    segment_matching::STOPPED_SPEED_THRESHOLD_MPS,
+};
 
 type TrackPoint = gpx::Waypoint;
 
+/// Max speed is the fastest speed held over at least this many seconds, so a
+/// single GPS spike doesn't set it.
+const MAX_SPEED_WINDOW_SECONDS: f64 = 5.0;
+
 pub trait TrackMetric {
     type Score;
     fn next_point(&mut self, point: &TrackPoint);
@@ -26,55 +36,150 @@ pub fn score_track(track: &Gpx) -> Scores {
     acc.finish()
 }
 
-/// Score track from TrackPointData (works with all file formats)
-pub fn score_track_points(points: &[TrackPointData]) -> Scores {
+/// Score a parsed activity: the track's scores plus heart rate, cadence and
+/// power statistics from its sensor data.
+pub fn score_activity(parsed: &ParsedActivity, elevation_hysteresis: f64) -> Scores {
+    let mut scores = score_track_points(&parsed.track_points, elevation_hysteresis);
+    let sensors = &parsed.sensor_data;
+    (scores.stats.avg_heart_rate, scores.stats.max_heart_rate) = sensor_stats(&sensors.heart_rates);
+    (scores.stats.avg_cadence, scores.stats.max_cadence) = sensor_stats(&sensors.cadences);
+    (scores.stats.avg_power, scores.stats.max_power) = sensor_stats(&sensors.powers);
+    scores
+}
+
+/// Average and maximum of the recorded samples.
+fn sensor_stats(samples: &[Option<i32>]) -> (Option<f64>, Option<i32>) {
+    let (sum, count, max) = samples
+        .iter()
+        .flatten()
+        .fold((0i64, 0usize, None), |(sum, count, max), &v| {
+            (sum + v as i64, count + 1, max.max(Some(v)))
+        });
+    ((count > 0).then(|| sum as f64 / count as f64), max)
+}
+
+/// Score track from TrackPointData (works with all file formats).
+/// Climbs and descents are only counted once they move `elevation_hysteresis`
+/// meters away from the last turning point, so noise doesn't add up to
+/// elevation gain or loss. Moving time counts the intervals covered at
+/// [`STOPPED_SPEED_THRESHOLD_MPS`] or faster, like segment efforts and stopped
+/// segment detection do, and average speed is the distance covered in them.
+pub fn score_track_points(points: &[TrackPointData], elevation_hysteresis: f64) -> Scores {
     let mut distance = 0.0f64;
     let mut elevation_gain = 0.0f64;
+    let mut elevation_loss = 0.0f64;
+    let mut elevation_range: Option<(f64, f64)> = None;
     let mut last_point: Option<Point> = None;
-    let mut last_elevation: Option<f64> = None;
+    let mut last_time: Option<time::OffsetDateTime> = None;
+    // Elevation that climbs and descents are measured from
+    let mut reference_elevation: Option<f64> = None;
     let mut start_time: Option<time::OffsetDateTime> = None;
     let mut end_time: Option<time::OffsetDateTime> = None;
+    // Only set once the track has a timed interval
+    let mut moving_time: Option<f64> = None;
+    let mut moving_distance = 0.0f64;
+    let mut max_speed: Option<f64> = None;
+    // The latest timed intervals as (meters, seconds), spanning at least
+    // MAX_SPEED_WINDOW_SECONDS once the track is long enough
+    let mut recent: VecDeque<(f64, f64)> = VecDeque::new();
 
     for point in points {
         // Distance calculation
         let current_point = Point::new(point.lon, point.lat);
-        if let Some(prev) = last_point {
-            distance += Haversine.distance(prev, current_point);
-        }
+        let step = last_point.map_or(0.0, |prev| Haversine.distance(prev, current_point));
+        distance += step;
         last_point = Some(current_point);
 
-        // Elevation gain calculation
+        // Elevation gain and loss calculation
         if let Some(ele) = point.elevation {
-            if let Some(last_ele) = last_elevation {
-                let gain = ele - last_ele;
-                if gain > 0.0 {
-                    elevation_gain += gain;
+            match reference_elevation {
+                Some(reference) if ele - reference >= elevation_hysteresis => {
+                    elevation_gain += ele - reference;
+                    reference_elevation = Some(ele);
+                }
+                Some(reference) if reference - ele >= elevation_hysteresis => {
+                    elevation_loss += reference - ele;
+                    reference_elevation = Some(ele);
                 }
+                Some(_) => {}
+                None => reference_elevation = Some(ele),
             }
-            last_elevation = Some(ele);
+            elevation_range = Some(match elevation_range {
+                Some((min, max)) => (min.min(ele), max.max(ele)),
+                None => (ele, ele),
+            });
         }
 
-        // Duration calculation
+        // Duration and speed calculation
         if let Some(ts) = point.timestamp {
             if start_time.is_none() {
                 start_time = Some(ts);
             }
             end_time = Some(ts);
+
+            if let Some(prev) = last_time {
+                let elapsed = (ts - prev).as_seconds_f64();
+                if elapsed > 0.0 {
+                    let speed = step / elapsed;
+                    recent.push_back((step, elapsed));
+                    while recent.len() > 1
+                        && window_time(&recent) - recent[0].1 >= MAX_SPEED_WINDOW_SECONDS
+                    {
+                        recent.pop_front();
+                    }
+
+                    let moving = moving_time.get_or_insert(0.0);
+                    if speed >= STOPPED_SPEED_THRESHOLD_MPS {
+                        *moving += elapsed;
+                        moving_distance += step;
+                        if window_time(&recent) >= MAX_SPEED_WINDOW_SECONDS {
+                            let window_speed = window_distance(&recent) / window_time(&recent);
+                            max_speed = Some(
+                                max_speed.map_or(window_speed, |max: f64| max.max(window_speed)),
+                            );
+                        }
+                    }
+                }
+            }
         }
+        last_time = point.timestamp;
     }
 
     let duration = match (start_time, end_time) {
         (Some(start), Some(end)) => (end - start).as_seconds_f64(),
         _ => 0.0,
     };
+    // A moving track too short to fill the window: its overall speed
+    if max_speed.is_none() && moving_distance > 0.0 {
+        max_speed = Some(window_distance(&recent) / window_time(&recent));
+    }
 
     Scores {
         distance,
         duration,
         elevation_gain,
+        stats: ActivityStats {
+            moving_time,
+            max_speed,
+            avg_speed: moving_time
+                .filter(|&t| t > 0.0)
+                .map(|t| moving_distance / t),
+            elevation_loss: elevation_range.map(|_| elevation_loss),
+            min_elevation: elevation_range.map(|(min, _)| min),
+            max_elevation: elevation_range.map(|(_, max)| max),
+            ..Default::default()
+        },
     }
 }
 
+fn window_distance(window: &VecDeque<(f64, f64)>) -> f64 {
+    window.iter().map(|(meters, _)| meters).sum()
+}
+
+fn window_time(window: &VecDeque<(f64, f64)>) -> f64 {
+    window.iter().map(|(_, seconds)| seconds).sum()
+}
+
 #[derive(Debug, Clone, Default)]
 struct Metrics {
     distance: Option<DistanceMetric>,
@@ -193,3 +298,125 @@ impl TrackMetric for ElevationGainMetric {
         self.total_gain
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(elevations: &[f64]) -> Vec<TrackPointData> {
+        elevations
+            .iter()
+            .map(|&ele| TrackPointData {
+                lat: 45.0,
+                lon: 7.0,
+                elevation: Some(ele),
+                timestamp: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_elevation_gain_hysteresis() {
+        // One meter of noise around a 10 m climb
+        let track = points(&[
+            100.0, 101.0, 100.0, 101.0, 100.0, 105.0, 110.0, 109.0, 110.0,
+        ]);
+
+        assert_eq!(score_track_points(&track, 0.0).elevation_gain, 13.0);
+        assert_eq!(score_track_points(&track, 2.0).elevation_gain, 10.0);
+    }
+
+    #[test]
+    fn test_elevation_loss_and_range() {
+        let track = points(&[100.0, 110.0, 109.0, 110.0, 95.0, 96.0]);
+        let stats = score_track_points(&track, 2.0).stats;
+
+        assert_eq!(stats.elevation_loss, Some(15.0));
+        assert_eq!(stats.min_elevation, Some(95.0));
+        assert_eq!(stats.max_elevation, Some(110.0));
+    }
+
+    #[test]
+    fn test_moving_time_skips_stops() {
+        let start = time::OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        // (seconds, meters north): 100 m in 20 s, a 60 s stop, 100 m in 10 s
+        let track: Vec<_> = [(0, 0.0), (20, 100.0), (80, 100.0), (90, 200.0)]
+            .iter()
+            .map(|&(secs, meters)| TrackPointData {
+                lat: 45.0 + meters / 111_195.0,
+                lon: 7.0,
+                elevation: None,
+                timestamp: Some(start + time::Duration::seconds(secs)),
+            })
+            .collect();
+        let scores = score_track_points(&track, 0.0);
+
+        assert_eq!(scores.duration, 90.0);
+        assert_eq!(scores.stats.moving_time, Some(30.0));
+        assert!((scores.stats.max_speed.unwrap() - 10.0).abs() < 0.01);
+        assert!((scores.stats.avg_speed.unwrap() - 200.0 / 30.0).abs() < 0.01);
+        assert_eq!(scores.stats.elevation_loss, None);
+    }
+
+    /// Points one second apart, each `meters` north of the previous one.
+    fn timed_steps(steps: &[f64]) -> Vec<TrackPointData> {
+        let start = time::OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        let mut north = 0.0;
+        std::iter::once(0.0)
+            .chain(steps.iter().copied())
+            .enumerate()
+            .map(|(secs, meters)| {
+                north += meters;
+                TrackPointData {
+                    lat: 45.0 + north / 111_195.0,
+                    lon: 7.0,
+                    elevation: None,
+                    timestamp: Some(start + time::Duration::seconds(secs as i64)),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_avg_speed_ignores_drift_while_stopped() {
+        // 50 m at 5 m/s, 20 s of GPS drift at 0.5 m/s, 50 m at 5 m/s
+        let steps: Vec<f64> = [vec![5.0; 10], vec![0.5; 20], vec![5.0; 10]].concat();
+        let stats = score_track_points(&timed_steps(&steps), 0.0).stats;
+
+        assert_eq!(stats.moving_time, Some(20.0));
+        assert!((stats.avg_speed.unwrap() - 5.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_max_speed_smooths_spikes() {
+        // Steady 5 m/s with a one-second jump of 40 m
+        let steps: Vec<f64> = [vec![5.0; 10], vec![40.0], vec![5.0; 10]].concat();
+        let stats = score_track_points(&timed_steps(&steps), 0.0).stats;
+
+        let max_speed = stats.max_speed.unwrap();
+        assert!((max_speed - 12.0).abs() < 0.01, "{max_speed}");
+    }
+
+    #[test]
+    fn test_short_track_max_speed() {
+        let stats = score_track_points(&timed_steps(&[3.0, 5.0]), 0.0).stats;
+        assert!((stats.max_speed.unwrap() - 4.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_untimed_track_has_no_speed() {
+        let stats = score_track_points(&points(&[100.0, 101.0]), 0.0).stats;
+        assert_eq!(stats.moving_time, None);
+        assert_eq!(stats.avg_speed, None);
+        assert_eq!(stats.max_speed, None);
+    }
+
+    #[test]
+    fn test_sensor_stats() {
+        assert_eq!(
+            sensor_stats(&[Some(120), None, Some(150), Some(141)]),
+            (Some(137.0), Some(150))
+        );
+        assert_eq!(sensor_stats(&[None, None]), (None, None));
+    }
+}
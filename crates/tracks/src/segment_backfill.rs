@@ -0,0 +1,290 @@
+//! Background segment backfills.
+//!
+//! Creating or reprocessing a segment queues a job that matches it against
+//! every existing activity whose track passes near both of its endpoints.
+//! Tracks are read from the database rather than re-parsed from the uploaded
+//! files, so activities of every file format are matched. Jobs and their
+//! progress live in the `segment_backfill_jobs` table; a job interrupted by a
+//! restart is requeued and runs again from the start, which is safe because
+//! traversals that already have an effort are skipped. Each claim carries a
+//! token, so a worker that lost its job to the reaper stops instead of
+//! overwriting the progress of the worker that picked it up.
+
+use std::{panic::AssertUnwindSafe, sync::Arc, time::Duration as StdDuration};
+
+use futures_util::FutureExt;
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::{
+    activity_queue,
+    database::{ClaimedSegmentBackfill, Database},
+    errors::AppError,
+    models::SegmentBackfillJob,
+    segment_matching::{ActivityMatch, SegmentCandidate},
+};
+
+/// How often an idle worker checks for queued backfills.
+const POLL_INTERVAL: StdDuration = StdDuration::from_secs(5);
+
+/// How often the reaper looks for backfills abandoned by a dead worker.
+const REAPER_INTERVAL: StdDuration = StdDuration::from_secs(60);
+
+/// A running backfill refreshes its claim whenever it records progress; one
+/// that hasn't for this long is assumed abandoned.
+const STALE_JOB_TIMEOUT: time::Duration = time::Duration::minutes(10);
+
+/// Activities checked between progress updates.
+const PROGRESS_INTERVAL: i32 = 20;
+
+/// Counters reported while a backfill runs.
+#[derive(Debug, Default)]
+struct BackfillProgress {
+    activities_total: i32,
+    activities_checked: i32,
+    efforts_created: i32,
+    failures: i32,
+}
+
+#[derive(Clone)]
+pub struct SegmentBackfillQueue {
+    db: Database,
+    wake: Arc<Notify>,
+}
+
+impl SegmentBackfillQueue {
+    pub fn new(db: Database) -> Self {
+        Self {
+            db,
+            wake: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Spawn the backfill worker plus the stale-job reaper on the current runtime.
+    pub fn start(&self) {
+        let queue = self.clone();
+        tokio::spawn(async move { queue.run_worker().await });
+
+        let db = self.db.clone();
+        let wake = self.wake.clone();
+        tokio::spawn(async move {
+            loop {
+                match db.requeue_stale_segment_backfills(STALE_JOB_TIMEOUT).await {
+                    Ok(0) => {}
+                    Ok(count) => {
+                        tracing::warn!(count, "Requeued stale segment backfills");
+                        wake.notify_one();
+                    }
+                    Err(e) => tracing::error!("Failed to requeue stale segment backfills: {e}"),
+                }
+                tokio::time::sleep(REAPER_INTERVAL).await;
+            }
+        });
+    }
+
+    /// Queue a backfill for a segment, or return the one already pending.
+    pub async fn submit(
+        &self,
+        segment_id: Uuid,
+        requested_by: Uuid,
+    ) -> Result<SegmentBackfillJob, AppError> {
+        let job = self
+            .db
+            .enqueue_segment_backfill(segment_id, requested_by)
+            .await?;
+        self.wake.notify_one();
+        Ok(job)
+    }
+
+    async fn run_worker(&self) {
+        loop {
+            let claim = match self.db.claim_segment_backfill().await {
+                Ok(Some(claim)) => claim,
+                Ok(None) => {
+                    let _ = tokio::time::timeout(POLL_INTERVAL, self.wake.notified()).await;
+                    continue;
+                }
+                Err(e) => {
+                    tracing::error!("Failed to claim segment backfill: {e}");
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                    continue;
+                }
+            };
+
+            let job = &claim.job;
+            tracing::info!(job_id = %job.id, segment_id = %job.segment_id, "Running segment backfill");
+
+            let result = AssertUnwindSafe(self.run_job(&claim))
+                .catch_unwind()
+                .await
+                .unwrap_or(Err(AppError::Internal));
+            let error = result.err().map(|e| {
+                tracing::error!(job_id = %job.id, "Segment backfill failed: {e}");
+                e.to_string()
+            });
+            match self
+                .db
+                .finish_segment_backfill(job.id, claim.claim_token, error.as_deref())
+                .await
+            {
+                Ok(true) => {}
+                Ok(false) => {
+                    tracing::warn!(job_id = %job.id, "Backfill was reclaimed, discarding result")
+                }
+                Err(e) => {
+                    tracing::error!(job_id = %job.id, "Failed to record backfill result: {e}")
+                }
+            }
+        }
+    }
+
+    async fn run_job(&self, claim: &ClaimedSegmentBackfill) -> Result<(), AppError> {
+        let job = &claim.job;
+        let Some(segment) = self.db.get_segment(job.segment_id).await? else {
+            tracing::info!(segment_id = %job.segment_id, "Segment deleted, skipping backfill");
+            return Ok(());
+        };
+
+        let segment_points = self.db.get_segment_points(segment.id).await?;
+        let (Some(&start_point), Some(&end_point)) =
+            (segment_points.first(), segment_points.last())
+        else {
+            return Err(AppError::Internal);
+        };
+        let candidate = SegmentCandidate {
+            segment_id: segment.id,
+            distance_meters: segment.distance_meters,
+            start_point,
+            end_point,
+        };
+
+        let matches = self
+            .db
+            .find_matching_activities_for_segment(segment.id)
+            .await?;
+        let mut progress = BackfillProgress {
+            activities_total: matches.len() as i32,
+            ..Default::default()
+        };
+        if !self.report(claim, &progress).await? {
+            return Ok(());
+        }
+
+        for activity_match in matches {
+            match self
+                .backfill_activity(&candidate, segment.activity_type_id, &activity_match)
+                .await
+            {
+                Ok(created) => progress.efforts_created += created,
+                Err(e) => {
+                    tracing::warn!(
+                        job_id = %job.id,
+                        activity_id = %activity_match.activity_id,
+                        "Failed to backfill activity: {e}"
+                    );
+                    progress.failures += 1;
+                }
+            }
+            progress.activities_checked += 1;
+
+            if progress.activities_checked % PROGRESS_INTERVAL == 0
+                && !self.report(claim, &progress).await?
+            {
+                return Ok(());
+            }
+        }
+
+        self.report(claim, &progress).await?;
+        tracing::info!(
+            job_id = %job.id,
+            segment_id = %segment.id,
+            activities = progress.activities_checked,
+            efforts = progress.efforts_created,
+            failures = progress.failures,
+            "Segment backfill finished"
+        );
+        Ok(())
+    }
+
+    /// Create efforts for every traversal of the segment in one activity.
+    /// Returns the number of efforts created.
+    async fn backfill_activity(
+        &self,
+        candidate: &SegmentCandidate,
+        segment_type_id: Uuid,
+        activity_match: &ActivityMatch,
+    ) -> Result<i32, AppError> {
+        // Read before the activity: efforts for a version edited meanwhile are
+        // rejected, and the edit re-matches the activity itself
+        let Some(match_generation) = self
+            .db
+            .get_activity_match_generation(activity_match.activity_id)
+            .await?
+        else {
+            return Ok(0);
+        };
+        let Some(activity) = self.db.get_activity(activity_match.activity_id).await? else {
+            // Deleted since the candidates were listed
+            return Ok(0);
+        };
+        let Some(track_points) = self.db.get_track_points(activity.id).await? else {
+            return Err(AppError::NotFound);
+        };
+
+        let traversals = candidate.traversals(&track_points);
+        let traversals = match (&activity.type_boundaries, &activity.segment_types) {
+            // Multi-sport: only traversals ridden as the segment's activity type
+            (Some(boundaries), Some(types)) => activity_queue::filter_multi_sport_matches(
+                traversals
+                    .into_iter()
+                    .map(|m| (m, segment_type_id))
+                    .collect(),
+                &track_points,
+                boundaries,
+                types,
+            ),
+            _ => traversals,
+        };
+
+        let mut created = 0;
+        for segment_match in traversals {
+            if activity_queue::process_segment_match(
+                &self.db,
+                &track_points,
+                activity_match.user_id,
+                activity_match.activity_id,
+                match_generation,
+                segment_match,
+            )
+            .await?
+            {
+                created += 1;
+            }
+        }
+        Ok(created)
+    }
+
+    /// Record progress. Returns false when another worker has taken the job
+    /// over, in which case this one should stop.
+    async fn report(
+        &self,
+        claim: &ClaimedSegmentBackfill,
+        progress: &BackfillProgress,
+    ) -> Result<bool, AppError> {
+        let held = self
+            .db
+            .update_segment_backfill_progress(
+                claim.job.id,
+                claim.claim_token,
+                progress.activities_total,
+                progress.activities_checked,
+                progress.efforts_created,
+                progress.failures,
+            )
+            .await?;
+        if !held {
+            tracing::warn!(job_id = %claim.job.id, "Backfill was reclaimed, stopping");
+        }
+        Ok(held)
+    }
+}
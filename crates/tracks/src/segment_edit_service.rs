@@ -0,0 +1,314 @@
+//! Segment geometry edits.
+//!
+//! Changing a segment's line invalidates every effort on it. The segment is
+//! locked while the new efforts are computed from the stored activity tracks,
+//! so efforts saved meanwhile aren't lost; then the new version, the efforts,
+//! PR flags and KOM/QOM/course-record holders are swapped in by one
+//! transaction, so nobody sees a half-rebuilt leaderboard. Riders whose crowns
+//! changed hands are notified afterwards.
+
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{
+    achievements_service, activity_queue,
+    database::Database,
+    errors::AppError,
+    models::{CrownChange, Segment, SegmentGeometry},
+    segment_matching::{self, MatchRejection, SegmentCandidate, haversine_distance},
+};
+
+/// A segment effort computed against a new segment geometry.
+#[derive(Debug, Clone)]
+pub struct RebuiltEffort {
+    pub activity_id: Uuid,
+    pub user_id: Uuid,
+    pub started_at: OffsetDateTime,
+    pub elapsed_time_seconds: f64,
+    pub moving_time_seconds: f64,
+    pub average_speed_mps: Option<f64>,
+    pub start_fraction: f64,
+    pub end_fraction: f64,
+}
+
+/// A traversal that failed the path conformance check against the new geometry.
+#[derive(Debug, Clone)]
+pub struct RebuiltRejection {
+    pub activity_id: Uuid,
+    pub user_id: Uuid,
+    pub start_fraction: f64,
+    pub end_fraction: f64,
+    pub rejection: MatchRejection,
+}
+
+/// Everything derived from activity tracks for one segment geometry.
+#[derive(Debug, Default)]
+pub struct SegmentRebuild {
+    pub efforts: Vec<RebuiltEffort>,
+    pub rejections: Vec<RebuiltRejection>,
+    pub tolerance_meters: f64,
+    pub activities_checked: usize,
+}
+
+/// Result of a segment geometry edit.
+#[derive(Debug)]
+pub struct GeometryEditOutcome {
+    pub version: i32,
+    pub activities_checked: usize,
+    pub efforts: usize,
+    pub crown_changes: Vec<CrownChange>,
+}
+
+/// Replace a segment's geometry with a new version and rebuild its efforts,
+/// personal records and crowns.
+pub async fn edit_segment_geometry(
+    db: &Database,
+    segment: &Segment,
+    editor_id: Uuid,
+    geometry: &SegmentGeometry,
+) -> Result<GeometryEditOutcome, AppError> {
+    let (version, mut crown_changes, rebuild) = db
+        .apply_segment_geometry_edit(segment.id, editor_id, geometry, async || {
+            rebuild_efforts(db, segment, geometry).await
+        })
+        .await?;
+
+    // Efforts that no longer match don't count towards Local Legend either
+    match achievements_service::update_local_legend(db, segment.id).await {
+        Ok(change) => crown_changes.extend(change),
+        Err(e) => tracing::warn!(
+            "Failed to update Local Legend for segment {}: {e}",
+            segment.id
+        ),
+    }
+
+    tracing::info!(
+        "Segment {} now at version {version}: {} efforts from {} activities, {} crown changes",
+        segment.id,
+        rebuild.efforts.len(),
+        rebuild.activities_checked,
+        crown_changes.len()
+    );
+
+    for change in &crown_changes {
+        achievements_service::notify_crown_change(db, segment.id, &segment.name, change).await;
+    }
+
+    Ok(GeometryEditOutcome {
+        version,
+        activities_checked: rebuild.activities_checked,
+        efforts: rebuild.efforts.len(),
+        crown_changes,
+    })
+}
+
+/// Match every candidate activity against the new geometry, using the stored
+/// tracks so activities of every upload format are included. Multi-sport
+/// activities only count traversals ridden as the segment's activity type.
+async fn rebuild_efforts(
+    db: &Database,
+    segment: &Segment,
+    geometry: &SegmentGeometry,
+) -> Result<SegmentRebuild, AppError> {
+    let (Some(&start_point), Some(&end_point)) = (geometry.points.first(), geometry.points.last())
+    else {
+        return Err(AppError::InvalidInput(
+            "Segment geometry has no points".to_string(),
+        ));
+    };
+    let candidate = SegmentCandidate {
+        segment_id: segment.id,
+        distance_meters: geometry.distance_meters,
+        start_point,
+        end_point,
+    };
+
+    let tolerance_meters = db.get_segment_match_tolerance(segment.id).await?;
+    let activities = db
+        .find_activities_near_endpoints(
+            segment.activity_type_id,
+            &geometry.start_wkt,
+            &geometry.end_wkt,
+        )
+        .await?;
+
+    let mut rebuild = SegmentRebuild {
+        tolerance_meters,
+        activities_checked: activities.len(),
+        ..Default::default()
+    };
+
+    for activity in activities {
+        let Some(track_points) = db.get_track_points(activity.activity_id).await? else {
+            continue;
+        };
+        let Some(parts) = db.get_activity(activity.activity_id).await? else {
+            continue;
+        };
+
+        let traversals = candidate.traversals(&track_points);
+        let traversals = match (&parts.type_boundaries, &parts.segment_types) {
+            (Some(boundaries), Some(types)) => activity_queue::filter_multi_sport_matches(
+                traversals
+                    .into_iter()
+                    .map(|m| (m, segment.activity_type_id))
+                    .collect(),
+                &track_points,
+                boundaries,
+                types,
+            ),
+            _ => traversals,
+        };
+
+        for traversal in traversals {
+            if let Err(rejection) = segment_matching::check_path_conformance(
+                &track_points,
+                &geometry.points,
+                traversal.start_fraction,
+                traversal.end_fraction,
+                tolerance_meters,
+            ) {
+                rebuild.rejections.push(RebuiltRejection {
+                    activity_id: activity.activity_id,
+                    user_id: activity.user_id,
+                    start_fraction: traversal.start_fraction,
+                    end_fraction: traversal.end_fraction,
+                    rejection,
+                });
+                continue;
+            }
+
+            let Some(timing) = segment_matching::extract_timing(
+                &track_points,
+                traversal.start_fraction,
+                traversal.end_fraction,
+            ) else {
+                continue;
+            };
+
+            let average_speed_mps = (timing.elapsed_time_seconds > 0.0)
+                .then(|| geometry.distance_meters / timing.elapsed_time_seconds);
+
+            rebuild.efforts.push(RebuiltEffort {
+                activity_id: activity.activity_id,
+                user_id: activity.user_id,
+                started_at: timing.started_at,
+                elapsed_time_seconds: timing.elapsed_time_seconds,
+                moving_time_seconds: timing.moving_time_seconds,
+                average_speed_mps,
+                start_fraction: traversal.start_fraction,
+                end_fraction: traversal.end_fraction,
+            });
+        }
+    }
+
+    Ok(rebuild)
+}
+
+/// Cut `trim_start_meters` off the start and `trim_end_meters` off the end of a
+/// (lat, lon, elevation) line, interpolating the new endpoints. Returns None
+/// when nothing would be left.
+pub fn trim_line(
+    points: &[(f64, f64, Option<f64>)],
+    trim_start_meters: f64,
+    trim_end_meters: f64,
+) -> Option<Vec<(f64, f64, Option<f64>)>> {
+    let mut cumulative = Vec::with_capacity(points.len());
+    let mut total = 0.0;
+    for (i, p) in points.iter().enumerate() {
+        if i > 0 {
+            let prev = points[i - 1];
+            total += haversine_distance(prev.0, prev.1, p.0, p.1);
+        }
+        cumulative.push(total);
+    }
+
+    let from = trim_start_meters.max(0.0);
+    let to = total - trim_end_meters.max(0.0);
+    if points.len() < 2 || from >= to {
+        return None;
+    }
+
+    let mut trimmed = vec![point_at(points, &cumulative, from)];
+    trimmed.extend(
+        points
+            .iter()
+            .zip(&cumulative)
+            .filter(|(_, d)| **d > from && **d < to)
+            .map(|(p, _)| *p),
+    );
+    trimmed.push(point_at(points, &cumulative, to));
+    Some(trimmed)
+}
+
+/// Interpolate the point `distance` meters along the line.
+fn point_at(
+    points: &[(f64, f64, Option<f64>)],
+    cumulative: &[f64],
+    distance: f64,
+) -> (f64, f64, Option<f64>) {
+    let i = cumulative
+        .partition_point(|d| *d < distance)
+        .clamp(1, points.len() - 1);
+    let (a, b) = (points[i - 1], points[i]);
+    let span = cumulative[i] - cumulative[i - 1];
+    let t = if span > 0.0 {
+        ((distance - cumulative[i - 1]) / span).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let ele = match (a.2, b.2) {
+        (Some(ea), Some(eb)) => Some(ea + (eb - ea) * t),
+        (ea, eb) => ea.or(eb),
+    };
+    (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t, ele)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A straight line heading north with a point every ~111m.
+    fn north_line(points: usize) -> Vec<(f64, f64, Option<f64>)> {
+        (0..points)
+            .map(|i| (45.0 + i as f64 * 0.001, 7.0, Some(100.0 + i as f64)))
+            .collect()
+    }
+
+    fn length(points: &[(f64, f64, Option<f64>)]) -> f64 {
+        points
+            .windows(2)
+            .map(|w| haversine_distance(w[0].0, w[0].1, w[1].0, w[1].1))
+            .sum()
+    }
+
+    #[test]
+    fn test_trim_line_without_trim_keeps_line() {
+        let line = north_line(10);
+        let trimmed = trim_line(&line, 0.0, 0.0).unwrap();
+        assert_eq!(trimmed.first(), line.first());
+        assert_eq!(trimmed.last(), line.last());
+        assert!((length(&trimmed) - length(&line)).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_trim_line_cuts_both_ends() {
+        let line = north_line(10);
+        let total = length(&line);
+        let trimmed = trim_line(&line, 150.0, 250.0).unwrap();
+
+        assert!((length(&trimmed) - (total - 400.0)).abs() < 0.5);
+        // New start lies between the second and third points, elevation interpolated
+        let start = trimmed[0];
+        assert!(start.0 > line[1].0 && start.0 < line[2].0);
+        assert!(start.2.unwrap() > 101.0 && start.2.unwrap() < 102.0);
+    }
+
+    #[test]
+    fn test_trim_line_rejects_trimming_everything() {
+        let line = north_line(10);
+        let total = length(&line);
+        assert!(trim_line(&line, total / 2.0, total / 2.0).is_none());
+        assert!(trim_line(&line, total + 1.0, 0.0).is_none());
+    }
+}
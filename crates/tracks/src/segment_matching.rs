@@ -1,9 +1,15 @@
-//! Segment matching utilities for finding and timing segment efforts from GPX tracks.
+//! Segment matching utilities for finding and timing segment efforts from activity tracks.
 
 use time::OffsetDateTime;
 use uuid::Uuid;
 
-/// A segment that an activity track passes through.
+use crate::{database::Database, errors::AppError, models::TrackPointData};
+
+/// Radius (meters) around a segment's start and end points that a track must pass through.
+pub const ENDPOINT_RADIUS_METERS: f64 = 50.0;
+
+/// A single traversal of a segment by an activity track.
+/// An activity that laps a segment produces one match per lap.
 #[derive(Debug, Clone)]
 pub struct SegmentMatch {
     pub segment_id: Uuid,
@@ -14,18 +20,41 @@ pub struct SegmentMatch {
     pub end_fraction: f64,
 }
 
-/// An activity whose track matches a segment.
+/// A segment whose start and end points the activity track passes near.
+/// Turned into zero or more [`SegmentMatch`]es with [`SegmentCandidate::traversals`].
+#[derive(Debug, Clone)]
+pub struct SegmentCandidate {
+    pub segment_id: Uuid,
+    pub distance_meters: f64,
+    /// (lat, lon) of the segment start
+    pub start_point: (f64, f64),
+    /// (lat, lon) of the segment end
+    pub end_point: (f64, f64),
+}
+
+impl SegmentCandidate {
+    /// Every traversal of this segment in the track, in track order.
+    pub fn traversals(&self, track_points: &[TrackPointData]) -> Vec<SegmentMatch> {
+        find_traversals(track_points, self.start_point, self.end_point)
+            .into_iter()
+            .map(|(start_fraction, end_fraction)| SegmentMatch {
+                segment_id: self.segment_id,
+                distance_meters: self.distance_meters,
+                start_fraction,
+                end_fraction,
+            })
+            .collect()
+    }
+}
+
+/// An activity whose track passes near both endpoints of a segment.
 #[derive(Debug, Clone)]
 pub struct ActivityMatch {
     pub activity_id: Uuid,
     pub user_id: Uuid,
-    /// Fractional position (0-1) of segment start along the track
-    pub start_fraction: f64,
-    /// Fractional position (0-1) of segment end along the track
-    pub end_fraction: f64,
 }
 
-/// Timing information extracted from GPX for a segment effort.
+/// Timing information extracted from a track for a segment effort.
 #[derive(Debug, Clone)]
 pub struct SegmentTiming {
     pub started_at: OffsetDateTime,
@@ -43,70 +72,58 @@ struct TrackPointWithFraction {
 
 /// Speed threshold in m/s below which we consider the user stopped.
 /// 1 m/s is approximately 2.2 mph (slow walking speed).
-const STOPPED_SPEED_THRESHOLD_MPS: f64 = 1.0;
+pub(crate) const STOPPED_SPEED_THRESHOLD_MPS: f64 = 1.0;
 
-/// Extract timing from GPX track for a segment match.
+/// Extract timing from track points for a segment match.
+///
+/// Works on the format-neutral `TrackPointData` produced by `file_parsers`, so
+/// GPX, TCX and FIT activities are all timed the same way.
 ///
 /// Uses the fractional positions (0-1) from PostGIS ST_LineLocatePoint to find
-/// the corresponding timestamps in the GPX track points.
+/// the corresponding timestamps in the track points.
 ///
 /// Also calculates moving time by excluding intervals where speed is below the
 /// stopped threshold (1 m/s).
-pub fn extract_timing_from_gpx(
-    gpx: &gpx::Gpx,
+pub fn extract_timing(
+    points: &[TrackPointData],
     start_fraction: f64,
     end_fraction: f64,
 ) -> Option<SegmentTiming> {
     // Collect all points with timestamps and their fractional positions
-    let mut points_with_time: Vec<(f64, gpx::Time)> = Vec::new();
-    let mut all_points: Vec<TrackPointWithFraction> = Vec::new();
+    let mut timed_points: Vec<TrackPointWithFraction> = Vec::new();
     let mut cumulative_distance = 0.0;
+    let mut prev_point: Option<&TrackPointData> = None;
 
-    for track in &gpx.tracks {
-        for seg in &track.segments {
-            let mut prev_point: Option<geo::geometry::Point<f64>> = None;
-
-            for pt in &seg.points {
-                if let Some(prev) = prev_point {
-                    let distance =
-                        haversine_distance(prev.y(), prev.x(), pt.point().y(), pt.point().x());
-                    cumulative_distance += distance;
-                }
-
-                if let Some(time) = pt.time {
-                    points_with_time.push((cumulative_distance, time));
-                    all_points.push(TrackPointWithFraction {
-                        fraction: cumulative_distance, // Will be normalized later
-                        time: time.into(),
-                        lat: pt.point().y(),
-                        lon: pt.point().x(),
-                    });
-                }
+    for pt in points {
+        if let Some(prev) = prev_point {
+            cumulative_distance += haversine_distance(prev.lat, prev.lon, pt.lat, pt.lon);
+        }
 
-                prev_point = Some(pt.point());
-            }
+        if let Some(time) = pt.timestamp {
+            timed_points.push(TrackPointWithFraction {
+                fraction: cumulative_distance, // Will be normalized later
+                time,
+                lat: pt.lat,
+                lon: pt.lon,
+            });
         }
+
+        prev_point = Some(pt);
     }
 
-    if points_with_time.is_empty() || cumulative_distance == 0.0 {
+    if timed_points.is_empty() || cumulative_distance == 0.0 {
         return None;
     }
 
     // Normalize distances to fractions (0-1)
     let total_distance = cumulative_distance;
-    for (dist, _) in &mut points_with_time {
-        *dist /= total_distance;
-    }
-    for pt in &mut all_points {
+    for pt in &mut timed_points {
         pt.fraction /= total_distance;
     }
 
     // Find timestamps at start and end fractions by interpolation
-    let start_time = interpolate_time(&points_with_time, start_fraction)?;
-    let end_time = interpolate_time(&points_with_time, end_fraction)?;
-
-    let start_dt: OffsetDateTime = start_time.into();
-    let end_dt: OffsetDateTime = end_time.into();
+    let start_dt = interpolate_time(&timed_points, start_fraction)?;
+    let end_dt = interpolate_time(&timed_points, end_fraction)?;
 
     let elapsed = (end_dt - start_dt).as_seconds_f64();
 
@@ -115,7 +132,7 @@ pub fn extract_timing_from_gpx(
     }
 
     // Calculate moving time by summing intervals where speed >= threshold
-    let moving_time = calculate_moving_time(&all_points, start_fraction, end_fraction);
+    let moving_time = calculate_moving_time(&timed_points, start_fraction, end_fraction);
 
     Some(SegmentTiming {
         started_at: start_dt,
@@ -124,6 +141,364 @@ pub fn extract_timing_from_gpx(
     })
 }
 
+/// Find every traversal of a segment in a track, as (start_fraction, end_fraction) pairs.
+///
+/// Each pass through the start zone (within [`ENDPOINT_RADIUS_METERS`] of the start
+/// point) is paired with the next pass through the end zone, unless the track comes
+/// back through the start zone first, in which case the later pass starts the lap.
+/// Fractions are by distance along the track, the same convention [`extract_timing`]
+/// uses. Loop segments, where start and end coincide, yield one traversal per lap.
+pub fn find_traversals(
+    track_points: &[TrackPointData],
+    start_point: (f64, f64),
+    end_point: (f64, f64),
+) -> Vec<(f64, f64)> {
+    if track_points.len() < 2 {
+        return Vec::new();
+    }
+
+    let mut cumulative = Vec::with_capacity(track_points.len());
+    let mut total = 0.0;
+    cumulative.push(0.0);
+    for pair in track_points.windows(2) {
+        total += haversine_distance(pair[0].lat, pair[0].lon, pair[1].lat, pair[1].lon);
+        cumulative.push(total);
+    }
+
+    if total == 0.0 {
+        return Vec::new();
+    }
+
+    let starts = zone_passes(track_points, &cumulative, start_point);
+    let ends = zone_passes(track_points, &cumulative, end_point);
+
+    let mut traversals = Vec::new();
+    for (i, &start) in starts.iter().enumerate() {
+        let Some(&end) = ends.iter().find(|&&end| end > start) else {
+            break;
+        };
+        if starts.get(i + 1).is_some_and(|&next| next < end) {
+            continue;
+        }
+        traversals.push((start / total, end / total));
+    }
+
+    traversals
+}
+
+/// Distances along the track of the closest approach to `target` on each separate
+/// pass through the zone around it.
+fn zone_passes(points: &[TrackPointData], cumulative: &[f64], target: (f64, f64)) -> Vec<f64> {
+    let mut passes = Vec::new();
+    // (distance from target, distance along track) of the closest approach so far
+    let mut current: Option<(f64, f64)> = None;
+
+    for (i, pair) in points.windows(2).enumerate() {
+        let (t, dist) = closest_on_edge(&pair[0], &pair[1], target);
+        if dist <= ENDPOINT_RADIUS_METERS {
+            let along = cumulative[i] + t * (cumulative[i + 1] - cumulative[i]);
+            if current.is_none_or(|(best, _)| dist < best) {
+                current = Some((dist, along));
+            }
+        } else if let Some((_, along)) = current.take() {
+            passes.push(along);
+        }
+    }
+    if let Some((_, along)) = current {
+        passes.push(along);
+    }
+
+    passes
+}
+
+/// Closest point to `target` on the edge between two track points, as the
+/// interpolation parameter (0-1) along the edge and the distance in meters.
+/// Uses a local flat projection around the target, which is accurate at the
+/// scale of the endpoint radius.
+fn closest_on_edge(a: &TrackPointData, b: &TrackPointData, target: (f64, f64)) -> (f64, f64) {
+    const METERS_PER_DEGREE: f64 = 111_320.0;
+    let lon_scale = target.0.to_radians().cos() * METERS_PER_DEGREE;
+    let project = |lat: f64, lon: f64| {
+        (
+            (lon - target.1) * lon_scale,
+            (lat - target.0) * METERS_PER_DEGREE,
+        )
+    };
+
+    let (ax, ay) = project(a.lat, a.lon);
+    let (bx, by) = project(b.lat, b.lon);
+    let (dx, dy) = (bx - ax, by - ay);
+    let len_sq = dx * dx + dy * dy;
+
+    let t = if len_sq > 0.0 {
+        (-(ax * dx + ay * dy) / len_sq).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let (px, py) = (ax + t * dx, ay + t * dy);
+
+    (t, (px * px + py * py).sqrt())
+}
+
+/// Flatten a parsed GPX file into format-neutral track points.
+///
+/// Used by callers that still read activities back from the object store as GPX.
+pub fn gpx_track_points(gpx: &gpx::Gpx) -> Vec<TrackPointData> {
+    gpx.tracks
+        .iter()
+        .flat_map(|track| &track.segments)
+        .flat_map(|seg| &seg.points)
+        .map(|pt| TrackPointData {
+            lat: pt.point().y(),
+            lon: pt.point().x(),
+            elevation: pt.elevation,
+            timestamp: pt.time.map(Into::into),
+        })
+        .collect()
+}
+
+// ============================================================================
+// Path Conformance
+// ============================================================================
+
+/// Default tolerance used when an activity type has no configured value.
+pub const DEFAULT_MATCH_TOLERANCE_METERS: f64 = 30.0;
+
+/// Smallest spacing used when densifying lines for the Fréchet comparison.
+const MIN_DENSIFY_SPACING_METERS: f64 = 5.0;
+
+/// Upper bound on vertices per densified line, keeps the O(n*m) comparison cheap
+/// on long segments by widening the spacing instead.
+const MAX_DENSIFIED_POINTS: usize = 2000;
+
+/// Why a geometric endpoint match was not accepted as a segment effort.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MatchRejection {
+    /// The track between the segment endpoints strays too far from the segment line
+    /// (e.g. an out-and-back or a shortcut).
+    PathDeviation {
+        frechet_distance_meters: f64,
+        tolerance_meters: f64,
+    },
+    /// Not enough points on the track or segment to compare the paths.
+    InsufficientPoints,
+}
+
+impl MatchRejection {
+    /// Stable reason code stored in `segment_match_rejections.reason`.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            MatchRejection::PathDeviation { .. } => "path_deviation",
+            MatchRejection::InsufficientPoints => "insufficient_points",
+        }
+    }
+}
+
+/// Check that the part of the track between `start_fraction` and `end_fraction`
+/// actually follows the segment.
+///
+/// Both lines are densified and compared with the discrete Fréchet distance, which
+/// respects the order of travel, so riding the segment backwards or skipping part of
+/// it fails even when both endpoints are hit. Returns the Fréchet distance in meters
+/// when the track conforms.
+pub fn check_path_conformance(
+    track_points: &[TrackPointData],
+    segment_points: &[(f64, f64)],
+    start_fraction: f64,
+    end_fraction: f64,
+    tolerance_meters: f64,
+) -> Result<f64, MatchRejection> {
+    let matched = track_portion(track_points, start_fraction, end_fraction);
+    if matched.len() < 2 || segment_points.len() < 2 {
+        return Err(MatchRejection::InsufficientPoints);
+    }
+
+    let distance = discrete_frechet_distance(&densify(&matched), &densify(segment_points));
+
+    if distance > tolerance_meters {
+        return Err(MatchRejection::PathDeviation {
+            frechet_distance_meters: distance,
+            tolerance_meters,
+        });
+    }
+
+    Ok(distance)
+}
+
+/// Run the path conformance check for an endpoint match against the stored segment,
+/// recording the rejection reason for the segment owner when it fails.
+///
+/// Returns true when the effort should be created, or the error loading the
+/// segment to check against.
+pub async fn verify_match_conformance(
+    db: &Database,
+    segment_id: Uuid,
+    activity_id: Uuid,
+    user_id: Uuid,
+    track_points: &[TrackPointData],
+    start_fraction: f64,
+    end_fraction: f64,
+) -> Result<bool, AppError> {
+    let (segment_points, tolerance) = tokio::try_join!(
+        db.get_segment_points(segment_id),
+        db.get_segment_match_tolerance(segment_id)
+    )?;
+
+    match check_path_conformance(
+        track_points,
+        &segment_points,
+        start_fraction,
+        end_fraction,
+        tolerance,
+    ) {
+        Ok(_) => {
+            if let Err(e) = db
+                .clear_segment_match_rejection(
+                    segment_id,
+                    activity_id,
+                    start_fraction,
+                    end_fraction,
+                )
+                .await
+            {
+                tracing::warn!("Failed to clear match rejection for segment {segment_id}: {e}");
+            }
+            Ok(true)
+        }
+        Err(rejection) => {
+            tracing::info!(
+                "Rejected match of activity {activity_id} on segment {segment_id}: {rejection:?}"
+            );
+            if let Err(e) = db
+                .record_segment_match_rejection(
+                    segment_id,
+                    activity_id,
+                    user_id,
+                    start_fraction,
+                    end_fraction,
+                    &rejection,
+                    tolerance,
+                )
+                .await
+            {
+                tracing::error!("Failed to record match rejection for segment {segment_id}: {e}");
+            }
+            Ok(false)
+        }
+    }
+}
+
+/// Extract the (lat, lon) polyline of the track between two fractional positions,
+/// interpolating the cut points so the portion starts and ends exactly at the fractions.
+fn track_portion(
+    points: &[TrackPointData],
+    start_fraction: f64,
+    end_fraction: f64,
+) -> Vec<(f64, f64)> {
+    if points.len() < 2 || start_fraction >= end_fraction {
+        return Vec::new();
+    }
+
+    let mut cumulative = Vec::with_capacity(points.len());
+    let mut total = 0.0;
+    cumulative.push(0.0);
+    for pair in points.windows(2) {
+        total += haversine_distance(pair[0].lat, pair[0].lon, pair[1].lat, pair[1].lon);
+        cumulative.push(total);
+    }
+
+    if total == 0.0 {
+        return Vec::new();
+    }
+
+    let start_dist = start_fraction.clamp(0.0, 1.0) * total;
+    let end_dist = end_fraction.clamp(0.0, 1.0) * total;
+
+    let point_at = |dist: f64| -> (f64, f64) {
+        let idx = cumulative
+            .partition_point(|&d| d < dist)
+            .min(points.len() - 1);
+        if idx == 0 {
+            return (points[0].lat, points[0].lon);
+        }
+        let (p1, p2) = (&points[idx - 1], &points[idx]);
+        let span = cumulative[idx] - cumulative[idx - 1];
+        let t = if span > 0.0 {
+            (dist - cumulative[idx - 1]) / span
+        } else {
+            0.0
+        };
+        (
+            p1.lat + (p2.lat - p1.lat) * t,
+            p1.lon + (p2.lon - p1.lon) * t,
+        )
+    };
+
+    let mut portion = vec![point_at(start_dist)];
+    portion.extend(
+        points
+            .iter()
+            .zip(&cumulative)
+            .filter(|&(_, &d)| d > start_dist && d < end_dist)
+            .map(|(p, _)| (p.lat, p.lon)),
+    );
+    portion.push(point_at(end_dist));
+    portion
+}
+
+/// Insert intermediate vertices so no two consecutive points are further apart than
+/// the densify spacing. Discrete Fréchet only compares vertices, so sparse lines
+/// would otherwise overstate the distance between two otherwise identical paths.
+pub(crate) fn densify(points: &[(f64, f64)]) -> Vec<(f64, f64)> {
+    let length: f64 = points
+        .windows(2)
+        .map(|w| haversine_distance(w[0].0, w[0].1, w[1].0, w[1].1))
+        .sum();
+    let spacing = (length / MAX_DENSIFIED_POINTS as f64).max(MIN_DENSIFY_SPACING_METERS);
+
+    let mut out = Vec::new();
+    for w in points.windows(2) {
+        let (a, b) = (w[0], w[1]);
+        let steps = (haversine_distance(a.0, a.1, b.0, b.1) / spacing)
+            .ceil()
+            .max(1.0) as usize;
+        for i in 0..steps {
+            let t = i as f64 / steps as f64;
+            out.push((a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t));
+        }
+    }
+    if let Some(&last) = points.last() {
+        out.push(last);
+    }
+    out
+}
+
+/// Discrete Fréchet distance in meters between two (lat, lon) polylines.
+pub(crate) fn discrete_frechet_distance(a: &[(f64, f64)], b: &[(f64, f64)]) -> f64 {
+    if a.is_empty() || b.is_empty() {
+        return f64::INFINITY;
+    }
+
+    // Only the previous row of the coupling table is needed at any time.
+    let mut prev = vec![0.0_f64; b.len()];
+    let mut curr = vec![0.0; b.len()];
+
+    for (i, pa) in a.iter().enumerate() {
+        for (j, pb) in b.iter().enumerate() {
+            let d = haversine_distance(pa.0, pa.1, pb.0, pb.1);
+            curr[j] = match (i, j) {
+                (0, 0) => d,
+                (0, _) => curr[j - 1].max(d),
+                (_, 0) => prev[0].max(d),
+                _ => prev[j].min(prev[j - 1]).min(curr[j - 1]).max(d),
+            };
+        }
+        std::mem::swap(&mut prev, &mut curr);
+    }
+
+    prev[b.len() - 1]
+}
+
 /// Calculate moving time by summing time intervals where speed >= threshold.
 ///
 /// Only considers points within the segment bounds (start_fraction to end_fraction).
@@ -167,15 +542,15 @@ fn calculate_moving_time(
 }
 
 /// Interpolate time at a given fractional position along the track.
-fn interpolate_time(points: &[(f64, gpx::Time)], fraction: f64) -> Option<gpx::Time> {
+fn interpolate_time(points: &[TrackPointWithFraction], fraction: f64) -> Option<OffsetDateTime> {
     if points.is_empty() {
         return None;
     }
 
     // Find the two points that bracket the fraction
     let mut lower_idx = 0;
-    for (i, (dist, _)) in points.iter().enumerate() {
-        if *dist <= fraction {
+    for (i, pt) in points.iter().enumerate() {
+        if pt.fraction <= fraction {
             lower_idx = i;
         } else {
             break;
@@ -184,30 +559,25 @@ fn interpolate_time(points: &[(f64, gpx::Time)], fraction: f64) -> Option<gpx::T
 
     // If at or past the last point, return last time
     if lower_idx == points.len() - 1 {
-        return Some(points[lower_idx].1);
+        return Some(points[lower_idx].time);
     }
 
-    let (dist1, time1) = points[lower_idx];
-    let (dist2, time2) = points[lower_idx + 1];
+    let p1 = &points[lower_idx];
+    let p2 = &points[lower_idx + 1];
 
     // Linear interpolation between the two points
-    if (dist2 - dist1).abs() < f64::EPSILON {
-        return Some(time1);
+    if (p2.fraction - p1.fraction).abs() < f64::EPSILON {
+        return Some(p1.time);
     }
 
-    let t = (fraction - dist1) / (dist2 - dist1);
+    let t = (fraction - p1.fraction) / (p2.fraction - p1.fraction);
 
-    let time1_dt: OffsetDateTime = time1.into();
-    let time2_dt: OffsetDateTime = time2.into();
-
-    let duration = time2_dt - time1_dt;
-    let interpolated = time1_dt + duration * t;
-
-    // Convert back to gpx::Time
-    Some(gpx::Time::from(interpolated))
+    let duration = p2.time - p1.time;
+    Some(p1.time + duration * t)
 }
 
-fn haversine_distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+/// Great-circle distance in meters between two (lat, lon) points.
+pub(crate) fn haversine_distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
     const R: f64 = 6371000.0; // Earth radius in meters
     let d_lat = (lat2 - lat1).to_radians();
     let d_lon = (lon2 - lon1).to_radians();
@@ -219,3 +589,241 @@ fn haversine_distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
     let c = 2.0 * a.sqrt().asin();
     R * c
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::Duration;
+
+    /// Straight track heading north at a steady ~11 m per 5 s (about 2.2 m/s).
+    fn make_track_points(count: usize) -> Vec<TrackPointData> {
+        let start = OffsetDateTime::now_utc();
+        (0..count)
+            .map(|i| TrackPointData {
+                lat: 40.0 + (i as f64 * 0.0001),
+                lon: -105.3,
+                elevation: Some(1650.0),
+                timestamp: Some(start + Duration::seconds(i as i64 * 5)),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_extract_timing_full_track() {
+        let points = make_track_points(101);
+        let timing = extract_timing(&points, 0.0, 1.0).unwrap();
+
+        assert_eq!(timing.started_at, points[0].timestamp.unwrap());
+        assert!((timing.elapsed_time_seconds - 500.0).abs() < 0.01);
+        assert!((timing.moving_time_seconds - 500.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_extract_timing_interpolates_fractions() {
+        let points = make_track_points(101);
+        let timing = extract_timing(&points, 0.25, 0.75).unwrap();
+
+        let expected_start = points[0].timestamp.unwrap() + Duration::seconds(125);
+        assert!((timing.started_at - expected_start).abs() < Duration::milliseconds(10));
+        assert!((timing.elapsed_time_seconds - 250.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_extract_timing_excludes_stopped_time_from_moving_time() {
+        // Ride for 50s, stand still for 60s, then ride another 50s
+        let mut points = make_track_points(21);
+        for pt in points.iter_mut().skip(11) {
+            pt.timestamp = pt.timestamp.map(|t| t + Duration::seconds(60));
+        }
+        let mut stopped = points[10].clone();
+        stopped.timestamp = stopped.timestamp.map(|t| t + Duration::seconds(60));
+        points.insert(11, stopped);
+
+        let timing = extract_timing(&points, 0.0, 1.0).unwrap();
+        assert!((timing.elapsed_time_seconds - 160.0).abs() < 0.01);
+        assert!((timing.moving_time_seconds - 100.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_extract_timing_without_timestamps() {
+        let points: Vec<TrackPointData> = make_track_points(10)
+            .into_iter()
+            .map(|p| TrackPointData {
+                timestamp: None,
+                ..p
+            })
+            .collect();
+
+        assert!(extract_timing(&points, 0.0, 1.0).is_none());
+    }
+
+    #[test]
+    fn test_extract_timing_rejects_reversed_fractions() {
+        let points = make_track_points(20);
+        assert!(extract_timing(&points, 0.8, 0.2).is_none());
+    }
+
+    fn lat_lon(points: &[TrackPointData]) -> Vec<(f64, f64)> {
+        points.iter().map(|p| (p.lat, p.lon)).collect()
+    }
+
+    #[test]
+    fn test_conformance_accepts_track_on_segment() {
+        let points = make_track_points(101);
+        let segment = lat_lon(&points);
+
+        let distance = check_path_conformance(&points, &segment, 0.0, 1.0, 25.0).unwrap();
+        assert!(distance < 1.0);
+    }
+
+    #[test]
+    fn test_conformance_uses_only_matched_portion() {
+        // Track runs well past both ends of the segment
+        let points = make_track_points(201);
+        let segment = lat_lon(&points[50..=150]);
+
+        let distance = check_path_conformance(&points, &segment, 0.25, 0.75, 25.0).unwrap();
+        assert!(distance < 5.0);
+    }
+
+    #[test]
+    fn test_conformance_rejects_shortcut() {
+        // Segment goes ~550 m north then ~430 m east; the track cuts the corner
+        let segment = vec![(40.0, -105.3), (40.005, -105.3), (40.005, -105.295)];
+        let start = OffsetDateTime::now_utc();
+        let points: Vec<TrackPointData> = (0..=50)
+            .map(|i| {
+                let t = i as f64 / 50.0;
+                TrackPointData {
+                    lat: 40.0 + 0.005 * t,
+                    lon: -105.3 + 0.005 * t,
+                    elevation: None,
+                    timestamp: Some(start + Duration::seconds(i * 5)),
+                }
+            })
+            .collect();
+
+        let result = check_path_conformance(&points, &segment, 0.0, 1.0, 25.0);
+        match result {
+            Err(MatchRejection::PathDeviation {
+                frechet_distance_meters,
+                tolerance_meters,
+            }) => {
+                assert!(frechet_distance_meters > 100.0);
+                assert_eq!(tolerance_meters, 25.0);
+            }
+            other => panic!("expected path deviation, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_conformance_rejects_out_and_back_detour() {
+        // Straight segment, but the track takes a ~250 m spur east and back halfway along
+        let segment = vec![(40.0, -105.3), (40.01, -105.3)];
+        let start = OffsetDateTime::now_utc();
+        let mut coords: Vec<(f64, f64)> = (0..=50)
+            .map(|i| (40.0 + 0.0001 * i as f64, -105.3))
+            .collect();
+        coords.extend((1..=30).map(|i| (40.005, -105.3 + 0.0001 * i as f64)));
+        coords.extend((0..30).rev().map(|i| (40.005, -105.3 + 0.0001 * i as f64)));
+        coords.extend((51..=100).map(|i| (40.0 + 0.0001 * i as f64, -105.3)));
+        let points: Vec<TrackPointData> = coords
+            .into_iter()
+            .enumerate()
+            .map(|(i, (lat, lon))| TrackPointData {
+                lat,
+                lon,
+                elevation: None,
+                timestamp: Some(start + Duration::seconds(i as i64 * 5)),
+            })
+            .collect();
+
+        let rejection = check_path_conformance(&points, &segment, 0.0, 1.0, 25.0).unwrap_err();
+        assert_eq!(rejection.reason(), "path_deviation");
+    }
+
+    #[test]
+    fn test_conformance_requires_segment_geometry() {
+        let points = make_track_points(20);
+        assert_eq!(
+            check_path_conformance(&points, &[(40.0, -105.3)], 0.0, 1.0, 25.0),
+            Err(MatchRejection::InsufficientPoints)
+        );
+    }
+
+    /// Track points at the given latitudes along a fixed meridian, 5 s apart.
+    fn points_at_lats(lats: impl IntoIterator<Item = f64>) -> Vec<TrackPointData> {
+        let start = OffsetDateTime::now_utc();
+        lats.into_iter()
+            .enumerate()
+            .map(|(i, lat)| TrackPointData {
+                lat,
+                lon: -105.3,
+                elevation: None,
+                timestamp: Some(start + Duration::seconds(i as i64 * 5)),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_find_traversals_single_pass() {
+        let points = make_track_points(101);
+        let traversals = find_traversals(&points, (40.002, -105.3), (40.008, -105.3));
+
+        assert_eq!(traversals.len(), 1);
+        let (start, end) = traversals[0];
+        assert!((start - 0.2).abs() < 0.01);
+        assert!((end - 0.8).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_find_traversals_hill_repeats() {
+        // Up, back down, and up again over a segment from 40.001 to 40.004
+        let up = (0..=50).map(|i| 40.0 + 0.0001 * i as f64);
+        let down = (0..50).rev().map(|i| 40.0 + 0.0001 * i as f64);
+        let up_again = (1..=50).map(|i| 40.0 + 0.0001 * i as f64);
+        let points = points_at_lats(up.chain(down).chain(up_again));
+
+        let traversals = find_traversals(&points, (40.001, -105.3), (40.004, -105.3));
+
+        assert_eq!(traversals.len(), 2);
+        assert!(traversals[0].1 < traversals[1].0);
+        for (start, end) in &traversals {
+            let timing = extract_timing(&points, *start, *end).unwrap();
+            assert!((timing.elapsed_time_seconds - 150.0).abs() < 1.0);
+        }
+    }
+
+    #[test]
+    fn test_find_traversals_loop_laps() {
+        // Three laps of a ~300 m radius circle, with the segment starting and
+        // ending at the lap line
+        let start = OffsetDateTime::now_utc();
+        let per_lap = 60;
+        let points: Vec<TrackPointData> = (0..=per_lap * 3)
+            .map(|i| {
+                let angle = std::f64::consts::TAU * i as f64 / per_lap as f64;
+                TrackPointData {
+                    lat: 40.0 + 0.0027 * angle.sin(),
+                    lon: -105.3 + 0.0035 * (1.0 - angle.cos()),
+                    elevation: None,
+                    timestamp: Some(start + Duration::seconds(i as i64 * 5)),
+                }
+            })
+            .collect();
+
+        let traversals = find_traversals(&points, (40.0, -105.3), (40.0, -105.3));
+
+        assert_eq!(traversals.len(), 3);
+        for (i, (start, end)) in traversals.iter().enumerate() {
+            assert!((start - i as f64 / 3.0).abs() < 0.01);
+            assert!((end - (i + 1) as f64 / 3.0).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn test_find_traversals_ignores_wrong_direction() {
+        let points = points_at_lats((0..=100).rev().map(|i| 40.0 + 0.0001 * i as f64));
+        assert!(find_traversals(&points, (40.002, -105.3), (40.008, -105.3)).is_empty());
+    }
+}
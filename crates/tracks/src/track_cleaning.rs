@@ -0,0 +1,377 @@
+//! GPS track cleaning, run on parsed tracks before scoring and segment matching.
+//!
+//! Raw GPS tracks contain spikes, jumps after signal loss and jitter while
+//! standing still, and barometric or GPS elevation is noisy. Summed naively
+//! these inflate an activity's distance and elevation gain, and with them the
+//! distance and speed leaderboards. Cleaning runs three steps:
+//!
+//! 1. Points reached at an impossible speed from the previous kept point are
+//!    dropped, unless the track carries on from them (the receiver really was
+//!    off before, not at the outlier).
+//! 2. Points within a few meters of the previous kept point are dropped, so
+//!    jitter at a stop adds no distance.
+//! 3. Elevation is smoothed with a moving average.
+//!
+//! Elevation hysteresis is then applied when summing the gain, see
+//! [`scoring::score_track_points`](crate::scoring::score_track_points).
+//!
+//! The uploaded file in the object store is never modified; only the points
+//! stored in `tracks` and everything derived from them are cleaned.
+
+use std::str::FromStr;
+
+use crate::{
+    file_parsers::{ParsedActivity, SensorData},
+    models::TrackPointData,
+    segment_matching::haversine_distance,
+};
+
+/// Consecutive points that must agree with each other, after an implausible
+/// jump, for the jump to be accepted as real.
+const JUMP_CONFIRMATION_POINTS: usize = 5;
+
+/// Settings for [`TrackCleaning::clean`]. A zero value disables its step.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrackCleaning {
+    /// Fastest plausible speed between two points, in m/s
+    pub max_speed_mps: f64,
+    /// Points closer than this to the previous kept point are dropped
+    pub min_movement_meters: f64,
+    /// Number of points averaged when smoothing elevation
+    pub elevation_smoothing_points: usize,
+    /// Climbs and descents smaller than this don't count towards elevation gain
+    pub elevation_hysteresis_meters: f64,
+}
+
+impl Default for TrackCleaning {
+    fn default() -> Self {
+        Self {
+            // 180 km/h, well above anything ridden, run or walked
+            max_speed_mps: 50.0,
+            min_movement_meters: 2.0,
+            elevation_smoothing_points: 5,
+            elevation_hysteresis_meters: 2.0,
+        }
+    }
+}
+
+/// How many points each cleaning step removed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CleaningReport {
+    pub outliers: usize,
+    pub stationary: usize,
+}
+
+impl TrackCleaning {
+    /// Leave tracks exactly as parsed.
+    pub fn disabled() -> Self {
+        Self {
+            max_speed_mps: 0.0,
+            min_movement_meters: 0.0,
+            elevation_smoothing_points: 0,
+            elevation_hysteresis_meters: 0.0,
+        }
+    }
+
+    /// Defaults, overridden by `TRACK_MAX_SPEED_MPS`,
+    /// `TRACK_MIN_MOVEMENT_METERS`, `TRACK_ELEVATION_SMOOTHING_POINTS` and
+    /// `TRACK_ELEVATION_HYSTERESIS_METERS`.
+    pub fn from_env() -> Self {
+        fn var<T: FromStr>(name: &str, default: T) -> T {
+            std::env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        }
+
+        let default = Self::default();
+        Self {
+            max_speed_mps: var("TRACK_MAX_SPEED_MPS", default.max_speed_mps),
+            min_movement_meters: var("TRACK_MIN_MOVEMENT_METERS", default.min_movement_meters),
+            elevation_smoothing_points: var(
+                "TRACK_ELEVATION_SMOOTHING_POINTS",
+                default.elevation_smoothing_points,
+            ),
+            elevation_hysteresis_meters: var(
+                "TRACK_ELEVATION_HYSTERESIS_METERS",
+                default.elevation_hysteresis_meters,
+            ),
+        }
+    }
+
+    /// Clean a parsed activity's track, keeping its sensor data in step with
+    /// the points that remain.
+    pub fn clean(&self, parsed: &mut ParsedActivity) -> CleaningReport {
+        let points = &parsed.track_points;
+        let mut keep = vec![true; points.len()];
+
+        let outliers = if self.max_speed_mps > 0.0 {
+            drop_outliers(points, &mut keep, self.max_speed_mps)
+        } else {
+            0
+        };
+        let stationary = if self.min_movement_meters > 0.0 {
+            drop_stationary(points, &mut keep, self.min_movement_meters)
+        } else {
+            0
+        };
+
+        if outliers + stationary > 0 {
+            retain(&mut parsed.track_points, &keep);
+            retain_sensor_data(&mut parsed.sensor_data, &keep);
+        }
+
+        if self.elevation_smoothing_points > 1 {
+            smooth_elevation(&mut parsed.track_points, self.elevation_smoothing_points);
+        }
+
+        CleaningReport {
+            outliers,
+            stationary,
+        }
+    }
+}
+
+/// Speed in m/s between two points, if both are timed and in order.
+fn speed(a: &TrackPointData, b: &TrackPointData) -> Option<f64> {
+    let secs = (b.timestamp? - a.timestamp?).as_seconds_f64();
+    (secs > 0.0).then(|| haversine_distance(a.lat, a.lon, b.lat, b.lon) / secs)
+}
+
+fn plausible(a: &TrackPointData, b: &TrackPointData, max_speed_mps: f64) -> bool {
+    speed(a, b).is_none_or(|s| s <= max_speed_mps)
+}
+
+/// Drop points reached implausibly fast from the last kept point. When the
+/// points after a jump carry on consistently from it, the jump is accepted
+/// and the track resumes from there.
+fn drop_outliers(points: &[TrackPointData], keep: &mut [bool], max_speed_mps: f64) -> usize {
+    let mut last_kept: Option<usize> = None;
+    // Points rejected since `last_kept`, which may turn out to be the real track
+    let mut pending: Vec<usize> = Vec::new();
+    let mut dropped = 0;
+
+    for i in 0..points.len() {
+        let Some(last) = last_kept else {
+            last_kept = Some(i);
+            continue;
+        };
+        if plausible(&points[last], &points[i], max_speed_mps) {
+            dropped += discard(&mut pending, keep);
+            last_kept = Some(i);
+            continue;
+        }
+
+        // Start a new run unless this point follows on from the current one
+        if pending
+            .last()
+            .is_some_and(|&p| !plausible(&points[p], &points[i], max_speed_mps))
+        {
+            dropped += discard(&mut pending, keep);
+        }
+        pending.push(i);
+
+        if pending.len() >= JUMP_CONFIRMATION_POINTS {
+            last_kept = pending.last().copied();
+            pending.clear();
+        }
+    }
+
+    // A short run at the very end has nothing to confirm it
+    dropped + discard(&mut pending, keep)
+}
+
+/// Mark the pending points as dropped, returning how many there were.
+fn discard(pending: &mut Vec<usize>, keep: &mut [bool]) -> usize {
+    for &p in pending.iter() {
+        keep[p] = false;
+    }
+    let count = pending.len();
+    pending.clear();
+    count
+}
+
+/// Drop points that barely moved from the last kept point. The final point
+/// is always kept so the track still ends where and when the activity did.
+fn drop_stationary(
+    points: &[TrackPointData],
+    keep: &mut [bool],
+    min_movement_meters: f64,
+) -> usize {
+    let Some(final_point) = keep.iter().rposition(|&k| k) else {
+        return 0;
+    };
+    let mut last_kept: Option<usize> = None;
+    let mut dropped = 0;
+
+    for i in 0..=final_point {
+        if !keep[i] {
+            continue;
+        }
+        if let Some(last) = last_kept
+            && i != final_point
+        {
+            let (a, b) = (&points[last], &points[i]);
+            if haversine_distance(a.lat, a.lon, b.lat, b.lon) < min_movement_meters {
+                keep[i] = false;
+                dropped += 1;
+                continue;
+            }
+        }
+        last_kept = Some(i);
+    }
+    dropped
+}
+
+fn retain<T>(values: &mut Vec<T>, keep: &[bool]) {
+    // Sensor arrays are empty when the file had no such data
+    if values.len() != keep.len() {
+        return;
+    }
+    let mut flags = keep.iter();
+    values.retain(|_| *flags.next().unwrap_or(&true));
+}
+
+fn retain_sensor_data(sensor_data: &mut SensorData, keep: &[bool]) {
+    retain(&mut sensor_data.heart_rates, keep);
+    retain(&mut sensor_data.cadences, keep);
+    retain(&mut sensor_data.powers, keep);
+    retain(&mut sensor_data.temperatures, keep);
+}
+
+/// Replace each elevation with the mean of the `window` elevations centered
+/// on it. Points without elevation are left as they are and skipped.
+fn smooth_elevation(points: &mut [TrackPointData], window: usize) {
+    let indices: Vec<usize> = (0..points.len())
+        .filter(|&i| points[i].elevation.is_some())
+        .collect();
+    let raw: Vec<f64> = indices
+        .iter()
+        .filter_map(|&i| points[i].elevation)
+        .collect();
+    let half = window / 2;
+
+    for (n, &i) in indices.iter().enumerate() {
+        let from = n.saturating_sub(half);
+        let to = (n + half + 1).min(raw.len());
+        let slice = &raw[from..to];
+        points[i].elevation = Some(slice.iter().sum::<f64>() / slice.len() as f64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{Duration, OffsetDateTime};
+
+    use super::*;
+
+    /// Points heading north at ~5 m/s, one per second.
+    fn ride(count: usize) -> Vec<TrackPointData> {
+        let start = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        (0..count)
+            .map(|i| TrackPointData {
+                lat: 45.0 + i as f64 * 0.000045,
+                lon: 7.0,
+                elevation: Some(100.0),
+                timestamp: Some(start + Duration::seconds(i as i64)),
+            })
+            .collect()
+    }
+
+    fn parsed(track_points: Vec<TrackPointData>) -> ParsedActivity {
+        let count = track_points.len();
+        ParsedActivity {
+            track_points,
+            sensor_data: SensorData {
+                heart_rates: (0..count).map(|i| Some(i as i32)).collect(),
+                ..Default::default()
+            },
+            sport_segments: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_spike_is_removed() {
+        let mut points = ride(20);
+        // One fix 2 km off to the east
+        points[10].lon += 0.025;
+        let mut activity = parsed(points);
+
+        let report = TrackCleaning::default().clean(&mut activity);
+
+        assert_eq!(report.outliers, 1);
+        assert_eq!(activity.track_points.len(), 19);
+        assert!(activity.track_points.iter().all(|p| p.lon == 7.0));
+        // Sensor data stays aligned with the remaining points
+        assert_eq!(activity.sensor_data.heart_rates.len(), 19);
+        assert_eq!(activity.sensor_data.heart_rates[10], Some(11));
+    }
+
+    #[test]
+    fn test_sustained_jump_is_kept() {
+        let mut points = ride(20);
+        // The receiver was off at the start, then the rest of the ride is 1 km east
+        for p in &mut points[3..] {
+            p.lon += 0.0127;
+        }
+        let mut activity = parsed(points);
+
+        let report = TrackCleaning::default().clean(&mut activity);
+
+        assert_eq!(report.outliers, 0);
+        assert_eq!(activity.track_points.len(), 20);
+    }
+
+    #[test]
+    fn test_stationary_jitter_is_removed() {
+        let mut points = ride(10);
+        // Stand still for 30 s, wandering by under a meter
+        let stop = points[9].clone();
+        let start = stop.timestamp.unwrap();
+        for i in 1..=30 {
+            points.push(TrackPointData {
+                lat: stop.lat + (i % 2) as f64 * 0.000005,
+                timestamp: Some(start + Duration::seconds(i)),
+                ..stop.clone()
+            });
+        }
+        let mut activity = parsed(points);
+
+        let report = TrackCleaning::default().clean(&mut activity);
+
+        assert_eq!(report.stationary, 29);
+        // The last point is kept, so the track still ends at the right time
+        assert_eq!(
+            activity.track_points.last().unwrap().timestamp,
+            Some(start + Duration::seconds(30))
+        );
+    }
+
+    #[test]
+    fn test_elevation_is_smoothed() {
+        let mut points = ride(5);
+        points[2].elevation = Some(110.0);
+        let mut activity = parsed(points);
+
+        TrackCleaning::default().clean(&mut activity);
+
+        // Windows are cut short at either end of the track
+        let expected = [310.0 / 3.0, 102.5, 102.0, 102.5, 310.0 / 3.0];
+        for (point, expected) in activity.track_points.iter().zip(expected) {
+            assert!((point.elevation.unwrap() - expected).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_disabled_leaves_track_alone() {
+        let mut points = ride(20);
+        points[10].lon += 0.025;
+        let mut activity = parsed(points.clone());
+
+        let report = TrackCleaning::disabled().clean(&mut activity);
+
+        assert_eq!(report, CleaningReport::default());
+        assert_eq!(activity.track_points.len(), points.len());
+        assert_eq!(activity.track_points[10].lon, points[10].lon);
+    }
+}
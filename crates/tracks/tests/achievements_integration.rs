@@ -0,0 +1,725 @@
+//! Integration tests for KOM/QOM, course record and Local Legend awards and
+//! the notifications they send.
+//!
+//! Run with: `DATABASE_URL=postgres://... cargo nextest run -p tracks achievements`
+
+mod common;
+
+use common::*;
+use time::{Duration, OffsetDateTime};
+use tracks::achievements_service::{
+    check_and_award_course_record, check_and_award_kom_qom, notify_crown_change,
+    notify_personal_record, process_achievements,
+};
+use tracks::database::{ActivityUpdate, Database};
+use tracks::models::{
+    AchievementCounts, AchievementType, Activity, CrownChange, NotificationType,
+    NotificationWithActor, SegmentEffort, Visibility, builtin_types,
+};
+use uuid::Uuid;
+
+/// Record an effort and run both crown checks on it, as the activity queue does.
+async fn ride(
+    db: &Database,
+    segment_id: Uuid,
+    user_id: Uuid,
+    elapsed_time_seconds: f64,
+) -> (Uuid, Vec<tracks::models::CrownChange>) {
+    let activity =
+        create_activity(db, user_id, builtin_types::ROAD, Visibility::Public, None).await;
+    let started_at = OffsetDateTime::now_utc() - Duration::hours(1);
+    let effort = create_effort(db, segment_id, &activity, started_at, elapsed_time_seconds).await;
+
+    let mut changes = Vec::new();
+    changes.extend(
+        check_and_award_kom_qom(db, segment_id, user_id, effort.id, elapsed_time_seconds)
+            .await
+            .expect("KOM/QOM check failed"),
+    );
+    changes.extend(
+        check_and_award_course_record(db, segment_id, user_id, effort.id, elapsed_time_seconds)
+            .await
+            .expect("Course record check failed"),
+    );
+    (effort.id, changes)
+}
+
+#[tokio::test]
+async fn test_faster_effort_dethrones_course_record_holder() {
+    let Some(pool) = get_test_pool().await else {
+        return;
+    };
+    let db = Database::new(pool.clone());
+
+    let first = create_test_user(&pool, "cr-first", Some("male")).await;
+    // No gender: eligible for the course record, never for KOM/QOM
+    let second = create_test_user(&pool, "cr-second", None).await;
+    let segment_id = create_test_segment(
+        &pool,
+        first,
+        builtin_types::ROAD,
+        (40.0, -105.3),
+        (40.004, -105.296),
+    )
+    .await;
+
+    let (first_effort, changes) = ride(&db, segment_id, first, 120.0).await;
+    assert_eq!(changes.len(), 2, "first effort takes the KOM and the CR");
+    let record = changes
+        .iter()
+        .find(|c| c.achievement_type == AchievementType::CourseRecord)
+        .unwrap();
+    assert_eq!(record.previous_holder_id, None);
+    assert_eq!(record.new_holder_id, Some(first));
+    assert_eq!(record.effort_id, Some(first_effort));
+
+    // Slower doesn't dethrone
+    let (_, changes) = ride(&db, segment_id, second, 130.0).await;
+    assert!(changes.is_empty());
+
+    let (second_effort, changes) = ride(&db, segment_id, second, 110.0).await;
+    assert_eq!(changes.len(), 1, "no KOM/QOM without a gender");
+    let record = &changes[0];
+    assert_eq!(record.achievement_type, AchievementType::CourseRecord);
+    assert_eq!(record.previous_holder_id, Some(first));
+    assert_eq!(record.new_holder_id, Some(second));
+    assert_eq!(record.effort_id, Some(second_effort));
+
+    let holder = db
+        .get_current_achievement_holder(segment_id, AchievementType::CourseRecord)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(holder.user_id, second);
+
+    // The first rider keeps the KOM but lost the CR
+    assert_eq!(
+        db.get_user_achievement_counts(first).await.unwrap(),
+        AchievementCounts {
+            kom_count: 1,
+            ..Default::default()
+        }
+    );
+    let profile = db.get_user_profile(second).await.unwrap().unwrap();
+    assert_eq!(
+        profile.achievements,
+        AchievementCounts {
+            course_record_count: 1,
+            ..Default::default()
+        }
+    );
+
+    cleanup_users(&pool, &[first, second]).await;
+}
+
+#[tokio::test]
+async fn test_equal_time_does_not_dethrone() {
+    let Some(pool) = get_test_pool().await else {
+        return;
+    };
+    let db = Database::new(pool.clone());
+
+    let first = create_test_user(&pool, "tie-first", Some("female")).await;
+    let second = create_test_user(&pool, "tie-second", Some("female")).await;
+    let segment_id = create_test_segment(
+        &pool,
+        first,
+        builtin_types::ROAD,
+        (40.0, -105.3),
+        (40.004, -105.296),
+    )
+    .await;
+
+    ride(&db, segment_id, first, 100.0).await;
+    let (_, changes) = ride(&db, segment_id, second, 100.0).await;
+    assert!(changes.is_empty(), "a tie leaves the earlier holder");
+
+    let (_, changes) = ride(&db, segment_id, second, 99.5).await;
+    let types: Vec<_> = changes.iter().map(|c| c.achievement_type).collect();
+    assert!(types.contains(&AchievementType::Qom));
+    assert!(types.contains(&AchievementType::CourseRecord));
+    assert!(
+        changes
+            .iter()
+            .all(|c| c.previous_holder_id == Some(first) && c.new_holder_id == Some(second))
+    );
+
+    cleanup_users(&pool, &[first, second]).await;
+}
+
+#[tokio::test]
+async fn test_concurrent_efforts_award_one_course_record() {
+    let Some(pool) = get_test_pool().await else {
+        return;
+    };
+    let db = Database::new(pool.clone());
+
+    let mut riders = Vec::new();
+    for i in 0..4 {
+        riders.push(create_test_user(&pool, &format!("cr-race-{i}"), None).await);
+    }
+    let segment_id = create_test_segment(
+        &pool,
+        riders[0],
+        builtin_types::ROAD,
+        (40.0, -105.3),
+        (40.004, -105.296),
+    )
+    .await;
+    let started_at = OffsetDateTime::now_utc() - Duration::hours(1);
+    let mut efforts = Vec::new();
+    for (i, &rider) in riders.iter().enumerate() {
+        let activity =
+            create_activity(&db, rider, builtin_types::ROAD, Visibility::Public, None).await;
+        let elapsed = 100.0 + i as f64;
+        let effort = create_effort(&db, segment_id, &activity, started_at, elapsed).await;
+        efforts.push((rider, effort.id, elapsed));
+    }
+
+    // Every effort is checked at once, as parallel workers would
+    let checks = efforts.iter().map(|&(rider, effort_id, elapsed)| {
+        check_and_award_course_record(&db, segment_id, rider, effort_id, elapsed)
+    });
+    for result in futures_util::future::join_all(checks).await {
+        result.expect("Course record check failed");
+    }
+
+    let holders: Vec<Uuid> = sqlx::query_scalar(
+        r#"
+        SELECT user_id FROM achievements
+        WHERE segment_id = $1 AND achievement_type = 'course_record' AND lost_at IS NULL
+        "#,
+    )
+    .bind(segment_id)
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    assert_eq!(holders, [riders[0]], "one holder, the fastest");
+
+    cleanup_users(&pool, &riders).await;
+}
+
+/// Record `count` efforts by `user_id`, each `age` ago.
+async fn ride_times(db: &Database, segment_id: Uuid, user_id: Uuid, count: usize, age: Duration) {
+    let activity =
+        create_activity(db, user_id, builtin_types::ROAD, Visibility::Public, None).await;
+    for i in 0..count {
+        let started_at = OffsetDateTime::now_utc() - age + Duration::minutes(i as i64);
+        create_effort(db, segment_id, &activity, started_at, 100.0).await;
+    }
+}
+
+#[tokio::test]
+async fn test_local_legend_counts_efforts_inside_window() {
+    let Some(pool) = get_test_pool().await else {
+        return;
+    };
+    let db = Database::new(pool.clone());
+    let window = Duration::days(90);
+
+    let regular = create_test_user(&pool, "ll-regular", None).await;
+    let veteran = create_test_user(&pool, "ll-veteran", None).await;
+    let segment_id = create_test_segment(
+        &pool,
+        regular,
+        builtin_types::ROAD,
+        (40.0, -105.3),
+        (40.004, -105.296),
+    )
+    .await;
+
+    // Old efforts don't count, however many there are
+    ride_times(&db, segment_id, veteran, 5, Duration::days(120)).await;
+    ride_times(&db, segment_id, regular, 2, Duration::days(10)).await;
+
+    let change = db
+        .refresh_local_legend(segment_id, window)
+        .await
+        .unwrap()
+        .expect("first holder is a change");
+    assert_eq!(change.achievement_type, AchievementType::LocalLegend);
+    assert_eq!(change.previous_holder_id, None);
+    assert_eq!(change.new_holder_id, Some(regular));
+
+    let holder = db
+        .get_current_achievement_holder(segment_id, AchievementType::LocalLegend)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(holder.user_id, regular);
+    assert_eq!(holder.effort_count, Some(2));
+
+    // Recomputing with no new efforts changes nothing
+    assert!(
+        db.refresh_local_legend(segment_id, window)
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    // Once every effort ages out, the title is vacated
+    let change = db
+        .refresh_local_legend(segment_id, Duration::days(5))
+        .await
+        .unwrap()
+        .expect("holder loses the title");
+    assert_eq!(change.previous_holder_id, Some(regular));
+    assert_eq!(change.new_holder_id, None);
+
+    cleanup_users(&pool, &[regular, veteran]).await;
+}
+
+#[tokio::test]
+async fn test_local_legend_ties_keep_holder() {
+    let Some(pool) = get_test_pool().await else {
+        return;
+    };
+    let db = Database::new(pool.clone());
+    let window = Duration::days(90);
+
+    let early = create_test_user(&pool, "ll-early", None).await;
+    let late = create_test_user(&pool, "ll-late", None).await;
+    let segment_id = create_test_segment(
+        &pool,
+        early,
+        builtin_types::ROAD,
+        (40.0, -105.3),
+        (40.004, -105.296),
+    )
+    .await;
+
+    // With equal counts and no holder, whoever got there first wins
+    ride_times(&db, segment_id, late, 2, Duration::days(1)).await;
+    ride_times(&db, segment_id, early, 2, Duration::days(20)).await;
+    let change = db
+        .refresh_local_legend(segment_id, window)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(change.new_holder_id, Some(early));
+
+    // Drawing level with the holder isn't enough, passing them is
+    ride_times(&db, segment_id, late, 1, Duration::hours(2)).await;
+    ride_times(&db, segment_id, early, 1, Duration::hours(3)).await;
+    assert!(
+        db.refresh_local_legend(segment_id, window)
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    ride_times(&db, segment_id, late, 1, Duration::hours(1)).await;
+    let change = db
+        .refresh_local_legend(segment_id, window)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(change.previous_holder_id, Some(early));
+    assert_eq!(change.new_holder_id, Some(late));
+
+    cleanup_users(&pool, &[early, late]).await;
+}
+
+async fn notifications(db: &Database, user_id: Uuid) -> Vec<NotificationWithActor> {
+    db.get_notifications(user_id, 50, 0).await.unwrap()
+}
+
+/// Record an effort and announce it as a PR, as the activity queue does.
+async fn ride_and_notify_pr(
+    db: &Database,
+    segment_id: Uuid,
+    activity: &Activity,
+    elapsed_time_seconds: f64,
+) -> SegmentEffort {
+    let started_at = OffsetDateTime::now_utc() - Duration::hours(1);
+    let effort = create_effort(db, segment_id, activity, started_at, elapsed_time_seconds).await;
+    notify_personal_record(
+        db,
+        segment_id,
+        activity.user_id,
+        effort.id,
+        elapsed_time_seconds,
+    )
+    .await
+    .expect("PR notification failed");
+    effort
+}
+
+#[tokio::test]
+async fn test_personal_record_notification() {
+    let Some(pool) = get_test_pool().await else {
+        return;
+    };
+    let db = Database::new(pool.clone());
+
+    let rider = create_test_user(&pool, "pr-rider", None).await;
+    let segment_id = create_test_segment(
+        &pool,
+        rider,
+        builtin_types::ROAD,
+        (40.0, -105.3),
+        (40.004, -105.296),
+    )
+    .await;
+    let activity = create_activity(&db, rider, builtin_types::ROAD, Visibility::Public, None).await;
+    // A first effort isn't announced, nor is one that doesn't improve on it
+    ride_and_notify_pr(&db, segment_id, &activity, 120.0).await;
+    ride_and_notify_pr(&db, segment_id, &activity, 125.0).await;
+    assert!(notifications(&db, rider).await.is_empty());
+
+    // Sub-second margins keep their tenths
+    let effort = ride_and_notify_pr(&db, segment_id, &activity, 119.6).await;
+    let sent = notifications(&db, rider).await;
+    assert_eq!(sent.len(), 1);
+    let notification = &sent[0];
+    assert_eq!(
+        notification.notification_type,
+        NotificationType::PersonalRecord.as_str()
+    );
+    assert_eq!(
+        notification.message.as_deref(),
+        Some("PR on Test Segment, 0.4s faster")
+    );
+    assert_eq!(notification.target_id, Some(segment_id));
+    assert_eq!(notification.segment_name.as_deref(), Some("Test Segment"));
+    assert_eq!(notification.effort_id, Some(effort.id));
+    assert_eq!(notification.effort_activity_id, Some(activity.id));
+    assert_eq!(notification.actor_id, None);
+
+    ride_and_notify_pr(&db, segment_id, &activity, 99.4).await;
+    let sent = notifications(&db, rider).await;
+    assert_eq!(sent.len(), 2);
+    assert_eq!(
+        sent[0].message.as_deref(),
+        Some("PR on Test Segment, 20s faster")
+    );
+
+    cleanup_users(&pool, &[rider]).await;
+}
+
+#[tokio::test]
+async fn test_crown_change_notifications() {
+    let Some(pool) = get_test_pool().await else {
+        return;
+    };
+    let db = Database::new(pool.clone());
+
+    let holder = create_test_user(&pool, "crown-holder", None).await;
+    let challenger = create_test_user(&pool, "crown-challenger", None).await;
+    let segment_id = create_test_segment(
+        &pool,
+        holder,
+        builtin_types::ROAD,
+        (40.0, -105.3),
+        (40.004, -105.296),
+    )
+    .await;
+    let activity = create_activity(
+        &db,
+        challenger,
+        builtin_types::ROAD,
+        Visibility::Public,
+        None,
+    )
+    .await;
+    let effort = create_effort(
+        &db,
+        segment_id,
+        &activity,
+        OffsetDateTime::now_utc() - Duration::hours(1),
+        95.0,
+    )
+    .await;
+
+    // Improving on your own record isn't news
+    let kept = CrownChange {
+        achievement_type: AchievementType::CourseRecord,
+        previous_holder_id: Some(challenger),
+        new_holder_id: Some(challenger),
+        effort_id: Some(effort.id),
+    };
+    notify_crown_change(&db, segment_id, "Test Segment", &kept).await;
+    assert!(notifications(&db, challenger).await.is_empty());
+
+    let taken = CrownChange {
+        previous_holder_id: Some(holder),
+        ..kept
+    };
+    notify_crown_change(&db, segment_id, "Test Segment", &taken).await;
+
+    let lost = notifications(&db, holder).await;
+    assert_eq!(lost.len(), 1);
+    assert_eq!(
+        lost[0].notification_type,
+        NotificationType::CrownLost.as_str()
+    );
+    assert_eq!(lost[0].actor_id, Some(challenger));
+    assert_eq!(
+        lost[0].actor_name.as_deref(),
+        Some("Test User crown-challenger")
+    );
+    assert_eq!(lost[0].effort_id, Some(effort.id));
+    assert_eq!(lost[0].effort_activity_id, Some(activity.id));
+    assert_eq!(lost[0].effort_elapsed_time_seconds, Some(95.0));
+
+    let achieved = notifications(&db, challenger).await;
+    assert_eq!(achieved.len(), 1);
+    assert_eq!(
+        achieved[0].notification_type,
+        NotificationType::CrownAchieved.as_str()
+    );
+    assert_eq!(achieved[0].actor_id, None);
+    assert_eq!(
+        achieved[0].message.as_deref(),
+        Some("Course Record on Test Segment")
+    );
+
+    // Notifications without an effort still point at the segment
+    let created = db
+        .create_effort_notification(
+            holder,
+            NotificationType::CrownLost.as_str(),
+            None,
+            segment_id,
+            None,
+            "Local Legend on Test Segment",
+        )
+        .await
+        .unwrap();
+    assert_eq!(created.target_type.as_deref(), Some("segment"));
+    assert_eq!(created.target_id, Some(segment_id));
+    assert_eq!(created.effort_id, None);
+    assert_eq!(created.read_at, None);
+
+    cleanup_users(&pool, &[holder, challenger]).await;
+}
+
+#[tokio::test]
+async fn test_imported_activities_set_prs_silently() {
+    let Some(pool) = get_test_pool().await else {
+        return;
+    };
+    let db = Database::new(pool.clone());
+    let aq = test_queue(&db);
+
+    let rider = create_test_user(&pool, "pr-import", None).await;
+    let uploaded = create_activity(&db, rider, builtin_types::ROAD, Visibility::Public, None).await;
+    let imported = create_activity(&db, rider, builtin_types::ROAD, Visibility::Public, None).await;
+
+    assert!(aq.submit(rider, uploaded.id).await.unwrap().notify);
+    assert!(!aq.submit_import(rider, imported.id).await.unwrap().notify);
+
+    cleanup_users(&pool, &[rider]).await;
+}
+
+#[tokio::test]
+async fn test_imported_activities_change_crowns_silently() {
+    let Some(pool) = get_test_pool().await else {
+        return;
+    };
+    let db = Database::new(pool.clone());
+
+    let holder = create_test_user(&pool, "crown-import-holder", None).await;
+    let importer = create_test_user(&pool, "crown-import-rider", None).await;
+    let segment_id = create_test_segment(
+        &pool,
+        holder,
+        builtin_types::ROAD,
+        (40.0, -105.3),
+        (40.004, -105.296),
+    )
+    .await;
+    let started_at = OffsetDateTime::now_utc() - Duration::hours(1);
+
+    let uploaded =
+        create_activity(&db, holder, builtin_types::ROAD, Visibility::Public, None).await;
+    let effort = create_effort(&db, segment_id, &uploaded, started_at, 120.0).await;
+    process_achievements(&db, segment_id, holder, effort.id, 120.0, true)
+        .await
+        .unwrap();
+    assert!(!notifications(&db, holder).await.is_empty());
+    sqlx::query("DELETE FROM notifications WHERE user_id = $1")
+        .bind(holder)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    // An imported effort takes the crowns without telling either rider
+    let imported =
+        create_activity(&db, importer, builtin_types::ROAD, Visibility::Public, None).await;
+    let effort = create_effort(&db, segment_id, &imported, started_at, 100.0).await;
+    process_achievements(&db, segment_id, importer, effort.id, 100.0, false)
+        .await
+        .unwrap();
+    let record = db
+        .get_current_achievement_holder(segment_id, AchievementType::CourseRecord)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(record.user_id, importer);
+    assert!(notifications(&db, holder).await.is_empty());
+    assert!(notifications(&db, importer).await.is_empty());
+
+    cleanup_users(&pool, &[holder, importer]).await;
+}
+
+async fn holder_of(
+    db: &Database,
+    segment_id: Uuid,
+    achievement_type: AchievementType,
+) -> Option<Uuid> {
+    db.get_current_achievement_holder(segment_id, achievement_type)
+        .await
+        .unwrap()
+        .map(|holder| holder.user_id)
+}
+
+#[tokio::test]
+async fn test_followers_only_efforts_dont_take_crowns() {
+    let Some(pool) = get_test_pool().await else {
+        return;
+    };
+    let db = Database::new(pool.clone());
+    let window = Duration::days(90);
+
+    let public = create_test_user(&pool, "crown-public", Some("female")).await;
+    let hidden = create_test_user(&pool, "crown-followers", Some("female")).await;
+    let segment_id = create_test_segment(
+        &pool,
+        public,
+        builtin_types::ROAD,
+        (40.0, -105.3),
+        (40.004, -105.296),
+    )
+    .await;
+    let (public_effort, _) = ride(&db, segment_id, public, 120.0).await;
+    db.refresh_local_legend(segment_id, window).await.unwrap();
+
+    // Faster and more often, but followers-only: no crown, no Local Legend
+    let hidden_ride = create_activity(
+        &db,
+        hidden,
+        builtin_types::ROAD,
+        Visibility::Followers,
+        None,
+    )
+    .await;
+    let started_at = OffsetDateTime::now_utc() - Duration::hours(1);
+    let mut hidden_effort = None;
+    for i in 0..3 {
+        let effort = create_effort(
+            &db,
+            segment_id,
+            &hidden_ride,
+            started_at + Duration::minutes(i),
+            100.0 + i as f64,
+        )
+        .await;
+        hidden_effort.get_or_insert(effort.id);
+    }
+    let hidden_effort = hidden_effort.unwrap();
+    assert!(
+        check_and_award_kom_qom(&db, segment_id, hidden, hidden_effort, 100.0)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(
+        check_and_award_course_record(&db, segment_id, hidden, hidden_effort, 100.0)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert!(
+        db.refresh_local_legend(segment_id, window)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert_eq!(
+        holder_of(&db, segment_id, AchievementType::Qom).await,
+        Some(public)
+    );
+    assert_eq!(
+        holder_of(&db, segment_id, AchievementType::CourseRecord).await,
+        Some(public)
+    );
+    assert_eq!(
+        holder_of(&db, segment_id, AchievementType::LocalLegend).await,
+        Some(public)
+    );
+
+    // Making the activity public hands the crowns over
+    let update = ActivityUpdate {
+        visibility: Some(Visibility::Public.as_str()),
+        ..Default::default()
+    };
+    let updated = db.update_activity(hidden_ride.id, &update).await.unwrap();
+    assert_eq!(updated.unwrap().reranked_segments, [segment_id]);
+    assert_eq!(
+        holder_of(&db, segment_id, AchievementType::Qom).await,
+        Some(hidden)
+    );
+    assert_eq!(
+        holder_of(&db, segment_id, AchievementType::CourseRecord).await,
+        Some(hidden)
+    );
+    db.refresh_local_legend(segment_id, window).await.unwrap();
+    assert_eq!(
+        holder_of(&db, segment_id, AchievementType::LocalLegend).await,
+        Some(hidden)
+    );
+    let hidden_record: Uuid = sqlx::query_scalar(
+        "SELECT id FROM achievements WHERE effort_id = $1 AND achievement_type = 'course_record'",
+    )
+    .bind(hidden_effort)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert!(
+        db.get_achievement_holder(hidden_record)
+            .await
+            .unwrap()
+            .is_some()
+    );
+
+    // And making it followers-only again gives them back, without showing the
+    // hidden rider's crown to live leaderboards
+    let update = ActivityUpdate {
+        visibility: Some(Visibility::Followers.as_str()),
+        ..Default::default()
+    };
+    db.update_activity(hidden_ride.id, &update).await.unwrap();
+    let record = db
+        .get_current_achievement_holder(segment_id, AchievementType::CourseRecord)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(record.user_id, public);
+    assert_eq!(record.elapsed_time_seconds, Some(120.0));
+    assert_eq!(
+        holder_of(&db, segment_id, AchievementType::Qom).await,
+        Some(public)
+    );
+    db.refresh_local_legend(segment_id, window).await.unwrap();
+    assert_eq!(
+        holder_of(&db, segment_id, AchievementType::LocalLegend).await,
+        Some(public)
+    );
+    assert!(
+        db.get_achievement_holder(hidden_record)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    let effort_id: Option<Uuid> = sqlx::query_scalar(
+        "SELECT effort_id FROM achievements WHERE segment_id = $1 AND achievement_type = 'course_record' AND lost_at IS NULL",
+    )
+    .bind(segment_id)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(effort_id, Some(public_effort));
+
+    cleanup_users(&pool, &[public, hidden]).await;
+}
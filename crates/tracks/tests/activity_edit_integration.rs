@@ -0,0 +1,288 @@
+//! Integration tests for editing an activity's type and multi-sport parts.
+//!
+//! The handler is called directly with no queue workers running, so the tests
+//! check what an edit leaves for the workers: cleared efforts, a bumped match
+//! generation and a job that will match the activity again.
+//!
+//! Run with: `DATABASE_URL=postgres://... cargo nextest run -p tracks activity_edit`
+
+mod common;
+
+use axum::{Extension, Json, extract::Path};
+use common::*;
+use sqlx::PgPool;
+use time::{Duration, OffsetDateTime};
+use tracks::activity_queue::ActivityQueue;
+use tracks::database::Database;
+use tracks::errors::AppError;
+use tracks::handlers::activities::{UpdateActivityRequest, update_activity};
+use tracks::models::{Activity, JobStatus, Visibility, builtin_types};
+use uuid::Uuid;
+
+async fn edit(
+    db: &Database,
+    aq: &ActivityQueue,
+    editor: Uuid,
+    activity_id: Uuid,
+    body: serde_json::Value,
+) -> Result<Activity, AppError> {
+    let req: UpdateActivityRequest = serde_json::from_value(body).unwrap();
+    update_activity(
+        Extension(db.clone()),
+        Extension(aq.clone()),
+        auth(editor),
+        Path(activity_id),
+        Json(req),
+    )
+    .await
+    .map(|Json(activity)| activity)
+}
+
+async fn effort_count(pool: &PgPool, activity_id: Uuid) -> i64 {
+    sqlx::query_scalar("SELECT COUNT(*) FROM segment_efforts WHERE activity_id = $1")
+        .bind(activity_id)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+/// The live job for an activity, with its rerun flag.
+async fn live_job(pool: &PgPool, activity_id: Uuid) -> Option<(Uuid, JobStatus, bool)> {
+    sqlx::query_as(
+        r#"
+        SELECT id, status, rerun FROM activity_jobs
+        WHERE activity_id = $1 AND status IN ('queued', 'processing')
+        "#,
+    )
+    .bind(activity_id)
+    .fetch_optional(pool)
+    .await
+    .unwrap()
+}
+
+fn rfc3339(t: OffsetDateTime) -> String {
+    t.format(&time::format_description::well_known::Rfc3339)
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_type_change_clears_efforts_and_reruns_running_job() {
+    let Some(pool) = get_test_pool().await else {
+        return;
+    };
+    let db = Database::new(pool.clone());
+    let aq = test_queue(&db);
+
+    let owner = create_test_user(&pool, "edit-type", None).await;
+    let segment_id = create_test_segment(
+        &pool,
+        owner,
+        builtin_types::ROAD,
+        (40.0, -105.3),
+        (40.004, -105.296),
+    )
+    .await;
+    let activity = create_activity(&db, owner, builtin_types::ROAD, Visibility::Public, None).await;
+    let started_at = OffsetDateTime::now_utc() - Duration::hours(1);
+    create_effort(&db, segment_id, &activity, started_at, 120.0).await;
+
+    // A job is part-way through matching the activity as a road ride
+    let job = aq.submit(owner, activity.id).await.unwrap();
+    let claim_token: Uuid = sqlx::query_scalar(
+        r#"
+        UPDATE activity_jobs
+        SET status = 'processing', attempts = 1, claim_token = gen_random_uuid()
+        WHERE id = $1
+        RETURNING claim_token
+        "#,
+    )
+    .bind(job.id)
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    let generation = db
+        .get_activity_match_generation(activity.id)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let updated = edit(
+        &db,
+        &aq,
+        owner,
+        activity.id,
+        serde_json::json!({ "activity_type_id": builtin_types::RUN }),
+    )
+    .await
+    .unwrap();
+    assert_eq!(updated.activity_type_id, builtin_types::RUN);
+    assert_eq!(effort_count(&pool, activity.id).await, 0);
+
+    // The running job is flagged to match again rather than a second job queued
+    assert_eq!(
+        live_job(&pool, activity.id).await,
+        Some((job.id, JobStatus::Processing, true))
+    );
+
+    // Efforts it finds for the road ride are no longer recorded
+    let stale = db
+        .create_segment_effort(
+            segment_id,
+            activity.id,
+            owner,
+            started_at,
+            120.0,
+            Some(120.0),
+            None,
+            None,
+            Some(0.1),
+            Some(0.9),
+            Some(generation),
+        )
+        .await
+        .unwrap();
+    assert!(stale.is_none());
+    assert_eq!(effort_count(&pool, activity.id).await, 0);
+
+    // Finishing puts it back on the queue as a fresh run
+    assert_eq!(
+        db.complete_activity_job(job.id, claim_token).await.unwrap(),
+        Some(true)
+    );
+    assert_eq!(
+        live_job(&pool, activity.id).await,
+        Some((job.id, JobStatus::Queued, false))
+    );
+
+    cleanup_users(&pool, &[owner]).await;
+}
+
+#[tokio::test]
+async fn test_multi_sport_boundary_edit_rematches() {
+    let Some(pool) = get_test_pool().await else {
+        return;
+    };
+    let db = Database::new(pool.clone());
+    let aq = test_queue(&db);
+
+    let owner = create_test_user(&pool, "edit-parts", None).await;
+    let segment_id = create_test_segment(
+        &pool,
+        owner,
+        builtin_types::ROAD,
+        (40.0, -105.3),
+        (40.004, -105.296),
+    )
+    .await;
+    // Whole seconds, so boundaries survive the round trip through Postgres
+    let start = OffsetDateTime::now_utc().replace_nanosecond(0).unwrap() - Duration::hours(2);
+    let types = vec![builtin_types::ROAD, builtin_types::RUN];
+    let boundaries = vec![
+        start,
+        start + Duration::minutes(30),
+        start + Duration::hours(1),
+    ];
+    let activity = create_activity(
+        &db,
+        owner,
+        builtin_types::ROAD,
+        Visibility::Public,
+        Some((boundaries.clone(), types.clone())),
+    )
+    .await;
+    create_effort(&db, segment_id, &activity, start, 300.0).await;
+
+    // Renaming doesn't touch the efforts
+    edit(
+        &db,
+        &aq,
+        owner,
+        activity.id,
+        serde_json::json!({ "name": "Brick session" }),
+    )
+    .await
+    .unwrap();
+    assert_eq!(effort_count(&pool, activity.id).await, 1);
+    assert_eq!(live_job(&pool, activity.id).await, None);
+
+    let moved = vec![
+        rfc3339(boundaries[0]),
+        rfc3339(boundaries[1] - Duration::minutes(10)),
+        rfc3339(boundaries[2]),
+    ];
+    let updated = edit(
+        &db,
+        &aq,
+        owner,
+        activity.id,
+        serde_json::json!({ "type_boundaries": moved, "segment_types": types }),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        updated.type_boundaries.as_ref().unwrap()[1],
+        boundaries[1] - Duration::minutes(10)
+    );
+    assert_eq!(effort_count(&pool, activity.id).await, 0);
+    assert_eq!(
+        db.get_activity_match_generation(activity.id).await.unwrap(),
+        Some(1)
+    );
+    assert!(matches!(
+        live_job(&pool, activity.id).await,
+        Some((_, JobStatus::Queued, false))
+    ));
+
+    cleanup_users(&pool, &[owner]).await;
+}
+
+#[tokio::test]
+async fn test_only_owner_and_admins_can_edit() {
+    let Some(pool) = get_test_pool().await else {
+        return;
+    };
+    let db = Database::new(pool.clone());
+    let aq = test_queue(&db);
+
+    let owner = create_test_user(&pool, "edit-owner", None).await;
+    let stranger = create_test_user(&pool, "edit-stranger", None).await;
+    let admin = create_test_user(&pool, "edit-admin", None).await;
+    make_admin(&pool, admin).await;
+    let activity = create_activity(&db, owner, builtin_types::ROAD, Visibility::Public, None).await;
+
+    let result = edit(
+        &db,
+        &aq,
+        stranger,
+        activity.id,
+        serde_json::json!({ "name": "Mine now" }),
+    )
+    .await;
+    assert!(matches!(result, Err(AppError::NotFound)));
+    let unchanged = db.get_activity(activity.id).await.unwrap().unwrap();
+    assert_eq!(unchanged.name, activity.name);
+
+    let renamed = edit(
+        &db,
+        &aq,
+        admin,
+        activity.id,
+        serde_json::json!({ "name": "Moderated" }),
+    )
+    .await
+    .unwrap();
+    assert_eq!(renamed.name, "Moderated");
+
+    let renamed = edit(
+        &db,
+        &aq,
+        owner,
+        activity.id,
+        serde_json::json!({ "name": "Evening Ride" }),
+    )
+    .await
+    .unwrap();
+    assert_eq!(renamed.name, "Evening Ride");
+
+    cleanup_users(&pool, &[owner, stranger, admin]).await;
+}
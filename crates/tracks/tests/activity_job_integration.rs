@@ -0,0 +1,126 @@
+//! Integration tests for activity processing jobs: claims, the reaper and
+//! retries.
+//!
+//! Run with: `DATABASE_URL=postgres://... cargo nextest run -p tracks activity_job`
+
+mod common;
+
+use common::*;
+use sqlx::PgPool;
+use time::Duration;
+use tracks::database::{Database, ProcessingErrorRecord};
+use tracks::models::{JobStatus, ProcessingErrorKind, ProcessingStage, Visibility, builtin_types};
+use uuid::Uuid;
+
+/// Jump the queue, in case the database holds other runnable jobs.
+async fn run_first(pool: &PgPool, job_id: Uuid) {
+    sqlx::query("UPDATE activity_jobs SET run_after = NOW() - INTERVAL '100 years' WHERE id = $1")
+        .bind(job_id)
+        .execute(pool)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_activity_job_reclaim_and_retry() {
+    let Some(pool) = get_test_pool().await else {
+        return;
+    };
+    let db = Database::new(pool.clone());
+
+    let owner = create_test_user(&pool, "activity-job-reclaim", None).await;
+    let activity = create_activity(&db, owner, builtin_types::ROAD, Visibility::Public, None).await;
+
+    let job = db
+        .enqueue_activity_job(activity.id, owner, true)
+        .await
+        .unwrap();
+    // One live job per activity
+    let again = db
+        .enqueue_activity_job(activity.id, owner, true)
+        .await
+        .unwrap();
+    assert_eq!(again.id, job.id);
+
+    run_first(&pool, job.id).await;
+    let first = db.claim_activity_job().await.unwrap().unwrap();
+    assert_eq!(first.job.id, job.id);
+    assert_eq!(first.job.status, JobStatus::Processing);
+    assert!(
+        db.set_activity_job_stage(job.id, first.claim_token, ProcessingStage::Parsing)
+            .await
+            .unwrap()
+    );
+
+    // The first worker stalls; the reaper requeues the job and another worker claims it
+    sqlx::query("UPDATE activity_jobs SET locked_at = NOW() - INTERVAL '1 hour' WHERE id = $1")
+        .bind(job.id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    assert!(
+        db.requeue_stale_activity_jobs(Duration::minutes(10))
+            .await
+            .unwrap()
+            >= 1
+    );
+    run_first(&pool, job.id).await;
+    let second = db.claim_activity_job().await.unwrap().unwrap();
+    assert_eq!(second.job.id, job.id);
+    assert_ne!(second.claim_token, first.claim_token);
+
+    // The stalled worker can neither refresh, complete nor fail the job
+    let error = ProcessingErrorRecord {
+        kind: ProcessingErrorKind::Database,
+        message: "Could not save the track",
+        detail: None,
+    };
+    assert!(
+        !db.heartbeat_activity_job(job.id, first.claim_token)
+            .await
+            .unwrap()
+    );
+    assert!(
+        !db.set_activity_job_stage(job.id, first.claim_token, ProcessingStage::MatchingSegments)
+            .await
+            .unwrap()
+    );
+    assert_eq!(
+        db.complete_activity_job(job.id, first.claim_token)
+            .await
+            .unwrap(),
+        None
+    );
+    assert_eq!(
+        db.fail_activity_job(job.id, first.claim_token, &error, None)
+            .await
+            .unwrap(),
+        None
+    );
+    let latest = db
+        .get_latest_activity_job(activity.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(latest.status, JobStatus::Processing);
+
+    // The new claim fails the job for good, and a retry starts it over afresh
+    assert!(
+        db.heartbeat_activity_job(job.id, second.claim_token)
+            .await
+            .unwrap()
+    );
+    assert_eq!(
+        db.fail_activity_job(job.id, second.claim_token, &error, None)
+            .await
+            .unwrap(),
+        Some(ProcessingStage::Parsing)
+    );
+    let retried = db.retry_activity_job(job.id, owner).await.unwrap().unwrap();
+    assert_eq!(retried.status, JobStatus::Queued);
+    assert_eq!(retried.attempts, 0);
+    assert_eq!(retried.last_error, None);
+    assert_eq!(retried.last_error_kind, None);
+
+    cleanup_users(&pool, &[owner]).await;
+}
@@ -0,0 +1,106 @@
+//! Integration tests for who may see an activity under each visibility, both
+//! when opening it and in activity listings.
+//!
+//! Run with: `DATABASE_URL=postgres://... cargo nextest run -p tracks activity_visibility`
+
+mod common;
+
+use common::*;
+use tracks::database::Database;
+use tracks::handlers::UserActivitiesQuery;
+use tracks::handlers::activities::can_view_activity;
+use tracks::models::{TeamJoinPolicy, TeamRole, TeamVisibility, Visibility, builtin_types};
+use uuid::Uuid;
+
+#[tokio::test]
+async fn test_visibility_by_viewer() {
+    let Some(pool) = get_test_pool().await else {
+        return;
+    };
+    let db = Database::new(pool.clone());
+
+    let owner = create_test_user(&pool, "visibility-owner", None).await;
+    let follower = create_test_user(&pool, "visibility-follower", None).await;
+    let stranger = create_test_user(&pool, "visibility-stranger", None).await;
+    let teammate = create_test_user(&pool, "visibility-teammate", None).await;
+    follow(&db, follower, owner).await;
+
+    let team = db
+        .create_team(
+            "Visibility Test Team",
+            None,
+            None,
+            TeamVisibility::Private,
+            TeamJoinPolicy::Invitation,
+            owner,
+        )
+        .await
+        .unwrap();
+    db.add_team_member(team.id, owner, TeamRole::Owner, None)
+        .await
+        .unwrap();
+    db.add_team_member(team.id, teammate, TeamRole::Member, Some(owner))
+        .await
+        .unwrap();
+
+    let mut activities = Vec::new();
+    for visibility in [
+        Visibility::Public,
+        Visibility::Followers,
+        Visibility::Private,
+        Visibility::TeamsOnly,
+    ] {
+        let activity = create_activity(&db, owner, builtin_types::ROAD, visibility, None).await;
+        if visibility == Visibility::TeamsOnly {
+            db.share_activity_with_teams(activity.id, &[team.id], owner)
+                .await
+                .unwrap();
+        }
+        activities.push((visibility, activity));
+    }
+
+    // Which of public, followers, private and teams_only each viewer sees
+    let viewers = [
+        ("owner", Some(owner), [true, true, true, true]),
+        ("follower", Some(follower), [true, true, false, false]),
+        ("stranger", Some(stranger), [true, false, false, false]),
+        ("teammate", Some(teammate), [true, false, false, true]),
+        ("anonymous", None, [true, false, false, false]),
+    ];
+    let query: UserActivitiesQuery = serde_json::from_value(serde_json::json!({})).unwrap();
+
+    for (name, viewer, expected) in viewers {
+        let listed: Vec<Uuid> = db
+            .get_user_activities_filtered(owner, viewer, &query)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|a| a.id)
+            .collect();
+
+        for ((visibility, activity), visible) in activities.iter().zip(expected) {
+            let can_view = can_view_activity(&db, viewer, activity.id, owner, visibility.as_str())
+                .await
+                .unwrap();
+            assert_eq!(
+                can_view,
+                visible,
+                "{name} opening a {} activity",
+                visibility.as_str()
+            );
+            assert_eq!(
+                listed.contains(&activity.id),
+                visible,
+                "{name} listing a {} activity",
+                visibility.as_str()
+            );
+        }
+    }
+
+    sqlx::query("DELETE FROM teams WHERE id = $1")
+        .bind(team.id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    cleanup_users(&pool, &[owner, follower, stranger, teammate]).await;
+}
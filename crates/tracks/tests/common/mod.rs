@@ -0,0 +1,227 @@
+//! Helpers shared by the database integration tests.
+//!
+//! Every test creates its own users with unique IDs and deletes them when it
+//! is done; deleting a user cascades to their activities, segments, efforts
+//! and achievements, so the tests can run against a development database.
+
+#![allow(dead_code)]
+
+use sqlx::{PgPool, postgres::PgPoolOptions};
+use std::env;
+use time::OffsetDateTime;
+use tracks::activity_queue::ActivityQueue;
+use tracks::auth::{AuthUser, Claims};
+use tracks::database::Database;
+use tracks::models::{Activity, SegmentEffort, TrackPointData, Visibility};
+use tracks::object_store_service::ObjectStoreService;
+use uuid::Uuid;
+
+/// Get database pool, skipping tests if DATABASE_URL is not set.
+/// Runs migrations to ensure the schema is up to date.
+pub async fn get_test_pool() -> Option<PgPool> {
+    let database_url = match env::var("DATABASE_URL") {
+        Ok(url) => url,
+        Err(_) => {
+            eprintln!("Skipping test: DATABASE_URL not set");
+            return None;
+        }
+    };
+
+    let pool = match PgPoolOptions::new()
+        .max_connections(4)
+        .connect(&database_url)
+        .await
+    {
+        Ok(pool) => pool,
+        Err(e) => {
+            eprintln!("Skipping test: Failed to connect to database: {e}");
+            return None;
+        }
+    };
+
+    sqlx::migrate!("./migrations")
+        .run(&pool)
+        .await
+        .expect("Failed to run migrations");
+
+    Some(pool)
+}
+
+/// Create a test user, with a gender when given one.
+pub async fn create_test_user(pool: &PgPool, test_id: &str, gender: Option<&str>) -> Uuid {
+    let user_id = Uuid::new_v4();
+    sqlx::query(
+        r#"
+        INSERT INTO users (id, name, email, password_hash, auth_provider, created_at, gender)
+        VALUES ($1, $2, $3, 'hash', 'email', NOW(), $4::gender)
+        "#,
+    )
+    .bind(user_id)
+    .bind(format!("Test User {test_id}"))
+    .bind(format!("test-{test_id}-{user_id}@example.com"))
+    .bind(gender)
+    .execute(pool)
+    .await
+    .expect("Failed to create test user");
+
+    user_id
+}
+
+/// Authenticate as a user when calling a handler directly.
+pub fn auth(user_id: Uuid) -> AuthUser {
+    AuthUser(Claims {
+        sub: user_id,
+        email: format!("{user_id}@example.com"),
+        exp: i64::MAX,
+        iat: 0,
+    })
+}
+
+/// An activity queue storing files in a temporary directory. No workers are
+/// started, so queued jobs stay queued.
+pub fn test_queue(db: &Database) -> ActivityQueue {
+    let path = std::env::temp_dir().join(format!("tracks-test-{}", Uuid::new_v4()));
+    let store = ObjectStoreService::new_local(path.to_string_lossy().into_owned());
+    ActivityQueue::new(db.clone(), store).expect("Failed to create queue")
+}
+
+/// Make a user a site admin.
+pub async fn make_admin(pool: &PgPool, user_id: Uuid) {
+    sqlx::query("UPDATE users SET is_admin = TRUE WHERE id = $1")
+        .bind(user_id)
+        .execute(pool)
+        .await
+        .expect("Failed to make user an admin");
+}
+
+/// Make `follower` follow `following`.
+pub async fn follow(db: &Database, follower: Uuid, following: Uuid) {
+    db.follow_user(follower, following)
+        .await
+        .expect("Failed to follow user");
+}
+
+/// Create an activity; `parts` makes it a multi-sport activity.
+pub async fn create_activity(
+    db: &Database,
+    user_id: Uuid,
+    activity_type_id: Uuid,
+    visibility: Visibility,
+    parts: Option<(Vec<OffsetDateTime>, Vec<Uuid>)>,
+) -> Activity {
+    let now = OffsetDateTime::now_utc();
+    let (type_boundaries, segment_types) = parts.unzip();
+    let activity = Activity {
+        id: Uuid::new_v4(),
+        user_id,
+        activity_type_id,
+        name: "Test Activity".to_string(),
+        object_store_path: format!("test/{}.gpx", Uuid::new_v4()),
+        started_at: now,
+        submitted_at: now,
+        visibility: visibility.as_str().to_string(),
+        type_boundaries,
+        segment_types,
+    };
+    db.save_activity(&activity, None)
+        .await
+        .expect("Failed to save activity");
+    activity
+}
+
+/// Create a straight segment between two points.
+pub async fn create_test_segment(
+    pool: &PgPool,
+    creator_id: Uuid,
+    activity_type_id: Uuid,
+    start: (f64, f64),
+    end: (f64, f64),
+) -> Uuid {
+    let segment_id = Uuid::new_v4();
+    let ((start_lat, start_lon), (end_lat, end_lon)) = (start, end);
+
+    sqlx::query(
+        r#"
+        INSERT INTO segments (
+            id, creator_id, name, activity_type_id,
+            geo, start_point, end_point,
+            distance_meters, visibility, created_at
+        )
+        VALUES (
+            $1, $2, 'Test Segment', $3,
+            ST_GeogFromText($4), ST_GeogFromText($5), ST_GeogFromText($6),
+            ST_Length(ST_GeogFromText($4)), 'public', NOW()
+        )
+        "#,
+    )
+    .bind(segment_id)
+    .bind(creator_id)
+    .bind(activity_type_id)
+    .bind(format!(
+        "LINESTRING Z({start_lon} {start_lat} 1650, {end_lon} {end_lat} 1660)"
+    ))
+    .bind(format!("POINT({start_lon} {start_lat})"))
+    .bind(format!("POINT({end_lon} {end_lat})"))
+    .execute(pool)
+    .await
+    .expect("Failed to create test segment");
+
+    segment_id
+}
+
+/// A track heading north-east from (40.0, -105.3), one point a minute.
+pub fn straight_track(start: OffsetDateTime, points: usize) -> Vec<TrackPointData> {
+    (0..points)
+        .map(|i| TrackPointData {
+            lat: 40.0 + i as f64 * 0.0001,
+            lon: -105.3 + i as f64 * 0.0001,
+            elevation: Some(1650.0 + i as f64 * 0.5),
+            timestamp: Some(start + time::Duration::minutes(i as i64)),
+        })
+        .collect()
+}
+
+/// Store an activity's track.
+pub async fn save_track(db: &Database, activity: &Activity, points: &[TrackPointData]) {
+    db.save_track_geometry_with_data(activity.user_id, activity.id, points)
+        .await
+        .expect("Failed to save track");
+}
+
+/// Record an effort on a segment.
+pub async fn create_effort(
+    db: &Database,
+    segment_id: Uuid,
+    activity: &Activity,
+    started_at: OffsetDateTime,
+    elapsed_time_seconds: f64,
+) -> SegmentEffort {
+    db.create_segment_effort(
+        segment_id,
+        activity.id,
+        activity.user_id,
+        started_at,
+        elapsed_time_seconds,
+        Some(elapsed_time_seconds),
+        None,
+        None,
+        Some(0.1),
+        Some(0.9),
+        None,
+    )
+    .await
+    .expect("Failed to create segment effort")
+    .expect("Unconditional efforts are always created")
+}
+
+/// Delete test users and, through cascades, everything they created.
+pub async fn cleanup_users(pool: &PgPool, user_ids: &[Uuid]) {
+    let _ = sqlx::query("DELETE FROM activity_types WHERE created_by = ANY($1)")
+        .bind(user_ids)
+        .execute(pool)
+        .await;
+    let _ = sqlx::query("DELETE FROM users WHERE id = ANY($1)")
+        .bind(user_ids)
+        .execute(pool)
+        .await;
+}
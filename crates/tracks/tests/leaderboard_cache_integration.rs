@@ -0,0 +1,222 @@
+//! Integration tests for segment leaderboards served from the leaderboard
+//! cache.
+//!
+//! Run with: `DATABASE_URL=postgres://... cargo nextest run -p tracks leaderboard_cache`
+
+mod common;
+
+use common::*;
+use sqlx::PgPool;
+use time::{Duration, OffsetDateTime};
+use tracks::database::{ActivityUpdate, Database};
+use tracks::models::{LeaderboardFilters, Visibility, builtin_types};
+use uuid::Uuid;
+
+async fn cached_entry_count(pool: &PgPool, segment_id: Uuid) -> Option<i32> {
+    sqlx::query_scalar(
+        r#"
+        SELECT entry_count FROM leaderboard_cache
+        WHERE segment_id = $1 AND scope = 'all_time' AND gender IS NULL AND age_group IS NULL
+        "#,
+    )
+    .bind(segment_id)
+    .fetch_optional(pool)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn test_cached_leaderboard_follows_effort_changes() {
+    let Some(pool) = get_test_pool().await else {
+        return;
+    };
+    let db = Database::new(pool.clone());
+
+    let fast = create_test_user(&pool, "cache-fast", None).await;
+    let slow = create_test_user(&pool, "cache-slow", None).await;
+    let late = create_test_user(&pool, "cache-late", None).await;
+    let segment_id = create_test_segment(
+        &pool,
+        fast,
+        builtin_types::ROAD,
+        (40.0, -105.3),
+        (40.004, -105.296),
+    )
+    .await;
+    let started_at = OffsetDateTime::now_utc() - Duration::hours(1);
+    let fast_ride = create_activity(&db, fast, builtin_types::ROAD, Visibility::Public, None).await;
+    let slow_ride = create_activity(&db, slow, builtin_types::ROAD, Visibility::Public, None).await;
+    create_effort(&db, segment_id, &fast_ride, started_at, 100.0).await;
+    create_effort(&db, segment_id, &slow_ride, started_at, 130.0).await;
+
+    // The first request ranks and caches the leaderboard
+    let filters = LeaderboardFilters {
+        limit: 10,
+        ..Default::default()
+    };
+    let (entries, total) = db
+        .get_filtered_leaderboard(segment_id, &filters)
+        .await
+        .unwrap();
+    assert_eq!(total, 2);
+    assert_eq!(cached_entry_count(&pool, segment_id).await, Some(2));
+    assert_eq!(entries[0].user_id, fast);
+    assert_eq!(entries[1].gap_seconds, Some(30.0));
+
+    // A new effort is ranked into the cached leaderboard, a tie going to the
+    // earlier effort
+    let late_ride = create_activity(&db, late, builtin_types::ROAD, Visibility::Public, None).await;
+    create_effort(
+        &db,
+        segment_id,
+        &late_ride,
+        started_at + Duration::minutes(5),
+        100.0,
+    )
+    .await;
+    assert_eq!(cached_entry_count(&pool, segment_id).await, Some(3));
+    let (entries, total) = db
+        .get_filtered_leaderboard(segment_id, &filters)
+        .await
+        .unwrap();
+    assert_eq!(total, 3);
+    let riders: Vec<_> = entries.iter().map(|e| (e.user_id, e.rank)).collect();
+    assert_eq!(riders, [(fast, 1), (late, 2), (slow, 3)]);
+    assert_eq!(entries[1].gap_seconds, Some(0.0));
+
+    // Pages and positions are read from the middle of the index
+    let second_page = LeaderboardFilters {
+        limit: 1,
+        offset: 1,
+        ..Default::default()
+    };
+    let (entries, _) = db
+        .get_filtered_leaderboard(segment_id, &second_page)
+        .await
+        .unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!((entries[0].user_id, entries[0].rank), (late, 2));
+    let (user_entry, above, below, total) = db
+        .get_user_leaderboard_position(segment_id, slow, &filters, 1)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(user_entry.rank, 3);
+    assert_eq!(above.len(), 1);
+    assert!(below.is_empty());
+    assert_eq!(total, 3);
+
+    // Names are read at request time
+    sqlx::query("UPDATE users SET name = 'Renamed Rider' WHERE id = $1")
+        .bind(fast)
+        .execute(&pool)
+        .await
+        .unwrap();
+    let (entries, _) = db
+        .get_filtered_leaderboard(segment_id, &filters)
+        .await
+        .unwrap();
+    assert_eq!(entries[0].user_name, "Renamed Rider");
+
+    // Deleting an activity takes its effort off and moves the rest up
+    assert!(db.delete_activity(fast_ride.id).await.unwrap());
+    assert_eq!(cached_entry_count(&pool, segment_id).await, Some(2));
+    let (entries, total) = db
+        .get_filtered_leaderboard(segment_id, &filters)
+        .await
+        .unwrap();
+    assert_eq!(total, 2);
+    let riders: Vec<_> = entries.iter().map(|e| (e.user_id, e.rank)).collect();
+    assert_eq!(riders, [(late, 1), (slow, 2)]);
+    assert_eq!(entries[0].gap_seconds, None);
+
+    cleanup_users(&pool, &[fast, slow, late]).await;
+}
+
+#[tokio::test]
+async fn test_followers_only_efforts_dont_rank() {
+    let Some(pool) = get_test_pool().await else {
+        return;
+    };
+    let db = Database::new(pool.clone());
+
+    let public = create_test_user(&pool, "rank-public", None).await;
+    let private = create_test_user(&pool, "rank-private", None).await;
+    let hidden = create_test_user(&pool, "rank-followers", None).await;
+    let segment_id = create_test_segment(
+        &pool,
+        public,
+        builtin_types::ROAD,
+        (40.0, -105.3),
+        (40.004, -105.296),
+    )
+    .await;
+    let started_at = OffsetDateTime::now_utc() - Duration::hours(1);
+    let public_ride =
+        create_activity(&db, public, builtin_types::ROAD, Visibility::Public, None).await;
+    let private_ride =
+        create_activity(&db, private, builtin_types::ROAD, Visibility::Private, None).await;
+    let hidden_ride = create_activity(
+        &db,
+        hidden,
+        builtin_types::ROAD,
+        Visibility::Followers,
+        None,
+    )
+    .await;
+    create_effort(&db, segment_id, &public_ride, started_at, 120.0).await;
+    create_effort(&db, segment_id, &private_ride, started_at, 110.0).await;
+    create_effort(&db, segment_id, &hidden_ride, started_at, 100.0).await;
+
+    let filters = LeaderboardFilters {
+        limit: 10,
+        ..Default::default()
+    };
+    let riders = |entries: Vec<tracks::models::LeaderboardEntry>| -> Vec<Uuid> {
+        entries.into_iter().map(|e| e.user_id).collect()
+    };
+    let (entries, total) = db
+        .get_filtered_leaderboard(segment_id, &filters)
+        .await
+        .unwrap();
+    assert_eq!((riders(entries), total), (vec![private, public], 2));
+    let efforts = db.get_segment_efforts(segment_id, 10).await.unwrap();
+    assert_eq!(efforts.len(), 2);
+
+    // Making the activity public ranks its effort on the cached leaderboard
+    let update = ActivityUpdate {
+        visibility: Some(Visibility::Public.as_str()),
+        ..Default::default()
+    };
+    db.update_activity(hidden_ride.id, &update).await.unwrap();
+    let (entries, total) = db
+        .get_filtered_leaderboard(segment_id, &filters)
+        .await
+        .unwrap();
+    assert_eq!((riders(entries), total), (vec![hidden, private, public], 3));
+
+    // Making it private keeps it ranked
+    let update = ActivityUpdate {
+        visibility: Some(Visibility::Private.as_str()),
+        ..Default::default()
+    };
+    let updated = db.update_activity(hidden_ride.id, &update).await.unwrap();
+    assert!(updated.unwrap().reranked_segments.is_empty());
+    assert_eq!(cached_entry_count(&pool, segment_id).await, Some(3));
+
+    // And making it followers-only takes it off
+    let update = ActivityUpdate {
+        visibility: Some(Visibility::Followers.as_str()),
+        ..Default::default()
+    };
+    let updated = db.update_activity(hidden_ride.id, &update).await.unwrap();
+    assert_eq!(updated.unwrap().reranked_segments, [segment_id]);
+    assert_eq!(cached_entry_count(&pool, segment_id).await, Some(2));
+    let (entries, _) = db
+        .get_filtered_leaderboard(segment_id, &filters)
+        .await
+        .unwrap();
+    assert_eq!(riders(entries), [private, public]);
+
+    cleanup_users(&pool, &[public, private, hidden]).await;
+}
@@ -20,6 +20,7 @@ use std::env;
 use time::{Duration, OffsetDateTime};
 use tracks::database::Database;
 use tracks::models::{Activity, Visibility, builtin_types};
+use tracks::segment_matching::DEFAULT_MATCH_TOLERANCE_METERS;
 use uuid::Uuid;
 
 /// Get database pool, skipping tests if DATABASE_URL is not set.
@@ -120,7 +121,7 @@ async fn create_single_sport_activity(db: &Database, user_id: Uuid) -> Activity
         type_boundaries: None,
         segment_types: None,
     };
-    db.save_activity(&activity)
+    db.save_activity(&activity, None)
         .await
         .expect("Failed to save activity");
     activity
@@ -146,7 +147,7 @@ async fn create_multi_sport_activity(
         type_boundaries: Some(boundaries),
         segment_types: Some(segment_types),
     };
-    db.save_activity(&activity)
+    db.save_activity(&activity, None)
         .await
         .expect("Failed to save activity");
     activity
@@ -291,7 +292,7 @@ async fn test_single_sport_activity_with_builtin_types() {
             segment_types: None,
         };
 
-        db.save_activity(&activity)
+        db.save_activity(&activity, None)
             .await
             .expect("Failed to save activity");
 
@@ -414,7 +415,7 @@ async fn test_create_custom_activity_type() {
     let type_name = format!("custom_type_{}", Uuid::new_v4().simple());
 
     let custom_type = db
-        .create_activity_type(&type_name, user_id)
+        .create_activity_type(&type_name, user_id, DEFAULT_MATCH_TOLERANCE_METERS)
         .await
         .expect("Failed to create custom type");
 
@@ -437,7 +438,7 @@ async fn test_create_custom_activity_type() {
         segment_types: None,
     };
 
-    db.save_activity(&activity)
+    db.save_activity(&activity, None)
         .await
         .expect("Failed to save activity with custom type");
 
@@ -464,7 +465,7 @@ async fn test_custom_type_in_multi_sport() {
     let type_name = format!("dig_{}", Uuid::new_v4().simple());
 
     let custom_type = db
-        .create_activity_type(&type_name, user_id)
+        .create_activity_type(&type_name, user_id, DEFAULT_MATCH_TOLERANCE_METERS)
         .await
         .expect("Failed to create custom type");
 
@@ -508,7 +509,7 @@ async fn test_list_activity_types_includes_custom() {
 
     // Create a custom type
     let custom_type = db
-        .create_activity_type(&type_name, user_id)
+        .create_activity_type(&type_name, user_id, DEFAULT_MATCH_TOLERANCE_METERS)
         .await
         .expect("Failed to create custom type");
 
@@ -813,7 +814,7 @@ async fn test_activity_with_empty_arrays() {
         segment_types: Some(vec![]),   // Empty but present
     };
 
-    db.save_activity(&activity)
+    db.save_activity(&activity, None)
         .await
         .expect("Failed to save activity");
 
@@ -857,7 +858,7 @@ async fn test_get_user_activities_returns_started_at() {
         segment_types: None,
     };
 
-    db.save_activity(&activity)
+    db.save_activity(&activity, None)
         .await
         .expect("Failed to save activity");
 
@@ -0,0 +1,198 @@
+//! Integration tests for segment backfill jobs and who may manage them.
+//!
+//! Run with: `DATABASE_URL=postgres://... cargo nextest run -p tracks segment_backfill`
+
+mod common;
+
+use axum::{Extension, extract::Path, http::StatusCode};
+use common::*;
+use sqlx::PgPool;
+use time::Duration;
+use tracks::database::Database;
+use tracks::errors::AppError;
+use tracks::handlers::segments::{get_segment_backfill, reprocess_segment};
+use tracks::models::{JobStatus, builtin_types};
+use tracks::segment_backfill::SegmentBackfillQueue;
+use uuid::Uuid;
+
+async fn test_segment(pool: &PgPool, creator: Uuid) -> Uuid {
+    create_test_segment(
+        pool,
+        creator,
+        builtin_types::ROAD,
+        (40.0, -105.3),
+        (40.004, -105.296),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn test_backfill_lifecycle_and_reclaim() {
+    let Some(pool) = get_test_pool().await else {
+        return;
+    };
+    let db = Database::new(pool.clone());
+
+    let creator = create_test_user(&pool, "backfill-lifecycle", None).await;
+    let segment_id = test_segment(&pool, creator).await;
+
+    let job = db
+        .enqueue_segment_backfill(segment_id, creator)
+        .await
+        .unwrap();
+    assert_eq!(job.status, JobStatus::Queued);
+    // One live backfill per segment
+    let again = db
+        .enqueue_segment_backfill(segment_id, creator)
+        .await
+        .unwrap();
+    assert_eq!(again.id, job.id);
+
+    // Jump the queue, in case the database holds other queued backfills
+    sqlx::query(
+        "UPDATE segment_backfill_jobs SET created_at = NOW() - INTERVAL '100 years' WHERE id = $1",
+    )
+    .bind(job.id)
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    let first = db.claim_segment_backfill().await.unwrap().unwrap();
+    assert_eq!(first.job.id, job.id);
+    assert_eq!(first.job.status, JobStatus::Processing);
+    assert!(
+        db.update_segment_backfill_progress(job.id, first.claim_token, 40, 20, 3, 1)
+            .await
+            .unwrap()
+    );
+
+    // The first worker stalls; the reaper requeues the job and another worker claims it
+    sqlx::query(
+        "UPDATE segment_backfill_jobs SET locked_at = NOW() - INTERVAL '1 hour' WHERE id = $1",
+    )
+    .bind(job.id)
+    .execute(&pool)
+    .await
+    .unwrap();
+    assert!(
+        db.requeue_stale_segment_backfills(Duration::minutes(10))
+            .await
+            .unwrap()
+            >= 1
+    );
+    let second = db.claim_segment_backfill().await.unwrap().unwrap();
+    assert_eq!(second.job.id, job.id);
+    assert_ne!(second.claim_token, first.claim_token);
+    assert_eq!(
+        second.job.activities_checked, 0,
+        "a reclaimed job starts over"
+    );
+
+    // The stalled worker can neither report progress nor finish the job
+    assert!(
+        !db.update_segment_backfill_progress(job.id, first.claim_token, 40, 40, 9, 0)
+            .await
+            .unwrap()
+    );
+    assert!(
+        !db.finish_segment_backfill(job.id, first.claim_token, Some("boom"))
+            .await
+            .unwrap()
+    );
+    let latest = db
+        .get_latest_segment_backfill(segment_id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(latest.status, JobStatus::Processing);
+    assert_eq!(latest.last_error, None);
+
+    assert!(
+        db.update_segment_backfill_progress(job.id, second.claim_token, 40, 40, 5, 0)
+            .await
+            .unwrap()
+    );
+    assert!(
+        db.finish_segment_backfill(job.id, second.claim_token, None)
+            .await
+            .unwrap()
+    );
+    let latest = db
+        .get_latest_segment_backfill(segment_id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(latest.status, JobStatus::Succeeded);
+    assert_eq!(latest.efforts_created, 5);
+    assert!(latest.finished_at.is_some());
+
+    // Finished jobs don't block a new backfill
+    let next = db
+        .enqueue_segment_backfill(segment_id, creator)
+        .await
+        .unwrap();
+    assert_ne!(next.id, job.id);
+
+    cleanup_users(&pool, &[creator]).await;
+}
+
+#[tokio::test]
+async fn test_only_creator_and_admins_manage_backfills() {
+    let Some(pool) = get_test_pool().await else {
+        return;
+    };
+    let db = Database::new(pool.clone());
+    let queue = SegmentBackfillQueue::new(db.clone());
+
+    let creator = create_test_user(&pool, "backfill-creator", None).await;
+    let stranger = create_test_user(&pool, "backfill-stranger", None).await;
+    let admin = create_test_user(&pool, "backfill-admin", None).await;
+    make_admin(&pool, admin).await;
+    let segment_id = test_segment(&pool, creator).await;
+
+    let result = reprocess_segment(
+        Extension(db.clone()),
+        Extension(queue.clone()),
+        auth(stranger),
+        Path(segment_id),
+    )
+    .await;
+    assert!(matches!(result, Err(AppError::Forbidden)));
+    let result =
+        get_segment_backfill(Extension(db.clone()), auth(stranger), Path(segment_id)).await;
+    assert!(matches!(result, Err(AppError::Forbidden)));
+    assert!(
+        db.get_latest_segment_backfill(segment_id)
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    let (status, job) = reprocess_segment(
+        Extension(db.clone()),
+        Extension(queue.clone()),
+        auth(creator),
+        Path(segment_id),
+    )
+    .await
+    .unwrap();
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(job.requested_by, creator);
+
+    // An admin sees the pending job, and asking again returns it
+    let (_, pending) = reprocess_segment(
+        Extension(db.clone()),
+        Extension(queue.clone()),
+        auth(admin),
+        Path(segment_id),
+    )
+    .await
+    .unwrap();
+    assert_eq!(pending.id, job.id);
+    let latest = get_segment_backfill(Extension(db.clone()), auth(admin), Path(segment_id))
+        .await
+        .unwrap();
+    assert_eq!(latest.id, job.id);
+
+    cleanup_users(&pool, &[creator, stranger, admin]).await;
+}